@@ -0,0 +1,90 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError, error::JsonPayloadError, http::StatusCode,
+};
+use serde::Serialize;
+use std::fmt;
+use tracing::error;
+
+/// Error returned by handlers, rendered in the same JSON shape as the 404
+/// fallback: `{ "error", "message", "timestamp" }`.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    /// Logs the underlying error and returns a generic 500, so internals
+    /// don't leak to clients.
+    #[allow(dead_code)]
+    pub fn internal(err: impl fmt::Display) -> Self {
+        error!("Internal error: {}", err);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred",
+        )
+    }
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            error: self.status.canonical_reason().unwrap_or("Error"),
+            message: &self.message,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+}
+
+/// Malformed or oversized JSON bodies get the usual error shape instead of
+/// actix's plain-text message.
+pub fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let error = match &err {
+        JsonPayloadError::ContentType => AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected Content-Type: application/json",
+        ),
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            AppError::new(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+        }
+        JsonPayloadError::Deserialize(e) if e.is_data() => {
+            AppError::new(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        }
+        _ => AppError::bad_request(err.to_string()),
+    };
+    error.into()
+}
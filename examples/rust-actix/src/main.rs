@@ -0,0 +1,217 @@
+use actix_cors::Cors;
+use actix_files::Files;
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer,
+    dev::ServerHandle,
+    web::{self, Json, Query},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tracing::{info, warn};
+use tracing_actix_web::TracingLogger;
+
+mod error;
+mod health;
+mod nsm;
+
+use error::{AppError, AppResult};
+use health::AppState;
+
+/// Longest message `/api/echo` accepts, in characters.
+const MAX_ECHO_LEN: usize = 4096;
+
+/// How long in-flight requests get to finish once shutdown starts.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    domain: String,
+    nsm_enabled: bool,
+    profile: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    headers: Option<HashMap<String, String>>,
+}
+
+async fn home_handler() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("../templates/index.html"))
+}
+
+async fn api_info_handler(
+    Query(params): Query<HashMap<String, String>>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Json<AppInfo> {
+    let mut header_map = HashMap::new();
+
+    // Include debug headers if requested
+    if params.contains_key("debug") {
+        for (name, value) in req.headers() {
+            if let Ok(value_str) = value.to_str() {
+                header_map.insert(name.to_string(), value_str.to_string());
+            }
+        }
+    }
+
+    Json(AppInfo {
+        name: nsm::PROJECT_NAME.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        domain: nsm::domain(),
+        nsm_enabled: nsm::nsm_enabled(),
+        profile: state.profile().to_string(),
+        timestamp: chrono::Utc::now(),
+        headers: if header_map.is_empty() {
+            None
+        } else {
+            Some(header_map)
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct EchoRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct EchoResponse {
+    echo: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    id: String,
+}
+
+async fn echo_handler(Json(payload): Json<EchoRequest>) -> AppResult<Json<EchoResponse>> {
+    let len = payload.message.chars().count();
+    if len == 0 {
+        return Err(AppError::bad_request("message must not be empty"));
+    }
+    if len > MAX_ECHO_LEN {
+        return Err(AppError::bad_request(format!(
+            "message must be at most {} characters",
+            MAX_ECHO_LEN
+        )));
+    }
+
+    Ok(Json(EchoResponse {
+        echo: payload.message,
+        timestamp: chrono::Utc::now(),
+        id: uuid::Uuid::new_v4().to_string(),
+    }))
+}
+
+async fn not_found() -> AppResult<HttpResponse> {
+    Err(AppError::not_found("The requested resource was not found"))
+}
+
+/// Delay between failing `/readyz` and closing the listener, so the proxy
+/// notices before connections are refused. `SHUTDOWN_DRAIN_DELAY_MS`,
+/// default one second.
+fn drain_delay() -> Duration {
+    let ms = std::env::var("SHUTDOWN_DRAIN_DELAY_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(ms)
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Stops accepting new work on a shutdown signal and lets in-flight requests
+/// finish, within `SHUTDOWN_TIMEOUT_SECS`.
+async fn graceful_shutdown(handle: ServerHandle, state: web::Data<AppState>) {
+    shutdown_signal().await;
+    info!("🛑 Shutdown signal received, draining connections");
+    state.begin_drain();
+    tokio::time::sleep(drain_delay()).await;
+    handle.stop(true).await;
+}
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| format!("{}=debug,actix_web=info", env!("CARGO_CRATE_NAME"))),
+        )
+        .init();
+
+    let config = nsm::load_nsm_config();
+    let state = web::Data::new(AppState::new());
+
+    let addr: SocketAddr = format!("{}:{}", config.host, config.http).parse()?;
+
+    let app_state = state.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
+            .wrap(Cors::permissive())
+            .wrap(TracingLogger::default())
+            .route("/", web::get().to(home_handler))
+            .route("/api/info", web::get().to(api_info_handler))
+            .route("/api/health", web::get().to(health::health_handler))
+            .route("/api/echo", web::post().to(echo_handler))
+            .route("/livez", web::get().to(health::livez_handler))
+            .route("/readyz", web::get().to(health::readyz_handler))
+            .service(Files::new("/static", "static"))
+            .default_service(web::to(not_found))
+    })
+    // Signals are handled by `graceful_shutdown`, which fails readiness first.
+    .disable_signals()
+    .shutdown_timeout(SHUTDOWN_TIMEOUT_SECS)
+    .bind(addr)?
+    .run();
+
+    info!("🚀 Rust server starting on {}", addr);
+    info!("🌐 Domain: {}", nsm::domain());
+    info!(
+        "📡 NSM: {}",
+        if nsm::nsm_enabled() {
+            "Enabled"
+        } else {
+            "Disabled"
+        }
+    );
+    info!("🦀 Framework: Actix Web");
+    println!();
+
+    tokio::spawn(graceful_shutdown(server.handle(), state.clone()));
+    state.mark_ready();
+    server.await?;
+
+    info!("👋 Server stopped");
+    Ok(())
+}
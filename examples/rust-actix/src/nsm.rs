@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tracing::{info, warn};
+
+pub const PROJECT_NAME: &str = "{{.ProjectName}}";
+const DEFAULT_DOMAIN: &str = "{{.Domain}}";
+
+/// Written by NSM with the ports it leased to this project.
+const NSM_PORTS_FILE: &str = ".nsm-ports.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NSMConfig {
+    pub http: u16,
+    pub https: u16,
+    pub host: String,
+}
+
+impl Default for NSMConfig {
+    fn default() -> Self {
+        Self {
+            http: {{.Port}},
+            https: {{.HTTPSPort}},
+            host: "127.0.0.1".to_string(),
+        }
+    }
+}
+
+pub fn nsm_enabled() -> bool {
+    std::env::var("NSM_ENABLED").unwrap_or_default() == "true"
+}
+
+/// The domain NSM routes to us (`NSM_DOMAIN`), or the one the project was
+/// generated with.
+pub fn domain() -> String {
+    std::env::var("NSM_DOMAIN").unwrap_or_else(|_| DEFAULT_DOMAIN.to_string())
+}
+
+/// `APP_PROFILE`, or `development`/`production` by build.
+pub fn profile() -> String {
+    std::env::var("APP_PROFILE").unwrap_or_else(|_| {
+        if cfg!(debug_assertions) {
+            "development".to_string()
+        } else {
+            "production".to_string()
+        }
+    })
+}
+
+pub fn load_nsm_config() -> NSMConfig {
+    match read_nsm_config() {
+        Ok(Some(config)) => {
+            info!("🔧 NSM: Using HTTP port {}", config.http);
+            config
+        }
+        Ok(None) => NSMConfig::default(),
+        Err(e) => {
+            warn!("NSM: Failed to parse configuration: {}", e);
+            NSMConfig::default()
+        }
+    }
+}
+
+/// The NSM port lease, or `None` when we weren't started by NSM.
+fn read_nsm_config() -> anyhow::Result<Option<NSMConfig>> {
+    match fs::read_to_string(NSM_PORTS_FILE) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
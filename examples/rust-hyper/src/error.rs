@@ -0,0 +1,64 @@
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use std::fmt;
+use tracing::error;
+
+use crate::response::{self, Body};
+
+/// Error returned by handlers, rendered as
+/// `{ "error", "message", "timestamp" }` like every other failure.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "The requested resource was not found",
+        )
+    }
+
+    /// Logs the underlying error and returns a generic 500, so internals
+    /// don't leak to clients.
+    pub fn internal(err: impl fmt::Display) -> Self {
+        error!("Internal error: {}", err);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred",
+        )
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        response::json(
+            self.status,
+            &ErrorBody {
+                error: self.status.canonical_reason().unwrap_or("Error"),
+                message: &self.message,
+                timestamp: chrono::Utc::now(),
+            },
+        )
+    }
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
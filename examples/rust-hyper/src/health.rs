@@ -0,0 +1,127 @@
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    nsm,
+    response::{self, Body},
+};
+
+/// Process-wide state the probes report on.
+pub struct AppState {
+    started: Instant,
+    started_at: chrono::DateTime<chrono::Utc>,
+    profile: String,
+    ready: AtomicBool,
+    draining: AtomicBool,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: chrono::Utc::now(),
+            profile: nsm::profile(),
+            ready: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /// Called once the listener is bound.
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    /// Called when graceful shutdown starts, so `/readyz` turns the proxy
+    /// away before connections are closed.
+    pub fn begin_drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    status: &'static str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    uptime: String,
+    uptime_seconds: u64,
+    started_at: chrono::DateTime<chrono::Utc>,
+    version: &'static str,
+    profile: String,
+}
+
+#[derive(Serialize)]
+pub struct ProbeResponse {
+    status: &'static str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes, seconds) =
+        (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// `GET /api/health`: uptime, version and profile.
+pub fn health_handler(state: &AppState) -> Response<Body> {
+    let uptime = state.started.elapsed();
+    response::json(
+        StatusCode::OK,
+        &HealthResponse {
+            status: "healthy",
+            timestamp: chrono::Utc::now(),
+            uptime: format_uptime(uptime),
+            uptime_seconds: uptime.as_secs(),
+            started_at: state.started_at,
+            version: env!("CARGO_PKG_VERSION"),
+            profile: state.profile().to_string(),
+        },
+    )
+}
+
+/// Liveness: the process is up and serving requests. Never depends on
+/// anything external, so a failing dependency doesn't get us restarted.
+pub fn livez_handler() -> Response<Body> {
+    response::json(
+        StatusCode::OK,
+        &ProbeResponse {
+            status: "alive",
+            timestamp: chrono::Utc::now(),
+        },
+    )
+}
+
+/// Readiness: whether the NSM proxy should route traffic here. Reports 503
+/// until startup completes and again once graceful shutdown begins.
+pub fn readyz_handler(state: &AppState) -> Response<Body> {
+    let (code, status) = if state.draining.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if !state.ready.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, "starting")
+    } else {
+        (StatusCode::OK, "ready")
+    };
+    response::json(
+        code,
+        &ProbeResponse {
+            status,
+            timestamp: chrono::Utc::now(),
+        },
+    )
+}
@@ -0,0 +1,128 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+mod error;
+mod health;
+mod nsm;
+mod response;
+mod routes;
+mod server;
+mod tls;
+
+use health::AppState;
+use server::Listener;
+
+/// How long in-flight requests get to finish once shutdown starts.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay between failing `/readyz` and closing the listeners, so the proxy
+/// notices before connections are refused. `SHUTDOWN_DRAIN_DELAY_MS`,
+/// default one second.
+fn drain_delay() -> Duration {
+    let ms = std::env::var("SHUTDOWN_DRAIN_DELAY_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(ms)
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME"))),
+        )
+        .init();
+
+    let config = nsm::load_nsm_config();
+    let state = Arc::new(AppState::new());
+
+    let addr: SocketAddr = format!("{}:{}", config.host, config.http).parse()?;
+    let http = Listener::bind(addr, None).await?;
+    // HTTPS on the leased `https` port, when NSM hands us a certificate.
+    let https = match tls::acceptor()? {
+        Some(acceptor) => {
+            let addr = SocketAddr::new(addr.ip(), config.https);
+            Some(Listener::bind(addr, Some(acceptor)).await?)
+        }
+        None => None,
+    };
+
+    info!("🚀 Rust server starting on {}", http.local_addr()?);
+    match &https {
+        Some(https) => info!("🔒 TLS: listening on {}", https.local_addr()?),
+        None => info!("🔒 TLS: Disabled (set NSM_CERT_PATH and NSM_KEY_PATH to enable)"),
+    }
+    info!("🌐 Domain: {}", nsm::domain());
+    info!(
+        "📡 NSM: {}",
+        if nsm::nsm_enabled() {
+            "Enabled"
+        } else {
+            "Disabled"
+        }
+    );
+    info!("🦀 Framework: hyper (no framework)");
+    println!();
+
+    let (stop, stopped) = watch::channel(false);
+    let mut servers = tokio::task::JoinSet::new();
+    servers.spawn(http.serve(state.clone(), stopped.clone()));
+    if let Some(https) = https {
+        servers.spawn(https.serve(state.clone(), stopped));
+    }
+    state.mark_ready();
+
+    shutdown_signal().await;
+    info!("🛑 Shutdown signal received, draining connections");
+    state.begin_drain();
+    tokio::time::sleep(drain_delay()).await;
+    let _ = stop.send(true);
+
+    let drained = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+        while servers.join_next().await.is_some() {}
+    });
+    if drained.await.is_err() {
+        warn!(
+            "Connections still open after {}s, closing them",
+            SHUTDOWN_TIMEOUT.as_secs()
+        );
+    }
+
+    info!("👋 Server stopped");
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use http_body_util::{BodyExt, Limited};
+use hyper::{
+    HeaderMap, Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tracing::debug;
+
+use crate::{
+    error::{AppError, AppResult},
+    health::{self, AppState},
+    nsm,
+    response::{self, Body},
+};
+
+/// Longest message `/api/echo` accepts, in characters.
+const MAX_ECHO_LEN: usize = 4096;
+
+/// Largest request body `/api/echo` reads.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Where `/static/*` is served from.
+const STATIC_DIR: &str = "static";
+
+/// Routes a request by method and path; there's no framework, so this
+/// `match` is the router.
+pub async fn handle(
+    req: Request<Incoming>,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Infallible> {
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    let result = match (&method, path.as_str()) {
+        (&Method::GET, "/") => Ok(response::html(include_str!("../templates/index.html"))),
+        (&Method::GET, "/api/info") => Ok(api_info_handler(&req, &state)),
+        (&Method::GET, "/api/health") => Ok(health::health_handler(&state)),
+        (&Method::POST, "/api/echo") => echo_handler(req).await,
+        (&Method::GET, "/livez") => Ok(health::livez_handler()),
+        (&Method::GET, "/readyz") => Ok(health::readyz_handler(&state)),
+        (&Method::GET | &Method::HEAD, _) if path.starts_with("/static/") => {
+            static_handler(&path["/static/".len()..]).await
+        }
+        // CORS preflight, for any path.
+        (&Method::OPTIONS, _) => Ok(response::empty(StatusCode::NO_CONTENT)),
+        _ => Err(AppError::not_found()),
+    };
+
+    let mut res = result.unwrap_or_else(AppError::into_response);
+    response::add_cors(res.headers_mut());
+    debug!("{} {} {}", method, path, res.status().as_u16());
+    Ok(res)
+}
+
+#[derive(Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    domain: String,
+    nsm_enabled: bool,
+    profile: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    headers: Option<HashMap<String, String>>,
+}
+
+fn api_info_handler(req: &Request<Incoming>, state: &AppState) -> Response<Body> {
+    let debug = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .any(|pair| pair.split('=').next() == Some("debug"));
+
+    // Include debug headers if requested
+    let headers = debug.then(|| header_map(req.headers()));
+
+    response::json(
+        StatusCode::OK,
+        &AppInfo {
+            name: nsm::PROJECT_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            domain: nsm::domain(),
+            nsm_enabled: nsm::nsm_enabled(),
+            profile: state.profile().to_string(),
+            timestamp: chrono::Utc::now(),
+            headers,
+        },
+    )
+}
+
+fn header_map(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct EchoRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct EchoResponse {
+    echo: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    id: String,
+}
+
+async fn echo_handler(req: Request<Incoming>) -> AppResult<Response<Body>> {
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return Err(AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected Content-Type: application/json",
+        ));
+    }
+
+    let payload: EchoRequest = serde_json::from_slice(&read_body(req).await?).map_err(|e| {
+        if e.is_data() {
+            AppError::new(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        } else {
+            AppError::bad_request(e.to_string())
+        }
+    })?;
+
+    let len = payload.message.chars().count();
+    if len == 0 {
+        return Err(AppError::bad_request("message must not be empty"));
+    }
+    if len > MAX_ECHO_LEN {
+        return Err(AppError::bad_request(format!(
+            "message must be at most {} characters",
+            MAX_ECHO_LEN
+        )));
+    }
+
+    Ok(response::json(
+        StatusCode::OK,
+        &EchoResponse {
+            echo: payload.message,
+            timestamp: chrono::Utc::now(),
+            id: uuid::Uuid::new_v4().to_string(),
+        },
+    ))
+}
+
+/// The whole body, refusing anything over `MAX_BODY_BYTES`.
+async fn read_body(req: Request<Incoming>) -> AppResult<Bytes> {
+    match Limited::new(req.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) if e.is::<http_body_util::LengthLimitError>() => Err(AppError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body is over {} bytes", MAX_BODY_BYTES),
+        )),
+        Err(e) => Err(AppError::bad_request(format!(
+            "Failed to read request body: {}",
+            e
+        ))),
+    }
+}
+
+/// A file under `static/`. Paths that would step outside it are not found.
+async fn static_handler(path: &str) -> AppResult<Response<Body>> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(AppError::not_found());
+    }
+    let mut file = PathBuf::from(STATIC_DIR).join(relative);
+    if tokio::fs::metadata(&file)
+        .await
+        .is_ok_and(|meta| meta.is_dir())
+    {
+        file.push("index.html");
+    }
+    match tokio::fs::read(&file).await {
+        Ok(contents) => Ok(response::with_body(
+            StatusCode::OK,
+            content_type(&file),
+            contents,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(AppError::not_found()),
+        Err(e) => Err(AppError::internal(format!("{}: {}", file.display(), e))),
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
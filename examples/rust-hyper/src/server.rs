@@ -0,0 +1,102 @@
+use anyhow::Context;
+use hyper::{Request, body::Incoming, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+use crate::{health::AppState, routes};
+
+/// Clients that haven't finished the TLS handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One listening socket, plain or TLS.
+pub struct Listener {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    pub async fn bind(addr: SocketAddr, tls: Option<TlsAcceptor>) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("cannot bind {}", addr))?;
+        Ok(Self { listener, tls })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until `stop` turns true, then waits for open
+    /// connections to finish the requests they're on.
+    pub async fn serve(self, state: Arc<AppState>, mut stop: watch::Receiver<bool>) {
+        let mut connections = JoinSet::new();
+        loop {
+            let (tcp, remote) = tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Accept failed: {}", e);
+                        continue;
+                    }
+                },
+                _ = stopped(&mut stop) => break,
+            };
+            let (tls, state, stop) = (self.tls.clone(), state.clone(), stop.clone());
+            connections.spawn(async move {
+                let served = match tls {
+                    Some(tls) => {
+                        match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(tcp)).await {
+                            Ok(Ok(tls)) => serve_connection(tls, state, stop).await,
+                            Ok(Err(e)) => Err(e.into()),
+                            Err(_) => Err(anyhow::anyhow!("handshake timed out")),
+                        }
+                    }
+                    None => serve_connection(tcp, state, stop).await,
+                };
+                if let Err(e) = served {
+                    debug!("Connection from {}: {:#}", remote, e);
+                }
+            });
+            // Reap finished connections so the set doesn't grow unbounded.
+            while connections.try_join_next().is_some() {}
+        }
+        while connections.join_next().await.is_some() {}
+    }
+}
+
+/// Resolves once `stop` turns true.
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    // An error means the sender is gone, which only happens on shutdown.
+    let _ = stop.wait_for(|stop| *stop).await;
+}
+
+/// Serves HTTP/1.1 over `io`; once `stop` turns true the connection closes
+/// after its current request.
+async fn serve_connection<I>(
+    io: I,
+    state: Arc<AppState>,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |req: Request<Incoming>| routes::handle(req, state.clone()));
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let served = tokio::select! {
+        served = conn.as_mut() => served,
+        _ = stopped(&mut stop) => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    Ok(served?)
+}
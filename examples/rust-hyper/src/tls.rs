@@ -0,0 +1,41 @@
+use anyhow::Context;
+use std::{fs, io::BufReader, path::PathBuf, sync::Arc};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{ServerConfig, crypto::ring::default_provider, pki_types::CertificateDer},
+};
+
+/// The HTTPS listener's acceptor, from the certificate and key NSM points
+/// us at with `NSM_CERT_PATH` and `NSM_KEY_PATH` (e.g. made by mkcert).
+/// `None` when either is unset, leaving TLS to the NSM proxy.
+pub fn acceptor() -> anyhow::Result<Option<TlsAcceptor>> {
+    let (Some(cert_path), Some(key_path)) = (
+        std::env::var_os("NSM_CERT_PATH").map(PathBuf::from),
+        std::env::var_os("NSM_KEY_PATH").map(PathBuf::from),
+    ) else {
+        return Ok(None);
+    };
+
+    let certs = fs::File::open(&cert_path)
+        .and_then(|file| {
+            rustls_pemfile::certs(&mut BufReader::new(file))
+                .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        })
+        .with_context(|| format!("failed to read {}", cert_path.display()))?;
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "no certificates in {}",
+        cert_path.display()
+    );
+    let key = fs::File::open(&key_path)
+        .and_then(|file| rustls_pemfile::private_key(&mut BufReader::new(file)))
+        .with_context(|| format!("failed to read {}", key_path.display()))?
+        .with_context(|| format!("no private key in {}", key_path.display()))?;
+
+    let mut tls = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(TlsAcceptor::from(Arc::new(tls))))
+}
@@ -0,0 +1,87 @@
+use rocket::{
+    Request,
+    http::Status,
+    response::{self, Responder},
+    serde::json::{self, Json},
+};
+use serde::Serialize;
+
+/// Error returned by handlers, rendered in the same JSON shape as the
+/// catchers: `{ "error", "message", "timestamp" }`.
+#[derive(Debug)]
+pub struct AppError {
+    status: Status,
+    message: String,
+}
+
+impl AppError {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(Status::BadRequest, message)
+    }
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// A body the `Json` guard couldn't read: 422 when it parsed but didn't fit
+/// the type, 400 otherwise.
+impl From<json::Error<'_>> for AppError {
+    fn from(err: json::Error<'_>) -> Self {
+        match err {
+            json::Error::Parse(_, e) if e.is_data() => {
+                Self::new(Status::UnprocessableEntity, e.to_string())
+            }
+            json::Error::Parse(_, e) => Self::bad_request(e.to_string()),
+            json::Error::Io(e) => Self::bad_request(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    error: &'static str,
+    message: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl ErrorBody {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            error: status.reason().unwrap_or("Error"),
+            message: message.into(),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AppError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = Json(ErrorBody::new(self.status, self.message));
+        (self.status, body).respond_to(req)
+    }
+}
+
+#[rocket::catch(404)]
+pub fn not_found() -> Json<ErrorBody> {
+    Json(ErrorBody::new(
+        Status::NotFound,
+        "The requested resource was not found",
+    ))
+}
+
+/// Everything else Rocket fails a request with, in the usual JSON shape
+/// instead of its HTML error page.
+#[rocket::catch(default)]
+pub fn default_catcher(status: Status, _req: &Request<'_>) -> (Status, Json<ErrorBody>) {
+    let message = match status.code {
+        500..=599 => "An internal error occurred".to_string(),
+        _ => status.reason().unwrap_or("Request failed").to_string(),
+    };
+    (status, Json(ErrorBody::new(status, message)))
+}
@@ -0,0 +1,168 @@
+use rocket::{
+    Orbit, Request, Response, Rocket,
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+};
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+use crate::{
+    health::AppState,
+    nsm::{self, NSMConfig},
+};
+
+/// Tags every response with the project and domain it came from, so
+/// responses are easy to tell apart behind the NSM proxy.
+pub struct NsmHeaders {
+    domain: String,
+}
+
+impl NsmHeaders {
+    pub fn new() -> Self {
+        Self {
+            domain: nsm::domain(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for NsmHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "NSM headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, _req: &'r Request<'_>, res: &mut Response<'r>) {
+        res.set_header(Header::new("X-Served-By", nsm::PROJECT_NAME));
+        res.set_header(Header::new("X-NSM-Domain", self.domain.clone()));
+    }
+}
+
+/// Allows any origin, like the other templates' permissive CORS. Preflight
+/// requests are answered by the `OPTIONS` catch-all route.
+pub struct Cors;
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, _req: &'r Request<'_>, res: &mut Response<'r>) {
+        res.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+        res.set_header(Header::new(
+            "Access-Control-Allow-Methods",
+            "GET, POST, OPTIONS",
+        ));
+        res.set_header(Header::new(
+            "Access-Control-Allow-Headers",
+            "content-type, authorization",
+        ));
+    }
+}
+
+/// Logs the startup banner, marks the app ready once Rocket is listening and
+/// owns graceful shutdown: Rocket's own signal handling is turned off so
+/// `/readyz` can fail before the listener closes, since Rocket refuses new
+/// requests as soon as its shutdown starts.
+pub struct Lifecycle {
+    state: Arc<AppState>,
+}
+
+impl Lifecycle {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Lifecycle {
+    fn info(&self) -> Info {
+        Info {
+            name: "Lifecycle",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if let Some(config) = rocket.state::<NSMConfig>() {
+            info!("🚀 Rust server starting on {}:{}", config.host, config.http);
+        }
+        info!("🌐 Domain: {}", nsm::domain());
+        info!(
+            "📡 NSM: {}",
+            if nsm::nsm_enabled() {
+                "Enabled"
+            } else {
+                "Disabled"
+            }
+        );
+        info!("🦀 Framework: Rocket");
+        println!();
+
+        self.state.mark_ready();
+
+        let state = self.state.clone();
+        let shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("🛑 Shutdown signal received, draining connections");
+            state.begin_drain();
+            rocket::tokio::time::sleep(drain_delay()).await;
+            shutdown.notify();
+        });
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        // Shutdown started some other way (a `Shutdown` guard, say).
+        self.state.begin_drain();
+    }
+}
+
+/// Delay between failing `/readyz` and closing the listener, so the proxy
+/// notices before connections are refused. `SHUTDOWN_DRAIN_DELAY_MS`,
+/// default one second.
+fn drain_delay() -> Duration {
+    let ms = std::env::var("SHUTDOWN_DRAIN_DELAY_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(ms)
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = rocket::tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use rocket::tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    rocket::tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,181 @@
+use rocket::{
+    Request,
+    fs::{FileServer, Options},
+    http::Status,
+    request::{FromRequest, Outcome},
+    response::content::RawHtml,
+    serde::json::{self, Json},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+use tracing::info;
+
+mod error;
+mod fairings;
+mod health;
+mod nsm;
+
+use error::{AppError, AppResult};
+use health::AppState;
+
+/// Longest message `/api/echo` accepts, in characters.
+const MAX_ECHO_LEN: usize = 4096;
+
+/// How long in-flight requests get to finish once shutdown starts.
+const SHUTDOWN_TIMEOUT_SECS: u32 = 30;
+
+#[derive(Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    domain: String,
+    nsm_enabled: bool,
+    profile: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    headers: Option<HashMap<String, String>>,
+}
+
+/// The request's headers, for `/api/info?debug`.
+struct RequestHeaders(HashMap<String, String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestHeaders {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = req
+            .headers()
+            .iter()
+            .map(|header| (header.name().to_string(), header.value().to_string()))
+            .collect();
+        Outcome::Success(RequestHeaders(headers))
+    }
+}
+
+#[rocket::get("/")]
+fn home_handler() -> RawHtml<&'static str> {
+    RawHtml(include_str!("../templates/index.html"))
+}
+
+#[rocket::get("/api/info?<debug>")]
+fn api_info_handler(
+    debug: Option<&str>,
+    headers: RequestHeaders,
+    state: &rocket::State<Arc<AppState>>,
+) -> Json<AppInfo> {
+    // Include debug headers if requested
+    let header_map = match debug {
+        Some(_) => headers.0,
+        None => HashMap::new(),
+    };
+
+    Json(AppInfo {
+        name: nsm::PROJECT_NAME.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        domain: nsm::domain(),
+        nsm_enabled: nsm::nsm_enabled(),
+        profile: state.profile().to_string(),
+        timestamp: chrono::Utc::now(),
+        headers: if header_map.is_empty() {
+            None
+        } else {
+            Some(header_map)
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct EchoRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct EchoResponse {
+    echo: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    id: String,
+}
+
+#[rocket::post("/api/echo", data = "<payload>")]
+fn echo_handler(
+    payload: Result<Json<EchoRequest>, json::Error<'_>>,
+) -> AppResult<Json<EchoResponse>> {
+    let payload = payload?.into_inner();
+    let len = payload.message.chars().count();
+    if len == 0 {
+        return Err(AppError::bad_request("message must not be empty"));
+    }
+    if len > MAX_ECHO_LEN {
+        return Err(AppError::bad_request(format!(
+            "message must be at most {} characters",
+            MAX_ECHO_LEN
+        )));
+    }
+
+    Ok(Json(EchoResponse {
+        echo: payload.message,
+        timestamp: chrono::Utc::now(),
+        id: uuid::Uuid::new_v4().to_string(),
+    }))
+}
+
+/// Answers CORS preflight requests for any path.
+#[rocket::options("/<_..>")]
+fn preflight_handler() -> Status {
+    Status::NoContent
+}
+
+#[rocket::main]
+async fn main() -> anyhow::Result<()> {
+    // Initialize tracing; Rocket's own `log` records are routed through it.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| format!("{}=debug,rocket=warn", env!("CARGO_CRATE_NAME"))),
+        )
+        .init();
+
+    let config = nsm::load_nsm_config();
+    let state = Arc::new(AppState::new());
+
+    // `ROCKET_*` variables and `Rocket.toml` still apply; the NSM port lease
+    // and the shutdown settings below win.
+    let figment = rocket::Config::figment()
+        .merge(("address", config.host.as_str()))
+        .merge(("port", config.http))
+        .merge(("shutdown.ctrlc", false))
+        .merge(("shutdown.signals", Vec::<String>::new()))
+        .merge(("shutdown.grace", SHUTDOWN_TIMEOUT_SECS));
+
+    rocket::custom(figment)
+        .manage(config)
+        .manage(state.clone())
+        .attach(fairings::Lifecycle::new(state))
+        .attach(fairings::NsmHeaders::new())
+        .attach(fairings::Cors)
+        .mount(
+            "/",
+            rocket::routes![
+                home_handler,
+                api_info_handler,
+                echo_handler,
+                preflight_handler,
+                health::health_handler,
+                health::livez_handler,
+                health::readyz_handler,
+            ],
+        )
+        .mount(
+            "/static",
+            FileServer::new("static", Options::Index | Options::Missing),
+        )
+        .register(
+            "/",
+            rocket::catchers![error::not_found, error::default_catcher],
+        )
+        .launch()
+        .await?;
+
+    info!("👋 Server stopped");
+    Ok(())
+}
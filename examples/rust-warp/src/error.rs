@@ -0,0 +1,91 @@
+use serde::Serialize;
+use std::{convert::Infallible, fmt};
+use tracing::error;
+use warp::{
+    Rejection, Reply,
+    body::BodyDeserializeError,
+    cors::CorsForbidden,
+    http::StatusCode,
+    reject::{MethodNotAllowed, PayloadTooLarge, Reject, UnsupportedMediaType},
+};
+
+/// Error returned by handlers as a custom rejection, rendered by [`recover`]
+/// in the same JSON shape as every other failure:
+/// `{ "error", "message", "timestamp" }`.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    /// Logs the underlying error and returns a generic 500, so internals
+    /// don't leak to clients.
+    pub fn internal(err: impl fmt::Display) -> Self {
+        error!("Internal error: {}", err);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred",
+        )
+    }
+}
+
+impl Reject for AppError {}
+
+pub type AppResult<T> = Result<T, Rejection>;
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+fn reply(status: StatusCode, message: &str) -> warp::reply::Response {
+    let body = warp::reply::json(&ErrorBody {
+        error: status.canonical_reason().unwrap_or("Error"),
+        message,
+        timestamp: chrono::Utc::now(),
+    });
+    warp::reply::with_status(body, status).into_response()
+}
+
+/// Turns any rejection into a JSON error response, so clients never see
+/// warp's plain-text defaults.
+pub async fn recover(rejection: Rejection) -> Result<warp::reply::Response, Infallible> {
+    let error = if rejection.is_not_found() {
+        AppError::new(
+            StatusCode::NOT_FOUND,
+            "The requested resource was not found",
+        )
+    } else if let Some(err) = rejection.find::<AppError>() {
+        AppError::new(err.status, err.message.clone())
+    } else if let Some(err) = rejection.find::<BodyDeserializeError>() {
+        AppError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+    } else if rejection.find::<UnsupportedMediaType>().is_some() {
+        AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected Content-Type: application/json",
+        )
+    } else if let Some(err) = rejection.find::<PayloadTooLarge>() {
+        AppError::new(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+    } else if let Some(err) = rejection.find::<CorsForbidden>() {
+        AppError::new(StatusCode::FORBIDDEN, err.to_string())
+    } else if let Some(err) = rejection.find::<MethodNotAllowed>() {
+        AppError::new(StatusCode::METHOD_NOT_ALLOWED, err.to_string())
+    } else {
+        AppError::internal(format!("unhandled rejection: {:?}", rejection))
+    };
+    Ok(reply(error.status, &error.message))
+}
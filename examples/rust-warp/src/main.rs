@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use warp::{Filter, Reply, http::HeaderMap};
+
+mod error;
+mod health;
+mod nsm;
+
+use error::{AppError, AppResult};
+use health::{AppState, with_state};
+
+/// Longest message `/api/echo` accepts, in characters.
+const MAX_ECHO_LEN: usize = 4096;
+
+/// Largest request body `/api/echo` reads.
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// How long in-flight requests get to finish once shutdown starts.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
+struct AppInfo {
+    name: String,
+    version: String,
+    domain: String,
+    nsm_enabled: bool,
+    profile: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    headers: Option<HashMap<String, String>>,
+}
+
+fn home_handler() -> impl Reply {
+    warp::reply::html(include_str!("../templates/index.html"))
+}
+
+fn api_info_handler(
+    params: HashMap<String, String>,
+    headers: HeaderMap,
+    state: Arc<AppState>,
+) -> impl Reply {
+    let mut header_map = HashMap::new();
+
+    // Include debug headers if requested
+    if params.contains_key("debug") {
+        for (name, value) in headers.iter() {
+            if let Ok(value_str) = value.to_str() {
+                header_map.insert(name.to_string(), value_str.to_string());
+            }
+        }
+    }
+
+    warp::reply::json(&AppInfo {
+        name: nsm::PROJECT_NAME.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        domain: nsm::domain(),
+        nsm_enabled: nsm::nsm_enabled(),
+        profile: state.profile().to_string(),
+        timestamp: chrono::Utc::now(),
+        headers: if header_map.is_empty() {
+            None
+        } else {
+            Some(header_map)
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct EchoRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct EchoResponse {
+    echo: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    id: String,
+}
+
+async fn echo_handler(payload: EchoRequest) -> AppResult<impl Reply> {
+    let len = payload.message.chars().count();
+    if len == 0 {
+        return Err(AppError::bad_request("message must not be empty").into());
+    }
+    if len > MAX_ECHO_LEN {
+        return Err(AppError::bad_request(format!(
+            "message must be at most {} characters",
+            MAX_ECHO_LEN
+        ))
+        .into());
+    }
+
+    Ok(warp::reply::json(&EchoResponse {
+        echo: payload.message,
+        timestamp: chrono::Utc::now(),
+        id: uuid::Uuid::new_v4().to_string(),
+    }))
+}
+
+/// Every route, with CORS, tracing and JSON errors applied.
+fn routes(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (impl Reply,), Error = std::convert::Infallible> + Clone {
+    let home = warp::path::end().and(warp::get()).map(home_handler);
+
+    let info = warp::path!("api" / "info")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::headers_cloned())
+        .and(with_state(state.clone()))
+        .map(api_info_handler);
+
+    let health = warp::path!("api" / "health")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .map(health::health_handler);
+
+    let echo = warp::path!("api" / "echo")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(echo_handler);
+
+    let livez = warp::path!("livez")
+        .and(warp::get())
+        .map(health::livez_handler);
+
+    let readyz = warp::path!("readyz")
+        .and(warp::get())
+        .and(with_state(state))
+        .map(health::readyz_handler);
+
+    let static_files = warp::path("static").and(warp::fs::dir("static"));
+
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_methods(vec!["GET", "POST", "OPTIONS"])
+        .allow_headers(vec!["content-type", "authorization"]);
+
+    home.or(info)
+        .or(health)
+        .or(echo)
+        .or(livez)
+        .or(readyz)
+        .or(static_files)
+        .with(cors)
+        .with(warp::trace::request())
+        .recover(error::recover)
+}
+
+/// Delay between failing `/readyz` and closing the listener, so the proxy
+/// notices before connections are refused. `SHUTDOWN_DRAIN_DELAY_MS`,
+/// default one second.
+fn drain_delay() -> Duration {
+    let ms = std::env::var("SHUTDOWN_DRAIN_DELAY_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(ms)
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| format!("{}=debug,warp=info", env!("CARGO_CRATE_NAME"))),
+        )
+        .init();
+
+    let config = nsm::load_nsm_config();
+    let state = Arc::new(AppState::new());
+
+    let addr: SocketAddr = format!("{}:{}", config.host, config.http).parse()?;
+
+    // Stopping the listener waits for `stop`, so readiness can fail first.
+    let (stop, stopped) = oneshot::channel::<()>();
+    let (addr, server) =
+        warp::serve(routes(state.clone())).try_bind_with_graceful_shutdown(addr, async {
+            stopped.await.ok();
+        })?;
+    let server = tokio::spawn(server);
+
+    info!("🚀 Rust server starting on {}", addr);
+    info!("🌐 Domain: {}", nsm::domain());
+    info!(
+        "📡 NSM: {}",
+        if nsm::nsm_enabled() {
+            "Enabled"
+        } else {
+            "Disabled"
+        }
+    );
+    info!("🦀 Framework: Warp");
+    println!();
+
+    state.mark_ready();
+    shutdown_signal().await;
+
+    info!("🛑 Shutdown signal received, draining connections");
+    state.begin_drain();
+    tokio::time::sleep(drain_delay()).await;
+    let _ = stop.send(());
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, server)
+        .await
+        .is_err()
+    {
+        warn!(
+            "Connections still open after {}s, closing them",
+            SHUTDOWN_TIMEOUT.as_secs()
+        );
+    }
+
+    info!("👋 Server stopped");
+    Ok(())
+}
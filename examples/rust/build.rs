@@ -0,0 +1,68 @@
+use std::process::Command;
+
+/// Embeds build metadata (git sha/branch, build time, rustc version, cargo
+/// profile) as `BUILD_*` env vars, read back by `src/build_info.rs`.
+fn main() {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+
+    let sha = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let dirty = git(&["status", "--porcelain"]).is_some_and(|s| !s.is_empty());
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", sha);
+    println!("cargo:rustc-env=BUILD_GIT_BRANCH={}", branch);
+    println!("cargo:rustc-env=BUILD_GIT_DIRTY={}", dirty);
+    println!(
+        "cargo:rustc-env=BUILD_TIMESTAMP={}",
+        chrono::Utc::now().to_rfc3339()
+    );
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version);
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_else(|_| "unknown".into())
+    );
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+
+    // Set by RUSTFLAGS for the `console` feature.
+    println!("cargo::rustc-check-cfg=cfg(tokio_unstable)");
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // Embedded by `sqlx::migrate!` with the `database` feature.
+    println!("cargo:rerun-if-changed=migrations");
+}
+
+/// Generates the gRPC code for `proto/` with protox, so no `protoc` install
+/// is needed, and keeps the descriptor set around for server reflection.
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    use protox::prost::Message;
+
+    let fds = protox::compile(["proto/echo.proto"], ["proto"]).expect("failed to compile protos");
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("echo_descriptor.bin"), fds.encode_to_vec())
+        .expect("failed to write descriptor set");
+    tonic_build::configure()
+        .compile_fds(fds)
+        .expect("failed to generate gRPC code");
+
+    println!("cargo:rerun-if-changed=proto");
+}
@@ -0,0 +1,9 @@
+-- Backs the /api/notes demo. Add new migrations as NNNN_name.sql; they run
+-- in order at startup and are embedded in the binary at build time.
+CREATE TABLE notes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Server-side sessions for the `sqlite` session backend. `expires_at` is in
+-- Unix seconds.
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
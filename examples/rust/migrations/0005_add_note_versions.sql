@@ -0,0 +1,3 @@
+-- Bumped on every update; /api/notes serves it as the ETag, so clients can
+-- make conditional requests.
+ALTER TABLE notes ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,19 @@
+-- Keys issued through /admin/api-keys. Only a SHA-256 of each key is kept;
+-- `prefix` is its first few characters, to tell keys apart in listings.
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    tier TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    revoked_at TEXT
+);
+
+-- Requests made with each key per UTC day (`YYYY-MM-DD`), for daily quotas.
+CREATE TABLE api_key_usage (
+    key_id INTEGER NOT NULL REFERENCES api_keys (id) ON DELETE CASCADE,
+    day TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    PRIMARY KEY (key_id, day)
+);
@@ -0,0 +1,3 @@
+-- Deleting a note sets this instead of removing the row, so it can be
+-- restored; the scheduler purges notes deleted long enough ago.
+ALTER TABLE notes ADD COLUMN deleted_at TEXT;
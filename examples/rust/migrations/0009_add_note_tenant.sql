@@ -0,0 +1,5 @@
+-- The tenant a note belongs to, with tenancy on. Notes from before then
+-- belong to the default tenant.
+ALTER TABLE notes ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX idx_notes_tenant ON notes (tenant, id);
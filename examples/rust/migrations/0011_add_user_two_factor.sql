@@ -0,0 +1,14 @@
+-- TOTP two-factor for accounts. `totp_secret` is the base32 shared secret,
+-- set once the account has confirmed a code; `totp_last_step` is the time
+-- step of the last code accepted, so a code can't be used twice.
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
+
+-- Single-use codes for when the authenticator is lost. Only a SHA-256 of
+-- each code is kept.
+CREATE TABLE recovery_codes (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    hash TEXT NOT NULL,
+    used_at TEXT,
+    PRIMARY KEY (user_id, hash)
+);
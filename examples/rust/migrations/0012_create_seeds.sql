@@ -0,0 +1,7 @@
+-- Files from seeds/ already applied, so `seed` and `database.seed_on_start`
+-- run each one once.
+CREATE TABLE seeds (
+    name TEXT PRIMARY KEY,
+    checksum TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- The tenant whose data an event carries, so the event hub only hands it
+-- to that tenant's subscribers. NULL for events anyone may see.
+ALTER TABLE outbox ADD COLUMN tenant TEXT;
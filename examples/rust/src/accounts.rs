@@ -0,0 +1,109 @@
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::{
+    error::{AppError, AppResult},
+    passwords::Verdict,
+    state::AppState,
+};
+
+/// Someone who can log in with a password: a row of `users`. Usernames are
+/// unique regardless of case.
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    /// Whether logging in also takes a TOTP code.
+    pub two_factor: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct Credentials {
+    #[sqlx(flatten)]
+    account: Account,
+    password_hash: String,
+}
+
+/// `password` hashed off the async workers.
+async fn hash(state: &AppState, password: String) -> AppResult<String> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || state.passwords().hash(&password))
+        .await
+        .map_err(AppError::internal)?
+        .map_err(AppError::internal)
+}
+
+/// Creates an account with `password`; `None` when the username is taken.
+pub async fn create(
+    state: &AppState,
+    username: &str,
+    password: String,
+) -> AppResult<Option<Account>> {
+    let password_hash = hash(state, password).await?;
+    let account: Option<Account> = sqlx::query_as(
+        "INSERT INTO users (username, password_hash, created_at) VALUES (?, ?, ?) \
+         ON CONFLICT (username) DO NOTHING RETURNING id, username, FALSE AS two_factor",
+    )
+    .bind(username)
+    .bind(password_hash)
+    .bind(Utc::now())
+    .fetch_optional(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    if let Some(account) = &account {
+        info!("👤 Created account {} ({})", account.id, account.username);
+    }
+    Ok(account)
+}
+
+/// The account `username` names, if `password` is its password. A
+/// password hashed the old way (bcrypt, lower cost, an older pepper) is
+/// hashed again with the current settings on the way.
+pub async fn authenticate(
+    state: &AppState,
+    username: &str,
+    password: String,
+) -> AppResult<Option<Account>> {
+    let found: Option<Credentials> = sqlx::query_as(
+        "SELECT id, username, totp_secret IS NOT NULL AS two_factor, password_hash \
+         FROM users WHERE username = ?",
+    )
+    .bind(username)
+    .fetch_optional(state.db())
+    .await
+    .map_err(AppError::internal)?;
+
+    let (verdict, password) = {
+        let state = state.clone();
+        let stored = found.as_ref().map(|c| c.password_hash.clone());
+        tokio::task::spawn_blocking(move || {
+            let verdict = state.passwords().verify(&password, stored.as_deref());
+            (verdict, password)
+        })
+        .await
+        .map_err(AppError::internal)?
+    };
+    let Verdict { matches, rehash } = verdict.map_err(AppError::internal)?;
+    let Some(Credentials { account, .. }) = found.filter(|_| matches) else {
+        return Ok(None);
+    };
+
+    if rehash {
+        // The login stands either way; the next one tries again.
+        match hash(state, password).await {
+            Ok(password_hash) => {
+                let updated = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+                    .bind(password_hash)
+                    .bind(account.id)
+                    .execute(state.db())
+                    .await;
+                match updated {
+                    Ok(_) => info!("👤 Rehashed the password of account {}", account.id),
+                    Err(e) => warn!("Failed to rehash account {}'s password: {}", account.id, e),
+                }
+            }
+            Err(_) => warn!("Failed to rehash account {}'s password", account.id),
+        }
+    }
+    Ok(Some(account))
+}
@@ -0,0 +1,363 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fmt::Write as _, path::PathBuf};
+
+use crate::{
+    basic_auth::BasicUser, cli, config::EffectiveConfig, error::AppError, state::AppState,
+    static_keys::StaticKey,
+};
+
+/// Default path of the admin console's Unix socket, relative to the working
+/// directory. Override with `APP_CONTROL_SOCKET`; set it empty to turn the
+/// console off.
+pub const CONTROL_SOCKET: &str = ".nsm-control.sock";
+
+const HELP: &str = "\
+status                 Uptime, readiness, maintenance, connections
+config                 The effective configuration
+requests               Requests waiting on a response, oldest first
+maintenance [on|off]   Show or set maintenance mode (503 for all but probes)
+log [FILTER|reset]     Show, set or restore the log filter, e.g. `log debug`
+quit                   Close the console
+";
+
+pub fn socket_path() -> Option<PathBuf> {
+    match std::env::var("APP_CONTROL_SOCKET") {
+        Ok(path) if path.is_empty() => None,
+        Ok(path) => Some(path.into()),
+        Err(_) => Some(CONTROL_SOCKET.into()),
+    }
+}
+
+/// In maintenance mode, answers `503` to everything but the probes, NSM's
+/// own endpoints and the switch to turn it off again.
+pub async fn reject_during_maintenance(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path();
+    if !state.in_maintenance()
+        || path == "/livez"
+        || path == "/readyz"
+        || path.starts_with("/__nsm/")
+        || path == "/admin/maintenance"
+    {
+        return next.run(req).await;
+    }
+    AppError::new(StatusCode::SERVICE_UNAVAILABLE, "Down for maintenance").into_response()
+}
+
+/// Whether the request carries `Authorization: Bearer $ADMIN_TOKEN`.
+pub fn has_admin_token(headers: &HeaderMap) -> bool {
+    let Some(expected) = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) else {
+        return false;
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compared as digests, so how long it takes says nothing about the token.
+    Sha256::digest(given) == Sha256::digest(&expected)
+}
+
+/// Guards the HTTP admin API (`/admin/...`): it takes
+/// `Authorization: Bearer $ADMIN_TOKEN`, a static API key or a Basic auth
+/// user. Without `ADMIN_TOKEN` it's open in debug builds and closed in
+/// release ones.
+pub async fn require_token(req: Request, next: Next) -> Response {
+    if req.extensions().get::<StaticKey>().is_some()
+        || req.extensions().get::<BasicUser>().is_some()
+    {
+        return next.run(req).await;
+    }
+
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => {}
+        _ if cfg!(debug_assertions) => return next.run(req).await,
+        _ => {
+            return AppError::new(
+                StatusCode::FORBIDDEN,
+                "Set ADMIN_TOKEN to use the admin API",
+            )
+            .into_response()
+        }
+    }
+    if !has_admin_token(req.headers()) {
+        let mut res =
+            AppError::new(StatusCode::UNAUTHORIZED, "Admin token required").into_response();
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return res;
+    }
+    next.run(req).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Maintenance {
+    on: bool,
+}
+
+/// `GET /admin/maintenance`: whether maintenance mode is on.
+pub async fn maintenance_handler(State(state): State<AppState>) -> Json<Maintenance> {
+    Json(Maintenance {
+        on: state.in_maintenance(),
+    })
+}
+
+/// `PUT /admin/maintenance`: turns maintenance mode on or off, like the
+/// console's `maintenance` command.
+pub async fn set_maintenance_handler(
+    State(state): State<AppState>,
+    Json(input): Json<Maintenance>,
+) -> Json<Maintenance> {
+    state.set_maintenance(input.on);
+    tracing::warn!(
+        "🚧 Maintenance mode {} from the admin API",
+        on_off(input.on)
+    );
+    Json(input)
+}
+
+/// What a console command prints, and whether to hang up afterwards.
+enum Reply {
+    Text(String),
+    Quit,
+}
+
+fn execute(state: &AppState, line: &str) -> Reply {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Reply::Text(String::new());
+    };
+    let args: Vec<&str> = words.collect();
+    let text = match (command, args.as_slice()) {
+        ("help", _) => HELP.to_string(),
+        ("quit" | "exit", _) => return Reply::Quit,
+        ("status", _) => status(state),
+        ("config", _) => {
+            let config = EffectiveConfig::new(state.profile(), state.config());
+            serde_json::to_string_pretty(&config).unwrap_or_else(|e| e.to_string()) + "\n"
+        }
+        ("requests", _) => requests(state),
+        ("maintenance", []) => format!("maintenance {}\n", on_off(state.in_maintenance())),
+        ("maintenance", ["on" | "off"]) => {
+            state.set_maintenance(args[0] == "on");
+            state.privileged_log().console(line, "ok");
+            tracing::warn!("🚧 Maintenance mode {} from the admin console", args[0]);
+            format!("maintenance {}\n", args[0])
+        }
+        ("log", []) => format!("{}\n", cli::current_log_filter().unwrap_or_default()),
+        ("log", [filter]) => {
+            let filter = (*filter != "reset").then_some(*filter);
+            match cli::set_log_filter(filter) {
+                Ok(()) => {
+                    state.privileged_log().console(line, "ok");
+                    format!("{}\n", cli::current_log_filter().unwrap_or_default())
+                }
+                Err(e) => {
+                    state.privileged_log().console(line, "failed");
+                    format!("error: {:#}\n", e)
+                }
+            }
+        }
+        _ => format!("unknown command {:?}; try `help`\n", line.trim()),
+    };
+    Reply::Text(text)
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn status(state: &AppState) -> String {
+    let uptime = state.uptime().as_secs();
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "uptime       {}h {}m {}s",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    );
+    let _ = writeln!(text, "profile      {}", state.profile());
+    let _ = writeln!(text, "ready        {}", state.is_ready());
+    let _ = writeln!(text, "draining     {}", state.is_draining());
+    let _ = writeln!(text, "maintenance  {}", on_off(state.in_maintenance()));
+    let _ = writeln!(text, "connections  {}", state.connections().open());
+    let _ = writeln!(text, "in flight    {}", state.in_flight().list().len());
+    text
+}
+
+fn requests(state: &AppState) -> String {
+    let requests = state.in_flight().list();
+    if requests.is_empty() {
+        return "no requests in flight\n".to_string();
+    }
+    let mut text = String::new();
+    for req in requests {
+        let _ = writeln!(
+            text,
+            "{:>9.1}ms  {:<7} {}  {}",
+            req.started.elapsed().as_secs_f64() * 1000.0,
+            req.method,
+            req.path,
+            req.request_id
+        );
+    }
+    text
+}
+
+#[cfg(unix)]
+mod socket {
+    use anyhow::Context;
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+    use tracing::{debug, info, warn};
+
+    use super::{execute, socket_path, Reply};
+    use crate::state::AppState;
+
+    const PROMPT: &str = "> ";
+
+    /// Listens on the control socket. Only the owner may connect.
+    pub fn spawn(state: AppState) -> anyhow::Result<()> {
+        let Some(path) = socket_path() else {
+            return Ok(());
+        };
+        // A previous run (or the build before a watch restart) leaves its
+        // socket behind.
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind control socket {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        info!("🛠️  Admin console: nc -U {}", path.display());
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(session(stream, state.clone()));
+                    }
+                    Err(e) => warn!("Control socket accept failed: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn session(stream: UnixStream, state: AppState) {
+        debug!("Admin console connected");
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let banner = format!(
+            "{} admin console; `help` lists commands\n{}",
+            crate::PROJECT_NAME,
+            PROMPT
+        );
+        if write.write_all(banner.as_bytes()).await.is_err() {
+            return;
+        }
+        let mut prompt = true;
+        while let Ok(Some(line)) = lines.next_line().await {
+            // Sent by `admin <command>`, which wants bare output.
+            if line.trim() == "prompt off" {
+                prompt = false;
+                continue;
+            }
+            let mut text = match execute(&state, &line) {
+                Reply::Text(text) => text,
+                Reply::Quit => break,
+            };
+            if prompt {
+                text.push_str(PROMPT);
+            }
+            if write.write_all(text.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        debug!("Admin console disconnected");
+    }
+
+    /// `admin`: relays the terminal to the console, or runs one command and
+    /// prints its output.
+    pub async fn client(command: Vec<String>) -> anyhow::Result<()> {
+        let path = socket_path().context("the control socket is turned off")?;
+        let stream = UnixStream::connect(&path).await.with_context(|| {
+            format!(
+                "failed to connect to {}; is the server running?",
+                path.display()
+            )
+        })?;
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+
+        if command.is_empty() {
+            let mut stdin = tokio::io::stdin();
+            let mut stdout = tokio::io::stdout();
+            tokio::select! {
+                _ = tokio::io::copy(&mut stdin, &mut write) => {}
+                _ = tokio::io::copy(&mut read, &mut stdout) => {}
+            }
+            return Ok(());
+        }
+
+        let mut banner = String::new();
+        read.read_line(&mut banner).await?;
+        let script = format!("prompt off\n{}\nquit\n", command.join(" "));
+        write.write_all(script.as_bytes()).await?;
+        let mut output = String::new();
+        read.read_to_string(&mut output).await?;
+        print!("{}", output.trim_start_matches(PROMPT));
+        Ok(())
+    }
+
+    /// Whether a server is answering on the control socket.
+    #[cfg(feature = "database")]
+    pub fn server_running() -> bool {
+        socket_path().is_some_and(|path| std::os::unix::net::UnixStream::connect(path).is_ok())
+    }
+
+    pub fn cleanup() {
+        if let Some(path) = socket_path() {
+            let _ = fs::remove_file(Path::new(&path));
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "database"))]
+pub use socket::server_running;
+#[cfg(unix)]
+pub use socket::{cleanup, client, spawn};
+
+#[cfg(not(unix))]
+pub fn spawn(_state: AppState) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn client(_command: Vec<String>) -> anyhow::Result<()> {
+    anyhow::bail!("the admin console needs a Unix socket")
+}
+
+#[cfg(not(unix))]
+pub fn cleanup() {}
+
+#[cfg(all(not(unix), feature = "database"))]
+pub fn server_running() -> bool {
+    false
+}
@@ -0,0 +1,357 @@
+use axum::{
+    extract::{Extension, Path, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::info;
+use validator::Validate;
+
+use crate::{
+    basic_auth::BasicUser,
+    config::{ApiKeyTier, ApiKeysConfig},
+    error::{AppError, AppResult, FieldErrors},
+    ratelimit::{too_many_requests, until_reset, RateLimiter},
+    signed_urls::SignedUrl,
+    state::AppState,
+    static_keys::StaticKey,
+    validation::ValidatedJson,
+};
+
+/// The header a key is sent in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Starts every key, so a leaked one is easy to search for.
+const KEY_PREFIX: &str = "sk_";
+
+/// Characters of a key kept in the clear, `sk_` included.
+const SHOWN_CHARS: usize = 11;
+
+/// The key a request was made with, in its extensions for handlers that
+/// want to know who's calling.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub tier: String,
+}
+
+/// The tiers from `api_keys` in the config, each with a rate limiter keyed
+/// by key id.
+pub struct ApiKeys {
+    config: ApiKeysConfig,
+    limiters: HashMap<String, RateLimiter>,
+}
+
+impl ApiKeys {
+    pub fn new(config: &ApiKeysConfig) -> Self {
+        Self {
+            config: config.clone(),
+            limiters: config
+                .tiers
+                .iter()
+                .map(|(name, tier)| (name.clone(), RateLimiter::new(&tier.rate_limit)))
+                .collect(),
+        }
+    }
+
+    fn tier(&self, name: &str) -> Option<(&ApiKeyTier, &RateLimiter)> {
+        Some((self.config.tiers.get(name)?, self.limiters.get(name)?))
+    }
+
+    fn required_for(&self, path: &str) -> bool {
+        self.config
+            .required_for
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
+
+fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key))
+}
+
+/// Whether `token` has the shape of one of our keys.
+pub fn is_key(token: &str) -> bool {
+    token.starts_with(KEY_PREFIX)
+}
+
+/// A key that hasn't been revoked, found by its value.
+#[derive(sqlx::FromRow)]
+pub struct LiveKey {
+    pub id: i64,
+    pub name: String,
+    pub tier: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn find_live(state: &AppState, key: &str) -> sqlx::Result<Option<LiveKey>> {
+    sqlx::query_as(
+        "SELECT id, name, tier, created_at FROM api_keys WHERE hash = ? AND revoked_at IS NULL",
+    )
+    .bind(hash(key))
+    .fetch_optional(state.db())
+    .await
+}
+
+/// Revokes the key with value `key`; its id, if it was live until now.
+pub async fn revoke_value(state: &AppState, key: &str) -> sqlx::Result<Option<i64>> {
+    let id = sqlx::query_scalar(
+        "UPDATE api_keys SET revoked_at = ? WHERE hash = ? AND revoked_at IS NULL RETURNING id",
+    )
+    .bind(Utc::now())
+    .bind(hash(key))
+    .fetch_optional(state.db())
+    .await?;
+    if let Some(id) = id {
+        info!("🔑 Revoked API key {}", id);
+    }
+    Ok(id)
+}
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+fn unauthorized(message: &str) -> Response {
+    AppError::new(StatusCode::UNAUTHORIZED, message).into_response()
+}
+
+/// Counts a request against `key_id`'s quota for today, unless it's used
+/// up. Returns today's count, or `None` when over quota.
+async fn count_request(state: &AppState, key_id: i64, quota: u64) -> sqlx::Result<Option<i64>> {
+    let quota = if quota == 0 { i64::MAX } else { quota as i64 };
+    sqlx::query_scalar(
+        "INSERT INTO api_key_usage (key_id, day, requests) VALUES (?1, ?2, 1) \
+         ON CONFLICT (key_id, day) DO UPDATE SET requests = requests + 1 \
+         WHERE requests < ?3 RETURNING requests",
+    )
+    .bind(key_id)
+    .bind(today())
+    .bind(quota)
+    .fetch_optional(state.db())
+    .await
+}
+
+/// Holds requests made with an `X-API-Key` to the limits of the key's tier:
+/// `401` for an unknown or revoked key, `429` with `Retry-After` once the
+/// rate limit or the daily quota is hit. Quota responses carry
+/// `X-Quota-Limit` and `X-Quota-Remaining`. Requests without a key go
+/// through unless `api_keys.required_for` covers the path. Probes, NSM's own
+/// endpoints, the admin API and requests with a static key or a signed link
+/// are never checked.
+pub async fn enforce_api_keys(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path();
+    if path == "/livez"
+        || path == "/readyz"
+        || path.starts_with("/__nsm/")
+        || path.starts_with("/admin/")
+        || req.extensions().get::<StaticKey>().is_some()
+        || req.extensions().get::<BasicUser>().is_some()
+        || req.extensions().get::<SignedUrl>().is_some()
+    {
+        return next.run(req).await;
+    }
+    let keys = state.api_keys();
+    let Some(key) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    else {
+        if keys.required_for(path) {
+            return unauthorized("An API key is required; send it as X-API-Key");
+        }
+        return next.run(req).await;
+    };
+
+    let found: Option<ApiKey> = match sqlx::query_as(
+        "SELECT id, name, tier FROM api_keys WHERE hash = ? AND revoked_at IS NULL",
+    )
+    .bind(hash(key))
+    .fetch_optional(state.db())
+    .await
+    {
+        Ok(found) => found,
+        Err(e) => return AppError::internal(e).into_response(),
+    };
+    let Some(api_key) = found else {
+        return unauthorized("Unknown or revoked API key");
+    };
+    let Some((tier, limiter)) = keys.tier(&api_key.tier) else {
+        return AppError::new(
+            StatusCode::FORBIDDEN,
+            format!("API key tier {:?} is no longer offered", api_key.tier),
+        )
+        .into_response();
+    };
+    // Throttled requests don't count towards the quota.
+    if let Err(wait) = limiter.acquire(&api_key.id.to_string()) {
+        return too_many_requests("Rate limit exceeded for this API key", wait);
+    }
+    let used = match count_request(&state, api_key.id, tier.daily_quota).await {
+        Ok(Some(used)) => used,
+        Ok(None) => {
+            return too_many_requests(
+                format!(
+                    "Daily quota of {} requests used up; it resets at midnight UTC",
+                    tier.daily_quota
+                ),
+                until_reset(),
+            )
+        }
+        Err(e) => return AppError::internal(e).into_response(),
+    };
+
+    let quota = tier.daily_quota;
+    req.extensions_mut().insert(api_key);
+    let mut res = next.run(req).await;
+    if quota > 0 {
+        let headers = res.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-quota-limit"),
+            HeaderValue::from(quota),
+        );
+        headers.insert(
+            HeaderName::from_static("x-quota-remaining"),
+            HeaderValue::from(quota.saturating_sub(used as u64)),
+        );
+    }
+    res
+}
+
+#[derive(Serialize)]
+pub struct CurrentKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    limits: ApiKeyTier,
+}
+
+/// `GET /api/key`: the key the request was made with, and its tier's
+/// limits. Answers `401` without one.
+pub async fn current_handler(
+    State(state): State<AppState>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<CurrentKey>> {
+    let Some(Extension(key)) = key else {
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "Send an API key as X-API-Key",
+        ));
+    };
+    let limits = state
+        .api_keys()
+        .tier(&key.tier)
+        .map(|(tier, _)| tier.clone())
+        .ok_or_else(|| AppError::internal("API key tier vanished mid-request"))?;
+    Ok(Json(CurrentKey { key, limits }))
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct KeyInfo {
+    id: i64,
+    name: String,
+    tier: String,
+    /// The start of the key, to tell it apart.
+    prefix: String,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revoked_at: Option<DateTime<Utc>>,
+    /// Requests made with it since midnight UTC.
+    used_today: i64,
+}
+
+/// `GET /admin/api-keys`: every key, newest first, revoked ones included.
+pub async fn list_handler(State(state): State<AppState>) -> AppResult<Json<Vec<KeyInfo>>> {
+    let keys = sqlx::query_as(
+        "SELECT k.id, k.name, k.tier, k.prefix, k.created_at, k.revoked_at, \
+         COALESCE(u.requests, 0) AS used_today FROM api_keys k \
+         LEFT JOIN api_key_usage u ON u.key_id = k.id AND u.day = ? \
+         ORDER BY k.id DESC",
+    )
+    .bind(today())
+    .fetch_all(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    Ok(Json(keys))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct NewKey {
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    name: String,
+    tier: String,
+}
+
+#[derive(Serialize)]
+pub struct IssuedKey {
+    #[serde(flatten)]
+    info: KeyInfo,
+    /// Shown this once; only its hash is stored.
+    key: String,
+}
+
+/// `POST /admin/api-keys`: issues a key of `tier` and returns it.
+pub async fn create_handler(
+    State(state): State<AppState>,
+    ValidatedJson(input): ValidatedJson<NewKey>,
+) -> AppResult<(StatusCode, Json<IssuedKey>)> {
+    let tiers = &state.api_keys().config.tiers;
+    if !tiers.contains_key(&input.tier) {
+        let names: Vec<_> = tiers.keys().map(String::as_str).collect();
+        return Err(AppError::validation(FieldErrors::from([(
+            "tier".to_string(),
+            vec![format!("must be one of {}", names.join(", "))],
+        )])));
+    }
+
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+    let info: KeyInfo = sqlx::query_as(
+        "INSERT INTO api_keys (name, tier, prefix, hash, created_at) VALUES (?, ?, ?, ?, ?) \
+         RETURNING id, name, tier, prefix, created_at, revoked_at, 0 AS used_today",
+    )
+    .bind(&input.name)
+    .bind(&input.tier)
+    .bind(&key[..SHOWN_CHARS])
+    .bind(hash(&key))
+    .bind(Utc::now())
+    .fetch_one(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    info!(
+        "🔑 Issued {} API key {} ({})",
+        info.tier, info.id, info.name
+    );
+    Ok((StatusCode::CREATED, Json(IssuedKey { info, key })))
+}
+
+/// `DELETE /admin/api-keys/:id`: revokes the key. Its requests are refused
+/// from then on; revoking it again changes nothing.
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> AppResult<StatusCode> {
+    let result =
+        sqlx::query("UPDATE api_keys SET revoked_at = COALESCE(revoked_at, ?) WHERE id = ?")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(state.db())
+            .await
+            .map_err(AppError::internal)?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found(format!("No API key with id {}", id)));
+    }
+    info!("🔑 Revoked API key {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,137 @@
+use axum::{extract::State, response::Json};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+use crate::state::AppState;
+
+/// Files larger than this are build artifacts rather than page assets and
+/// aren't worth hashing at startup.
+const MAX_FINGERPRINT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Hex digits of the SHA-256 kept in fingerprinted names.
+const HASH_LEN: usize = 8;
+
+/// Content-hashed names for the files under `static/`, computed once at
+/// startup: `css/app.css` is also served as `css/app.1a2b3c4d.css` with
+/// immutable caching. Restart to pick up changed files.
+#[derive(Default)]
+pub struct AssetManifest {
+    /// Logical path -> fingerprinted path, both relative to `static/`.
+    hashed: BTreeMap<String, String>,
+    /// Fingerprinted path -> logical path.
+    logical: HashMap<String, String>,
+}
+
+impl AssetManifest {
+    pub fn build(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        let mut files = Vec::new();
+        collect_files(root, root, &mut files);
+
+        let mut manifest = Self::default();
+        for (path, name) in files {
+            match hash_file(&path) {
+                Ok(hash) => {
+                    let hashed = fingerprinted_name(&name, &hash);
+                    manifest.logical.insert(hashed.clone(), name.clone());
+                    manifest.hashed.insert(name, hashed);
+                }
+                Err(e) => warn!("Skipping fingerprint for {}: {}", path.display(), e),
+            }
+        }
+        debug!("Fingerprinted {} static assets", manifest.hashed.len());
+        manifest
+    }
+
+    /// URL for a logical asset path; unknown assets get their plain
+    /// `/static` URL so templates don't break.
+    pub fn url(&self, logical: &str) -> String {
+        let logical = logical.trim_start_matches('/');
+        let path = self.hashed.get(logical).map_or(logical, String::as_str);
+        format!("/static/{}", path)
+    }
+
+    /// The logical path behind a fingerprinted one.
+    pub fn resolve(&self, hashed: &str) -> Option<&str> {
+        self.logical.get(hashed).map(String::as_str)
+    }
+}
+
+/// Regular files under `dir`, paired with their `/`-separated path relative
+/// to `root`. Dotfiles and precompressed `.br`/`.gz` siblings are skipped;
+/// the latter are picked up by `ServeDir` when the original is requested.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, String)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            collect_files(root, &path, files);
+            continue;
+        }
+        let precompressed = [".br", ".gz"]
+            .iter()
+            .any(|ext| file_name.ends_with(ext) && path.with_extension("").exists());
+        if !metadata.is_file() || precompressed || metadata.len() > MAX_FINGERPRINT_BYTES {
+            continue;
+        }
+
+        if let Ok(relative) = path.strip_prefix(root) {
+            let name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((path, name));
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    let digest = hex::encode(hasher.finalize());
+    Ok(digest[..HASH_LEN].to_string())
+}
+
+/// `css/app.css` -> `css/app.<hash>.css`; extensionless files get the hash
+/// appended.
+fn fingerprinted_name(name: &str, hash: &str) -> String {
+    let (dir, file) = name.rsplit_once('/').map_or(("", name), |(d, f)| (d, f));
+    let file = match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}.{}.{}", stem, hash, ext),
+        _ => format!("{}.{}", file, hash),
+    };
+    if dir.is_empty() {
+        file
+    } else {
+        format!("{}/{}", dir, file)
+    }
+}
+
+/// `/static-manifest.json`: logical asset path -> fingerprinted URL.
+pub async fn manifest_handler(State(state): State<AppState>) -> Json<BTreeMap<String, String>> {
+    let assets = state.assets();
+    Json(
+        assets
+            .hashed
+            .keys()
+            .map(|name| (name.clone(), assets.url(name)))
+            .collect(),
+    )
+}
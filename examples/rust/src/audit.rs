@@ -0,0 +1,215 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query, State},
+    http::request::Parts,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::SqliteConnection;
+use std::convert::Infallible;
+
+use crate::{
+    apikeys::ApiKey,
+    auth::Verified,
+    basic_auth::BasicUser,
+    error::{AppError, AppResult},
+    mtls::ClientCert,
+    pagination::{Page, Pagination},
+    sessions::Session,
+    signed_urls::SignedUrl,
+    state::AppState,
+    static_keys::StaticKey,
+};
+
+/// Who a change is put down to, and the request that made it. As an
+/// extractor: the API key if there was one, otherwise the static key,
+/// otherwise the Basic auth user, otherwise the client certificate's
+/// identity, otherwise the subject of a bearer token already verified,
+/// otherwise the signed-in user, otherwise `anonymous`.
+#[derive(Clone, Debug)]
+pub struct Actor {
+    name: String,
+    request_id: Option<String>,
+}
+
+impl Actor {
+    /// The app itself, e.g. a scheduled task.
+    pub fn system(task: &str) -> Self {
+        Self {
+            name: format!("system:{}", task),
+            request_id: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = || parts.extensions.get::<Session>()?.get::<String>("user");
+        let subject = || parts.extensions.get::<Verified>()?.subject();
+        let name = if let Some(key) = parts.extensions.get::<ApiKey>() {
+            format!("api-key:{}", key.id)
+        } else if let Some(key) = parts.extensions.get::<StaticKey>() {
+            format!("static-key:{}", key.name)
+        } else if let Some(user) = parts.extensions.get::<BasicUser>() {
+            format!("basic:{}", user.name)
+        } else if let Some(cert) = parts.extensions.get::<ClientCert>() {
+            format!("cert:{}", cert.name)
+        } else if let Some(subject) = subject() {
+            format!("jwt:{}", subject)
+        } else if let Some(user) = user() {
+            format!("user:{}", user)
+        } else if parts.extensions.get::<SignedUrl>().is_some() {
+            "signed-url".to_string()
+        } else {
+            "anonymous".to_string()
+        };
+        let request_id = parts
+            .headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        Ok(Self { name, request_id })
+    }
+}
+
+/// `field: [before, after]` for every top-level field that differs, with
+/// `null` for a side that's missing.
+fn diff(before: Option<Value>, after: Option<Value>) -> Value {
+    let object = |value: Option<Value>| match value {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let (before, mut after) = (object(before), object(after));
+    let mut changes = Map::new();
+    for (field, old) in before {
+        let new = after.remove(&field).unwrap_or(Value::Null);
+        if old != new {
+            changes.insert(field, Value::Array(vec![old, new]));
+        }
+    }
+    for (field, new) in after {
+        changes.insert(field, Value::Array(vec![Value::Null, new]));
+    }
+    Value::Object(changes)
+}
+
+/// Appends an entry for `action` on `entity` `entity_id`, with what changed
+/// between `before` and `after`. Write it in the change's own transaction,
+/// so there's an entry exactly when the change happened.
+pub async fn record<T: Serialize>(
+    conn: &mut SqliteConnection,
+    actor: &Actor,
+    action: &str,
+    entity: &str,
+    entity_id: i64,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    let diff = diff(
+        before.map(serde_json::to_value).transpose()?,
+        after.map(serde_json::to_value).transpose()?,
+    );
+    sqlx::query(
+        "INSERT INTO audit_log (at, actor, action, entity, entity_id, diff, request_id) \
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Utc::now())
+    .bind(&actor.name)
+    .bind(action)
+    .bind(entity)
+    .bind(entity_id)
+    .bind(diff.to_string())
+    .bind(&actor.request_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct AuditFilter {
+    entity: Option<String>,
+    entity_id: Option<i64>,
+    actor: Option<String>,
+    action: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    id: i64,
+    at: DateTime<Utc>,
+    actor: String,
+    action: String,
+    entity: String,
+    entity_id: i64,
+    diff: String,
+    request_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    id: i64,
+    at: DateTime<Utc>,
+    actor: String,
+    action: String,
+    entity: String,
+    entity_id: i64,
+    diff: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl From<AuditRow> for AuditEntry {
+    fn from(row: AuditRow) -> Self {
+        Self {
+            id: row.id,
+            at: row.at,
+            actor: row.actor,
+            action: row.action,
+            entity: row.entity,
+            entity_id: row.entity_id,
+            diff: serde_json::from_str(&row.diff).unwrap_or(Value::String(row.diff)),
+            request_id: row.request_id,
+        }
+    }
+}
+
+/// `GET /admin/audit`: entries newest first, filtered by any of `entity`,
+/// `entity_id`, `actor` and `action`.
+pub async fn list_handler(
+    State(state): State<AppState>,
+    pagination: Pagination,
+    Query(filter): Query<AuditFilter>,
+) -> AppResult<Json<Page<AuditEntry>>> {
+    pagination.sort(&[])?;
+    let matches = "(?1 IS NULL OR entity = ?1) AND (?2 IS NULL OR entity_id = ?2) \
+                   AND (?3 IS NULL OR actor = ?3) AND (?4 IS NULL OR action = ?4)";
+    let (total,): (i64,) =
+        sqlx::query_as(&format!("SELECT COUNT(*) FROM audit_log WHERE {}", matches))
+            .bind(&filter.entity)
+            .bind(filter.entity_id)
+            .bind(&filter.actor)
+            .bind(&filter.action)
+            .fetch_one(state.db())
+            .await
+            .map_err(AppError::internal)?;
+    let rows: Vec<AuditRow> = sqlx::query_as(&format!(
+        "SELECT * FROM audit_log WHERE {} ORDER BY id DESC LIMIT ?5 OFFSET ?6",
+        matches
+    ))
+    .bind(&filter.entity)
+    .bind(filter.entity_id)
+    .bind(&filter.actor)
+    .bind(&filter.action)
+    .bind(pagination.limit as i64)
+    .bind(pagination.offset as i64)
+    .fetch_all(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    let entries = rows.into_iter().map(AuditEntry::from).collect();
+    Ok(Json(pagination.paged(entries, total as usize)))
+}
@@ -0,0 +1,478 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full, Limited};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    config::AuthConfig,
+    error::{AppError, AppResult},
+    mtls::ClientCert,
+    outbound::{self, HttpsClient},
+    signed_urls::SignedUrl,
+    state::AppState,
+};
+
+/// Wait for the JWKS endpoint before giving up on it.
+const JWKS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Key sets larger than this are refused.
+const JWKS_BODY_LIMIT: usize = 256 * 1024;
+
+/// Between fetches of the key set however many unknown keys turn up, so
+/// a flood of bad tokens can't hammer the issuer.
+const MIN_REFETCH: Duration = Duration::from_secs(30);
+
+/// Lifetime of `/debug/token` tokens unless asked otherwise.
+const DEBUG_TOKEN_SECS: u64 = 3600;
+
+/// Why a request was turned away, answered as `application/problem+json`
+/// (RFC 9457) with a `WWW-Authenticate` challenge.
+#[derive(Debug)]
+pub enum AuthError {
+    /// 401: no bearer token.
+    Missing,
+    /// 401: a token that doesn't verify.
+    Invalid(String),
+    /// 403: a valid token without this scope.
+    InsufficientScope(String),
+    /// 403: a principal whose roles don't grant this permission.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    Forbidden(String),
+    /// 503: the issuer's keys couldn't be fetched.
+    KeysUnavailable,
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, detail, challenge) = match self {
+            AuthError::Missing => (
+                StatusCode::UNAUTHORIZED,
+                "A bearer token is required".to_string(),
+                Some("Bearer".to_string()),
+            ),
+            AuthError::Invalid(reason) => (
+                StatusCode::UNAUTHORIZED,
+                format!("Invalid token: {}", reason),
+                Some(r#"Bearer error="invalid_token""#.to_string()),
+            ),
+            AuthError::InsufficientScope(scope) => (
+                StatusCode::FORBIDDEN,
+                format!("The token lacks scope {:?}", scope),
+                Some(format!(
+                    r#"Bearer error="insufficient_scope", scope="{}""#,
+                    scope
+                )),
+            ),
+            AuthError::Forbidden(permission) => (
+                StatusCode::FORBIDDEN,
+                format!("Permission {:?} is required", permission),
+                None,
+            ),
+            AuthError::KeysUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Token signing keys are unavailable; try again later".to_string(),
+                None,
+            ),
+        };
+        let body = Json(Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: &detail,
+        });
+        let mut res = (status, body).into_response();
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(challenge) = challenge.and_then(|c| HeaderValue::from_str(&c).ok()) {
+            res.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        res
+    }
+}
+
+fn invalid(e: jsonwebtoken::errors::Error) -> AuthError {
+    use jsonwebtoken::errors::ErrorKind;
+    AuthError::Invalid(match e.kind() {
+        ErrorKind::ExpiredSignature => "expired".to_string(),
+        ErrorKind::ImmatureSignature => "not valid yet".to_string(),
+        ErrorKind::InvalidSignature => "bad signature".to_string(),
+        ErrorKind::InvalidIssuer => "wrong issuer".to_string(),
+        ErrorKind::InvalidAudience => "wrong audience".to_string(),
+        ErrorKind::MissingRequiredClaim(claim) => format!("no {} claim", claim),
+        _ => "malformed".to_string(),
+    })
+}
+
+/// The usual claims, for handlers that don't need a type of their own.
+/// Whatever else the token carries is in `extra`.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Claims {
+    pub sub: String,
+    /// Expiry, in seconds since the epoch.
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Space-separated, as in OAuth 2.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A verified token's claims, kept in the request's extensions.
+#[derive(Clone)]
+pub struct Verified(Arc<Value>);
+
+impl Verified {
+    /// The `sub` claim.
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub")?.as_str()
+    }
+
+    pub fn claim(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    /// Claims for a caller that presented a client certificate instead of a
+    /// token: its identity as `sub`, valid until the certificate expires.
+    pub fn from_client_cert(cert: &ClientCert) -> Self {
+        Self(Arc::new(json!({
+            "sub": cert.name,
+            "iss": "mtls",
+            "exp": cert.not_after,
+            "scope": "",
+            "cert": cert,
+        })))
+    }
+
+    /// Whether `scope` is among the token's scopes, given as OAuth 2's
+    /// space-separated `scope` or as an `scp` array.
+    fn has_scope(&self, scope: &str) -> bool {
+        match (self.0.get("scope"), self.0.get("scp")) {
+            (Some(Value::String(scopes)), _) => scopes.split_whitespace().any(|s| s == scope),
+            (_, Some(Value::Array(scopes))) => scopes.iter().any(|s| s == scope),
+            _ => false,
+        }
+    }
+}
+
+/// The issuer's key set as last fetched.
+#[derive(Default)]
+struct KeyCache {
+    keys: Option<JwkSet>,
+    fetched: Option<Instant>,
+    tried: Option<Instant>,
+}
+
+/// Verifies bearer tokens: HS256 against `JWT_SECRET`, RS256 and ES256
+/// against the issuer's key set, which is fetched when first needed and
+/// cached, and ES256 from the dev OIDC provider.
+pub struct Auth {
+    config: AuthConfig,
+    secret: Vec<u8>,
+    /// The dev OIDC provider's key id and key.
+    local: Option<(String, DecodingKey)>,
+    client: HttpsClient,
+    /// Held across a fetch, so concurrent requests wait for it rather than
+    /// fetching too.
+    keys: Mutex<KeyCache>,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig, local: Option<(String, DecodingKey)>) -> anyhow::Result<Self> {
+        if let Some(url) = &config.jwks_url {
+            url.parse::<Uri>()
+                .ok()
+                .filter(|uri| uri.host().is_some())
+                .with_context(|| format!("auth.jwks_url: invalid url {}", url))?;
+        }
+        // Without a secret nothing outside this process can sign an HS256
+        // token, which leaves `/debug/token` working until a restart.
+        let secret = match std::env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => {
+                let mut secret = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+        Ok(Self {
+            config: config.clone(),
+            secret,
+            local,
+            client: outbound::https_client()?,
+            keys: Mutex::new(KeyCache::default()),
+        })
+    }
+
+    /// The claims of `token`, once its signature, expiry and the configured
+    /// issuer and audience check out.
+    pub async fn verify(&self, token: &str) -> Result<Value, AuthError> {
+        let header = jsonwebtoken::decode_header(token).map_err(invalid)?;
+        let local = self
+            .local
+            .as_ref()
+            .filter(|(kid, _)| header.alg == Algorithm::ES256 && header.kid.as_ref() == Some(kid));
+        let key = match (header.alg, local) {
+            (Algorithm::HS256, _) => DecodingKey::from_secret(&self.secret),
+            (_, Some((_, key))) => key.clone(),
+            (Algorithm::RS256 | Algorithm::ES256, _) if self.config.jwks_url.is_some() => {
+                self.jwks_key(header.kid.as_deref()).await?
+            }
+            (alg, _) => {
+                return Err(AuthError::Invalid(format!(
+                    "{:?} tokens aren't accepted",
+                    alg
+                )))
+            }
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway_secs;
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        // The dev provider's issuer is whatever host it was reached on.
+        if let Some(issuer) = self.config.issuer.as_ref().filter(|_| local.is_none()) {
+            validation.set_issuer(&[issuer]);
+        }
+        jsonwebtoken::decode::<Value>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(invalid)
+    }
+
+    /// The issuer's key `kid`, or its only key when the token names none.
+    /// The key set is fetched again once it's older than
+    /// `auth.jwks_cache_secs`, or when it lacks `kid`, as it would just
+    /// after the issuer rotates its keys.
+    async fn jwks_key(&self, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+        let find = |keys: &JwkSet| match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+        let due = |at: Option<Instant>, after: Duration| at.is_none_or(|at| at.elapsed() >= after);
+
+        let mut cache = self.keys.lock().await;
+        let known = cache.keys.as_ref().and_then(find).is_some();
+        let ttl = Duration::from_secs(self.config.jwks_cache_secs);
+        if (!known || due(cache.fetched, ttl)) && due(cache.tried, MIN_REFETCH) {
+            cache.tried = Some(Instant::now());
+            match self.fetch_keys().await {
+                Ok(keys) => {
+                    debug!("🔑 Fetched {} signing keys", keys.keys.len());
+                    cache.keys = Some(keys);
+                    cache.fetched = cache.tried;
+                }
+                // Keys we already have stay good until we hear otherwise.
+                Err(e) => warn!("Failed to fetch the JWKS: {:#}", e),
+            }
+        }
+        let Some(keys) = &cache.keys else {
+            return Err(AuthError::KeysUnavailable);
+        };
+        let jwk =
+            find(keys).ok_or_else(|| AuthError::Invalid("unknown signing key".to_string()))?;
+        DecodingKey::from_jwk(&jwk).map_err(invalid)
+    }
+
+    async fn fetch_keys(&self) -> anyhow::Result<JwkSet> {
+        let url = self.config.jwks_url.as_deref().unwrap_or_default();
+        let req = axum::http::Request::get(url)
+            .header(header::ACCEPT, "application/json")
+            .body(Full::new(Bytes::new()))?;
+        let res = tokio::time::timeout(JWKS_TIMEOUT, self.client.request(req))
+            .await
+            .with_context(|| format!("{}: timed out", url))?
+            .with_context(|| url.to_string())?;
+        if !res.status().is_success() {
+            anyhow::bail!("{}: {}", url, res.status());
+        }
+        let body = Limited::new(res.into_body(), JWKS_BODY_LIMIT)
+            .collect()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}: {}", url, e))?
+            .to_bytes();
+        serde_json::from_slice(&body).with_context(|| format!("{}: not a JWKS", url))
+    }
+
+    /// An HS256 token for `claims`, which verifies here while `JWT_SECRET`
+    /// stays the same.
+    fn sign(&self, claims: &Value) -> anyhow::Result<String> {
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            claims,
+            &EncodingKey::from_secret(&self.secret),
+        )?)
+    }
+}
+
+/// Verifies the request's `Authorization: Bearer` token.
+pub async fn verify_request(state: &AppState, headers: &HeaderMap) -> Result<Verified, AuthError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(AuthError::Missing)?;
+    let claims = state.auth().verify(token.trim()).await?;
+    Ok(Verified(Arc::new(claims)))
+}
+
+/// The claims of the request's verified bearer token, as `C`. Without a
+/// valid token the handler isn't called and the client gets a 401.
+pub struct RequireAuth<C = Claims>(pub C);
+
+#[async_trait]
+impl<C: DeserializeOwned> FromRequestParts<AppState> for RequireAuth<C> {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let verified = match parts.extensions.get::<Verified>() {
+            Some(verified) => verified.clone(),
+            None => {
+                let verified = verify_request(state, &parts.headers).await?;
+                parts.extensions.insert(verified.clone());
+                verified
+            }
+        };
+        C::deserialize(&*verified.0)
+            .map(RequireAuth)
+            .map_err(|e| AuthError::Invalid(format!("unexpected claims: {}", e)))
+    }
+}
+
+/// Turns away requests under `auth.required_for` or `auth.scopes` without
+/// a valid bearer token or client certificate (401), or without every
+/// scope their path needs (403). The claims are kept for [`RequireAuth`]
+/// and the audit log. Probes, NSM's own endpoints, the admin API, which
+/// has its own token, and requests let in by a signed link are left alone.
+pub async fn authenticate(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if path == "/livez"
+        || path == "/readyz"
+        || path.starts_with("/__nsm/")
+        || path.starts_with("/admin/")
+        || req.extensions().get::<SignedUrl>().is_some()
+    {
+        return next.run(req).await;
+    }
+    let config = &state.config().auth;
+    let scopes: Vec<&String> = config
+        .scopes
+        .iter()
+        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .map(|(_, scope)| scope)
+        .collect();
+    let required = config
+        .required_for
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()));
+    if !required && scopes.is_empty() {
+        return next.run(req).await;
+    }
+
+    // Already there for requests over the mTLS listener.
+    let verified = match req.extensions().get::<Verified>() {
+        Some(verified) => verified.clone(),
+        None => match verify_request(&state, req.headers()).await {
+            Ok(verified) => verified,
+            Err(e) => return e.into_response(),
+        },
+    };
+    if let Some(scope) = scopes.into_iter().find(|scope| !verified.has_scope(scope)) {
+        return AuthError::InsufficientScope(scope.clone()).into_response();
+    }
+    req.extensions_mut().insert(verified);
+    next.run(req).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The bearer token's claims", body = Claims),
+        (status = 401, description = "No valid bearer token")
+    )
+)]
+pub async fn me_handler(RequireAuth(claims): RequireAuth) -> Json<Claims> {
+    Json(claims)
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    sub: String,
+    #[serde(default)]
+    scope: String,
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct IssuedToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// `POST /debug/token` (debug builds): an HS256 token for `sub` with
+/// `scope`, plus the configured issuer and audience, to try protected
+/// routes with.
+pub async fn token_handler(
+    State(state): State<AppState>,
+    Json(input): Json<TokenRequest>,
+) -> AppResult<Json<IssuedToken>> {
+    if input.sub.is_empty() {
+        return Err(AppError::bad_request("sub can't be empty"));
+    }
+    let config = &state.config().auth;
+    let now = Utc::now();
+    let expires_at = now + Duration::from_secs(input.ttl_secs.unwrap_or(DEBUG_TOKEN_SECS));
+    let mut claims = json!({
+        "sub": input.sub,
+        "iat": now.timestamp(),
+        "exp": expires_at.timestamp(),
+    });
+    if !input.scope.is_empty() {
+        claims["scope"] = input.scope.into();
+    }
+    if let Some(issuer) = &config.issuer {
+        claims["iss"] = issuer.as_str().into();
+    }
+    if let Some(audience) = &config.audience {
+        claims["aud"] = audience.as_str().into();
+    }
+    let token = state.auth().sign(&claims).map_err(AppError::internal)?;
+    Ok(Json(IssuedToken { token, expires_at }))
+}
@@ -0,0 +1,208 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    config::BasicAuthConfig, error::AppError, ratelimit::too_many_requests, signed_urls::SignedUrl,
+    state::AppState,
+};
+
+/// Failures further apart than this don't add up to a lockout.
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// The Basic auth user a request signed in as.
+#[derive(Clone, Debug)]
+pub struct BasicUser {
+    pub name: String,
+}
+
+/// Recent failures from one client.
+struct Failures {
+    count: u32,
+    first: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Serialize)]
+pub struct BasicAuthStats {
+    /// Wrong or missing credentials since startup.
+    failures: u64,
+    /// Clients locked out right now.
+    locked_out: usize,
+}
+
+/// The configured users, and who has been getting their passwords wrong.
+pub struct BasicAuth {
+    config: BasicAuthConfig,
+    /// Digests of `Authorization` headers that verified, so bcrypt runs once
+    /// per credential rather than on every request.
+    verified: Mutex<HashSet<[u8; 32]>>,
+    failures: Mutex<HashMap<String, Failures>>,
+    failed: AtomicU64,
+}
+
+impl BasicAuth {
+    pub fn new(config: &BasicAuthConfig) -> anyhow::Result<Self> {
+        for (user, hash) in &config.users {
+            if hash.parse::<bcrypt::HashParts>().is_err() {
+                anyhow::bail!("basic_auth.users.{}: not a bcrypt hash", user);
+            }
+        }
+        Ok(Self {
+            config: config.clone(),
+            verified: Mutex::new(HashSet::new()),
+            failures: Mutex::new(HashMap::new()),
+            failed: AtomicU64::new(0),
+        })
+    }
+
+    fn protects(&self, path: &str) -> bool {
+        !self.config.users.is_empty()
+            && self
+                .config
+                .protect
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    /// How much longer `client` stays locked out, if it is.
+    fn locked(&self, client: &str) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let until = failures.get(client)?.locked_until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    fn fail(&self, client: &str) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, f| {
+            now.duration_since(f.first) < FAILURE_WINDOW || f.locked_until.is_some_and(|t| t > now)
+        });
+        let entry = failures.entry(client.to_string()).or_insert(Failures {
+            count: 0,
+            first: now,
+            locked_until: None,
+        });
+        entry.count += 1;
+        if entry.count >= self.config.max_failures {
+            warn!(
+                "🔒 {} failed Basic auth {} times; locked out for {}s",
+                client, entry.count, self.config.lockout_secs
+            );
+            entry.count = 0;
+            entry.first = now;
+            entry.locked_until = Some(now + Duration::from_secs(self.config.lockout_secs));
+        }
+    }
+
+    /// The user `authorization` signs in as, if its password checks out.
+    async fn verify(&self, authorization: &str) -> Option<String> {
+        let credentials = STANDARD
+            .decode(authorization.strip_prefix("Basic ")?.trim())
+            .ok()?;
+        let credentials = String::from_utf8(credentials).ok()?;
+        let (user, password) = credentials.split_once(':')?;
+        let hash = self.config.users.get(user)?;
+
+        let digest: [u8; 32] = Sha256::digest(authorization).into();
+        if self.verified.lock().unwrap().contains(&digest) {
+            return Some(user.to_string());
+        }
+        // bcrypt is slow on purpose; keep it off the async workers.
+        let (password, hash) = (password.to_string(), hash.clone());
+        let ok = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .ok()?
+            .ok()?;
+        if !ok {
+            return None;
+        }
+        self.verified.lock().unwrap().insert(digest);
+        Some(user.to_string())
+    }
+
+    pub fn stats(&self) -> BasicAuthStats {
+        let now = Instant::now();
+        BasicAuthStats {
+            failures: self.failed.load(Ordering::Relaxed),
+            locked_out: self
+                .failures
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|f| f.locked_until.is_some_and(|t| t > now))
+                .count(),
+        }
+    }
+}
+
+fn challenge() -> Response {
+    let mut res = AppError::new(StatusCode::UNAUTHORIZED, "Sign in to continue").into_response();
+    res.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static(r#"Basic realm="{{.ProjectName}}", charset="UTF-8""#),
+    );
+    res
+}
+
+/// Asks for Basic auth on paths under `basic_auth.protect`, answering `401`
+/// with a challenge browsers prompt for. After `basic_auth.max_failures`
+/// wrong tries a client gets `429` until its lockout ends. A signed-in
+/// request carries a [`BasicUser`], which the admin API, static keys and
+/// database-backed API keys take as enough. Requests without Basic
+/// credentials are left to the static keys when those protect the path
+/// too, so either will do. Requests let in by a signed link aren't asked.
+pub async fn require_basic_auth(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let basic = state.basic_auth();
+    let path = req.uri().path();
+    if !basic.protects(path) || req.extensions().get::<SignedUrl>().is_some() {
+        return next.run(req).await;
+    }
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with("Basic "))
+        .map(str::to_string);
+    let Some(authorization) = authorization else {
+        if state.static_keys().protects(path) {
+            return next.run(req).await;
+        }
+        return challenge();
+    };
+
+    let client = state
+        .trusted_proxies()
+        .client(req.headers(), req.extensions());
+    if let Some(wait) = basic.locked(&client) {
+        return too_many_requests("Too many failed sign-ins", wait.as_secs_f64());
+    }
+    let Some(name) = basic.verify(&authorization).await else {
+        basic.fail(&client);
+        return challenge();
+    };
+    basic.failures.lock().unwrap().remove(&client);
+    tracing::Span::current().record("user", name.as_str());
+    req.extensions_mut().insert(BasicUser { name });
+    next.run(req).await
+}
@@ -0,0 +1,266 @@
+use anyhow::{bail, Context};
+use axum::{
+    body::Bytes,
+    http::{HeaderName, HeaderValue, Method, Request, Uri},
+};
+use clap::Args;
+use http_body_util::{BodyExt, Full};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Route on the running instance (e.g. /api/info), or a full http:// URL
+    #[arg(default_value = "/")]
+    pub target: String,
+    /// Requests in flight at once
+    #[arg(short, long, default_value_t = 10)]
+    pub concurrency: u64,
+    /// Total requests to send
+    #[arg(short = 'n', long, default_value_t = 1000, conflicts_with = "duration")]
+    pub requests: u64,
+    /// Send for this many seconds instead of a fixed number of requests
+    #[arg(short, long)]
+    pub duration: Option<u64>,
+    /// HTTP method
+    #[arg(short = 'X', long, default_value = "GET")]
+    pub method: Method,
+    /// Extra request header, `Name: value`; repeatable
+    #[arg(short = 'H', long = "header")]
+    pub headers: Vec<String>,
+    /// Request body
+    #[arg(long)]
+    pub body: Option<String>,
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// When to stop sending.
+enum Budget {
+    Requests(u64),
+    Until(Instant),
+}
+
+/// Latency summary in milliseconds.
+#[derive(Serialize)]
+pub struct Latency {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct BenchReport {
+    pub url: String,
+    pub method: String,
+    pub concurrency: u64,
+    pub requests: u64,
+    pub duration_secs: f64,
+    pub requests_per_sec: f64,
+    /// Responses by status code.
+    pub statuses: BTreeMap<u16, u64>,
+    /// Requests that got no response at all (refused, reset, ...).
+    pub failures: u64,
+    /// Failures plus 5xx responses, as a fraction of all requests.
+    pub error_rate: f64,
+    pub latency: Option<Latency>,
+}
+
+impl BenchReport {
+    pub fn print(&self, json: bool) -> anyhow::Result<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+        println!(
+            "{} {} ({} concurrent)",
+            self.method, self.url, self.concurrency
+        );
+        println!(
+            "  {} requests in {:.2}s, {:.1} req/s",
+            self.requests, self.duration_secs, self.requests_per_sec
+        );
+        if let Some(l) = &self.latency {
+            println!(
+                "  latency ms: min {:.2}  mean {:.2}  p50 {:.2}  p90 {:.2}  p99 {:.2}  max {:.2}",
+                l.min, l.mean, l.p50, l.p90, l.p99, l.max
+            );
+        }
+        let statuses: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{}×{}", status, count))
+            .collect();
+        if !statuses.is_empty() {
+            println!("  status: {}", statuses.join("  "));
+        }
+        if self.failures > 0 {
+            println!("  failed: {}", self.failures);
+        }
+        println!("  error rate: {:.2}%", self.error_rate * 100.0);
+        Ok(())
+    }
+}
+
+/// Rounds to microseconds so the report stays readable.
+fn ms(d: Duration) -> f64 {
+    (d.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+fn latency(mut samples: Vec<Duration>) -> Option<Latency> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_unstable();
+    let at = |q: f64| samples[((samples.len() - 1) as f64 * q).round() as usize];
+    let total: Duration = samples.iter().sum();
+    Some(Latency {
+        min: ms(samples[0]),
+        mean: ms(total / samples.len() as u32),
+        p50: ms(at(0.5)),
+        p90: ms(at(0.9)),
+        p99: ms(at(0.99)),
+        max: ms(samples[samples.len() - 1]),
+    })
+}
+
+/// Routes resolve against the port NSM leased us, like `serve` binds.
+fn target_url(target: &str) -> anyhow::Result<Uri> {
+    let url = if target.starts_with('/') {
+        let ports = crate::read_nsm_config()?.unwrap_or_default();
+        let host = match ports.host.as_str() {
+            "0.0.0.0" => "127.0.0.1",
+            host => host,
+        };
+        format!("http://{}:{}{}", host, ports.http, target)
+    } else {
+        target.to_string()
+    };
+    let uri: Uri = url
+        .parse()
+        .with_context(|| format!("invalid URL {}", url))?;
+    if uri.scheme_str() != Some("http") {
+        bail!("only http:// targets are supported; bench the app port, not NSM's proxy");
+    }
+    Ok(uri)
+}
+
+fn parse_header(header: &str) -> anyhow::Result<(HeaderName, HeaderValue)> {
+    let (name, value) = header
+        .split_once(':')
+        .with_context(|| format!("header {:?} is not `Name: value`", header))?;
+    Ok((name.trim().parse()?, value.trim().parse()?))
+}
+
+pub async fn run(args: BenchArgs) -> anyhow::Result<BenchReport> {
+    if args.concurrency == 0 {
+        bail!("concurrency must be at least 1");
+    }
+    let uri = target_url(&args.target)?;
+    let headers = args
+        .headers
+        .iter()
+        .map(|h| parse_header(h))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let body = Bytes::from(args.body.unwrap_or_default());
+
+    let client: Client<HttpConnector, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build_http();
+    let budget = Arc::new(match args.duration {
+        Some(secs) => Budget::Until(Instant::now() + Duration::from_secs(secs)),
+        None => Budget::Requests(args.requests),
+    });
+    let sent = Arc::new(AtomicU64::new(0));
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..args.concurrency)
+        .map(|_| {
+            let (client, budget, sent) = (client.clone(), budget.clone(), sent.clone());
+            let (method, uri, headers, body) = (
+                args.method.clone(),
+                uri.clone(),
+                headers.clone(),
+                body.clone(),
+            );
+            tokio::spawn(async move {
+                let mut results = Vec::new();
+                loop {
+                    let more = match *budget {
+                        Budget::Requests(n) => sent.fetch_add(1, Ordering::Relaxed) < n,
+                        Budget::Until(deadline) => Instant::now() < deadline,
+                    };
+                    if !more {
+                        break;
+                    }
+                    let mut req = Request::builder().method(method.clone()).uri(uri.clone());
+                    for (name, value) in &headers {
+                        req = req.header(name, value);
+                    }
+                    let req = req.body(Full::new(body.clone())).expect("valid request");
+
+                    let start = Instant::now();
+                    let status = match client.request(req).await {
+                        // Read the whole body so latency covers the full response.
+                        Ok(res) => {
+                            let status = res.status().as_u16();
+                            res.into_body().collect().await.ok().map(|_| status)
+                        }
+                        Err(_) => None,
+                    };
+                    results.push((start.elapsed(), status));
+                }
+                results
+            })
+        })
+        .collect();
+
+    let mut samples = Vec::new();
+    let mut statuses = BTreeMap::new();
+    let mut failures = 0;
+    for worker in workers {
+        for (elapsed, status) in worker.await? {
+            match status {
+                Some(status) => {
+                    *statuses.entry(status).or_insert(0) += 1;
+                    samples.push(elapsed);
+                }
+                None => failures += 1,
+            }
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let requests = samples.len() as u64 + failures;
+    let server_errors: u64 = statuses.range(500..).map(|(_, n)| n).sum();
+    Ok(BenchReport {
+        url: uri.to_string(),
+        method: args.method.to_string(),
+        concurrency: args.concurrency,
+        requests,
+        duration_secs: elapsed,
+        requests_per_sec: requests as f64 / elapsed,
+        statuses,
+        failures,
+        error_rate: if requests == 0 {
+            0.0
+        } else {
+            (failures + server_errors) as f64 / requests as f64
+        },
+        latency: latency(samples),
+    })
+}
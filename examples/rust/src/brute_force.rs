@@ -0,0 +1,342 @@
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use http_body_util::{BodyExt, Full, Limited};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    config::BruteForceConfig,
+    error::AppError,
+    outbound::{self, HttpsClient},
+    ratelimit::too_many_requests,
+};
+
+/// The header a solved CAPTCHA's token is sent in.
+const CAPTCHA_HEADER: &str = "x-captcha-token";
+
+/// Set on a `401` when the next try needs a solved CAPTCHA.
+const CAPTCHA_REQUIRED_HEADER: &str = "x-captcha-required";
+
+/// Clients and accounts tracked at once; past this, quiet ones are dropped.
+const MAX_TRACKED: usize = 10_000;
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const VERIFY_BODY_LIMIT: usize = 64 * 1024;
+
+/// Recent failures for one client or account.
+#[derive(Default)]
+struct Window {
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Window {
+    fn forget_before(&mut self, cutoff: Instant) {
+        while self.failures.front().is_some_and(|at| *at < cutoff) {
+            self.failures.pop_front();
+        }
+    }
+
+    fn locked(&self, now: Instant) -> Option<Duration> {
+        self.locked_until?.checked_duration_since(now)
+    }
+}
+
+/// Failure windows by client or by account.
+struct Windows {
+    /// `client` or `account`, for the logs.
+    kind: &'static str,
+    max: u32,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl Windows {
+    fn new(kind: &'static str, max: u32) -> Self {
+        Self {
+            kind,
+            max,
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Failures within the window, and how much longer `key` stays locked
+    /// out, if it is.
+    fn standing(&self, key: &str, cutoff: Instant, now: Instant) -> (usize, Option<Duration>) {
+        let mut windows = self.windows.lock().unwrap();
+        let Some(window) = windows.get_mut(key) else {
+            return (0, None);
+        };
+        window.forget_before(cutoff);
+        (window.failures.len(), window.locked(now))
+    }
+
+    /// Records a failure; true when it locks `key` out.
+    fn fail(&self, key: &str, cutoff: Instant, now: Instant, lockout: Duration) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_TRACKED {
+            windows.retain(|_, w| {
+                w.locked(now).is_some() || w.failures.back().is_some_and(|at| *at >= cutoff)
+            });
+        }
+        let window = windows.entry(key.to_string()).or_default();
+        window.forget_before(cutoff);
+        window.failures.push_back(now);
+        if self.max == 0 || window.failures.len() < self.max as usize {
+            return false;
+        }
+        warn!(
+            "🔒 {} {} failed to log in {} times; locked out for {}s",
+            self.kind,
+            key,
+            window.failures.len(),
+            lockout.as_secs()
+        );
+        window.failures.clear();
+        window.locked_until = Some(now + lockout);
+        true
+    }
+
+    fn clear(&self, key: &str) {
+        self.windows.lock().unwrap().remove(key);
+    }
+
+    fn locked_out(&self, now: Instant) -> usize {
+        let windows = self.windows.lock().unwrap();
+        windows.values().filter(|w| w.locked(now).is_some()).count()
+    }
+}
+
+/// A siteverify-style CAPTCHA endpoint and our secret for it.
+struct SiteVerify {
+    url: String,
+    secret: String,
+    client: HttpsClient,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl SiteVerify {
+    /// Whether the provider says `token` was a solved CAPTCHA.
+    async fn verify(&self, token: &str, client: &str) -> anyhow::Result<bool> {
+        let form: Vec<String> = [
+            ("secret", self.secret.as_str()),
+            ("response", token),
+            ("remoteip", client),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+        .collect();
+        let req = axum::http::Request::post(&self.url)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(Full::new(Bytes::from(form.join("&"))))?;
+        let res = tokio::time::timeout(VERIFY_TIMEOUT, self.client.request(req))
+            .await
+            .with_context(|| format!("{}: timed out", self.url))??;
+        if !res.status().is_success() {
+            anyhow::bail!("{}: {}", self.url, res.status());
+        }
+        let body = Limited::new(res.into_body(), VERIFY_BODY_LIMIT)
+            .collect()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.url, e))?
+            .to_bytes();
+        let verdict: SiteVerifyResponse = serde_json::from_slice(&body)
+            .with_context(|| format!("{}: not a siteverify response", self.url))?;
+        Ok(verdict.success)
+    }
+}
+
+#[derive(Serialize)]
+pub struct BruteForceStats {
+    /// Failed logins since startup.
+    failures: u64,
+    /// Clients and accounts locked out since startup.
+    lockouts: u64,
+    /// Locked out right now.
+    locked_out_clients: usize,
+    locked_out_accounts: usize,
+    /// Logins turned away for want of a solved CAPTCHA, and CAPTCHAs the
+    /// provider didn't accept.
+    captchas_required: u64,
+    captchas_failed: u64,
+}
+
+/// Login failures per client and per account, over a sliding window, with
+/// the lockouts and CAPTCHAs they lead to. The login handler asks
+/// [`BruteForce::admit`] before checking a password and reports how it went
+/// with [`BruteForce::failed`] or [`BruteForce::succeeded`]. Each instance
+/// counts its own failures.
+pub struct BruteForce {
+    config: BruteForceConfig,
+    clients: Windows,
+    accounts: Windows,
+    captcha: Option<SiteVerify>,
+    failures: AtomicU64,
+    lockouts: AtomicU64,
+    captchas_required: AtomicU64,
+    captchas_failed: AtomicU64,
+}
+
+impl BruteForce {
+    pub fn new(config: &BruteForceConfig) -> anyhow::Result<Self> {
+        let captcha = match &config.captcha.verify_url {
+            Some(url) => {
+                let secret = std::env::var("CAPTCHA_SECRET")
+                    .ok()
+                    .filter(|s| !s.is_empty())
+                    .context("brute_force.captcha.verify_url is set; set CAPTCHA_SECRET too")?;
+                Some(SiteVerify {
+                    url: url.clone(),
+                    secret,
+                    client: outbound::https_client()?,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            config: config.clone(),
+            clients: Windows::new("client", config.max_per_client),
+            accounts: Windows::new("account", config.max_per_account),
+            captcha,
+            failures: AtomicU64::new(0),
+            lockouts: AtomicU64::new(0),
+            captchas_required: AtomicU64::new(0),
+            captchas_failed: AtomicU64::new(0),
+        })
+    }
+
+    fn cutoff(&self, now: Instant) -> Instant {
+        now.checked_sub(Duration::from_secs(self.config.window_secs))
+            .unwrap_or(now)
+    }
+
+    /// Whether `client` may try logging in as `account` now: `429` with
+    /// `Retry-After` while either is locked out, `401` with
+    /// `X-Captcha-Required` when a CAPTCHA is due and `headers` carry no
+    /// solved one.
+    pub async fn admit(
+        &self,
+        client: &str,
+        account: &str,
+        headers: &HeaderMap,
+    ) -> Result<(), Response> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let cutoff = self.cutoff(now);
+        let (client_failures, client_locked) = self.clients.standing(client, cutoff, now);
+        let (account_failures, account_locked) =
+            self.accounts.standing(&account_key(account), cutoff, now);
+        if let Some(wait) = client_locked.max(account_locked) {
+            return Err(too_many_requests(
+                "Too many failed logins; try again later",
+                wait.as_secs_f64(),
+            ));
+        }
+
+        let Some(captcha) = &self.captcha else {
+            return Ok(());
+        };
+        let after = self.config.captcha.after_failures as usize;
+        if client_failures.max(account_failures) < after {
+            return Ok(());
+        }
+        let token = headers
+            .get(CAPTCHA_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|t| !t.is_empty());
+        let Some(token) = token else {
+            self.captchas_required.fetch_add(1, Ordering::Relaxed);
+            return Err(captcha_required(
+                "Solve the CAPTCHA and send its token as X-Captcha-Token",
+            ));
+        };
+        match captcha.verify(token, client).await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.captchas_failed.fetch_add(1, Ordering::Relaxed);
+                // A wrong answer is a failed try from this client, but says
+                // nothing about the account's password.
+                self.fail(&self.clients, client);
+                Err(captcha_required("The CAPTCHA wasn't solved; try another"))
+            }
+            Err(e) => {
+                warn!("🔒 CAPTCHA check failed: {:#}", e);
+                Err(AppError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "The CAPTCHA can't be checked right now; try again shortly",
+                )
+                .into_response())
+            }
+        }
+    }
+
+    fn fail(&self, windows: &Windows, key: &str) {
+        let now = Instant::now();
+        let lockout = Duration::from_secs(self.config.lockout_secs);
+        if windows.fail(key, self.cutoff(now), now, lockout) {
+            self.lockouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a wrong password against both `client` and `account`.
+    pub fn failed(&self, client: &str, account: &str) {
+        if !self.config.enabled {
+            return;
+        }
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.fail(&self.clients, client);
+        self.fail(&self.accounts, &account_key(account));
+    }
+
+    /// Forgets the account's failures. The client's stay, so one account it
+    /// knows the password of doesn't let it keep guessing at others.
+    pub fn succeeded(&self, account: &str) {
+        self.accounts.clear(&account_key(account));
+    }
+
+    pub fn stats(&self) -> BruteForceStats {
+        let now = Instant::now();
+        BruteForceStats {
+            failures: self.failures.load(Ordering::Relaxed),
+            lockouts: self.lockouts.load(Ordering::Relaxed),
+            locked_out_clients: self.clients.locked_out(now),
+            locked_out_accounts: self.accounts.locked_out(now),
+            captchas_required: self.captchas_required.load(Ordering::Relaxed),
+            captchas_failed: self.captchas_failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Usernames differing only in case are one account as far as guessing
+/// goes.
+fn account_key(account: &str) -> String {
+    account.trim().to_lowercase()
+}
+
+fn captcha_required(message: &str) -> Response {
+    let mut res = AppError::new(StatusCode::UNAUTHORIZED, message).into_response();
+    res.headers_mut()
+        .insert(CAPTCHA_REQUIRED_HEADER, HeaderValue::from_static("true"));
+    res
+}
+
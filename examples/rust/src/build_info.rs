@@ -0,0 +1,47 @@
+use axum::response::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Metadata captured by `build.rs` at compile time.
+#[derive(Serialize, Clone, Copy, ToSchema)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub git_branch: &'static str,
+    pub git_dirty: bool,
+    pub build_timestamp: &'static str,
+    pub rustc_version: &'static str,
+    pub profile: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_sha: env!("BUILD_GIT_SHA"),
+    git_branch: env!("BUILD_GIT_BRANCH"),
+    git_dirty: matches!(env!("BUILD_GIT_DIRTY").as_bytes(), b"true"),
+    build_timestamp: env!("BUILD_TIMESTAMP"),
+    rustc_version: env!("BUILD_RUSTC_VERSION"),
+    profile: env!("BUILD_PROFILE"),
+};
+
+impl BuildInfo {
+    /// Abbreviated commit, e.g. `1a2b3c4` (or `1a2b3c4-dirty`).
+    pub fn short_sha(&self) -> String {
+        let sha = &self.git_sha[..self.git_sha.len().min(7)];
+        if self.git_dirty {
+            format!("{}-dirty", sha)
+        } else {
+            sha.to_string()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "system",
+    responses((status = 200, description = "Build metadata", body = BuildInfo))
+)]
+pub async fn version_handler() -> Json<BuildInfo> {
+    Json(BUILD_INFO)
+}
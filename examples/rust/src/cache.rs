@@ -0,0 +1,239 @@
+use anyhow::Context;
+use futures::{future::BoxFuture, StreamExt};
+use redis::{aio::ConnectionManager, AsyncCommands, Client, RedisResult};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast::error::RecvError, OnceCell};
+use tracing::{info, warn};
+
+use crate::{error::AppResult, health::HealthCheck, state::AppState};
+
+/// Used when `REDIS_URL` is unset.
+pub const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
+
+/// Channel every instance relays its live events through.
+const EVENTS_CHANNEL: &str = "{{.ProjectName}}:events";
+
+/// Wait before re-subscribing after the relay loses Redis, doubling up to
+/// the maximum while it stays away.
+const RELAY_RETRY: Duration = Duration::from_secs(2);
+const MAX_RELAY_RETRY: Duration = Duration::from_secs(60);
+
+pub fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or_else(|_| DEFAULT_REDIS_URL.to_string())
+}
+
+/// Shared Redis handle. It connects on first use, so the app starts even
+/// while Redis is down; the connection manager reconnects by itself after
+/// that.
+pub struct Redis {
+    client: Client,
+    manager: OnceCell<ConnectionManager>,
+    /// Tags what this instance relays, so it can skip its own messages.
+    instance: String,
+    /// Hub events that came in from Redis and mustn't be sent back out.
+    relayed: Mutex<HashSet<u64>>,
+}
+
+/// An event on [`EVENTS_CHANNEL`].
+#[derive(Serialize, Deserialize)]
+struct RelayedEvent {
+    origin: String,
+    kind: String,
+    data: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+}
+
+impl Redis {
+    pub fn new() -> anyhow::Result<Self> {
+        let url = redis_url();
+        Ok(Self {
+            client: Client::open(url.as_str())
+                .with_context(|| format!("invalid REDIS_URL {}", url))?,
+            manager: OnceCell::new(),
+            instance: uuid::Uuid::new_v4().to_string(),
+            relayed: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// A cheap clone of the shared connection.
+    pub async fn connection(&self) -> RedisResult<ConnectionManager> {
+        self.manager
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+    }
+
+    /// Cache-aside read: returns the JSON cached under `key`, or calls
+    /// `load` and caches its result for `ttl`. Redis being unavailable only
+    /// costs the cache; the value is loaded as if it were a miss.
+    #[allow(dead_code)]
+    pub async fn cached<T, F, Fut>(&self, key: &str, ttl: Duration, load: F) -> AppResult<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AppResult<T>>,
+    {
+        match self.get_json(key).await {
+            Ok(Some(value)) => return Ok(value),
+            Ok(None) => {}
+            Err(e) => warn!("Cache read of '{}' failed, loading instead: {}", key, e),
+        }
+        let value = load().await?;
+        if let Err(e) = self.set_json(key, &value, ttl).await {
+            warn!("Cache write of '{}' failed: {}", key, e);
+        }
+        Ok(value)
+    }
+
+    /// `None` on a miss, and for values that no longer deserialize as `T`.
+    async fn get_json<T: DeserializeOwned>(&self, key: &str) -> RedisResult<Option<T>> {
+        let cached: Option<String> = self.connection().await?.get(key).await?;
+        Ok(cached.and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    async fn set_json<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) -> RedisResult<()> {
+        let Ok(json) = serde_json::to_string(value) else {
+            return Ok(());
+        };
+        self.connection()
+            .await?
+            .set_ex(key, json, ttl.as_secs().max(1))
+            .await
+    }
+}
+
+/// Adds Redis to `/api/health` and starts relaying live events between
+/// instances through [`EVENTS_CHANNEL`].
+pub fn init(state: &AppState) {
+    info!(
+        "🧰 Redis: {} (events on {})",
+        state.redis().client.get_connection_info().addr,
+        EVENTS_CHANNEL
+    );
+    state.health().register(RedisCheck(state.clone()));
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let shutdown = state.shutdown_token();
+        let mut retry = RELAY_RETRY;
+        loop {
+            let started = Instant::now();
+            tokio::select! {
+                result = relay(&state) => match result {
+                    Ok(()) => return,
+                    Err(e) => {
+                        // A relay that ran for a while had reconnected;
+                        // start the backoff over.
+                        if started.elapsed() > MAX_RELAY_RETRY {
+                            retry = RELAY_RETRY;
+                        }
+                        warn!("Redis event relay down, retrying in {:?}: {:#}", retry, e);
+                    }
+                },
+                _ = shutdown.cancelled() => return,
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(retry) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            retry = (retry * 2).min(MAX_RELAY_RETRY);
+        }
+    });
+}
+
+/// Publishes this instance's hub events to Redis and feeds other instances'
+/// into the hub, so SSE, WebSocket and long-poll clients see events from
+/// every instance. Returns once the hub closes.
+async fn relay(state: &AppState) -> anyhow::Result<()> {
+    let redis = state.redis();
+    let mut pubsub = redis.client.get_async_pubsub().await?;
+    pubsub.subscribe(EVENTS_CHANNEL).await?;
+    let mut messages = pubsub.on_message();
+    let mut connection = redis.connection().await?;
+    let mut events = state.events().subscribe();
+
+    loop {
+        tokio::select! {
+            message = messages.next() => {
+                let message = message.context("subscription closed")?;
+                let payload: String = message.get_payload()?;
+                let event: RelayedEvent = match serde_json::from_str(&payload) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Ignoring malformed event on {}: {}", EVENTS_CHANNEL, e);
+                        continue;
+                    }
+                };
+                if event.origin == redis.instance {
+                    continue;
+                }
+                // Publishing under the lock means the loop below can't see
+                // the event before it's marked as relayed.
+                let mut relayed = redis.relayed.lock().unwrap();
+                relayed.insert(
+                    state
+                        .events()
+                        .publish_to(event.tenant, event.kind, event.data)
+                        .id,
+                );
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Redis event relay lagging, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if redis.relayed.lock().unwrap().remove(&event.id) {
+                    continue;
+                }
+                let payload = serde_json::to_string(&RelayedEvent {
+                    origin: redis.instance.clone(),
+                    kind: event.kind,
+                    data: event.data,
+                    tenant: event.tenant,
+                })?;
+                let _: () = connection.publish(EVENTS_CHANNEL, payload).await?;
+            }
+        }
+    }
+}
+
+struct RedisCheck(AppState);
+
+impl HealthCheck for RedisCheck {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let mut connection = self
+                .0
+                .redis()
+                .connection()
+                .await
+                .map_err(|e| e.to_string())?;
+            redis::cmd("PING")
+                .query_async::<String>(&mut connection)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    /// Reads fall back to the source while Redis is away, so losing it
+    /// degrades the service rather than taking it down.
+    fn critical(&self) -> bool {
+        false
+    }
+}
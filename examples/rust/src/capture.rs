@@ -0,0 +1,435 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use http_body::{Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+use crate::{
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// How many requests the buffer remembers.
+const CAPTURE_LIMIT: usize = 100;
+
+/// Request bodies of known length up to this size are kept for replay;
+/// response bodies are kept up to this size too.
+pub const CAPTURE_BODY_LIMIT: u64 = 64 * 1024;
+
+/// Replays of streaming endpoints (SSE, long polls) stop reading here.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Marks a request re-issued by `/debug/requests/:id/replay`.
+const REPLAY_HEADER: &str = "x-nsm-replay-of";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+pub fn header_list(headers: &HeaderMap) -> Vec<Header> {
+    headers
+        .iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+/// A finished request, as shown on the dev dashboard. The URI, headers and
+/// bodies have the secrets `redaction` names masked.
+#[derive(Serialize, Clone)]
+pub struct CapturedRequest {
+    /// The `x-request-id`, so entries can be matched against logs.
+    pub id: String,
+    pub method: String,
+    pub uri: String,
+    pub http_version: String,
+    pub headers: Vec<Header>,
+    /// Size of the request body, when it was known up front.
+    pub body_size: Option<u64>,
+    /// The request body, unless it was streamed or over the capture limit.
+    #[serde(skip)]
+    pub body: Option<Bytes>,
+    /// The request unmasked, only ever used to replay it.
+    #[serde(skip)]
+    original: Original,
+    pub status: u16,
+    /// Time to response headers; streamed bodies may take longer.
+    pub duration_ms: f64,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Filled in as the response body streams out.
+    #[serde(skip)]
+    pub response: Arc<Mutex<CapturedResponse>>,
+}
+
+#[derive(Clone)]
+struct Original {
+    uri: String,
+    headers: Vec<Header>,
+    body: Option<Bytes>,
+}
+
+#[derive(Default)]
+pub struct CapturedResponse {
+    pub headers: Vec<Header>,
+    /// The first `CAPTURE_BODY_LIMIT` bytes of the body as sent.
+    pub body: Vec<u8>,
+    /// Bytes sent so far, including any past the limit.
+    pub size: u64,
+    /// Time from headers to the end of the body; `None` while it's still
+    /// streaming (or if the client went away).
+    pub receive_ms: Option<f64>,
+}
+
+impl CapturedResponse {
+    fn record(&mut self, data: &[u8]) {
+        let room = (CAPTURE_BODY_LIMIT as usize).saturating_sub(self.body.len());
+        self.body.extend_from_slice(&data[..data.len().min(room)]);
+        self.size += data.len() as u64;
+    }
+}
+
+/// Passes a response body through unchanged (size hint included, so
+/// `Content-Length` survives) while copying it into a `CapturedResponse`.
+struct TeeBody {
+    inner: Body,
+    sink: Arc<Mutex<CapturedResponse>>,
+    started: Instant,
+    /// `Content-Length`, or the size hint when that's exact.
+    expected: Option<u64>,
+}
+
+impl TeeBody {
+    fn new(inner: Body, expected: Option<u64>, sink: Arc<Mutex<CapturedResponse>>) -> Self {
+        let expected = expected.or(inner.size_hint().exact());
+        // Empty bodies (and HEAD responses) may never be polled.
+        if inner.is_end_stream() || expected == Some(0) {
+            sink.lock().unwrap().receive_ms = Some(0.0);
+        }
+        Self {
+            expected,
+            inner,
+            sink,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl HttpBody for TeeBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        let mut sink = self.sink.lock().unwrap();
+        if let Some(Ok(frame)) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            sink.record(data);
+        }
+        // Hyper stops polling once it has `Content-Length` bytes, so the end
+        // of the stream isn't always observed as `None`.
+        let done = frame.is_none()
+            || self.inner.is_end_stream()
+            || self.expected.is_some_and(|len| sink.size >= len);
+        if done {
+            sink.receive_ms
+                .get_or_insert(self.started.elapsed().as_secs_f64() * 1000.0);
+        }
+        drop(sink);
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The most recent requests, oldest dropped first.
+#[derive(Default)]
+pub struct CaptureBuffer {
+    entries: Mutex<VecDeque<CapturedRequest>>,
+}
+
+impl CaptureBuffer {
+    fn push(&self, entry: CapturedRequest) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == CAPTURE_LIMIT {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Newest first.
+    pub fn recent(&self) -> Vec<CapturedRequest> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<CapturedRequest> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().find(|e| e.id == id).cloned()
+    }
+}
+
+/// Records every request except NSM's own `/__nsm/*` endpoints and the
+/// `/debug/requests` API, which would otherwise drown out app traffic.
+pub async fn capture_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if path.starts_with("/__nsm/") || path.starts_with("/debug/requests") {
+        return next.run(req).await;
+    }
+
+    let started_at = chrono::Utc::now();
+    let started = Instant::now();
+    let id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let (parts, body) = req.into_parts();
+    let body_size = body.size_hint().exact();
+    let (body, captured) = match body_size {
+        Some(size) if size <= CAPTURE_BODY_LIMIT => {
+            match axum::body::to_bytes(body, size as usize).await {
+                Ok(bytes) => (Body::from(bytes.clone()), Some(bytes)),
+                Err(e) => return AppError::bad_request(e.to_string()).into_response(),
+            }
+        }
+        _ => (body, None),
+    };
+    let redactor = state.redactor();
+    let original = Original {
+        uri: parts.uri.to_string(),
+        headers: header_list(&parts.headers),
+        body: captured,
+    };
+    let (method, uri, http_version, headers) = (
+        parts.method.to_string(),
+        redactor.uri(&original.uri),
+        format!("{:?}", parts.version),
+        redactor.header_list(&parts.headers),
+    );
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let captured = original
+        .body
+        .as_ref()
+        .map(|body| match std::str::from_utf8(body) {
+            Ok(text) => Bytes::from(redactor.body(content_type, text)),
+            Err(_) => body.clone(),
+        });
+    let is_head = parts.method == Method::HEAD;
+
+    let res = next.run(Request::from_parts(parts, body)).await;
+
+    let response = Arc::new(Mutex::new(CapturedResponse {
+        headers: redactor.header_list(res.headers()),
+        ..Default::default()
+    }));
+    state.capture().push(CapturedRequest {
+        id,
+        method,
+        uri,
+        http_version,
+        headers,
+        body_size,
+        body: captured,
+        original,
+        status: res.status().as_u16(),
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        started_at,
+        response: response.clone(),
+    });
+
+    let (parts, body) = res.into_parts();
+    let expected = if is_head {
+        Some(0)
+    } else {
+        parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse().ok())
+    };
+    Response::from_parts(parts, Body::new(TeeBody::new(body, expected, response)))
+}
+
+/// `GET /debug/requests`: the capture buffer, newest first.
+pub async fn list_handler(State(state): State<AppState>) -> Json<Vec<CapturedRequest>> {
+    Json(state.capture().recent())
+}
+
+/// Changes to make before re-issuing a captured request.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ReplayOptions {
+    /// Headers to set; `null` removes one.
+    headers: BTreeMap<String, Option<String>>,
+    /// Replacement body. Required when the original wasn't captured.
+    body: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ReplayResult {
+    /// Request id of the captured request.
+    original: String,
+    /// Request id of the replay, which is captured in turn.
+    id: Option<String>,
+    status: u16,
+    headers: Vec<Header>,
+    body: String,
+    body_truncated: bool,
+    duration_ms: f64,
+}
+
+/// `POST /debug/requests/:id/replay`: re-issues a captured request through
+/// the full middleware stack, with optional header and body changes given as
+/// a JSON body.
+pub async fn replay_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    options: Bytes,
+) -> AppResult<Json<ReplayResult>> {
+    let options: ReplayOptions = if options.is_empty() {
+        ReplayOptions::default()
+    } else {
+        serde_json::from_slice(&options).map_err(|e| AppError::bad_request(e.to_string()))?
+    };
+    let original = state
+        .capture()
+        .get(&id)
+        .ok_or_else(|| AppError::not_found(format!("No captured request {}", id)))?;
+    let app = state
+        .app()
+        .ok_or_else(|| AppError::internal("router not initialised"))?;
+
+    let Original {
+        uri,
+        headers: original_headers,
+        body,
+    } = original.original;
+    let body = match (options.body, body) {
+        (Some(body), _) => Bytes::from(body),
+        (None, Some(body)) => body,
+        (None, None) => {
+            return Err(AppError::new(
+                StatusCode::CONFLICT,
+                "The original body wasn't captured (streamed or over 64 KiB); pass a replacement `body`",
+            ));
+        }
+    };
+
+    let mut req = Request::builder()
+        .method(original.method.as_str())
+        .uri(uri.as_str())
+        .body(Body::empty())
+        .map_err(AppError::internal)?;
+    let headers = req.headers_mut();
+    for Header { name, value } in original_headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.append(name, value);
+        }
+    }
+    // Ask for an identity body so the result is readable; still overridable.
+    headers.remove(header::ACCEPT_ENCODING);
+    for (name, value) in options.headers {
+        let name = HeaderName::try_from(name).map_err(|e| AppError::bad_request(e.to_string()))?;
+        match value {
+            Some(value) => {
+                let value = HeaderValue::try_from(value)
+                    .map_err(|e| AppError::bad_request(e.to_string()))?;
+                headers.insert(name, value);
+            }
+            None => {
+                headers.remove(name);
+            }
+        }
+    }
+    // A fresh request id, and a length that matches the (possibly new) body.
+    headers.remove("x-request-id");
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    headers.insert(
+        REPLAY_HEADER,
+        HeaderValue::from_str(&id).map_err(AppError::internal)?,
+    );
+    *req.body_mut() = Body::from(body);
+
+    let started = Instant::now();
+    let res = app.oneshot(req).await.map_err(AppError::internal)?;
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let (parts, body) = res.into_parts();
+    let (body, body_truncated) = read_limited(body).await;
+    let redactor = state.redactor();
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+
+    Ok(Json(ReplayResult {
+        original: id,
+        id: parts
+            .headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        status: parts.status.as_u16(),
+        headers: redactor.header_list(&parts.headers),
+        body: redactor.body(content_type, &String::from_utf8_lossy(&body)),
+        body_truncated,
+        duration_ms,
+    }))
+}
+
+/// Reads up to `CAPTURE_BODY_LIMIT` bytes, giving up on endless streams
+/// after `REPLAY_TIMEOUT`. The flag says whether anything was cut off.
+async fn read_limited(body: Body) -> (Vec<u8>, bool) {
+    let mut stream = body.into_data_stream();
+    let mut buf = Vec::new();
+    let deadline = tokio::time::sleep(REPLAY_TIMEOUT);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            chunk = stream.next() => match chunk {
+                Some(Ok(chunk)) => {
+                    let room = CAPTURE_BODY_LIMIT as usize - buf.len();
+                    if chunk.len() > room {
+                        buf.extend_from_slice(&chunk[..room]);
+                        return (buf, true);
+                    }
+                    buf.extend_from_slice(&chunk);
+                }
+                Some(Err(_)) => return (buf, true),
+                None => return (buf, false),
+            },
+            _ = &mut deadline => return (buf, true),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use http_body::{Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::Sleep;
+use tracing::info;
+
+use crate::{
+    config::{ChaosConfig, ChaosRule},
+    error::AppError,
+    selfcheck::SELF_CHECK_HEADER,
+    state::AppState,
+};
+
+/// Marks responses we tampered with, so a confused frontend dev can tell
+/// injected failures from real ones.
+const CHAOS_HEADER: &str = "x-nsm-chaos";
+
+/// How long a truncated response stalls before the connection is cut.
+const TRUNCATE_FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+/// Runtime switch for the faults in `config.json`'s `chaos` section.
+pub struct Chaos {
+    enabled: AtomicBool,
+}
+
+impl Chaos {
+    pub fn new(config: &ChaosConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.enabled),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+fn roll(probability: f64) -> bool {
+    probability > 0.0 && rand::random::<f64>() < probability
+}
+
+/// Injects the first matching rule's faults: latency first, then at most
+/// one of a dropped connection, an error status or a truncated body. NSM's
+/// own `/__nsm/*` endpoints are exempt so chaos can always be switched off,
+/// as is the startup self-check.
+pub async fn inject_faults(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if !state.chaos().is_enabled()
+        || path.starts_with("/__nsm/")
+        || req.headers().contains_key(SELF_CHECK_HEADER)
+    {
+        return next.run(req).await;
+    }
+    let Some(rule) = state.config().chaos.rules.iter().find(|r| r.matches(path)) else {
+        return next.run(req).await;
+    };
+    let path = path.to_string();
+
+    if roll(rule.latency) {
+        info!("🐒 Chaos: delaying {} by {}ms", path, rule.latency_ms);
+        tokio::time::sleep(Duration::from_millis(rule.latency_ms)).await;
+    }
+
+    if roll(rule.drop) {
+        info!("🐒 Chaos: dropping connection for {}", path);
+        // A body that fails before its first byte makes hyper close the
+        // connection without writing the response.
+        let body = Body::from_stream(futures::stream::once(async {
+            Err::<Bytes, _>(io::Error::other("chaos: dropped connection"))
+        }));
+        return Response::new(body);
+    }
+
+    if roll(rule.error) {
+        info!("🐒 Chaos: answering {} with {}", path, rule.status);
+        let status = StatusCode::from_u16(rule.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        return tag(
+            AppError::new(status, "Injected by chaos mode").into_response(),
+            "error",
+        );
+    }
+
+    let res = next.run(req).await;
+    if roll(rule.truncate) {
+        info!("🐒 Chaos: truncating response body for {}", path);
+        let (parts, body) = res.into_parts();
+        let res = Response::from_parts(parts, Body::new(Truncated::new(body)));
+        return tag(res, "truncate");
+    }
+    res
+}
+
+fn tag(mut res: Response, fault: &'static str) -> Response {
+    res.headers_mut()
+        .insert(CHAOS_HEADER, HeaderValue::from_static(fault));
+    res
+}
+
+/// Passes through half of a sized body (or the first chunk of a streamed
+/// one), then fails so the connection is cut mid-response. The size hint
+/// is kept, so clients see a short read against `Content-Length`.
+struct Truncated {
+    inner: Body,
+    /// Bytes still to pass through; `None` until the first chunk of a body
+    /// of unknown length.
+    remaining: Option<u64>,
+    /// Hyper discards unflushed output when a body fails, so we pause
+    /// before failing to let the partial body reach the client.
+    flush: Option<Pin<Box<Sleep>>>,
+}
+
+impl Truncated {
+    fn new(inner: Body) -> Self {
+        Self {
+            remaining: inner.size_hint().exact().map(|len| len / 2),
+            inner,
+            flush: None,
+        }
+    }
+}
+
+impl HttpBody for Truncated {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if self.remaining == Some(0) {
+            let flush = self
+                .flush
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(TRUNCATE_FLUSH_DELAY)));
+            ready!(flush.as_mut().poll(cx));
+            return Poll::Ready(Some(Err(axum::Error::new("chaos: truncated body"))));
+        }
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        let Some(Ok(frame)) = frame else {
+            return Poll::Ready(frame);
+        };
+        let frame = match frame.into_data() {
+            Ok(mut data) => {
+                let keep = match self.remaining {
+                    Some(remaining) => remaining.min(data.len() as u64),
+                    None => data.len() as u64,
+                };
+                data.truncate(keep as usize);
+                self.remaining = Some(self.remaining.map_or(0, |r| r - keep));
+                Frame::data(data)
+            }
+            Err(frame) => frame,
+        };
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChaosStatus<'a> {
+    enabled: bool,
+    rules: &'a [ChaosRule],
+}
+
+#[derive(Deserialize)]
+pub struct ChaosToggle {
+    enabled: bool,
+}
+
+fn status(state: &AppState) -> Json<ChaosStatus<'_>> {
+    Json(ChaosStatus {
+        enabled: state.chaos().is_enabled(),
+        rules: &state.config().chaos.rules,
+    })
+}
+
+/// `GET /__nsm/chaos`: whether faults are being injected, and the rules.
+pub async fn status_handler(State(state): State<AppState>) -> Response {
+    status(&state).into_response()
+}
+
+/// `PUT /__nsm/chaos` with `{"enabled": true|false}`.
+pub async fn toggle_handler(
+    State(state): State<AppState>,
+    Json(toggle): Json<ChaosToggle>,
+) -> Response {
+    state.chaos().set_enabled(toggle.enabled);
+    info!(
+        "🐒 Chaos injection {}",
+        if toggle.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    status(&state).into_response()
+}
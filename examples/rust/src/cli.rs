@@ -0,0 +1,372 @@
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::{
+    io::IsTerminal,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    bench::{self, BenchArgs},
+    build_info::BUILD_INFO,
+    config::{AppConfig, EffectiveConfig},
+    preflight, preview,
+    redaction::{RedactedStdout, Redactor},
+    request_log::DevFormat,
+    routes::RouteInfo,
+    state::{config_profile, AppState},
+};
+
+/// Command line. With no subcommand the binary serves, so `cargo run` and
+/// NSM's start command keep working unchanged.
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+impl Cli {
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Serve(self.serve))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve(ServeArgs),
+    /// Print the route table without starting the server
+    Routes {
+        /// Print JSON, as served by /api/routes
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the effective configuration as JSON
+    Config,
+    /// Preflight checks; prints a JSON report and exits nonzero on failure
+    Check,
+    /// Diagnose the NSM setup: proxy, CA trust, DNS and environment
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Load-test a route on the running instance and report latency
+    Bench(BenchArgs),
+    /// Print build metadata
+    Version {
+        /// Print JSON, as served by /api/version
+        #[arg(long)]
+        json: bool,
+    },
+    /// Open the admin console on the running server, or run one console
+    /// command (e.g. `admin maintenance on`)
+    Admin { command: Vec<String> },
+    /// Serve every template in templates/ with sample context, reloading on
+    /// changes
+    PreviewTemplates {
+        /// Port to listen on (127.0.0.1 only)
+        #[arg(long, default_value_t = preview::PREVIEW_PORT)]
+        port: u16,
+    },
+    /// Manage the database schema with the migrations built into this binary
+    #[cfg(feature = "database")]
+    Migrate {
+        #[command(subcommand)]
+        command: crate::db::MigrateCommand,
+    },
+    /// Save the database as a named snapshot, or put one back
+    #[cfg(feature = "database")]
+    Db {
+        #[command(subcommand)]
+        command: crate::db::DbCommand,
+    },
+    /// Load the fixture data in seeds/ (JSON or SQL) that isn't in the database yet
+    #[cfg(feature = "database")]
+    Seed,
+    /// Print a shell completion script for bash, zsh, fish, elvish or PowerShell
+    Completions { shell: Shell },
+    /// Print the man page, or write one per subcommand into a directory
+    Manpage {
+        /// Write `<name>.1`, `<name>-serve.1`, ... here instead
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Rebuild on source changes and restart in place, keeping the port
+    #[arg(long, env = "APP_WATCH")]
+    pub watch: bool,
+    /// Only log warnings and errors, and skip the banner
+    #[arg(short, long, env = "APP_QUIET", conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Log more: -v for debug, -vv for trace
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Skip the startup banner
+    #[arg(long, env = "APP_NO_BANNER")]
+    pub no_banner: bool,
+    /// Skip requesting /api/health and /readyz through the listener at startup
+    #[arg(long, env = "APP_NO_SELF_CHECK")]
+    pub no_self_check: bool,
+    /// Log format; `auto` is dev (grouped by request) on a terminal in debug
+    /// builds, pretty in release builds and JSON when piped
+    #[arg(long, env = "APP_LOG_FORMAT", value_enum, default_value_t = LogFormat::Auto)]
+    pub log_format: LogFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Auto,
+    Pretty,
+    /// Each request's events grouped under a colored summary line
+    Dev,
+    Json,
+}
+
+impl LogFormat {
+    /// Resolves `auto` against stdout, which is where the logs go.
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Auto if std::io::stdout().is_terminal() && cfg!(debug_assertions) => Self::Dev,
+            Self::Auto if std::io::stdout().is_terminal() => Self::Pretty,
+            Self::Auto => Self::Json,
+            format => format,
+        }
+    }
+}
+
+/// The running log filter, what it falls back to when the config file
+/// stops setting one, and what it was at startup.
+struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default: String,
+    startup: String,
+}
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+/// Applies the config file's `log_level` to the running process. Returns
+/// `false` when `RUST_LOG` is set, since it takes precedence.
+pub fn reload_log_filter(config: Option<&str>) -> anyhow::Result<bool> {
+    if std::env::var_os("RUST_LOG").is_some() {
+        return Ok(false);
+    }
+    let Some(log) = LOG_FILTER.get() else {
+        return Ok(false);
+    };
+    let directives = config.unwrap_or(&log.default);
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|e| anyhow::anyhow!("invalid log_level {:?}: {}", directives, e))?;
+    log.handle.reload(filter)?;
+    Ok(true)
+}
+
+/// Replaces the running log filter, even over `RUST_LOG`; `None` goes back
+/// to the one the process started with.
+pub fn set_log_filter(directives: Option<&str>) -> anyhow::Result<()> {
+    let Some(log) = LOG_FILTER.get() else {
+        anyhow::bail!("logging is not set up");
+    };
+    let directives = directives.unwrap_or(&log.startup);
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|e| anyhow::anyhow!("invalid log filter {:?}: {}", directives, e))?;
+    log.handle.reload(filter)?;
+    Ok(())
+}
+
+/// The running log filter's directives.
+pub fn current_log_filter() -> Option<String> {
+    LOG_FILTER
+        .get()?
+        .handle
+        .with_current(ToString::to_string)
+        .ok()
+}
+
+/// Serves tokio-console on `console.port` (localhost only).
+#[cfg(feature = "console")]
+fn console_layer<S>(config: &AppConfig) -> Option<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], config.console.port));
+    Some(
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(addr)
+            .spawn(),
+    )
+}
+
+#[cfg(not(feature = "console"))]
+fn console_layer<S: tracing::Subscriber>(_config: &AppConfig) -> Option<impl Layer<S>> {
+    None::<tracing_subscriber::layer::Identity>
+}
+
+impl ServeArgs {
+    /// The default `EnvFilter` directives; `RUST_LOG` still wins when set.
+    pub fn log_filter(&self) -> String {
+        let crate_name = env!("CARGO_CRATE_NAME");
+        match (self.quiet, self.verbose) {
+            (true, _) => "warn".to_string(),
+            (false, 0) => format!("{}=info,tower_http=info", crate_name),
+            (false, 1) => format!("{}=debug,tower_http=debug", crate_name),
+            (false, _) => format!("{}=trace,tower_http=trace", crate_name),
+        }
+    }
+
+    pub fn banner(&self) -> bool {
+        !(self.quiet || self.no_banner)
+    }
+
+    /// Sets up the global subscriber. JSON lines are one event each, so
+    /// NSM's log collector never sees a multi-line or colored record.
+    /// `RUST_LOG` beats the config file's `log_level`, which beats `-q`/`-v`.
+    /// Every format masks the secrets `redaction` names. The filter only
+    /// applies to the log output, so tokio-console still sees every task.
+    pub fn init_tracing(&self, config: &AppConfig) {
+        let default = self.log_filter();
+        let directives = std::env::var("RUST_LOG")
+            .ok()
+            .or(config.log_level.clone())
+            .unwrap_or_else(|| default.clone());
+        let (filter, handle) = reload::Layer::new(EnvFilter::new(&directives));
+        let _ = LOG_FILTER.set(LogFilter {
+            handle,
+            default,
+            startup: directives,
+        });
+
+        let stdout = RedactedStdout(Arc::new(Redactor::new(&config.redaction)));
+        let registry = tracing_subscriber::registry();
+        match self.log_format.resolve() {
+            LogFormat::Json => registry
+                .with(
+                    fmt::layer()
+                        .json()
+                        .flatten_event(true)
+                        .with_writer(stdout)
+                        .with_filter(filter),
+                )
+                .with(console_layer(config))
+                .init(),
+            LogFormat::Dev => registry
+                .with(DevFormat::new(std::io::stdout().is_terminal(), stdout).with_filter(filter))
+                .with(console_layer(config))
+                .init(),
+            _ => registry
+                .with(
+                    fmt::layer()
+                        .with_ansi(std::io::stdout().is_terminal())
+                        .with_writer(stdout)
+                        .with_filter(filter),
+                )
+                .with(console_layer(config))
+                .init(),
+        }
+    }
+}
+
+pub fn routes(json: bool) -> anyhow::Result<()> {
+    let state = AppState::new(config_profile(), AppConfig::load()?)?;
+    let (_, table) = crate::app_routes(&state).into_parts();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&table)?);
+        return Ok(());
+    }
+
+    let methods = |route: &RouteInfo| route.methods.join(",");
+    let methods_width = table.iter().map(|r| methods(r).len()).max().unwrap_or(0);
+    let path_width = table.iter().map(|r| r.path.len()).max().unwrap_or(0);
+    for route in &table {
+        let auth = match route.permission {
+            Some(permission) => format!(" (auth: {})", permission),
+            None if route.auth => " (auth)".to_string(),
+            None => String::new(),
+        };
+        let line = format!(
+            "{:methods_width$}  {:path_width$}  {}{}",
+            methods(route),
+            route.path,
+            route.description.unwrap_or_default(),
+            auth,
+        );
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+pub fn config() -> anyhow::Result<()> {
+    let (profile, app) = (config_profile(), AppConfig::load()?);
+    let config = EffectiveConfig::new(&profile, &app);
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
+
+pub fn check() -> anyhow::Result<()> {
+    let report = preflight::check();
+    report.print(true)?;
+    if !report.ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub async fn doctor(json: bool) -> anyhow::Result<()> {
+    let report = preflight::doctor().await;
+    report.print(json)?;
+    if !report.ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub async fn bench(args: BenchArgs) -> anyhow::Result<()> {
+    let json = args.json;
+    bench::run(args).await?.print(json)
+}
+
+pub fn version(json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&BUILD_INFO)?);
+    } else {
+        println!(
+            "{} v{} ({}, {}, {})",
+            crate::PROJECT_NAME,
+            BUILD_INFO.version,
+            BUILD_INFO.short_sha(),
+            BUILD_INFO.profile,
+            BUILD_INFO.rustc_version
+        );
+    }
+    Ok(())
+}
+
+pub fn completions(shell: Shell) -> anyhow::Result<()> {
+    let mut cmd = <Cli as CommandFactory>::command();
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+    Ok(())
+}
+
+pub fn manpage(out_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            clap_mangen::generate_to(<Cli as CommandFactory>::command(), &dir)?;
+            eprintln!("Wrote man pages to {}", dir.display());
+        }
+        None => clap_mangen::Man::new(<Cli as CommandFactory>::command())
+            .render(&mut std::io::stdout())?,
+    }
+    Ok(())
+}
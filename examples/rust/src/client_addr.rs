@@ -0,0 +1,86 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap},
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::{config::ProxyConfig, outbound::Cidr, state::AppState};
+
+/// What [`TrustedProxies::client`] gives for requests that didn't come in
+/// over a connection of ours, e.g. ones replayed from `/debug/requests`.
+const UNKNOWN: &str = "unknown";
+
+/// The proxies in `proxy.trusted`, whose `X-Forwarded-For` is believed.
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    pub fn new(config: &ProxyConfig) -> anyhow::Result<Self> {
+        config
+            .trusted
+            .iter()
+            .map(|entry| {
+                Cidr::parse(entry.trim()).ok_or_else(|| {
+                    anyhow::anyhow!("proxy.trusted: {:?} is not an address or range", entry)
+                })
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address a request came from, which rate limits, lockouts and the
+    /// like key on: the connection's peer, unless that's a trusted proxy,
+    /// in which case the right-most `X-Forwarded-For` hop that isn't one.
+    /// Hops further left were written by the client and prove nothing.
+    pub fn client(&self, headers: &HeaderMap, extensions: &Extensions) -> String {
+        let Some(ConnectInfo(peer)) = extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return UNKNOWN.to_string();
+        };
+        let mut addr = peer.ip().to_canonical();
+        if self.trusts(addr) {
+            let hops: Vec<&str> = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .map(str::trim)
+                .collect();
+            for hop in hops.into_iter().rev() {
+                let Ok(ip) = hop.parse::<IpAddr>() else {
+                    break;
+                };
+                addr = ip.to_canonical();
+                if !self.trusts(addr) {
+                    break;
+                }
+            }
+        }
+        addr.to_string()
+    }
+}
+
+/// The request's [`TrustedProxies::client`] address, for handlers.
+pub struct ClientAddr(pub String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientAddr {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            state
+                .trusted_proxies()
+                .client(&parts.headers, &parts.extensions),
+        ))
+    }
+}
@@ -0,0 +1,1351 @@
+use anyhow::Context;
+use axum::http::HeaderValue;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::PathBuf};
+
+use crate::NSMConfig;
+
+/// Default location of the optional app config, relative to the working
+/// directory. Override with `APP_CONFIG`.
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Settings read from `config.json`. Every section is optional; a missing
+/// file is the same as `{}`.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// `EnvFilter` directives, e.g. `info,demo_app=debug`. `RUST_LOG` wins;
+    /// without either, `-q`/`-v` pick the level.
+    pub log_level: Option<String>,
+    pub redaction: RedactionConfig,
+    pub cors: CorsConfig,
+    pub rate_limit: RateLimitConfig,
+    /// Used by builds with the `console` feature.
+    pub console: ConsoleConfig,
+    pub watchdog: WatchdogConfig,
+    pub hooks: HooksConfig,
+    pub metadata: MetadataConfig,
+    #[serde(rename = "static")]
+    pub static_files: StaticFilesConfig,
+    pub chaos: ChaosConfig,
+    /// Used by builds with the `database` feature.
+    pub database: DatabaseConfig,
+    pub kv: KvConfig,
+    pub sessions: SessionConfig,
+    pub cookie_keys: CookieKeysConfig,
+    /// Used by builds with the `database` feature.
+    pub passwords: PasswordsConfig,
+    /// Used by builds with the `database` feature.
+    pub two_factor: TwoFactorConfig,
+    pub jobs: JobsConfig,
+    pub scheduler: SchedulerConfig,
+    pub outbox: OutboxConfig,
+    pub outbound: OutboundConfig,
+    pub proxy: ProxyConfig,
+    /// Used by builds with the `nats` feature.
+    pub consumers: ConsumersConfig,
+    /// Used by builds with the `email` feature.
+    pub email: EmailConfig,
+    /// Used by builds with the `database` feature.
+    pub api_keys: ApiKeysConfig,
+    pub auth: AuthConfig,
+    pub static_api_keys: StaticApiKeysConfig,
+    pub basic_auth: BasicAuthConfig,
+    pub brute_force: BruteForceConfig,
+    pub rbac: RbacConfig,
+    pub signed_requests: SignedRequestsConfig,
+    pub signed_urls: SignedUrlsConfig,
+    pub mtls: MtlsConfig,
+    pub privileged_log: PrivilegedLogConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub dev_oidc: DevOidcConfig,
+    pub tenancy: TenancyConfig,
+    /// Third-party APIs reached through `/upstream/<name>/...`, by name.
+    pub upstreams: BTreeMap<String, UpstreamConfig>,
+}
+
+/// What's masked in log output and in the debug capture buffer (the
+/// dashboard, `/debug/requests` and the HAR export).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionConfig {
+    /// Header names, matched regardless of case.
+    pub headers: Vec<String>,
+    /// JSON fields, form fields and query parameters whose names contain one
+    /// of these, regardless of case, e.g. `password` also covers
+    /// `new_password`.
+    pub fields: Vec<String>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        let strings = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Self {
+            headers: strings(&[
+                "authorization",
+                "proxy-authorization",
+                "cookie",
+                "set-cookie",
+                "x-api-key",
+            ]),
+            fields: strings(&["password", "token", "secret", "recovery_code"]),
+        }
+    }
+}
+
+/// Cross-origin access. Reloaded on SIGHUP.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call us, e.g. `https://web.myapp.dev`; `*` allows
+    /// any.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+}
+
+/// Per-client token bucket, and the limits of signed-in principals.
+/// Reloaded on SIGHUP.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained rate per client; `0` turns limiting off.
+    pub requests_per_second: f64,
+    /// Requests a client may make at once before the rate applies. Defaults
+    /// to one second's worth.
+    pub burst: Option<u32>,
+    /// Limits by principal, named as in the audit log (`user:alice`,
+    /// `api-key:3`), or `*` for any other signed-in one. A principal listed
+    /// gets a bucket of its own instead of its client's; one that isn't
+    /// shares its client's.
+    pub principals: BTreeMap<String, PrincipalLimit>,
+}
+
+/// One principal's bucket and daily quota.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrincipalLimit {
+    /// Sustained rate; `0` means no rate limit, only the quota.
+    pub requests_per_second: f64,
+    /// Defaults to one second's worth.
+    pub burst: Option<u32>,
+    /// Requests per UTC day; `0` means no quota.
+    pub daily_quota: u64,
+}
+
+/// Where tokio-console connects to inspect tasks.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    pub port: u16,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        // tokio-console's own default.
+        Self { port: 6669 }
+    }
+}
+
+/// How the watchdog probes the runtime for blocked workers.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// How long a probe may wait to be scheduled before it counts as a
+    /// stall; `0` turns the watchdog off.
+    pub threshold_ms: u64,
+    /// Pause between probes.
+    pub interval_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            threshold_ms: 100,
+            interval_ms: 100,
+        }
+    }
+}
+
+/// Shell commands run at points in the server's life, e.g.
+/// `"on_ready": ["open $APP_URL"]`. They see `APP_URL` and `APP_HOOK`, and
+/// their output goes to the log. A failing hook is logged and otherwise
+/// ignored.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Before the listener is bound.
+    pub on_start: Vec<String>,
+    /// Once the server is ready and the self-check has passed; run in the
+    /// background.
+    pub on_ready: Vec<String>,
+    /// After the server has drained, before the process exits.
+    pub on_shutdown: Vec<String>,
+    /// Each command is killed after this long.
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_start: Vec::new(),
+            on_ready: Vec::new(),
+            on_shutdown: Vec::new(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// The connection itself comes from `DATABASE_URL`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// How long startup keeps retrying until the database accepts
+    /// connections, for one that comes up alongside the app; `0` gives up
+    /// after the first attempt.
+    pub wait_secs: u64,
+    /// Bound on the `SELECT 1` behind `/readyz` and `/api/health`.
+    pub ping_timeout_ms: u64,
+    /// How long the pool probe may wait for a connection before it warns
+    /// that the pool is saturated; `0` turns the probe off.
+    pub slow_acquire_ms: u64,
+    /// Pause between pool probes.
+    pub probe_interval_ms: u64,
+    /// Days a deleted note stays in the trash before the nightly
+    /// `purge-deleted-notes` task removes it; `0` keeps them forever.
+    pub trash_retention_days: u64,
+    /// Apply new files from `seeds/` after the startup migrations, as the
+    /// `seed` command does.
+    pub seed_on_start: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            wait_secs: 0,
+            ping_timeout_ms: 1000,
+            slow_acquire_ms: 500,
+            probe_interval_ms: 1000,
+            trash_retention_days: 30,
+            seed_on_start: false,
+        }
+    }
+}
+
+/// Backing store for `/api/kv`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KvConfig {
+    pub backend: KvBackend,
+    /// Where the `file` backend keeps one JSON file per key.
+    pub dir: PathBuf,
+}
+
+impl Default for KvConfig {
+    fn default() -> Self {
+        Self {
+            backend: KvBackend::default(),
+            dir: "kv".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KvBackend {
+    /// Lost on restart.
+    #[default]
+    Memory,
+    /// Files under `kv.dir`; survives restarts.
+    File,
+    /// Shared by every instance; needs the `redis` feature.
+    Redis,
+}
+
+/// Server-side sessions; the cookie only carries a signed id.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub backend: SessionBackend,
+    /// Idle time after which a session expires. Each use pushes it back.
+    pub ttl_secs: u64,
+    /// Send the cookie over HTTPS only.
+    pub secure_cookie: bool,
+    /// The cookie's `Domain`, which its subdomains share. Unset, it's NSM's
+    /// domain when running under NSM, so tenant and `api.` subdomains see
+    /// the same session, and otherwise just the host. Empty for the host
+    /// only.
+    pub cookie_domain: Option<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            backend: SessionBackend::default(),
+            ttl_secs: 24 * 60 * 60,
+            secure_cookie: false,
+            cookie_domain: None,
+        }
+    }
+}
+
+/// The keys cookies are encrypted with, by version. Cookies are sealed with
+/// the highest version and open with any listed, so rotating means adding
+/// a higher version, then dropping the old one once its cookies have
+/// expired (`sessions.ttl_secs`). `SESSION_SECRET` is version `0`; with no
+/// keys at all, a random one is made at startup.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieKeysConfig {
+    /// Secret by version, e.g. `{"2": "..."}`. `COOKIE_KEYS`
+    /// (`version=secret,...`) adds more.
+    #[serde(serialize_with = "redacted")]
+    pub keys: BTreeMap<String, String>,
+    /// A JSON file of more in the same shape, e.g. a mounted secret.
+    pub keyring_path: Option<PathBuf>,
+}
+
+/// How account passwords are hashed: argon2id, at OWASP's recommended
+/// minimum cost by default. Raising the cost or adding a pepper rehashes
+/// each account's password the next time it logs in.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordsConfig {
+    /// Memory per hash, in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Secrets by version, mixed into every hash but kept out of the
+    /// database, so a leaked users table can't be cracked without them. New
+    /// hashes use the highest version; drop an old one only once no account
+    /// is hashed with it. `PASSWORD_PEPPERS` (`version=pepper,...`) adds
+    /// more.
+    #[serde(serialize_with = "redacted")]
+    pub peppers: BTreeMap<String, String>,
+}
+
+impl Default for PasswordsConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+            peppers: BTreeMap::new(),
+        }
+    }
+}
+
+/// TOTP two-factor for accounts, which each account turns on for itself.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TwoFactorConfig {
+    /// Shown in authenticator apps above the username; the project name by
+    /// default.
+    pub issuer: Option<String>,
+    /// Recovery codes handed out when two-factor is turned on.
+    pub recovery_codes: usize,
+    /// How long after the password the code may follow.
+    pub challenge_secs: u64,
+    /// How long a code counts as recent for actions that ask for one again
+    /// (turning two-factor off, new recovery codes).
+    pub step_up_secs: u64,
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            recovery_codes: 10,
+            challenge_secs: 300,
+            step_up_secs: 600,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    /// Lost on restart.
+    #[default]
+    Memory,
+    /// Needs the `redis` feature.
+    Redis,
+    /// The `sessions` table; needs the `database` feature.
+    Sqlite,
+}
+
+/// The background job queue. Jobs are kept in SQLite with the `database`
+/// feature, so they survive restarts, and in memory otherwise.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Jobs run concurrently; `0` leaves them queued.
+    pub workers: usize,
+    /// Runs of a job, the first included, before it's marked failed.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            max_attempts: 5,
+            backoff_ms: 1000,
+            max_backoff_ms: 5 * 60 * 1000,
+        }
+    }
+}
+
+/// Tasks from `scheduler::tasks()`.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// Names of tasks that shouldn't run here.
+    pub disabled: Vec<String>,
+}
+
+/// Delivery of events recorded through the outbox, which needs the
+/// `database` feature. Every event goes to the live event hub, and to each
+/// webhook that subscribes to its kind.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboxConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    /// Delivery attempts, the first included, before an event is
+    /// dead-lettered.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Per webhook request.
+    pub timeout_secs: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            max_attempts: 8,
+            backoff_ms: 1000,
+            max_backoff_ms: 10 * 60 * 1000,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// What requests to URLs from users or config (the outbox's webhooks) may
+/// reach. Names are checked once resolved, so one pointed at an internal
+/// address is blocked too.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboundConfig {
+    /// Block private (RFC 1918, unique local), shared, link-local and cloud
+    /// metadata (`169.254.169.254`) addresses.
+    pub block_private: bool,
+    /// Block loopback too. Off, so webhooks can reach services on the dev
+    /// machine.
+    pub block_loopback: bool,
+    /// Host names, addresses or ranges reachable all the same, e.g.
+    /// `hooks.internal` or `10.0.4.0/24`.
+    pub allow: Vec<String>,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            block_private: true,
+            block_loopback: false,
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// Proxies in front of the app whose `X-Forwarded-For` is believed when
+/// working out where a request came from, for rate limits, lockouts, signed
+/// URL binding and the privileged log. Other clients are known by their
+/// connection's address. NSM's proxy passes on whatever first
+/// `X-Forwarded-For` entry it was sent, so it isn't trusted by default.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Addresses or ranges, e.g. `10.0.0.0/8`.
+    pub trusted: Vec<String>,
+}
+
+/// Jobs that arrive as messages from NATS JetStream at `NATS_URL`, by
+/// default `nats://127.0.0.1:4222`: a local `nats-server -js`. Message types
+/// come from `consumer::registry()`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsumersConfig {
+    /// JetStream stream holding the subjects; created with them if missing.
+    pub stream: String,
+    /// Subject by job kind, in place of the one in code.
+    pub subjects: BTreeMap<String, String>,
+    /// Job kinds not consumed here.
+    pub disabled: Vec<String>,
+}
+
+impl Default for ConsumersConfig {
+    fn default() -> Self {
+        Self {
+            stream: "{{.ProjectName}}".to_string(),
+            subjects: BTreeMap::new(),
+            disabled: Vec::new(),
+        }
+    }
+}
+
+/// Outgoing email. SMTP goes to `SMTP_URL`, by default
+/// `smtp://127.0.0.1:1025`: a local Mailpit or MailHog, which keeps every
+/// message for its web UI instead of delivering it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    pub transport: EmailTransport,
+    /// Sender of every email, e.g. `My App <hello@myapp.dev>`.
+    pub from: String,
+    /// Recent emails kept for `/debug/emails`.
+    pub keep: usize,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            transport: EmailTransport::default(),
+            from: "{{.ProjectName}} <noreply@localhost>".to_string(),
+            keep: 50,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransport {
+    #[default]
+    Smtp,
+    /// Nothing is sent; emails only show up in `/debug/emails`.
+    Capture,
+}
+
+/// API keys, sent as `X-API-Key` and issued through `/admin/api-keys`. A
+/// request with a key is held to its tier's limits; one without goes
+/// through unless its path is under `required_for`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiKeysConfig {
+    /// Path prefixes that need a key, e.g. `/api/notes`.
+    pub required_for: Vec<String>,
+    /// Limits by tier name. Keys of a tier missing here are refused.
+    pub tiers: BTreeMap<String, ApiKeyTier>,
+}
+
+impl Default for ApiKeysConfig {
+    fn default() -> Self {
+        let tier = |daily_quota, requests_per_second, burst| ApiKeyTier {
+            daily_quota,
+            rate_limit: RateLimitConfig {
+                requests_per_second,
+                burst: Some(burst),
+                principals: BTreeMap::new(),
+            },
+        };
+        Self {
+            required_for: Vec::new(),
+            tiers: BTreeMap::from([
+                ("free".to_string(), tier(1_000, 2.0, 10)),
+                ("pro".to_string(), tier(100_000, 50.0, 100)),
+            ]),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyTier {
+    /// Requests per key per UTC day; `0` means no quota.
+    #[serde(default)]
+    pub daily_quota: u64,
+    /// Per key, on top of the per-client `rate_limit`.
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// Bearer JWTs, checked for the paths below and wherever a handler takes
+/// `RequireAuth`. HS256 tokens are signed with `JWT_SECRET`; RS256 ones
+/// with a key from `jwks_url`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Where the issuer publishes its signing keys, e.g.
+    /// `https://issuer.example/.well-known/jwks.json`. Unset, only HS256
+    /// tokens are accepted.
+    pub jwks_url: Option<String>,
+    /// Keys are fetched again after this long, or sooner for a token
+    /// signed with a key we haven't seen.
+    pub jwks_cache_secs: u64,
+    /// Required `iss`, if set.
+    pub issuer: Option<String>,
+    /// Required `aud`, if set.
+    pub audience: Option<String>,
+    /// Clock skew allowed when checking `exp` and `nbf`.
+    pub leeway_secs: u64,
+    /// Path prefixes that need a valid token, e.g. `/api/notes`.
+    pub required_for: Vec<String>,
+    /// Scope a token needs by path prefix, e.g. `{"/api/notes": "notes"}`.
+    /// These paths need a token too.
+    pub scopes: BTreeMap<String, String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwks_url: None,
+            jwks_cache_secs: 3600,
+            issuer: None,
+            audience: None,
+            leeway_secs: 60,
+            required_for: Vec::new(),
+            scopes: BTreeMap::new(),
+        }
+    }
+}
+
+/// Fixed API keys guarding the admin and debug routes until real auth
+/// exists, sent as `X-API-Key` or `Authorization: Bearer`. More can come
+/// from `STATIC_API_KEYS` (`name=key,...`). With none, nothing changes.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticApiKeysConfig {
+    /// Key by name; logs and `/debug/stats` show the name.
+    #[serde(serialize_with = "redacted")]
+    pub keys: BTreeMap<String, String>,
+    /// Path prefixes that need one of the keys.
+    pub protect: Vec<String>,
+}
+
+impl Default for StaticApiKeysConfig {
+    fn default() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            protect: vec!["/admin/".to_string(), "/debug/".to_string()],
+        }
+    }
+}
+
+/// HTTP Basic auth for the admin API and debug endpoints, on once a user is
+/// configured.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BasicAuthConfig {
+    /// bcrypt hash by user name, e.g. from `htpasswd -nbB user password`.
+    #[serde(serialize_with = "redacted")]
+    pub users: BTreeMap<String, String>,
+    /// Path prefixes that need a user.
+    pub protect: Vec<String>,
+    /// Wrong tries in a row before a client is locked out.
+    pub max_failures: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for BasicAuthConfig {
+    fn default() -> Self {
+        Self {
+            users: BTreeMap::new(),
+            protect: vec!["/admin/".to_string(), "/debug/".to_string()],
+            max_failures: 5,
+            lockout_secs: 300,
+        }
+    }
+}
+
+/// Failed logins, counted per client and per account over a sliding
+/// window. Past the limits, the client or account is locked out for a
+/// while; with a CAPTCHA provider set up, a solved CAPTCHA is asked for
+/// well before that.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BruteForceConfig {
+    pub enabled: bool,
+    /// Failures older than this are forgotten.
+    pub window_secs: u64,
+    /// Failures from one client within the window before it's locked out;
+    /// `0` never locks clients out.
+    pub max_per_client: u32,
+    /// Failures for one account, from anywhere, before it's locked out;
+    /// `0` never locks accounts out.
+    pub max_per_account: u32,
+    pub lockout_secs: u64,
+    pub captcha: CaptchaConfig,
+}
+
+impl Default for BruteForceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 900,
+            max_per_client: 20,
+            max_per_account: 10,
+            lockout_secs: 900,
+            captcha: CaptchaConfig::default(),
+        }
+    }
+}
+
+/// A CAPTCHA provider with a siteverify-style endpoint (hCaptcha, reCAPTCHA,
+/// Turnstile, or your own), which is posted `secret`, `response` and
+/// `remoteip` and answers `{"success": true}`. The secret comes from
+/// `CAPTCHA_SECRET`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptchaConfig {
+    /// e.g. `https://hcaptcha.com/siteverify`. Unset, no CAPTCHA is asked
+    /// for.
+    pub verify_url: Option<String>,
+    /// Failures, for the client or the account, after which the login needs
+    /// a solved CAPTCHA as well.
+    pub after_failures: u32,
+}
+
+impl Default for CaptchaConfig {
+    fn default() -> Self {
+        Self {
+            verify_url: None,
+            after_failures: 3,
+        }
+    }
+}
+
+/// HMAC-signed requests between services, for webhook-style routes. The
+/// sender puts `X-Signature: key=<name>, ts=<unix secs>, nonce=<random>,
+/// sig=<hex>`, with `sig` an HMAC-SHA256 under the shared secret of the
+/// timestamp, nonce, method, path with query and hex SHA-256 of the body,
+/// one per line. `signing::sign` makes one; the outbox sends one with each
+/// webhook that has a secret, named after the project.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignedRequestsConfig {
+    /// Shared secret by key name. `REQUEST_SIGNING_KEYS` (`name=secret,...`)
+    /// adds more.
+    #[serde(serialize_with = "redacted")]
+    pub keys: BTreeMap<String, String>,
+    /// Path prefixes that need a signature, once there are keys.
+    pub protect: Vec<String>,
+    /// How far a signature's timestamp may be from now, either way.
+    pub max_skew_secs: u64,
+}
+
+impl Default for SignedRequestsConfig {
+    fn default() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            protect: vec!["/hooks/".to_string()],
+            max_skew_secs: 300,
+        }
+    }
+}
+
+/// Expiring links that let whoever holds them make one kind of request
+/// (e.g. download an upload) without credentials, like S3 presigned URLs.
+/// They're signed with `URL_SIGNING_SECRET`, or a key made up at startup
+/// if that's unset.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignedUrlsConfig {
+    /// Path prefixes a signed link may be made for and stand in for
+    /// credentials on. The default covers uploading (`/api/upload`) and
+    /// downloads (`/api/uploads/:id`).
+    pub paths: Vec<String>,
+    /// Tie links to the address of the client they were handed to, unless
+    /// the request for one says otherwise. Behind a proxy, that's only the
+    /// client's own address once the proxy is in `proxy.trusted`; until
+    /// then every link is bound to the proxy.
+    pub bind_client: bool,
+}
+
+impl Default for SignedUrlsConfig {
+    fn default() -> Self {
+        Self {
+            paths: vec!["/api/upload".to_string()],
+            bind_client: false,
+        }
+    }
+}
+
+/// A second, HTTPS listener that asks callers for a client certificate,
+/// e.g. one made with `mkcert -client nsm-proxy`. A certificate that chains
+/// to `client_ca` signs the request in as the identity it maps to.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MtlsConfig {
+    pub enabled: bool,
+    /// Defaults to one above the HTTP port.
+    pub port: Option<u16>,
+    /// Server certificate and key; default `NSM_CERT_PATH` and
+    /// `NSM_KEY_PATH`.
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    /// CA bundle client certificates must chain to; defaults to mkcert's
+    /// `rootCA.pem`.
+    pub client_ca: Option<PathBuf>,
+    /// Take connections without a certificate too, leaving them to tokens.
+    pub optional: bool,
+    /// Identity by certificate name: `CN=<common name>`, `DNS:<name>`,
+    /// `URI:<uri>` or `email:<address>`. Certificates matching none go by
+    /// their common name, or else their first subject alternative name.
+    pub identities: BTreeMap<String, String>,
+}
+
+/// Who did what on the admin and debug endpoints, and with the console:
+/// every hit, let in or not, appended to a file of its own.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivilegedLogConfig {
+    pub enabled: bool,
+    /// Opened for appending, never truncated or rotated by the app.
+    pub path: PathBuf,
+    /// Path prefixes whose requests are logged.
+    pub paths: Vec<String>,
+}
+
+impl Default for PrivilegedLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: PathBuf::from(".nsm-privileged.ndjson"),
+            paths: vec![
+                "/admin/".to_string(),
+                "/debug/".to_string(),
+                "/__nsm/chaos".to_string(),
+            ],
+        }
+    }
+}
+
+/// Headers added to every response. The Content-Security-Policy allows our
+/// own origin and, for inline scripts and styles, a nonce fresh for each
+/// request; routes serving third-party assets add their sources to it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    pub enabled: bool,
+    /// Send the policy as `Content-Security-Policy-Report-Only`, to see what
+    /// it would block without blocking it.
+    pub report_only: bool,
+    /// Sources added to the policy by directive, e.g.
+    /// `{"img-src": ["https://images.example"]}`.
+    pub csp: BTreeMap<String, Vec<String>>,
+    /// Where browsers report violations.
+    pub report_uri: Option<String>,
+    /// Empty leaves `Referrer-Policy` out.
+    pub referrer_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            report_only: false,
+            csp: BTreeMap::new(),
+            report_uri: None,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+        }
+    }
+}
+
+/// Who may do what, for routes guarded with `rbac::require_permission`.
+/// Permissions are strings like `notes:delete`; a role grants a list of
+/// them, where `*` is everything and `notes:*` everything under `notes:`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RbacConfig {
+    /// Permissions by role.
+    pub roles: BTreeMap<String, Vec<String>>,
+    /// Roles by principal, named as in the audit log: `user:<name>`,
+    /// `jwt:<sub>`, `cert:<identity>`, `basic:<user>`, `static-key:<name>` or
+    /// `api-key:<id>`.
+    pub principals: BTreeMap<String, Vec<String>>,
+    /// Roles everyone signed in has.
+    pub authenticated_roles: Vec<String>,
+    /// Roles of requests from nobody in particular.
+    pub anonymous_roles: Vec<String>,
+    /// The JWT claim listing a token's roles, as an array or space-separated.
+    pub roles_claim: String,
+}
+
+impl Default for RbacConfig {
+    fn default() -> Self {
+        let role = |permissions: &[&str]| permissions.iter().map(|p| p.to_string()).collect();
+        Self {
+            roles: BTreeMap::from([
+                ("admin".to_string(), role(&["*"])),
+                ("editor".to_string(), role(&["notes:read", "notes:write"])),
+                ("viewer".to_string(), role(&["notes:read"])),
+            ]),
+            principals: BTreeMap::new(),
+            authenticated_roles: Vec::new(),
+            anonymous_roles: Vec::new(),
+            roles_claim: "roles".to_string(),
+        }
+    }
+}
+
+/// A stand-in OpenID Connect provider at `/__nsm/oidc` in debug builds, so
+/// frontends can go through a real sign-in without a cloud IdP. Any client
+/// id and redirect URI is accepted; its access tokens pass `RequireAuth`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevOidcConfig {
+    pub enabled: bool,
+    /// Who can sign in, offered on the sign-in page.
+    pub users: Vec<DevOidcUser>,
+    /// Lifetime of issued tokens.
+    pub token_ttl_secs: u64,
+}
+
+impl Default for DevOidcConfig {
+    fn default() -> Self {
+        let user = |sub: &str, name: &str, roles: &[&str]| DevOidcUser {
+            sub: sub.to_string(),
+            name: name.to_string(),
+            email: format!("{}@example.com", sub),
+            claims: serde_json::Map::from_iter([("roles".to_string(), roles.into())]),
+        };
+        Self {
+            enabled: true,
+            users: vec![
+                user("alice", "Alice Example", &["admin"]),
+                user("bob", "Bob Example", &[]),
+            ],
+            token_ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DevOidcUser {
+    pub sub: String,
+    pub name: String,
+    pub email: String,
+    /// More claims for the user's tokens, e.g. `{"roles": ["admin"]}`.
+    #[serde(default)]
+    pub claims: serde_json::Map<String, serde_json::Value>,
+}
+
+/// One app serving several tenants, each on its own subdomain of the NSM
+/// domain, e.g. `acme.myapp.test`. Notes and `/api/kv` keys are kept apart
+/// by tenant. Off, everything belongs to the `default` tenant.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenancyConfig {
+    pub enabled: bool,
+    /// Tenants that exist; requests for any other get a 404. Empty allows
+    /// any subdomain.
+    pub tenants: Vec<String>,
+}
+
+/// An endpoint that gets outbox events POSTed to it as JSON.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event kinds to send, e.g. `note.created`; `note.*` matches a prefix.
+    /// Empty sends everything.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Signs each body with HMAC-SHA256, sent as
+    /// `X-Outbox-Signature: sha256=<hex>`, and the request as a whole as in
+    /// `signed_requests`, sent as `X-Signature`.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// Content for `/favicon.ico`, `/robots.txt`, `/.well-known/*` and
+/// `/security.txt`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// Icon file to serve. Defaults to `static/favicon.ico` when it exists,
+    /// otherwise `/favicon.ico` answers `204 No Content`.
+    pub favicon: Option<PathBuf>,
+    /// Body of `/robots.txt`. Dev servers ask crawlers to stay away.
+    pub robots_txt: String,
+    /// Documents served as `/.well-known/<name>`. `security.txt` and
+    /// `change-password` come from `security_txt` instead.
+    pub well_known: BTreeMap<String, WellKnownDocument>,
+    pub security_txt: SecurityTxtConfig,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            favicon: None,
+            robots_txt: "User-agent: *\nDisallow: /\n".to_string(),
+            well_known: BTreeMap::new(),
+            security_txt: SecurityTxtConfig::default(),
+        }
+    }
+}
+
+/// `/.well-known/security.txt` (RFC 9116), telling researchers where to
+/// report vulnerabilities, and `/.well-known/change-password`, which
+/// password managers send users to. Each list is one field line per entry;
+/// empty lists are left out.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityTxtConfig {
+    pub enabled: bool,
+    /// `mailto:`, `https:` or `tel:` URIs, most preferred first; without
+    /// one there's no file. Replace the placeholder before going live.
+    pub contact: Vec<String>,
+    /// When the file stops being current. Unset, it's always a year from
+    /// the request, which suits a scaffold but not a published policy.
+    pub expires: Option<chrono::DateTime<chrono::Utc>>,
+    /// Where the file is published, e.g.
+    /// `https://myapp.dev/.well-known/security.txt`.
+    pub canonical: Vec<String>,
+    /// Links to a PGP key for encrypted reports.
+    pub encryption: Vec<String>,
+    /// The disclosure policy, and the people thanked under it.
+    pub policy: Vec<String>,
+    pub acknowledgments: Vec<String>,
+    /// Language tags reports may be written in, e.g. `en`.
+    pub preferred_languages: Vec<String>,
+    pub hiring: Vec<String>,
+    /// Where `/.well-known/change-password` redirects; unset, it's a `404`
+    /// like any other unknown well-known name.
+    pub change_password: Option<String>,
+}
+
+impl Default for SecurityTxtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            contact: vec!["mailto:security@example.com".to_string()],
+            expires: None,
+            canonical: Vec::new(),
+            encryption: Vec::new(),
+            policy: Vec::new(),
+            acknowledgments: Vec::new(),
+            preferred_languages: vec!["en".to_string()],
+            hiring: Vec::new(),
+            change_password: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WellKnownDocument {
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    pub body: String,
+}
+
+fn default_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+/// Behaviour of `/static`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticFilesConfig {
+    /// Render an HTML index for directories that have no `index.html`.
+    pub directory_listing: bool,
+    /// `Cache-Control` rules, first match wins. Unmatched files get none.
+    pub cache_control: Vec<CachePolicy>,
+    /// Hash files at startup and serve them under fingerprinted names too.
+    pub fingerprint: bool,
+}
+
+impl Default for StaticFilesConfig {
+    fn default() -> Self {
+        Self {
+            directory_listing: false,
+            fingerprint: true,
+            cache_control: vec![CachePolicy {
+                pattern: "**/*.html".to_string(),
+                matcher: glob("**/*.html").expect("valid default pattern"),
+                value: HeaderValue::from_static("no-cache"),
+            }],
+        }
+    }
+}
+
+/// `{"pattern": "assets/**", "value": "public, max-age=31536000, immutable"}`.
+/// Patterns are globs relative to `static/`; `*` stops at `/`, `**` doesn't.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "RawCachePolicy")]
+pub struct CachePolicy {
+    pattern: String,
+    #[serde(skip)]
+    matcher: GlobMatcher,
+    #[serde(serialize_with = "header_str")]
+    pub value: HeaderValue,
+}
+
+impl CachePolicy {
+    pub fn matches(&self, path: &str) -> bool {
+        self.matcher.is_match(path)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCachePolicy {
+    pattern: String,
+    value: String,
+}
+
+impl TryFrom<RawCachePolicy> for CachePolicy {
+    type Error = String;
+
+    fn try_from(raw: RawCachePolicy) -> Result<Self, Self::Error> {
+        let matcher = glob(&raw.pattern).map_err(|e| e.to_string())?;
+        let value = HeaderValue::from_str(&raw.value)
+            .map_err(|_| format!("invalid Cache-Control value {:?}", raw.value))?;
+        Ok(Self {
+            pattern: raw.pattern,
+            matcher,
+            value,
+        })
+    }
+}
+
+/// Fault injection for exercising clients' retry and loading states.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Initial state; flip it at runtime with `PUT /__nsm/chaos`.
+    pub enabled: bool,
+    /// Per-route faults, first match wins. Unmatched requests are untouched.
+    pub rules: Vec<ChaosRule>,
+}
+
+/// `{"pattern": "/api/**", "latency": 0.5, "latency_ms": 2000, "error": 0.1}`.
+/// Patterns are globs over the request path. Each field other than
+/// `latency_ms` and `status` is a probability between 0 and 1.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "RawChaosRule")]
+pub struct ChaosRule {
+    pattern: String,
+    #[serde(skip)]
+    matcher: GlobMatcher,
+    /// Chance of delaying the request by `latency_ms`.
+    pub latency: f64,
+    pub latency_ms: u64,
+    /// Chance of answering `status` instead of running the handler.
+    pub error: f64,
+    pub status: u16,
+    /// Chance of closing the connection without a response.
+    pub drop: f64,
+    /// Chance of cutting the response body off halfway.
+    pub truncate: f64,
+}
+
+impl ChaosRule {
+    pub fn matches(&self, path: &str) -> bool {
+        self.matcher.is_match(path)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawChaosRule {
+    pattern: String,
+    #[serde(default)]
+    latency: f64,
+    #[serde(default = "default_latency_ms")]
+    latency_ms: u64,
+    #[serde(default)]
+    error: f64,
+    #[serde(default = "default_chaos_status")]
+    status: u16,
+    #[serde(default)]
+    drop: f64,
+    #[serde(default)]
+    truncate: f64,
+}
+
+fn default_latency_ms() -> u64 {
+    1000
+}
+
+fn default_chaos_status() -> u16 {
+    503
+}
+
+impl TryFrom<RawChaosRule> for ChaosRule {
+    type Error = String;
+
+    fn try_from(raw: RawChaosRule) -> Result<Self, Self::Error> {
+        let matcher = glob(&raw.pattern).map_err(|e| e.to_string())?;
+        let probabilities = [
+            ("latency", raw.latency),
+            ("error", raw.error),
+            ("drop", raw.drop),
+            ("truncate", raw.truncate),
+        ];
+        for (name, p) in probabilities {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("chaos {} must be between 0 and 1, got {}", name, p));
+            }
+        }
+        if !(500..=599).contains(&raw.status) {
+            return Err(format!("chaos status must be a 5xx, got {}", raw.status));
+        }
+        Ok(Self {
+            pattern: raw.pattern,
+            matcher,
+            latency: raw.latency,
+            latency_ms: raw.latency_ms,
+            error: raw.error,
+            status: raw.status,
+            drop: raw.drop,
+            truncate: raw.truncate,
+        })
+    }
+}
+
+/// An upstream API whose responses are recorded to a cassette file and
+/// replayed from it, so development doesn't need the network.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// e.g. `https://api.github.com`; the request path is appended.
+    pub base_url: String,
+    #[serde(default)]
+    pub mode: UpstreamMode,
+    /// Defaults to `cassettes/<name>.json`.
+    #[serde(default)]
+    pub cassette: Option<PathBuf>,
+    /// What makes two requests the same interaction.
+    #[serde(default, rename = "match")]
+    pub matching: MatchRules,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamMode {
+    /// Replay when recorded, otherwise fetch and record.
+    #[default]
+    Auto,
+    /// Always fetch, overwriting what was recorded.
+    Record,
+    /// Never touch the network; unrecorded requests fail with 502.
+    Replay,
+    /// Plain proxy, no cassette.
+    Passthrough,
+}
+
+impl std::str::FromStr for UpstreamMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_ascii_lowercase()))
+            .map_err(|_| format!("unknown upstream mode {:?}", s))
+    }
+}
+
+/// Method and path always have to match; the rest is opt-in or opt-out.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchRules {
+    pub query: bool,
+    pub body: bool,
+    /// Request headers that have to match too, e.g. `accept`.
+    pub headers: Vec<String>,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            query: true,
+            body: false,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Keeps secrets out of `config` output and the dashboard.
+fn redacted<S: Serializer>(
+    values: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(values.keys().map(|key| (key, "<redacted>")))
+}
+
+fn header_str<S: Serializer>(value: &HeaderValue, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(value.to_str().unwrap_or_default())
+}
+
+fn glob(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    Ok(GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Everything the server runs with, after env overrides and defaults. Shown
+/// by the `config` subcommand and the dev dashboard.
+#[derive(Serialize)]
+pub struct EffectiveConfig<'a> {
+    profile: &'a str,
+    domain: String,
+    nsm_enabled: bool,
+    listen: NSMConfig,
+    config_file: String,
+    #[serde(flatten)]
+    app: &'a AppConfig,
+}
+
+impl<'a> EffectiveConfig<'a> {
+    pub fn new(profile: &'a str, app: &'a AppConfig) -> Self {
+        Self {
+            profile,
+            domain: crate::domain(),
+            nsm_enabled: crate::nsm_enabled(),
+            listen: crate::read_nsm_config().ok().flatten().unwrap_or_default(),
+            config_file: AppConfig::path(),
+            app,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads `APP_CONFIG` (or `config.json`). A missing file yields the
+    /// defaults; a malformed one is a startup error.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse config file {}", path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read config file {}", path)),
+        }
+    }
+
+    /// Where `load` looks for the config file.
+    pub fn path() -> String {
+        std::env::var("APP_CONFIG").unwrap_or_else(|_| CONFIG_FILE.to_string())
+    }
+}
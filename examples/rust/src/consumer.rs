@@ -0,0 +1,306 @@
+use anyhow::anyhow;
+use async_nats::{
+    connection::State as ConnectionState,
+    jetstream::{
+        self,
+        consumer::{pull, PullConsumer},
+        stream, AckKind,
+    },
+    Client, ConnectOptions,
+};
+use futures::{future::BoxFuture, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    health::HealthCheck,
+    jobs::{DemoJob, Job},
+    state::AppState,
+};
+
+/// Used when `NATS_URL` is unset: a local `nats-server -js`.
+pub const DEFAULT_NATS_URL: &str = "nats://127.0.0.1:4222";
+
+/// Wait before re-subscribing after losing NATS, doubling up to the maximum
+/// while it stays away.
+const RETRY: Duration = Duration::from_secs(2);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+/// Wait before a message we couldn't queue is delivered again.
+const REDELIVER_AFTER: Duration = Duration::from_secs(5);
+
+pub fn nats_url() -> String {
+    std::env::var("NATS_URL").unwrap_or_else(|_| DEFAULT_NATS_URL.to_string())
+}
+
+/// A job that arrives as a JSON message on a NATS subject. Each message is
+/// queued as a job of the same type, so it gets the queue's retries and
+/// backoff, and is acked once queued. Add the type to both [`registry`] and
+/// `jobs::registry()`.
+pub trait Message: Job {
+    /// Where the messages come from unless `consumers.subjects` says
+    /// otherwise.
+    const SUBJECT: &'static str;
+}
+
+impl Message for DemoJob {
+    const SUBJECT: &'static str = "{{.ProjectName}}.jobs.demo";
+}
+
+/// Why a message wasn't queued.
+enum Rejected {
+    /// Doesn't deserialize; redelivering won't help.
+    Invalid(serde_json::Error),
+    /// The queue refused it, e.g. with the database away.
+    Failed(anyhow::Error),
+}
+
+type EnqueueFn = for<'a> fn(&'a AppState, &'a [u8]) -> BoxFuture<'a, Result<i64, Rejected>>;
+
+fn enqueue<'a, M: Message>(
+    state: &'a AppState,
+    payload: &'a [u8],
+) -> BoxFuture<'a, Result<i64, Rejected>> {
+    Box::pin(async move {
+        let message: M = serde_json::from_slice(payload).map_err(Rejected::Invalid)?;
+        state
+            .jobs()
+            .enqueue(message)
+            .await
+            .map_err(Rejected::Failed)
+    })
+}
+
+struct Subscription {
+    kind: &'static str,
+    subject: &'static str,
+    enqueue: EnqueueFn,
+}
+
+/// What a consumer has seen since startup.
+#[derive(Serialize, Clone, Default)]
+pub struct ConsumerStats {
+    subject: String,
+    received: u64,
+    queued: u64,
+    /// Dropped for not deserializing.
+    invalid: u64,
+    /// Messages on the subject not yet delivered to us, as of the latest
+    /// delivery.
+    lag: u64,
+}
+
+/// The message types consumed, and how each consumer is doing.
+#[derive(Default)]
+pub struct Consumers {
+    subscriptions: Vec<Subscription>,
+    client: OnceLock<Client>,
+    stats: Mutex<BTreeMap<&'static str, ConsumerStats>>,
+}
+
+impl Consumers {
+    pub fn register<M: Message>(mut self) -> Self {
+        self.subscriptions.push(Subscription {
+            kind: M::KIND,
+            subject: M::SUBJECT,
+            enqueue: enqueue::<M>,
+        });
+        self
+    }
+
+    /// By job kind, for `/debug/stats`.
+    pub fn stats(&self) -> BTreeMap<&'static str, ConsumerStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    fn update(&self, kind: &'static str, f: impl FnOnce(&mut ConsumerStats)) {
+        f(self.stats.lock().unwrap().entry(kind).or_default());
+    }
+}
+
+/// Every message type consumed from NATS.
+pub fn registry() -> Consumers {
+    Consumers::default().register::<DemoJob>()
+}
+
+/// Connects to NATS, adds it to `/api/health` and starts a durable
+/// consumer per message type, except those in `consumers.disabled`. NATS
+/// being down doesn't hold up startup; consumers keep retrying until it's
+/// back.
+pub async fn start(state: &AppState) -> anyhow::Result<()> {
+    let config = &state.config().consumers;
+    let consumers = state.consumers();
+    let mut subjects = Vec::new();
+    for (index, subscription) in consumers.subscriptions.iter().enumerate() {
+        if config.disabled.iter().any(|kind| kind == subscription.kind) {
+            continue;
+        }
+        let subject = config
+            .subjects
+            .get(subscription.kind)
+            .map_or(subscription.subject, String::as_str);
+        subjects.push((index, subject.to_string()));
+    }
+    for kind in config.subjects.keys() {
+        if !consumers.subscriptions.iter().any(|s| s.kind == kind) {
+            warn!("consumers.subjects: no message type of kind {:?}", kind);
+        }
+    }
+    if subjects.is_empty() {
+        return Ok(());
+    }
+
+    let url = nats_url();
+    let client = ConnectOptions::new()
+        .name("{{.ProjectName}}")
+        .retry_on_initial_connect()
+        .connect(url.as_str())
+        .await?;
+    info!(
+        "📬 NATS: {} ({} subjects on stream {})",
+        url,
+        subjects.len(),
+        config.stream
+    );
+    let _ = consumers.client.set(client.clone());
+    state.health().register(NatsCheck(state.clone()));
+
+    let stream = stream::Config {
+        name: config.stream.clone(),
+        subjects: subjects
+            .iter()
+            .map(|(_, subject)| subject.clone())
+            .collect(),
+        ..Default::default()
+    };
+    for (index, subject) in subjects {
+        let kind = consumers.subscriptions[index].kind;
+        consumers.update(kind, |stats| stats.subject = subject.clone());
+        let (state, client, stream) = (state.clone(), client.clone(), stream.clone());
+        tokio::spawn(async move {
+            let subscription = &state.consumers().subscriptions[index];
+            let shutdown = state.shutdown_token();
+            let mut retry = RETRY;
+            loop {
+                let started = Instant::now();
+                tokio::select! {
+                    result = consume(&state, &client, &stream, subscription, &subject) => {
+                        let Err(e) = result;
+                        // A consumer that ran for a while had reconnected;
+                        // start the backoff over.
+                        if started.elapsed() > MAX_RETRY {
+                            retry = RETRY;
+                        }
+                        warn!("NATS consumer of {} down, retrying in {:?}: {:#}", subject, retry, e);
+                    }
+                    _ = shutdown.cancelled() => return,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(retry) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+                retry = (retry * 2).min(MAX_RETRY);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Queues each message on `subject` as a job, acking it once queued, until
+/// the connection fails. The durable consumer is named after the job kind,
+/// so instances of the app share its messages rather than each getting
+/// every one.
+async fn consume(
+    state: &AppState,
+    client: &Client,
+    stream: &stream::Config,
+    subscription: &Subscription,
+    subject: &str,
+) -> anyhow::Result<Infallible> {
+    let consumers = state.consumers();
+    let durable = subscription.kind.replace(
+        |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_',
+        "_",
+    );
+    let consumer: PullConsumer = jetstream::new(client.clone())
+        .get_or_create_stream(stream.clone())
+        .await?
+        .get_or_create_consumer(
+            &durable,
+            pull::Config {
+                durable_name: Some(durable.clone()),
+                filter_subject: subject.to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let pending = consumer.cached_info().num_pending;
+    consumers.update(subscription.kind, |stats| stats.lag = pending);
+    let mut messages = consumer.messages().await?;
+    debug!("NATS consumer {} on {} started", durable, subject);
+
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let pending = message.info().map(|info| info.pending).unwrap_or(0);
+        let ack = match (subscription.enqueue)(state, &message.payload).await {
+            Ok(id) => {
+                debug!("📬 {} message queued as job #{}", subject, id);
+                consumers.update(subscription.kind, |stats| stats.queued += 1);
+                AckKind::Ack
+            }
+            Err(Rejected::Invalid(e)) => {
+                warn!("Dropping malformed message on {}: {}", subject, e);
+                consumers.update(subscription.kind, |stats| stats.invalid += 1);
+                AckKind::Term
+            }
+            Err(Rejected::Failed(e)) => {
+                warn!(
+                    "Failed to queue a message on {}, will redeliver: {:#}",
+                    subject, e
+                );
+                AckKind::Nak(Some(REDELIVER_AFTER))
+            }
+        };
+        consumers.update(subscription.kind, |stats| {
+            stats.received += 1;
+            stats.lag = pending;
+        });
+        message
+            .ack_with(ack)
+            .await
+            .map_err(|e| anyhow!("ack failed: {}", e))?;
+    }
+    Err(anyhow!("message stream ended"))
+}
+
+struct NatsCheck(AppState);
+
+impl HealthCheck for NatsCheck {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let Some(client) = self.0.consumers().client.get() else {
+                return Err("not connected yet".to_string());
+            };
+            match client.connection_state() {
+                ConnectionState::Connected => Ok(()),
+                state => Err(format!("{}", state)),
+            }
+        })
+    }
+
+    /// Messages wait in the stream while we're away, so losing NATS delays
+    /// them rather than taking the service down.
+    fn critical(&self) -> bool {
+        false
+    }
+}
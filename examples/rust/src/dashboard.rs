@@ -0,0 +1,62 @@
+use axum::{extract::State, response::Html};
+use serde::Serialize;
+
+use crate::{
+    build_info::BUILD_INFO,
+    capture::CapturedRequest,
+    config::EffectiveConfig,
+    error::{AppError, AppResult},
+    health::{self, HealthResponse},
+    preflight::{self, Check},
+    routes::RouteInfo,
+    state::AppState,
+    NSMConfig, PROJECT_NAME,
+};
+
+/// Context for `templates/dashboard.html`.
+#[derive(Serialize)]
+struct DashboardContext<'a> {
+    project_name: &'a str,
+    version: &'a str,
+    nsm: NsmStatus,
+    health: HealthResponse,
+    requests: Vec<CapturedRequest>,
+    routes: &'a [RouteInfo],
+    /// Pretty-printed effective config.
+    config: String,
+}
+
+/// How NSM sees us: the domain and port lease it gave us, and whether its
+/// proxy in front of us is up.
+#[derive(Serialize)]
+struct NsmStatus {
+    enabled: bool,
+    domain: String,
+    lease: Option<NSMConfig>,
+    proxy: Check,
+}
+
+/// `/__nsm/dashboard` (debug builds): config, routes, recent requests,
+/// health and NSM status on one page.
+pub async fn dashboard_handler(State(state): State<AppState>) -> AppResult<Html<String>> {
+    let ((_, health), proxy) =
+        tokio::join!(health::health_report(&state), preflight::check_proxy());
+    let config = EffectiveConfig::new(state.profile(), state.config());
+
+    let context = DashboardContext {
+        project_name: PROJECT_NAME,
+        version: BUILD_INFO.version,
+        nsm: NsmStatus {
+            enabled: crate::nsm_enabled(),
+            domain: crate::domain(),
+            lease: crate::read_nsm_config().ok().flatten(),
+            proxy,
+        },
+        health,
+        requests: state.capture().recent(),
+        routes: state.routes(),
+        config: serde_json::to_string_pretty(&config).map_err(AppError::internal)?,
+    };
+
+    Ok(Html(state.templates().render("dashboard.html", &context)?))
+}
@@ -0,0 +1,533 @@
+use anyhow::Context;
+use clap::Subcommand;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use sqlx::{
+    migrate::{Migrate, Migration, Migrator},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    SqlitePool,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{health::HealthCheck, state::AppState};
+
+/// Used when `DATABASE_URL` is unset: a file next to the project, created on
+/// first run.
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://{{.ProjectName}}.db";
+
+const MAX_CONNECTIONS: u32 = 5;
+
+/// Wait between connection attempts at startup, doubling up to the maximum.
+const CONNECT_RETRY: Duration = Duration::from_millis(250);
+const MAX_CONNECT_RETRY: Duration = Duration::from_secs(5);
+
+/// Where `migrate new` writes, relative to the working directory.
+const MIGRATIONS_DIR: &str = "migrations";
+
+/// Where `db snapshot` keeps snapshots, relative to the working directory.
+const SNAPSHOTS_DIR: &str = "db-snapshots";
+
+/// Everything under `migrations/`, compiled into the binary.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub fn database_url() -> String {
+    std::env::var("DATABASE_URL").unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string())
+}
+
+/// The pool opens connections on first use, so building the state doesn't
+/// wait on the database; [`init`] is what touches it first.
+pub fn connect() -> anyhow::Result<SqlitePool> {
+    let url = database_url();
+    let options = SqliteConnectOptions::from_str(&url)
+        .with_context(|| format!("invalid DATABASE_URL {}", url))?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+    Ok(SqlitePoolOptions::new()
+        .max_connections(MAX_CONNECTIONS)
+        .connect_lazy_with(options))
+}
+
+/// Waits for the database (up to `database.wait_secs`), applies pending
+/// migrations, and adds the database to `/api/health` and `/readyz`.
+pub async fn init(state: &AppState) -> anyhow::Result<()> {
+    let url = database_url();
+    let config = &state.config().database;
+    wait_for(state.db(), Duration::from_secs(config.wait_secs))
+        .await
+        .with_context(|| format!("database {} isn't accepting connections", url))?;
+    migrate_quietly(state.db()).await?;
+    info!(
+        "🗄️  Database: {} ({} migrations)",
+        url,
+        MIGRATOR.iter().count()
+    );
+    if config.seed_on_start {
+        crate::seeds::on_start(state.db()).await?;
+    }
+    state.health().register(DatabaseCheck {
+        pool: state.db().clone(),
+        timeout: Duration::from_millis(config.ping_timeout_ms),
+    });
+    spawn_probe(state);
+    Ok(())
+}
+
+/// Applies pending migrations without reporting them, for startup and `seed`.
+pub async fn migrate_quietly(pool: &SqlitePool) -> anyhow::Result<()> {
+    MIGRATOR
+        .run(pool)
+        .await
+        .with_context(|| format!("failed to migrate {}", database_url()))
+}
+
+/// Pings until the database answers or `deadline` has passed.
+async fn wait_for(pool: &SqlitePool, deadline: Duration) -> sqlx::Result<()> {
+    let started = Instant::now();
+    let mut retry = CONNECT_RETRY;
+    loop {
+        match sqlx::query("SELECT 1").execute(pool).await {
+            Ok(_) => return Ok(()),
+            Err(e) if started.elapsed() + retry > deadline => return Err(e),
+            Err(e) => {
+                warn!("⏳ Database not ready, retrying in {:?}: {}", retry, e);
+                tokio::time::sleep(retry).await;
+                retry = (retry * 2).min(MAX_CONNECT_RETRY);
+            }
+        }
+    }
+}
+
+/// What the pool probe has seen since startup.
+#[derive(Default)]
+pub struct PoolMonitor {
+    probes: AtomicU64,
+    total_wait_us: AtomicU64,
+    last_wait_us: AtomicU64,
+    max_wait_us: AtomicU64,
+    slow: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct PoolStats {
+    /// Connections open, idle or not.
+    size: u32,
+    idle: usize,
+    max_connections: u32,
+    probes: u64,
+    last_wait_ms: f64,
+    avg_wait_ms: f64,
+    max_wait_ms: f64,
+    /// Probes that waited longer than `database.slow_acquire_ms`.
+    slow_acquires: u64,
+    /// Probes that gave up before a connection came free.
+    timeouts: u64,
+}
+
+impl PoolMonitor {
+    pub fn stats(&self, pool: &SqlitePool) -> PoolStats {
+        let ms = |us: &AtomicU64| us.load(Ordering::Relaxed) as f64 / 1000.0;
+        let probes = self.probes.load(Ordering::Relaxed);
+        PoolStats {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max_connections: pool.options().get_max_connections(),
+            probes,
+            last_wait_ms: ms(&self.last_wait_us),
+            avg_wait_ms: ms(&self.total_wait_us) / probes.max(1) as f64,
+            max_wait_ms: ms(&self.max_wait_us),
+            slow_acquires: self.slow.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, waited: Duration) {
+        let us = waited.as_micros() as u64;
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_us.fetch_add(us, Ordering::Relaxed);
+        self.last_wait_us.store(us, Ordering::Relaxed);
+        self.max_wait_us.fetch_max(us, Ordering::Relaxed);
+    }
+}
+
+/// Starts a task that takes a connection every `database.probe_interval_ms`
+/// and times how long that took. While any connection is idle it's
+/// immediate, so a slow probe means the whole pool is busy: usually a long
+/// transaction, or a handler holding a connection across slow work.
+fn spawn_probe(state: &AppState) {
+    let config = &state.config().database;
+    if config.slow_acquire_ms == 0 {
+        return;
+    }
+    let threshold = Duration::from_millis(config.slow_acquire_ms);
+    let interval = Duration::from_millis(config.probe_interval_ms);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let shutdown = state.shutdown_token();
+        let pool = state.db();
+        let monitor = state.pool_monitor();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let in_use = pool.size() - pool.num_idle() as u32;
+            let started = Instant::now();
+            let acquired = tokio::select! {
+                acquired = pool.acquire() => acquired,
+                _ = shutdown.cancelled() => return,
+            };
+            let waited = started.elapsed();
+            monitor.record(waited);
+            match acquired {
+                Ok(conn) => drop(conn),
+                Err(sqlx::Error::PoolTimedOut) => {
+                    monitor.timeouts.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "🐢 Database pool exhausted: no connection came free in {:?} \
+                         ({} of {} in use). Requests that need one are hanging.",
+                        waited, in_use, MAX_CONNECTIONS
+                    );
+                    continue;
+                }
+                // The database itself is down; `/readyz` reports that.
+                Err(_) => continue,
+            }
+            if waited > threshold {
+                monitor.slow.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "🐢 Database pool saturated: waited {} ms for a connection ({} of {} \
+                     in use). Look for long transactions or handlers holding a connection \
+                     across slow work; see /debug/stats.",
+                    waited.as_millis(),
+                    in_use,
+                    MAX_CONNECTIONS
+                );
+            }
+        }
+    });
+}
+
+/// Critical, and gates `/readyz`, since most of what the app stores lives
+/// there.
+struct DatabaseCheck {
+    pool: SqlitePool,
+    timeout: Duration,
+}
+
+impl HealthCheck for DatabaseCheck {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            sqlx::query("SELECT 1")
+                .execute(&self.pool)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn readiness(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply pending migrations
+    Up,
+    /// Revert the latest applied migration, or every one after `--to`
+    Down {
+        /// Keep this version and everything before it
+        #[arg(long)]
+        to: Option<i64>,
+    },
+    /// List the embedded migrations and whether each is applied
+    Status,
+    /// Create a reversible migration pair in migrations/
+    New { name: String },
+}
+
+/// `migrate`: the same embedded migrations the server runs at startup.
+pub async fn migrate(command: MigrateCommand) -> anyhow::Result<()> {
+    if let MigrateCommand::New { name } = command {
+        return new_migration(&name);
+    }
+
+    let url = database_url();
+    let pool = connect()?;
+    let applied = applied_versions(&pool)
+        .await
+        .with_context(|| format!("failed to read migrations from {}", url))?;
+    let reversible = |version| {
+        MIGRATOR
+            .iter()
+            .any(|m| m.version == version && m.migration_type.is_down_migration())
+    };
+
+    match command {
+        MigrateCommand::Up => {
+            MIGRATOR.run(&pool).await?;
+            let pending: Vec<_> = ups()
+                .filter(|m| !applied.contains_key(&m.version))
+                .collect();
+            for migration in &pending {
+                println!("Applied {}", describe(migration));
+            }
+            if pending.is_empty() {
+                println!("{} is up to date", url);
+            }
+        }
+        MigrateCommand::Down { to } => {
+            // Without `--to`, everything after the second-latest: the latest.
+            let target = to.unwrap_or_else(|| applied.keys().rev().nth(1).copied().unwrap_or(0));
+            let reverted: Vec<_> = ups()
+                .rev()
+                .filter(|m| m.version > target && applied.contains_key(&m.version))
+                .collect();
+            if reverted.is_empty() {
+                println!("Nothing to revert in {}", url);
+                return Ok(());
+            }
+            if let Some(stuck) = reverted.iter().find(|m| !reversible(m.version)) {
+                anyhow::bail!(
+                    "{} has no .down.sql, so it can't be reverted",
+                    describe(stuck)
+                );
+            }
+            MIGRATOR.undo(&pool, target).await?;
+            for migration in &reverted {
+                println!("Reverted {}", describe(migration));
+            }
+        }
+        MigrateCommand::Status => {
+            println!("{}", url);
+            for migration in ups() {
+                let status = match applied.get(&migration.version) {
+                    None => "pending",
+                    Some(checksum) if *checksum != *migration.checksum => "changed",
+                    Some(_) => "applied",
+                };
+                let note = if reversible(migration.version) {
+                    ""
+                } else {
+                    "  (irreversible)"
+                };
+                println!("  {:8} {}{}", status, describe(migration), note);
+            }
+            for version in applied.keys().filter(|v| !ups().any(|m| m.version == **v)) {
+                println!("  {:8} {:04} (not in this build)", "missing", version);
+            }
+        }
+        MigrateCommand::New { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Applied versions and their checksums.
+async fn applied_versions(pool: &SqlitePool) -> anyhow::Result<BTreeMap<i64, Vec<u8>>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum.into_owned()))
+        .collect())
+}
+
+/// The forward half of each migration, oldest first.
+fn ups() -> impl DoubleEndedIterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+}
+
+fn describe(migration: &Migration) -> String {
+    format!("{:04} {}", migration.version, migration.description)
+}
+
+fn new_migration(name: &str) -> anyhow::Result<()> {
+    let dir = Path::new(MIGRATIONS_DIR);
+    if !dir.is_dir() {
+        anyhow::bail!(
+            "no {}/ here; run this from the project directory",
+            MIGRATIONS_DIR
+        );
+    }
+    let slug = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let slug = slug.trim_matches('_');
+    if slug.is_empty() {
+        anyhow::bail!("migration name needs a letter or digit");
+    }
+
+    let on_disk = std::fs::read_dir(dir)?.filter_map(|entry| {
+        let name = entry.ok()?.file_name();
+        let name = name.to_str()?;
+        name[..name.find('_')?].parse::<i64>().ok()
+    });
+    let version = on_disk
+        .chain(MIGRATOR.iter().map(|m| m.version))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    for (direction, sql) in [
+        ("up", format!("-- {}\n", name.trim())),
+        ("down", "-- Undo what the .up.sql does.\n".to_string()),
+    ] {
+        let path = dir.join(format!("{:04}_{}.{}.sql", version, slug, direction));
+        std::fs::write(&path, sql)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        println!("Created {}", path.display());
+    }
+    println!("Migrations are embedded at build time; rebuild to apply it.");
+    Ok(())
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Save the database under a name, e.g. after seeding
+    Snapshot {
+        name: String,
+        /// Replace an existing snapshot of that name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Replace the database with a snapshot; stop the server first
+    Restore { name: String },
+    /// List the saved snapshots
+    Snapshots,
+}
+
+/// The SQLite file behind `DATABASE_URL`.
+fn database_file() -> anyhow::Result<PathBuf> {
+    let url = database_url();
+    let options = SqliteConnectOptions::from_str(&url)
+        .with_context(|| format!("invalid DATABASE_URL {}", url))?;
+    let path = options.get_filename();
+    if path.as_os_str().is_empty() || path == Path::new(":memory:") {
+        anyhow::bail!("{} is in memory, so there's no file to snapshot", url);
+    }
+    Ok(path.to_path_buf())
+}
+
+fn snapshot_path(name: &str) -> anyhow::Result<PathBuf> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        anyhow::bail!("snapshot names are A-Z a-z 0-9 _ - . and can't start with a dot");
+    }
+    Ok(Path::new(SNAPSHOTS_DIR).join(format!("{}.db", name)))
+}
+
+/// `db`: snapshots are whole copies of the SQLite file in `db-snapshots/`,
+/// so restoring one is as quick as copying it, with no seeds to re-run.
+pub async fn snapshots(command: DbCommand) -> anyhow::Result<()> {
+    match command {
+        DbCommand::Snapshot { name, force } => {
+            let path = snapshot_path(&name)?;
+            if path.exists() {
+                if !force {
+                    anyhow::bail!("snapshot {} exists; pass --force to replace it", name);
+                }
+                std::fs::remove_file(&path)?;
+            }
+            std::fs::create_dir_all(SNAPSHOTS_DIR)?;
+            // VACUUM INTO copies a consistent state, even with the server
+            // writing, and leaves out the WAL.
+            let pool = connect()?;
+            sqlx::query("VACUUM INTO ?")
+                .bind(path.to_string_lossy())
+                .execute(&pool)
+                .await
+                .with_context(|| format!("failed to snapshot {}", database_url()))?;
+            pool.close().await;
+            println!(
+                "Saved {} as snapshot {} ({} KiB)",
+                database_url(),
+                name,
+                std::fs::metadata(&path)?.len() / 1024
+            );
+        }
+        DbCommand::Restore { name } => {
+            let path = snapshot_path(&name)?;
+            if !path.is_file() {
+                anyhow::bail!("no snapshot {} in {}/", name, SNAPSHOTS_DIR);
+            }
+            // A running server would keep writing to the file being replaced.
+            if crate::admin::server_running() {
+                anyhow::bail!("the server is running; stop it before restoring");
+            }
+            let target = database_file()?;
+            let staged = target.with_extension("restoring");
+            std::fs::copy(&path, &staged)
+                .with_context(|| format!("failed to copy {}", path.display()))?;
+            std::fs::rename(&staged, &target)
+                .with_context(|| format!("failed to replace {}", target.display()))?;
+            // A WAL left from the old database would be replayed onto the
+            // restored one.
+            for suffix in ["-wal", "-shm"] {
+                let mut side = target.clone().into_os_string();
+                side.push(suffix);
+                match std::fs::remove_file(&side) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            println!("Restored {} from snapshot {}", database_url(), name);
+        }
+        DbCommand::Snapshots => {
+            let mut snapshots: Vec<_> = match std::fs::read_dir(SNAPSHOTS_DIR) {
+                Ok(entries) => entries
+                    .filter_map(|entry| {
+                        let entry = entry.ok()?;
+                        let name = entry.file_name().to_str()?.strip_suffix(".db")?.to_string();
+                        Some((name, entry.metadata().ok()?))
+                    })
+                    .collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e.into()),
+            };
+            if snapshots.is_empty() {
+                println!("No snapshots in {}/", SNAPSHOTS_DIR);
+            }
+            snapshots.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, metadata) in snapshots {
+                let saved = metadata
+                    .modified()
+                    .map(|at| {
+                        chrono::DateTime::<chrono::Utc>::from(at)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                println!("  {:24} {:>8} KiB  {}", name, metadata.len() / 1024, saved);
+            }
+        }
+    }
+    Ok(())
+}
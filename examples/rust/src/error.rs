@@ -0,0 +1,175 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::error;
+
+use crate::state::AppState;
+
+/// Field name -> what's wrong with it, for validation failures.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// Error returned by handlers, rendered in the same JSON shape as the 404
+/// fallback: `{ "error", "message", "timestamp" }`, plus `fields` for
+/// validation failures.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    message: String,
+    fields: Option<FieldErrors>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            fields: None,
+        }
+    }
+
+    /// 422 listing the offending fields.
+    pub fn validation(fields: FieldErrors) -> Self {
+        Self {
+            fields: Some(fields),
+            ..Self::new(StatusCode::UNPROCESSABLE_ENTITY, "Validation failed")
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    /// Logs the underlying error and returns a generic 500, so internals
+    /// don't leak to clients.
+    pub fn internal(err: impl std::fmt::Display) -> Self {
+        error!("Internal error: {}", err);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred",
+        )
+    }
+}
+
+/// Attached to error responses so `error_pages` can re-render them.
+#[derive(Clone)]
+struct ErrorDetails {
+    status: StatusCode,
+    message: String,
+    fields: Option<FieldErrors>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: &'a str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<&'a FieldErrors>,
+}
+
+fn reason(status: StatusCode) -> &'static str {
+    status.canonical_reason().unwrap_or("Error")
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let body = Json(ErrorBody {
+            error: reason(self.status),
+            message: &self.message,
+            timestamp: chrono::Utc::now(),
+            request_id: None,
+            fields: self.fields.as_ref(),
+        });
+        let mut res = (self.status, body).into_response();
+        res.extensions_mut().insert(ErrorDetails {
+            status: self.status,
+            message: self.message,
+            fields: self.fields,
+        });
+        res
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        Self::internal(err)
+    }
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+#[derive(Serialize)]
+struct ErrorPageContext<'a> {
+    project_name: &'a str,
+    status: u16,
+    error: &'a str,
+    message: &'a str,
+    path: &'a str,
+    request_id: Option<&'a str>,
+    fields: Option<&'a FieldErrors>,
+}
+
+/// Browsers prefer HTML; API clients (`fetch` defaults to `*/*`) get JSON.
+fn wants_html(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    accept.contains("text/html")
+}
+
+/// Content-negotiates `AppError` responses: an HTML error page (with request
+/// id and links back into the app) for browsers, JSON including the request
+/// id for everyone else.
+pub async fn error_pages(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let html = wants_html(req.headers());
+    let path = req.uri().path().to_string();
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let res = next.run(req).await;
+    let Some(details) = res.extensions().get::<ErrorDetails>().cloned() else {
+        return res;
+    };
+    let (mut parts, _) = res.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+
+    if html {
+        let context = ErrorPageContext {
+            project_name: crate::PROJECT_NAME,
+            status: details.status.as_u16(),
+            error: reason(details.status),
+            message: &details.message,
+            path: &path,
+            request_id: request_id.as_deref(),
+            fields: details.fields.as_ref(),
+        };
+        if let Ok(page) = state.templates().render("error.html", &context) {
+            return (parts, Html(page)).into_response();
+        }
+    }
+
+    let body = Json(ErrorBody {
+        error: reason(details.status),
+        message: &details.message,
+        timestamp: chrono::Utc::now(),
+        request_id: request_id.as_deref(),
+        fields: details.fields.as_ref(),
+    });
+    (parts, body).into_response()
+}
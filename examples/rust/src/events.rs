@@ -0,0 +1,393 @@
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        Json,
+    },
+};
+use futures::{stream, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppError, AppResult},
+    state::AppState,
+    tenancy::Tenant,
+};
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// How many past events are kept for `Last-Event-ID` resume.
+const HISTORY_CAPACITY: usize = 512;
+
+const SSE_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Long-poll wait when the client doesn't pick one; below common proxy idle
+/// timeouts.
+const DEFAULT_POLL_TIMEOUT_SECS: u64 = 25;
+const MAX_POLL_TIMEOUT_SECS: u64 = 60;
+
+/// A message fanned out to every live-update subscriber (WebSocket and SSE
+/// clients).
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct Event {
+    pub id: u64,
+    pub kind: String,
+    #[schema(value_type = Object)]
+    pub data: serde_json::Value,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The tenant whose data this is; only that tenant's subscribers get
+    /// it. `None` for events every subscriber may see.
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+impl Event {
+    /// Whether a subscriber acting for `tenant` may see this event.
+    pub fn visible_to(&self, tenant: &Tenant) -> bool {
+        self.tenant.as_deref().is_none_or(|id| id == tenant.id())
+    }
+}
+
+/// A typed event. Publish it with [`EventHub::emit`] and listen with
+/// [`EventHub::on`]; subscribers that aren't typed (WebSocket, SSE,
+/// long-poll, the Redis relay) see it as an [`Event`] of kind `KIND`.
+pub trait Topic: Serialize + DeserializeOwned + Send + 'static {
+    /// e.g. `job.demo`; clients filter on it with `?topics=`.
+    const KIND: &'static str;
+}
+
+/// In-process broadcast channel shared by the live-update endpoints.
+pub struct EventHub {
+    tx: broadcast::Sender<Event>,
+    next_id: AtomicU64,
+    history: Mutex<VecDeque<Event>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            tx,
+            next_id: AtomicU64::new(1),
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_CAPACITY)),
+        }
+    }
+}
+
+impl EventHub {
+    /// Publishes an event to all current subscribers. Having nobody listening
+    /// is not an error.
+    pub fn publish(&self, kind: impl Into<String>, data: serde_json::Value) -> Event {
+        self.publish_to(None, kind, data)
+    }
+
+    /// [`publish`](Self::publish), but seen only by subscribers acting for
+    /// `tenant` when one is given.
+    pub fn publish_to(
+        &self,
+        tenant: Option<String>,
+        kind: impl Into<String>,
+        data: serde_json::Value,
+    ) -> Event {
+        // Holding the history lock while assigning the id keeps history
+        // ordered by id.
+        let mut history = self.history.lock().unwrap();
+        let event = Event {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            kind: kind.into(),
+            data,
+            timestamp: chrono::Utc::now(),
+            tenant,
+        };
+        if history.len() == HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+        let _ = self.tx.send(event.clone());
+        event
+    }
+
+    /// Publishes `message` as a `T::KIND` event.
+    pub fn emit<T: Topic>(&self, message: &T) -> Event {
+        let data = serde_json::to_value(message).unwrap_or_default();
+        self.publish(T::KIND, data)
+    }
+
+    /// Receives `T` events published from here on, including ones relayed
+    /// from other instances.
+    #[allow(dead_code)]
+    pub fn on<T: Topic>(&self) -> TopicReceiver<T> {
+        TopicReceiver {
+            rx: self.subscribe(),
+            topic: PhantomData,
+        }
+    }
+
+    /// Buffered events newer than `last_id`, oldest first.
+    pub fn since(&self, last_id: u64) -> Vec<Event> {
+        let history = self.history.lock().unwrap();
+        history.iter().filter(|e| e.id > last_id).cloned().collect()
+    }
+
+    /// Id of the most recently published event, 0 if none.
+    pub fn last_id(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed) - 1
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+/// From [`EventHub::on`].
+pub struct TopicReceiver<T> {
+    rx: broadcast::Receiver<Event>,
+    topic: PhantomData<fn() -> T>,
+}
+
+impl<T: Topic> TopicReceiver<T> {
+    /// The next `T`, skipping other kinds and payloads that don't parse as
+    /// one. `None` once the hub is gone.
+    #[allow(dead_code)]
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) if event.kind == T::KIND => match serde_json::from_value(event.data) {
+                    Ok(message) => return Some(message),
+                    Err(e) => warn!("Ignoring malformed {} event #{}: {}", T::KIND, event.id, e),
+                },
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("{} subscriber lagging, skipped {} events", T::KIND, skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Event kinds a subscriber wants, e.g. `note.*,job.demo`; a trailing `*`
+/// matches a prefix. Empty matches everything.
+#[derive(Clone, Default, Debug)]
+pub struct TopicFilter(Vec<String>);
+
+impl TopicFilter {
+    pub fn new(patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self(patterns.into_iter().map(Into::into).collect())
+    }
+
+    /// From a comma-separated `?topics=` value.
+    pub fn parse(topics: Option<&str>) -> Self {
+        Self::new(
+            topics
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|topic| !topic.is_empty()),
+        )
+    }
+
+    pub fn matches(&self, kind: &str) -> bool {
+        self.0.is_empty()
+            || self
+                .0
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => kind.starts_with(prefix),
+                    None => pattern == kind,
+                })
+    }
+}
+
+/// Turns a broadcast receiver into a stream, skipping over lag gaps.
+pub fn live_stream(rx: broadcast::Receiver<Event>) -> impl Stream<Item = Event> {
+    stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event subscriber lagging, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SseParams {
+    /// Resume after this event id; for clients that can't set the
+    /// `Last-Event-ID` header.
+    last_event_id: Option<u64>,
+    /// Comma-separated event kinds to receive, e.g. `note.*,job.demo`;
+    /// everything when omitted.
+    topics: Option<String>,
+}
+
+/// `/api/events`: server-sent events from the event hub, leaving out other
+/// tenants' events. Reconnecting clients get buffered events after their
+/// `Last-Event-ID` before the live stream. The stream ends when graceful
+/// shutdown begins.
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "events",
+    params(SseParams),
+    responses((status = 200, description = "text/event-stream of events", content_type = "text/event-stream"))
+)]
+pub async fn sse_handler(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(params): Query<SseParams>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<sse::Event, axum::Error>>> {
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or(params.last_event_id);
+
+    // Subscribe before reading history so nothing published in between is
+    // lost; duplicates are filtered by id below.
+    let rx = state.events().subscribe();
+    let backlog = last_id
+        .map(|id| state.events().since(id))
+        .unwrap_or_default();
+    let replayed_up_to = backlog.last().map(|e| e.id).or(last_id).unwrap_or(0);
+    let topics = TopicFilter::parse(params.topics.as_deref());
+
+    let live = live_stream(rx).filter(move |e| futures::future::ready(e.id > replayed_up_to));
+    let events = stream::iter(backlog)
+        .chain(live)
+        .filter(move |e| futures::future::ready(topics.matches(&e.kind) && e.visible_to(&tenant)))
+        .map(|event| {
+            sse::Event::default()
+                .id(event.id.to_string())
+                .event(event.kind.clone())
+                .json_data(&event)
+        })
+        .take_until(state.shutdown_token().cancelled_owned());
+
+    Sse::new(events).keep_alive(KeepAlive::new().interval(SSE_HEARTBEAT).text("heartbeat"))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PollParams {
+    /// Cursor from the previous response; omit to wait for the next event.
+    since: Option<u64>,
+    /// Seconds to wait for new events (default 25, max 60).
+    timeout: Option<u64>,
+    /// Comma-separated event kinds to return, e.g. `note.*,job.demo`;
+    /// everything when omitted.
+    topics: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PollResponse {
+    events: Vec<Event>,
+    /// Pass as `since` on the next poll.
+    cursor: u64,
+}
+
+/// `/api/poll`: long-polling for clients that can't hold a WebSocket or SSE
+/// stream open; other tenants' events are left out. Answers immediately
+/// when events newer than `since` are buffered, otherwise parks until one
+/// is published, the timeout passes (empty `events`), or shutdown begins.
+#[utoipa::path(
+    get,
+    path = "/api/poll",
+    tag = "events",
+    params(PollParams),
+    responses(
+        (status = 200, description = "Events after the cursor, possibly none", body = PollResponse),
+        (status = 400, description = "Malformed query, e.g. a non-numeric since")
+    )
+)]
+pub async fn poll_handler(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    params: Result<Query<PollParams>, QueryRejection>,
+) -> AppResult<Json<PollResponse>> {
+    let Query(params) = params.map_err(|e| AppError::new(e.status(), e.body_text()))?;
+    let hub = state.events();
+    let timeout = Duration::from_secs(
+        params
+            .timeout
+            .unwrap_or(DEFAULT_POLL_TIMEOUT_SECS)
+            .min(MAX_POLL_TIMEOUT_SECS),
+    );
+
+    // Subscribe before checking history so an event published in between
+    // still wakes us.
+    let mut rx = hub.subscribe();
+    // A cursor from the future means we restarted and ids began again;
+    // resync to now rather than waiting forever.
+    let since = params
+        .since
+        .filter(|since| *since <= hub.last_id())
+        .unwrap_or_else(|| hub.last_id());
+    let topics = TopicFilter::parse(params.topics.as_deref());
+    let visible = |e: &Event| topics.matches(&e.kind) && e.visible_to(&tenant);
+    let mut events = hub.since(since);
+
+    if !events.iter().any(visible) {
+        let shutdown = state.shutdown_token();
+        let wanted = async {
+            while let Ok(event) = rx.recv().await {
+                if visible(&event) {
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::timeout(timeout, wanted) => {}
+            _ = shutdown.cancelled() => {}
+        }
+        events = hub.since(since);
+    }
+
+    // The cursor moves past events the filter dropped too, so they aren't
+    // looked at again.
+    let cursor = events.last().map_or(since, |e| e.id);
+    events.retain(|e| visible(e));
+    Ok(Json(PollResponse { events, cursor }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PublishRequest {
+    kind: String,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    data: serde_json::Value,
+}
+
+/// Publishes an event to every WebSocket and SSE subscriber.
+#[utoipa::path(
+    post,
+    path = "/api/events",
+    tag = "events",
+    request_body = PublishRequest,
+    responses((status = 202, description = "Event published", body = Event))
+)]
+pub async fn publish_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<PublishRequest>,
+) -> (StatusCode, Json<Event>) {
+    let event = state.events().publish(payload.kind, payload.data);
+    (StatusCode::ACCEPTED, Json(event))
+}
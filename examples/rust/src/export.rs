@@ -0,0 +1,212 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use futures::{stream, Future};
+use serde::{Deserialize, Serialize};
+use std::{io, time::Instant};
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::error::{AppError, AppResult};
+
+/// Rows read from the database per query. Each batch becomes one chunk of
+/// the response, so this also bounds how much an export holds in memory.
+pub const BATCH_SIZE: i64 = 500;
+
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// `text/csv`, with a header row.
+    Csv,
+    /// `application/x-ndjson`, one JSON object per line.
+    #[default]
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// The format a request body is in, from its `Content-Type`.
+    pub fn from_headers(headers: &HeaderMap) -> AppResult<Self> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "text/csv" => Ok(ExportFormat::Csv),
+            "application/x-ndjson" | "application/jsonl" => Ok(ExportFormat::Ndjson),
+            _ => Err(AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Send text/csv or application/x-ndjson",
+            )),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// A row that can be exported: serialized as is for NDJSON, and as
+/// `csv_fields` (in `CSV_HEADER` order) for CSV.
+pub trait ExportRow: Serialize {
+    const CSV_HEADER: &'static [&'static str];
+
+    fn csv_fields(&self) -> Vec<String>;
+
+    /// Where the next batch starts: rows come in increasing `cursor` order.
+    fn cursor(&self) -> i64;
+}
+
+/// Appends one RFC 4180 record: fields quoted only when they need it, CRLF
+/// line endings.
+fn write_csv_record<S: AsRef<str>>(out: &mut Vec<u8>, fields: &[S]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\r', '\n']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+/// How far an export got, logged when it ends. Dropped without finishing
+/// means the body was dropped early: the client went away.
+struct Progress {
+    name: &'static str,
+    after: i64,
+    batches: u64,
+    rows: u64,
+    started: Instant,
+    done: bool,
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if !self.done {
+            info!(
+                "📦 Export of {} cancelled after {} rows: client disconnected",
+                self.name, self.rows
+            );
+        }
+    }
+}
+
+/// Streams every row `fetch` returns as a `format` attachment named after
+/// `name`. `fetch(after, limit)` returns up to `limit` rows with a cursor
+/// past `after`, in cursor order.
+///
+/// Batches are fetched lazily as the body is polled, so a slow client
+/// applies backpressure to the queries, and one that disconnects stops
+/// them. No connection is held between batches. A query that fails part
+/// way through aborts the response, and the client sees a truncated
+/// chunked body rather than a clean end.
+pub fn stream_rows<T, F, Fut>(
+    name: &'static str,
+    format: ExportFormat,
+    fetch: F,
+) -> AppResult<Response>
+where
+    T: ExportRow + Send + 'static,
+    F: FnMut(i64, i64) -> Fut + Send + 'static,
+    Fut: Future<Output = sqlx::Result<Vec<T>>> + Send,
+{
+    let progress = Progress {
+        name,
+        after: i64::MIN,
+        batches: 0,
+        rows: 0,
+        started: Instant::now(),
+        done: false,
+    };
+    let chunks = stream::unfold(Some((fetch, progress)), move |state| async move {
+        let (mut fetch, mut progress) = state?;
+        let mut chunk = Vec::new();
+        if progress.batches == 0 && format == ExportFormat::Csv {
+            write_csv_record(&mut chunk, T::CSV_HEADER);
+        }
+
+        let batch = match fetch(progress.after, BATCH_SIZE).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!(
+                    "Export of {} failed after {} rows: {}",
+                    name, progress.rows, e
+                );
+                progress.done = true;
+                return Some((Err(io::Error::other(e)), None));
+            }
+        };
+        for row in &batch {
+            match format {
+                ExportFormat::Csv => write_csv_record(&mut chunk, &row.csv_fields()),
+                ExportFormat::Ndjson => {
+                    if let Err(e) = serde_json::to_writer(&mut chunk, row) {
+                        error!("Export of {} failed to serialize a row: {}", name, e);
+                        progress.done = true;
+                        return Some((Err(io::Error::other(e)), None));
+                    }
+                    chunk.push(b'\n');
+                }
+            }
+        }
+        progress.batches += 1;
+        progress.rows += batch.len() as u64;
+        if let Some(row) = batch.last() {
+            progress.after = row.cursor();
+        }
+
+        let next = if batch.len() < BATCH_SIZE as usize {
+            info!(
+                "📦 Exported {} rows of {} in {} batches, {}ms",
+                progress.rows,
+                name,
+                progress.batches,
+                progress.started.elapsed().as_millis()
+            );
+            progress.done = true;
+            None
+        } else {
+            Some((fetch, progress))
+        };
+        if chunk.is_empty() {
+            return None;
+        }
+        Some((Ok::<_, io::Error>(Bytes::from(chunk)), next))
+    });
+
+    let filename = format!(
+        "{}-{}.{}",
+        name,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+    // No Content-Length, so HTTP/1.1 clients get chunked transfer.
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from_stream(chunks))
+        .map_err(AppError::internal)
+}
@@ -0,0 +1,77 @@
+use async_graphql::{
+    http::GraphiQLSource, BatchRequest, BatchResponse, Context, EmptyMutation, EmptySubscription,
+    Object, Schema,
+};
+use axum::{
+    extract::{Extension, Json},
+    response::Html,
+};
+
+use crate::{
+    health::HealthResponse,
+    security_headers::{self, ContentSecurityPolicy},
+    state::AppState,
+    AppInfo, EchoResponse,
+};
+
+pub type AppSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deeply nested queries are almost always a mistake or abuse.
+const MAX_DEPTH: usize = 16;
+
+pub fn schema(state: AppState) -> AppSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(state)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// The same info/health/echo data as the REST endpoints.
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Application metadata, as in `/api/info`.
+    async fn info(&self) -> AppInfo {
+        crate::app_info(None)
+    }
+
+    /// Health with dependency checks, as in `/api/health`.
+    async fn health(&self, ctx: &Context<'_>) -> HealthResponse {
+        let (_, report) = crate::health::health_report(ctx.data_unchecked::<AppState>()).await;
+        report
+    }
+
+    /// Echoes the message back, as `POST /api/echo` does.
+    async fn echo(
+        &self,
+        #[graphql(validator(min_length = 1, max_length = 4096))] message: String,
+    ) -> EchoResponse {
+        crate::echo(message)
+    }
+}
+
+/// `POST /graphql`: single or batched queries.
+pub async fn graphql_handler(
+    Extension(schema): Extension<AppSchema>,
+    Json(request): Json<BatchRequest>,
+) -> Json<BatchResponse> {
+    Json(schema.execute_batch(request).await)
+}
+
+/// `GET /graphql` in debug builds: the GraphiQL playground. Its assets come
+/// from unpkg, and its inline scripts and styles get the request's nonce.
+pub async fn graphiql_handler() -> (ContentSecurityPolicy, Html<String>) {
+    let policy = ContentSecurityPolicy::new()
+        .script_src("https://unpkg.com")
+        .style_src("https://unpkg.com")
+        .img_src("https://graphql.org")
+        .font_src("https://unpkg.com");
+    let nonce = security_headers::nonce();
+    let page = GraphiQLSource::build()
+        .endpoint("/graphql")
+        .finish()
+        .replace("<script", &format!("<script nonce=\"{}\"", nonce))
+        .replace("<style", &format!("<style nonce=\"{}\"", nonce));
+    (policy, Html(page))
+}
@@ -0,0 +1,104 @@
+use std::convert::Infallible;
+use tonic::{server::NamedService, Request, Response, Status};
+use tonic_health::server::HealthReporter;
+
+use crate::{routes::Routes, state::AppState};
+
+pub mod proto {
+    tonic::include_proto!("echo.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("echo_descriptor");
+}
+
+use proto::echo_server::{Echo, EchoServer};
+
+/// Same limit as `EchoRequest` on the REST side.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+#[derive(Default)]
+pub struct EchoService;
+
+#[tonic::async_trait]
+impl Echo for EchoService {
+    async fn echo(
+        &self,
+        request: Request<proto::EchoRequest>,
+    ) -> Result<Response<proto::EchoResponse>, Status> {
+        let message = request.into_inner().message;
+        if message.is_empty() || message.chars().count() > MAX_MESSAGE_LEN {
+            return Err(Status::invalid_argument(format!(
+                "message must be 1 to {} characters",
+                MAX_MESSAGE_LEN
+            )));
+        }
+
+        let echo = crate::echo(message);
+        Ok(Response::new(proto::EchoResponse {
+            echo: echo.echo,
+            timestamp: echo.timestamp.to_rfc3339(),
+            id: echo.id,
+        }))
+    }
+}
+
+/// Mounts the gRPC services (echo, `grpc.health.v1`, reflection) on the HTTP
+/// router. `axum::serve` speaks h2c, so gRPC clients reach them on the same
+/// port as everything else.
+pub fn add_services<S>(routes: Routes<S>, state: &AppState) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let (reporter, health) = tonic_health::server::health_reporter();
+    tokio::spawn(report_health(reporter, state.clone()));
+
+    let reflection_v1 = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1()
+        .expect("embedded descriptor sets are valid");
+    let reflection_v1alpha = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1alpha()
+        .expect("embedded descriptor sets are valid");
+
+    let routes = mount(routes, EchoServer::new(EchoService), "gRPC echo");
+    let routes = mount(routes, health, "gRPC health checking");
+    let routes = mount(routes, reflection_v1, "gRPC server reflection");
+    mount(
+        routes,
+        reflection_v1alpha,
+        "gRPC server reflection (v1alpha)",
+    )
+}
+
+/// gRPC dispatches on `/<package>.<Service>/<Method>`.
+fn mount<S, T>(routes: Routes<S>, service: T, description: &'static str) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+    T: NamedService
+        + tower::Service<axum::extract::Request, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    T::Response: axum::response::IntoResponse,
+    T::Future: Send + 'static,
+{
+    routes.route_service(
+        &format!("/{}/*method", T::NAME),
+        &["POST"],
+        service,
+        description,
+    )
+}
+
+/// `SERVING` while we run, `NOT_SERVING` once graceful shutdown begins, in
+/// step with `/readyz`.
+async fn report_health(mut reporter: HealthReporter, state: AppState) {
+    reporter.set_serving::<EchoServer<EchoService>>().await;
+    state.shutdown_token().cancelled().await;
+    reporter.set_not_serving::<EchoServer<EchoService>>().await;
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+}
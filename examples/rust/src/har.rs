@@ -0,0 +1,322 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json},
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+
+use crate::{
+    build_info::BUILD_INFO,
+    capture::{CapturedRequest, Header, CAPTURE_BODY_LIMIT},
+    redaction::Redactor,
+    state::AppState,
+    PROJECT_NAME,
+};
+
+// HAR 1.2 (http://www.softwareishard.com/blog/har-12-spec/). Fields starting
+// with `_` are custom, as the spec allows.
+
+#[derive(Serialize)]
+struct Har {
+    log: Log,
+}
+
+#[derive(Serialize)]
+struct Log {
+    version: &'static str,
+    creator: Creator,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: chrono::DateTime<chrono::Utc>,
+    time: f64,
+    request: Request,
+    response: Response,
+    cache: Cache,
+    timings: Timings,
+    #[serde(rename = "_requestId")]
+    request_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<Header>,
+    headers: Vec<Header>,
+    query_string: Vec<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(rename = "_truncated")]
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    status: u16,
+    status_text: &'static str,
+    http_version: String,
+    cookies: Vec<Header>,
+    headers: Vec<Header>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(rename = "_truncated")]
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+/// Requests are dispatched in-process, so there's no blocked/dns/connect
+/// phase: `wait` is time to headers, `receive` the body.
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+fn find<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Rebuilds the URL the client used, preferring what NSM's proxy forwarded.
+fn absolute_url(captured: &CapturedRequest) -> String {
+    let headers = &captured.headers;
+    let scheme = find(headers, "x-forwarded-proto").unwrap_or("http");
+    let host = find(headers, "x-forwarded-host")
+        .or_else(|| find(headers, "host"))
+        .unwrap_or("localhost");
+    format!("{}://{}{}", scheme, host, captured.uri)
+}
+
+fn query_string(uri: &str) -> Vec<Header> {
+    let Some((_, query)) = uri.split_once('?') else {
+        return Vec::new();
+    };
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Header {
+                name: decode(name),
+                value: decode(value),
+            }
+        })
+        .collect()
+}
+
+/// `Cookie: a=1; b=2` on requests, one `Set-Cookie` per cookie on responses.
+fn cookies(headers: &[Header], name: &str) -> Vec<Header> {
+    headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case(name))
+        .flat_map(|h| {
+            let pairs: Vec<&str> = if name == "cookie" {
+                h.value.split(';').collect()
+            } else {
+                h.value.split(';').take(1).collect()
+            };
+            pairs.into_iter().filter_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                Some(Header {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+            })
+        })
+        .collect()
+}
+
+fn post_data(captured: &CapturedRequest) -> Option<PostData> {
+    let mime_type = find(&captured.headers, "content-type")
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    match &captured.body {
+        Some(body) if body.is_empty() => None,
+        Some(body) => Some(match std::str::from_utf8(body) {
+            Ok(text) => PostData {
+                mime_type,
+                text: text.to_string(),
+                comment: None,
+                truncated: false,
+            },
+            Err(_) => PostData {
+                mime_type,
+                text: String::new(),
+                comment: Some("binary body not included".to_string()),
+                truncated: true,
+            },
+        }),
+        None => Some(PostData {
+            mime_type,
+            text: String::new(),
+            comment: Some(format!(
+                "body not captured (streamed or over {} KiB)",
+                CAPTURE_BODY_LIMIT / 1024
+            )),
+            truncated: true,
+        }),
+    }
+}
+
+/// The response body is masked here, as it's only complete once streamed.
+fn entry(captured: CapturedRequest, redactor: &Redactor) -> Entry {
+    let response = captured.response.lock().unwrap();
+    let complete = response.receive_ms.is_some();
+    let encoding = find(&response.headers, "content-encoding").filter(|e| *e != "identity");
+    let content_type = find(&response.headers, "content-type");
+
+    let (text, comment) = match (encoding, std::str::from_utf8(&response.body)) {
+        (Some(encoding), _) => (
+            None,
+            Some(format!("{}-encoded body not included", encoding)),
+        ),
+        (None, Ok(text)) => (Some(redactor.body(content_type, text)), None),
+        // A multi-byte character cut in half by the limit is still text.
+        (None, Err(e)) if e.error_len().is_none() => (
+            Some(redactor.body(
+                content_type,
+                &String::from_utf8_lossy(&response.body[..e.valid_up_to()]),
+            )),
+            None,
+        ),
+        (None, Err(_)) => (None, Some("binary body not included".to_string())),
+    };
+    let truncated = !complete || response.size > response.body.len() as u64;
+    let comment = if !complete {
+        Some("still streaming when exported".to_string())
+    } else if truncated && comment.is_none() {
+        Some(format!(
+            "truncated to {} of {} bytes",
+            response.body.len(),
+            response.size
+        ))
+    } else {
+        comment
+    };
+
+    let wait = captured.duration_ms;
+    let receive = response.receive_ms.unwrap_or(0.0);
+    let status = StatusCode::from_u16(captured.status).ok();
+
+    Entry {
+        started_date_time: captured.started_at,
+        time: wait + receive,
+        request: Request {
+            method: captured.method.clone(),
+            url: absolute_url(&captured),
+            http_version: captured.http_version.clone(),
+            cookies: cookies(&captured.headers, "cookie"),
+            headers: captured.headers.clone(),
+            query_string: query_string(&captured.uri),
+            post_data: post_data(&captured),
+            headers_size: -1,
+            body_size: captured.body_size.map_or(-1, |size| size as i64),
+        },
+        response: Response {
+            status: captured.status,
+            status_text: status.and_then(|s| s.canonical_reason()).unwrap_or(""),
+            http_version: captured.http_version.clone(),
+            cookies: cookies(&response.headers, "set-cookie"),
+            headers: response.headers.clone(),
+            content: Content {
+                size: response.size as i64,
+                mime_type: find(&response.headers, "content-type")
+                    .unwrap_or("")
+                    .to_string(),
+                text,
+                comment,
+                truncated,
+            },
+            redirect_url: find(&response.headers, "location")
+                .unwrap_or("")
+                .to_string(),
+            headers_size: -1,
+            body_size: response.size as i64,
+        },
+        cache: Cache {},
+        timings: Timings {
+            send: 0.0,
+            wait,
+            receive,
+        },
+        request_id: captured.id.clone(),
+    }
+}
+
+/// `GET /debug/requests/export.har`: the capture buffer as a HAR 1.2 file,
+/// oldest request first, for browser devtools or Fiddler.
+pub async fn export_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let mut requests = state.capture().recent();
+    requests.reverse();
+
+    let har = Har {
+        log: Log {
+            version: "1.2",
+            creator: Creator {
+                name: PROJECT_NAME,
+                version: BUILD_INFO.version,
+            },
+            entries: requests
+                .into_iter()
+                .map(|captured| entry(captured, state.redactor()))
+                .collect(),
+        },
+    };
+
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.har\"", PROJECT_NAME),
+        )],
+        Json(har),
+    )
+}
@@ -0,0 +1,324 @@
+use axum::{extract::State, http::StatusCode, response::Json};
+use futures::future::{join_all, BoxFuture};
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{build_info::BUILD_INFO, state::AppState};
+
+const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A dependency probe (database, cache, upstream API, ...) that contributes to
+/// `/api/health`.
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Runs the probe. `Err` carries a short, human-readable reason.
+    fn check(&self) -> BoxFuture<'_, Result<(), String>>;
+
+    fn timeout(&self) -> Duration {
+        DEFAULT_CHECK_TIMEOUT
+    }
+
+    /// Critical checks make the service unhealthy (503) when they fail;
+    /// non-critical ones only degrade it.
+    fn critical(&self) -> bool {
+        true
+    }
+
+    /// Readiness checks also gate `/readyz`, so the proxy stops routing here
+    /// while they fail. Keep them to dependencies no request can do without.
+    fn readiness(&self) -> bool {
+        false
+    }
+}
+
+/// Checks registered at startup; modules add their own as they come up.
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: RwLock<Vec<Arc<dyn HealthCheck>>>,
+}
+
+impl HealthRegistry {
+    pub fn register(&self, check: impl HealthCheck + 'static) {
+        self.checks.write().unwrap().push(Arc::new(check));
+    }
+
+    /// Runs every registered check concurrently, each bounded by its own
+    /// timeout.
+    pub async fn run(&self) -> Vec<CheckResult> {
+        let checks = self.checks.read().unwrap().clone();
+        join_all(checks.iter().map(|check| run_check(check.as_ref()))).await
+    }
+
+    /// Runs just the readiness checks, for `/readyz`.
+    pub async fn run_readiness(&self) -> Vec<CheckResult> {
+        let checks: Vec<_> = self
+            .checks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|check| check.readiness())
+            .cloned()
+            .collect();
+        join_all(checks.iter().map(|check| run_check(check.as_ref()))).await
+    }
+}
+
+async fn run_check(check: &dyn HealthCheck) -> CheckResult {
+    let started = Instant::now();
+    let (status, error) = match tokio::time::timeout(check.timeout(), check.check()).await {
+        Ok(Ok(())) => (CheckStatus::Up, None),
+        Ok(Err(e)) => (CheckStatus::Down, Some(e)),
+        Err(_) => (
+            CheckStatus::Timeout,
+            Some(format!(
+                "no response within {}ms",
+                check.timeout().as_millis()
+            )),
+        ),
+    };
+
+    if let Some(error) = &error {
+        warn!("Health check '{}' failed: {}", check.name(), error);
+    }
+
+    CheckResult {
+        name: check.name().to_string(),
+        status,
+        critical: check.critical(),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        error,
+    }
+}
+
+/// Reports whether a TCP connection to `addr` can be opened. Good enough for
+/// databases, caches and upstream APIs that don't have a client in the app yet.
+pub struct TcpCheck {
+    name: String,
+    addr: String,
+    critical: bool,
+}
+
+impl TcpCheck {
+    pub fn new(name: impl Into<String>, addr: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            addr: addr.into(),
+            critical: true,
+        }
+    }
+
+    pub fn non_critical(mut self) -> Self {
+        self.critical = false;
+        self
+    }
+}
+
+impl HealthCheck for TcpCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            tokio::net::TcpStream::connect(&self.addr)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{}: {}", self.addr, e))
+        })
+    }
+
+    fn critical(&self) -> bool {
+        self.critical
+    }
+}
+
+/// Registers TCP checks from `HEALTH_CHECKS`, a comma-separated list of
+/// `name=host:port` pairs. A `?` suffix on the name marks the check as
+/// non-critical, e.g. `db=127.0.0.1:5432,cache?=127.0.0.1:6379`.
+pub fn register_env_checks(registry: &HealthRegistry) {
+    let Ok(spec) = std::env::var("HEALTH_CHECKS") else {
+        return;
+    };
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once('=') {
+            Some((name, addr)) => match name.strip_suffix('?') {
+                Some(name) => registry.register(TcpCheck::new(name, addr).non_critical()),
+                None => registry.register(TcpCheck::new(name, addr)),
+            },
+            None => warn!("Ignoring malformed HEALTH_CHECKS entry '{}'", entry),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+    Timeout,
+}
+
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct CheckResult {
+    name: String,
+    status: CheckStatus,
+    critical: bool,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Rolls individual results up into an overall status and HTTP code.
+fn overall_status(results: &[CheckResult]) -> (StatusCode, &'static str) {
+    let failed = results.iter().filter(|r| r.status != CheckStatus::Up);
+    let (mut critical, mut degraded) = (false, false);
+    for result in failed {
+        if result.critical {
+            critical = true;
+        } else {
+            degraded = true;
+        }
+    }
+
+    if critical {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    } else if degraded {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "healthy")
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct HealthResponse {
+    status: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    uptime: String,
+    uptime_seconds: u64,
+    started_at: chrono::DateTime<chrono::Utc>,
+    version: String,
+    profile: String,
+    checks: Vec<CheckResult>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProbeResponse {
+    status: &'static str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    /// Readiness checks that failed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checks: Vec<CheckResult>,
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes, seconds) =
+        (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "system",
+    responses(
+        (status = 200, description = "Healthy or degraded", body = HealthResponse),
+        (status = 503, description = "A critical dependency check failed", body = HealthResponse)
+    )
+)]
+pub async fn health_handler(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    let (code, report) = health_report(&state).await;
+    (code, Json(report))
+}
+
+/// Runs every registered check and builds the `/api/health` body.
+pub async fn health_report(state: &AppState) -> (StatusCode, HealthResponse) {
+    let checks = state.health().run().await;
+    let (code, status) = overall_status(&checks);
+    let uptime = state.uptime();
+
+    let report = HealthResponse {
+        status: status.to_string(),
+        timestamp: chrono::Utc::now(),
+        uptime: format_uptime(uptime),
+        uptime_seconds: uptime.as_secs(),
+        started_at: state.started_at(),
+        version: BUILD_INFO.version.to_string(),
+        profile: state.profile().to_string(),
+        checks,
+    };
+
+    (code, report)
+}
+
+/// Liveness: the process is up and serving requests. Never depends on
+/// anything external, so a failing dependency doesn't get us restarted.
+#[utoipa::path(
+    get,
+    path = "/livez",
+    tag = "system",
+    responses((status = 200, description = "Process is alive", body = ProbeResponse))
+)]
+pub async fn livez_handler() -> Json<ProbeResponse> {
+    Json(ProbeResponse {
+        status: "alive",
+        timestamp: chrono::Utc::now(),
+        checks: Vec::new(),
+    })
+}
+
+/// Readiness: whether the NSM proxy should route traffic here. Reports 503
+/// until startup completes, while a readiness check (such as the database
+/// ping) fails, and again once graceful shutdown begins.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "system",
+    responses(
+        (status = 200, description = "Ready to receive traffic", body = ProbeResponse),
+        (status = 503, description = "Starting up, draining or a readiness check failed", body = ProbeResponse)
+    )
+)]
+pub async fn readyz_handler(State(state): State<AppState>) -> (StatusCode, Json<ProbeResponse>) {
+    let mut checks = Vec::new();
+    let (code, status) = if state.is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if !state.is_ready() {
+        (StatusCode::SERVICE_UNAVAILABLE, "starting")
+    } else {
+        checks = state.health().run_readiness().await;
+        checks.retain(|check| check.status != CheckStatus::Up);
+        if checks.is_empty() {
+            (StatusCode::OK, "ready")
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+        }
+    };
+
+    (
+        code,
+        Json(ProbeResponse {
+            status,
+            timestamp: chrono::Utc::now(),
+            checks,
+        }),
+    )
+}
@@ -0,0 +1,88 @@
+use std::{net::SocketAddr, process::Stdio, time::Duration};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::{config::HooksConfig, selfcheck::loopback};
+
+#[derive(Clone, Copy)]
+pub enum Hook {
+    Start,
+    Ready,
+    Shutdown,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Start => "on_start",
+            Self::Ready => "on_ready",
+            Self::Shutdown => "on_shutdown",
+        }
+    }
+
+    fn commands(self, config: &HooksConfig) -> &[String] {
+        match self {
+            Self::Start => &config.on_start,
+            Self::Ready => &config.on_ready,
+            Self::Shutdown => &config.on_shutdown,
+        }
+    }
+}
+
+/// Runs the hook's commands one after another, logging their output.
+pub async fn run(hook: Hook, config: HooksConfig, addr: SocketAddr) {
+    let url = format!("http://{}", loopback(addr));
+    let timeout = Duration::from_secs(config.timeout_secs);
+    for command in hook.commands(&config) {
+        run_command(hook, command, &url, timeout).await;
+    }
+}
+
+fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
+
+async fn run_command(hook: Hook, command: &str, url: &str, timeout: Duration) {
+    let name = hook.name();
+    let mut cmd = shell(command);
+    cmd.env("APP_URL", url)
+        .env("APP_HOOK", name)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    info!("🪝 {}: {}", name, command);
+    let output = match tokio::time::timeout(timeout, cmd.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            warn!("🪝 {}: failed to run `{}`: {}", name, command, e);
+            return;
+        }
+        Err(_) => {
+            warn!(
+                "🪝 {}: `{}` killed after {}s",
+                name,
+                command,
+                timeout.as_secs()
+            );
+            return;
+        }
+    };
+    for stream in [&output.stdout, &output.stderr] {
+        for line in String::from_utf8_lossy(stream).lines() {
+            info!("🪝 {} | {}", name, line);
+        }
+    }
+    if !output.status.success() {
+        warn!("🪝 {}: `{}` failed ({})", name, command, output.status);
+    }
+}
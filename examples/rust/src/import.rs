@@ -0,0 +1,287 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, StatusCode},
+};
+use futures::{stream::BoxStream, Future, StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+use tokio_util::{
+    codec::{FramedRead, LinesCodec, LinesCodecError},
+    io::StreamReader,
+};
+use tracing::{error, info};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    error::{AppError, AppResult, FieldErrors},
+    export::ExportFormat,
+    validation::field_errors,
+};
+
+/// Valid rows written per transaction.
+pub const BATCH_SIZE: usize = 500;
+
+/// Longest record accepted, so one runaway row can't exhaust memory.
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+/// Rows past this many errors are still counted, but not reported.
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+#[derive(Serialize, ToSchema)]
+pub struct RowError {
+    /// Where the row starts in the body, counting from 1.
+    line: u64,
+    /// Why the row couldn't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    /// Failed validation rules, as in a 422.
+    #[serde(skip_serializing_if = "FieldErrors::is_empty")]
+    fields: FieldErrors,
+}
+
+#[derive(Serialize, ToSchema, Default)]
+pub struct ImportReport {
+    /// Rows read, not counting blank lines or the CSV header.
+    rows: u64,
+    imported: u64,
+    failed: u64,
+    /// The first failures, in order.
+    errors: Vec<RowError>,
+    /// More rows failed than `errors` lists.
+    errors_truncated: bool,
+}
+
+impl ImportReport {
+    fn reject(&mut self, error: RowError) {
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error);
+        } else {
+            self.errors_truncated = true;
+        }
+    }
+}
+
+type BodyLines = FramedRead<StreamReader<BoxStream<'static, io::Result<Bytes>>, Bytes>, LinesCodec>;
+
+/// Reads rows from a CSV or NDJSON body a line at a time.
+struct RowReader {
+    lines: BodyLines,
+    format: ExportFormat,
+    line: u64,
+    /// CSV column names, from the first record.
+    columns: Option<Vec<String>>,
+}
+
+impl RowReader {
+    fn new(format: ExportFormat, body: Body) -> Self {
+        let stream = body.into_data_stream().map_err(io::Error::other).boxed();
+        Self {
+            lines: FramedRead::new(
+                StreamReader::new(stream),
+                LinesCodec::new_with_max_length(MAX_RECORD_BYTES),
+            ),
+            format,
+            line: 0,
+            columns: None,
+        }
+    }
+
+    async fn next_line(&mut self) -> AppResult<Option<String>> {
+        let line = match self.lines.next().await {
+            None => return Ok(None),
+            Some(Ok(line)) => line,
+            Some(Err(LinesCodecError::MaxLineLengthExceeded)) => {
+                return Err(too_large(self.line + 1))
+            }
+            Some(Err(LinesCodecError::Io(e))) => {
+                return Err(AppError::bad_request(format!(
+                    "Couldn't read line {}: {}",
+                    self.line + 1,
+                    e
+                )))
+            }
+        };
+        self.line += 1;
+        Ok(Some(line))
+    }
+
+    /// The next non-blank record and the line it starts on. A CSV record
+    /// spans lines while a quoted field is open.
+    async fn next_record(&mut self) -> AppResult<Option<(u64, String)>> {
+        loop {
+            let Some(mut record) = self.next_line().await? else {
+                return Ok(None);
+            };
+            let start = self.line;
+            if self.format == ExportFormat::Csv {
+                while record.matches('"').count() % 2 == 1 {
+                    let Some(more) = self.next_line().await? else {
+                        return Err(AppError::bad_request(format!(
+                            "Unterminated quoted field in the record on line {}",
+                            start
+                        )));
+                    };
+                    record.push('\n');
+                    record.push_str(&more);
+                    if record.len() > MAX_RECORD_BYTES {
+                        return Err(too_large(start));
+                    }
+                }
+            }
+            if !record.trim().is_empty() {
+                return Ok(Some((start, record)));
+            }
+        }
+    }
+
+    /// The next row and the line it starts on, or why it isn't a valid `T`.
+    async fn next<T: DeserializeOwned + Validate>(
+        &mut self,
+    ) -> AppResult<Option<(u64, Result<T, RowError>)>> {
+        if self.format == ExportFormat::Csv && self.columns.is_none() {
+            let Some((_, header)) = self.next_record().await? else {
+                return Ok(None);
+            };
+            let header = header.strip_prefix('\u{feff}').unwrap_or(&header);
+            self.columns = Some(parse_csv_record(header));
+        }
+        let Some((line, record)) = self.next_record().await? else {
+            return Ok(None);
+        };
+        let parsed = match &self.columns {
+            Some(columns) => {
+                let values = parse_csv_record(&record);
+                if values.len() != columns.len() {
+                    Err(format!(
+                        "expected {} fields, found {}",
+                        columns.len(),
+                        values.len()
+                    ))
+                } else {
+                    let object = columns
+                        .iter()
+                        .cloned()
+                        .zip(values.into_iter().map(serde_json::Value::String))
+                        .collect();
+                    serde_json::from_value(serde_json::Value::Object(object))
+                        .map_err(|e| e.to_string())
+                }
+            }
+            None => serde_json::from_str(&record).map_err(|e| e.to_string()),
+        };
+        let row = match parsed {
+            Err(message) => Err(RowError {
+                line,
+                message: Some(message),
+                fields: FieldErrors::new(),
+            }),
+            Ok(value) => match T::validate(&value) {
+                Ok(()) => Ok(value),
+                Err(errors) => Err(RowError {
+                    line,
+                    message: None,
+                    fields: field_errors(&errors),
+                }),
+            },
+        };
+        Ok(Some((line, row)))
+    }
+}
+
+fn too_large(line: u64) -> AppError {
+    AppError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "The record on line {} is longer than {} bytes",
+            line, MAX_RECORD_BYTES
+        ),
+    )
+}
+
+/// Splits one RFC 4180 record into its fields, unquoting as it goes.
+fn parse_csv_record(record: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Reads `T`s from `body` as the `Content-Type` says, validates each, and
+/// hands the valid ones to `apply` `BATCH_SIZE` at a time, which should
+/// write each batch in one transaction. Invalid rows are skipped and
+/// reported.
+///
+/// The body is parsed as it arrives, so an import of any size holds at
+/// most one batch in memory. A batch that fails to apply stops the import;
+/// the batches before it stay.
+pub async fn import_rows<T, F, Fut>(
+    name: &'static str,
+    headers: &HeaderMap,
+    body: Body,
+    mut apply: F,
+) -> AppResult<ImportReport>
+where
+    T: DeserializeOwned + Validate,
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut rows = RowReader::new(ExportFormat::from_headers(headers)?, body);
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut batch_line = 0;
+    loop {
+        let next = rows.next::<T>().await?;
+        let end = next.is_none();
+        if let Some((line, row)) = next {
+            report.rows += 1;
+            match row {
+                Ok(value) => {
+                    if batch.is_empty() {
+                        batch_line = line;
+                    }
+                    batch.push(value);
+                }
+                Err(error) => report.reject(error),
+            }
+        }
+
+        if batch.len() == BATCH_SIZE || (end && !batch.is_empty()) {
+            let count = batch.len() as u64;
+            let values = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if let Err(e) = apply(values).await {
+                error!("Import of {} failed: {:#}", name, e);
+                return Err(AppError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!(
+                        "Import stopped at the batch starting on line {}; the {} rows before it were imported",
+                        batch_line, report.imported
+                    ),
+                ));
+            }
+            report.imported += count;
+        }
+        if end {
+            break;
+        }
+    }
+    info!(
+        "📥 Imported {} of {} {} rows ({} failed)",
+        report.imported, report.rows, name, report.failed
+    );
+    Ok(report)
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    Form,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::{
+    auth::AuthError,
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// A token posted as `application/x-www-form-urlencoded`. RFC 7662 and 7009
+/// also allow a `token_type_hint`; it's ignored, since the token's shape
+/// says what it is.
+#[derive(Deserialize)]
+pub struct TokenForm {
+    token: String,
+}
+
+/// What `/oauth/introspect` knows about a token (RFC 7662). An inactive one,
+/// whether expired, revoked or never ours, is only `{"active": false}`.
+#[derive(Serialize, Default)]
+pub struct Introspection {
+    active: bool,
+    /// `api_key` or `jwt`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    /// An API key's tier.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nbf: Option<i64>,
+}
+
+impl Introspection {
+    fn inactive() -> Self {
+        Self::default()
+    }
+
+    fn from_claims(claims: &Value) -> Self {
+        let string = |name: &str| claims.get(name)?.as_str().map(str::to_string);
+        let number = |name: &str| claims.get(name)?.as_i64();
+        Self {
+            active: true,
+            token_type: Some("jwt"),
+            sub: string("sub"),
+            client_id: string("client_id").or_else(|| string("azp")),
+            username: string("preferred_username"),
+            scope: string("scope"),
+            iss: string("iss"),
+            aud: claims.get("aud").cloned(),
+            exp: number("exp"),
+            iat: number("iat"),
+            nbf: number("nbf"),
+            ..Self::default()
+        }
+    }
+}
+
+/// JWTs carry everything in themselves; there's nothing here to revoke.
+fn looks_like_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+#[cfg(feature = "database")]
+async fn introspect_key(state: &AppState, token: &str) -> AppResult<Introspection> {
+    let Some(key) = crate::apikeys::find_live(state, token)
+        .await
+        .map_err(AppError::internal)?
+    else {
+        return Ok(Introspection::inactive());
+    };
+    Ok(Introspection {
+        active: true,
+        token_type: Some("api_key"),
+        sub: Some(format!("api-key:{}", key.id)),
+        client_id: Some(key.name),
+        tier: Some(key.tier),
+        iat: Some(key.created_at.timestamp()),
+        ..Introspection::default()
+    })
+}
+
+/// `POST /oauth/introspect`: whether a token is one of ours and still good,
+/// and whose it is, so other services can check API keys and bearer tokens
+/// here instead of each keeping the keys or parsing JWTs. API keys are
+/// looked up in the database (with the `database` feature); JWTs are
+/// verified as `auth` would.
+pub async fn introspect_handler(
+    State(state): State<AppState>,
+    Form(form): Form<TokenForm>,
+) -> AppResult<Json<Introspection>> {
+    let token = form.token.trim();
+    #[cfg(feature = "database")]
+    if crate::apikeys::is_key(token) {
+        return introspect_key(&state, token).await.map(Json);
+    }
+    if !looks_like_jwt(token) {
+        return Ok(Json(Introspection::inactive()));
+    }
+    match state.auth().verify(token).await {
+        Ok(claims) => Ok(Json(Introspection::from_claims(&claims))),
+        // Not knowing isn't the same as inactive; have the caller retry.
+        Err(AuthError::KeysUnavailable) => Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Token signing keys are unavailable; try again later",
+        )),
+        Err(e) => {
+            debug!("Introspected token is inactive: {:?}", e);
+            Ok(Json(Introspection::inactive()))
+        }
+    }
+}
+
+/// `POST /oauth/revoke` (RFC 7009): revokes an API key, so it's refused
+/// everywhere from then on. Answers `200` whether or not the token was
+/// live, so callers can't probe for keys; JWTs, which can't be revoked, are
+/// `400` with `unsupported_token_type`.
+#[cfg_attr(not(feature = "database"), allow(unused_variables))]
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    Form(form): Form<TokenForm>,
+) -> AppResult<Response> {
+    let token = form.token.trim();
+    #[cfg(feature = "database")]
+    if crate::apikeys::is_key(token) {
+        crate::apikeys::revoke_value(&state, token)
+            .await
+            .map_err(AppError::internal)?;
+        return Ok(StatusCode::OK.into_response());
+    }
+    if looks_like_jwt(token) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "unsupported_token_type",
+                "error_description": "JWTs can't be revoked; they last until they expire",
+            })),
+        )
+            .into_response());
+    }
+    Ok(StatusCode::OK.into_response())
+}
@@ -0,0 +1,652 @@
+use anyhow::anyhow;
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+use tracing::{error, info, info_span, warn, Instrument};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    config::JobsConfig,
+    error::{AppError, AppResult},
+    events::Topic,
+    state::AppState,
+    validation::ValidatedJson,
+};
+
+/// How often idle workers look for jobs whose retry has come due.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Finished and failed jobs kept for `/debug/jobs`.
+const KEEP_FINISHED: i64 = 1000;
+
+/// Jobs listed by `/debug/jobs`.
+const LIST_LIMIT: usize = 100;
+
+/// A unit of background work. Implement it for a serializable struct, add
+/// the type to [`registry`], and queue it with `state.jobs().enqueue(job)`.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Stored with each queued job to find its handler; keep it stable
+    /// across releases.
+    const KIND: &'static str;
+
+    /// An error is retried with backoff until `jobs.max_attempts` runs.
+    fn run(self, ctx: JobContext) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// What a running job gets besides its own fields.
+pub struct JobContext {
+    pub state: AppState,
+    pub id: i64,
+    /// 1 on the first run.
+    pub attempt: u32,
+}
+
+type RunFn = Arc<dyn Fn(Value, JobContext) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+/// Maps job kinds to their handlers.
+#[derive(Default)]
+pub struct JobRegistry {
+    kinds: BTreeMap<&'static str, RunFn>,
+}
+
+impl JobRegistry {
+    pub fn register<J: Job>(mut self) -> Self {
+        self.kinds.insert(
+            J::KIND,
+            Arc::new(|payload, ctx| {
+                Box::pin(async move {
+                    let job: J = serde_json::from_value(payload)
+                        .map_err(|e| anyhow!("invalid payload: {}", e))?;
+                    job.run(ctx).await
+                })
+            }),
+        );
+        self
+    }
+}
+
+/// Every job type the workers know how to run.
+pub fn registry() -> JobRegistry {
+    JobRegistry::default().register::<DemoJob>()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for `run_at`.
+    Queued,
+    Running,
+    Done,
+    /// Out of attempts, or of a kind nothing handles.
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub payload: Value,
+    pub status: JobStatus,
+    /// Runs so far, the current one included.
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub run_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Where queued jobs live.
+trait JobStore: Send + Sync {
+    fn insert(
+        &self,
+        kind: &'static str,
+        payload: Value,
+        max_attempts: u32,
+    ) -> BoxFuture<'_, anyhow::Result<i64>>;
+
+    /// Marks the next due job as running and counts the attempt.
+    fn claim(&self) -> BoxFuture<'_, anyhow::Result<Option<JobRecord>>>;
+
+    fn finish(
+        &self,
+        id: i64,
+        status: JobStatus,
+        run_at: DateTime<Utc>,
+        error: Option<String>,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Puts jobs left running by a previous process back in the queue.
+    fn requeue_running(&self) -> BoxFuture<'_, anyhow::Result<u64>>;
+
+    /// Newest first.
+    fn list(&self, limit: usize) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>>;
+
+    fn counts(&self) -> BoxFuture<'_, anyhow::Result<BTreeMap<JobStatus, u64>>>;
+}
+
+/// Used without the `database` feature; jobs are lost on restart.
+#[cfg(not(feature = "database"))]
+#[derive(Default)]
+struct MemoryStore {
+    jobs: std::sync::Mutex<MemoryJobs>,
+}
+
+#[cfg(not(feature = "database"))]
+#[derive(Default)]
+struct MemoryJobs {
+    next_id: i64,
+    jobs: BTreeMap<i64, JobRecord>,
+}
+
+#[cfg(not(feature = "database"))]
+impl JobStore for MemoryStore {
+    fn insert(
+        &self,
+        kind: &'static str,
+        payload: Value,
+        max_attempts: u32,
+    ) -> BoxFuture<'_, anyhow::Result<i64>> {
+        Box::pin(async move {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.next_id += 1;
+            let id = jobs.next_id;
+            let now = Utc::now();
+            jobs.jobs.insert(
+                id,
+                JobRecord {
+                    id,
+                    kind: kind.to_string(),
+                    payload,
+                    status: JobStatus::Queued,
+                    attempts: 0,
+                    max_attempts,
+                    run_at: now,
+                    last_error: None,
+                    created_at: now,
+                    updated_at: now,
+                },
+            );
+            let finished: Vec<i64> = jobs
+                .jobs
+                .values()
+                .filter(|job| matches!(job.status, JobStatus::Done | JobStatus::Failed))
+                .map(|job| job.id)
+                .collect();
+            let excess = finished.len().saturating_sub(KEEP_FINISHED as usize);
+            for id in &finished[..excess] {
+                jobs.jobs.remove(id);
+            }
+            Ok(id)
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, anyhow::Result<Option<JobRecord>>> {
+        Box::pin(async move {
+            let mut jobs = self.jobs.lock().unwrap();
+            let now = Utc::now();
+            let next = jobs
+                .jobs
+                .values_mut()
+                .filter(|job| job.status == JobStatus::Queued && job.run_at <= now)
+                .min_by_key(|job| (job.run_at, job.id));
+            Ok(next.map(|job| {
+                job.status = JobStatus::Running;
+                job.attempts += 1;
+                job.updated_at = now;
+                job.clone()
+            }))
+        })
+    }
+
+    fn finish(
+        &self,
+        id: i64,
+        status: JobStatus,
+        run_at: DateTime<Utc>,
+        error: Option<String>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            if let Some(job) = self.jobs.lock().unwrap().jobs.get_mut(&id) {
+                job.status = status;
+                job.run_at = run_at;
+                job.last_error = error.or(job.last_error.take());
+                job.updated_at = Utc::now();
+            }
+            Ok(())
+        })
+    }
+
+    fn requeue_running(&self) -> BoxFuture<'_, anyhow::Result<u64>> {
+        // Nothing outlives the process.
+        Box::pin(async { Ok(0) })
+    }
+
+    fn list(&self, limit: usize) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>> {
+        Box::pin(async move {
+            let jobs = self.jobs.lock().unwrap();
+            Ok(jobs.jobs.values().rev().take(limit).cloned().collect())
+        })
+    }
+
+    fn counts(&self) -> BoxFuture<'_, anyhow::Result<BTreeMap<JobStatus, u64>>> {
+        Box::pin(async move {
+            let mut counts = BTreeMap::new();
+            for job in self.jobs.lock().unwrap().jobs.values() {
+                *counts.entry(job.status).or_default() += 1;
+            }
+            Ok(counts)
+        })
+    }
+}
+
+/// The `jobs` table from `migrations/`.
+#[cfg(feature = "database")]
+struct SqliteStore(sqlx::SqlitePool);
+
+#[cfg(feature = "database")]
+#[derive(sqlx::FromRow)]
+struct JobRow {
+    id: i64,
+    kind: String,
+    payload: String,
+    status: String,
+    attempts: i64,
+    max_attempts: i64,
+    run_at: i64,
+    last_error: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[cfg(feature = "database")]
+impl TryFrom<JobRow> for JobRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(row: JobRow) -> anyhow::Result<Self> {
+        let time = |ms| DateTime::from_timestamp_millis(ms).unwrap_or_default();
+        Ok(Self {
+            id: row.id,
+            kind: row.kind,
+            payload: serde_json::from_str(&row.payload)?,
+            status: serde_json::from_value(Value::String(row.status))?,
+            attempts: row.attempts.try_into()?,
+            max_attempts: row.max_attempts.try_into()?,
+            run_at: time(row.run_at),
+            last_error: row.last_error,
+            created_at: time(row.created_at),
+            updated_at: time(row.updated_at),
+        })
+    }
+}
+
+#[cfg(feature = "database")]
+fn status_str(status: JobStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "database")]
+impl JobStore for SqliteStore {
+    fn insert(
+        &self,
+        kind: &'static str,
+        payload: Value,
+        max_attempts: u32,
+    ) -> BoxFuture<'_, anyhow::Result<i64>> {
+        Box::pin(async move {
+            let now = Utc::now().timestamp_millis();
+            let (id,): (i64,) = sqlx::query_as(
+                "INSERT INTO jobs (kind, payload, status, max_attempts, run_at, created_at, updated_at) \
+                 VALUES (?, ?, 'queued', ?, ?, ?, ?) RETURNING id",
+            )
+            .bind(kind)
+            .bind(payload.to_string())
+            .bind(max_attempts)
+            .bind(now)
+            .bind(now)
+            .bind(now)
+            .fetch_one(&self.0)
+            .await?;
+            sqlx::query(
+                "DELETE FROM jobs WHERE status IN ('done', 'failed') AND id NOT IN \
+                 (SELECT id FROM jobs WHERE status IN ('done', 'failed') ORDER BY id DESC LIMIT ?)",
+            )
+            .bind(KEEP_FINISHED)
+            .execute(&self.0)
+            .await?;
+            Ok(id)
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, anyhow::Result<Option<JobRecord>>> {
+        Box::pin(async move {
+            let now = Utc::now().timestamp_millis();
+            let row: Option<JobRow> = sqlx::query_as(
+                "UPDATE jobs SET status = 'running', attempts = attempts + 1, updated_at = ? \
+                 WHERE id = (SELECT id FROM jobs WHERE status = 'queued' AND run_at <= ? \
+                 ORDER BY run_at, id LIMIT 1) RETURNING *",
+            )
+            .bind(now)
+            .bind(now)
+            .fetch_optional(&self.0)
+            .await?;
+            row.map(JobRecord::try_from).transpose()
+        })
+    }
+
+    fn finish(
+        &self,
+        id: i64,
+        status: JobStatus,
+        run_at: DateTime<Utc>,
+        error: Option<String>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "UPDATE jobs SET status = ?, run_at = ?, last_error = COALESCE(?, last_error), \
+                 updated_at = ? WHERE id = ?",
+            )
+            .bind(status_str(status))
+            .bind(run_at.timestamp_millis())
+            .bind(error)
+            .bind(Utc::now().timestamp_millis())
+            .bind(id)
+            .execute(&self.0)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn requeue_running(&self) -> BoxFuture<'_, anyhow::Result<u64>> {
+        Box::pin(async move {
+            let result = sqlx::query("UPDATE jobs SET status = 'queued' WHERE status = 'running'")
+                .execute(&self.0)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn list(&self, limit: usize) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>> {
+        Box::pin(async move {
+            let rows: Vec<JobRow> = sqlx::query_as("SELECT * FROM jobs ORDER BY id DESC LIMIT ?")
+                .bind(limit as i64)
+                .fetch_all(&self.0)
+                .await?;
+            rows.into_iter().map(JobRecord::try_from).collect()
+        })
+    }
+
+    fn counts(&self) -> BoxFuture<'_, anyhow::Result<BTreeMap<JobStatus, u64>>> {
+        Box::pin(async move {
+            let rows: Vec<(String, i64)> =
+                sqlx::query_as("SELECT status, COUNT(*) FROM jobs GROUP BY status")
+                    .fetch_all(&self.0)
+                    .await?;
+            rows.into_iter()
+                .map(|(status, count)| {
+                    Ok((
+                        serde_json::from_value(Value::String(status))?,
+                        count.try_into()?,
+                    ))
+                })
+                .collect()
+        })
+    }
+}
+
+/// The queue, its handlers and the settings the workers run with.
+pub struct Jobs {
+    store: Box<dyn JobStore>,
+    registry: JobRegistry,
+    config: JobsConfig,
+    /// Wakes an idle worker when a job is queued.
+    queued: Notify,
+}
+
+impl Jobs {
+    pub fn new(config: &JobsConfig, #[cfg(feature = "database")] db: sqlx::SqlitePool) -> Self {
+        #[cfg(feature = "database")]
+        let store = Box::new(SqliteStore(db));
+        #[cfg(not(feature = "database"))]
+        let store = Box::new(MemoryStore::default());
+        Self {
+            store,
+            registry: registry(),
+            config: config.clone(),
+            queued: Notify::new(),
+        }
+    }
+
+    /// Queues `job` to run as soon as a worker is free. Returns its id.
+    pub async fn enqueue<J: Job>(&self, job: J) -> anyhow::Result<i64> {
+        if !self.registry.kinds.contains_key(J::KIND) {
+            anyhow::bail!("job kind {:?} isn't in jobs::registry()", J::KIND);
+        }
+        let id = self
+            .store
+            .insert(
+                J::KIND,
+                serde_json::to_value(job)?,
+                self.config.max_attempts,
+            )
+            .await?;
+        self.queued.notify_one();
+        Ok(id)
+    }
+
+    /// Wait before the run after `attempt`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.config
+                .backoff_ms
+                .saturating_mul(factor)
+                .min(self.config.max_backoff_ms),
+        )
+    }
+
+    async fn run(&self, state: &AppState, job: JobRecord) {
+        let started = Instant::now();
+        let handler = self.registry.kinds.get(job.kind.as_str());
+        let result = match handler {
+            Some(handler) => {
+                let ctx = JobContext {
+                    state: state.clone(),
+                    id: job.id,
+                    attempt: job.attempts,
+                };
+                // Spawned so a panic fails the job rather than the worker.
+                tokio::spawn(handler(job.payload.clone(), ctx).in_current_span())
+                    .await
+                    .unwrap_or_else(|e| Err(anyhow!("{}", e)))
+            }
+            None => Err(anyhow!("no handler for job kind {:?}", job.kind)),
+        };
+
+        let now = Utc::now();
+        let (status, run_at, error) = match result {
+            Ok(()) => {
+                info!(
+                    "✅ Job {} #{} done in {:.1}ms",
+                    job.kind,
+                    job.id,
+                    started.elapsed().as_secs_f64() * 1000.0
+                );
+                (JobStatus::Done, now, None)
+            }
+            Err(e) if handler.is_some() && job.attempts < job.max_attempts => {
+                let delay = self.backoff(job.attempts);
+                warn!(
+                    "Job {} #{} failed (attempt {}/{}), retrying in {:?}: {:#}",
+                    job.kind, job.id, job.attempts, job.max_attempts, delay, e
+                );
+                let run_at = now + chrono::Duration::from_std(delay).unwrap_or_default();
+                (JobStatus::Queued, run_at, Some(format!("{:#}", e)))
+            }
+            Err(e) => {
+                error!(
+                    "❌ Job {} #{} failed after {} attempts: {:#}",
+                    job.kind, job.id, job.attempts, e
+                );
+                (JobStatus::Failed, now, Some(format!("{:#}", e)))
+            }
+        };
+        if let Err(e) = self.store.finish(job.id, status, run_at, error).await {
+            error!("Failed to record the outcome of job #{}: {:#}", job.id, e);
+        }
+    }
+}
+
+/// Requeues jobs a previous run left half done, then starts `jobs.workers`
+/// workers. They stop taking jobs once shutdown begins.
+pub async fn start(state: &AppState) -> anyhow::Result<()> {
+    let jobs = state.jobs();
+    let requeued = jobs.store.requeue_running().await?;
+    if requeued > 0 {
+        info!("♻️  Requeued {} interrupted jobs", requeued);
+    }
+    for _ in 0..jobs.config.workers {
+        tokio::spawn(work(state.clone()));
+    }
+    Ok(())
+}
+
+async fn work(state: AppState) {
+    let shutdown = state.shutdown_token();
+    let jobs = state.jobs();
+    while !shutdown.is_cancelled() {
+        let job = jobs.store.claim().await.unwrap_or_else(|e| {
+            error!("Failed to claim a job: {:#}", e);
+            None
+        });
+        let Some(job) = job else {
+            tokio::select! {
+                _ = jobs.queued.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = shutdown.cancelled() => {}
+            }
+            continue;
+        };
+        let span = info_span!("job", kind = %job.kind, id = job.id, attempt = job.attempts);
+        jobs.run(&state, job).instrument(span).await;
+    }
+}
+
+/// Logs `message` after `delay_ms` and publishes it as a `job.demo` event.
+/// Its first `fail_times` runs fail, to show retries.
+#[derive(Serialize, Deserialize, ToSchema, Validate)]
+pub struct DemoJob {
+    #[validate(length(min = 1, max = 4096, message = "must be 1 to 4096 characters"))]
+    #[schema(min_length = 1, max_length = 4096)]
+    message: String,
+    #[serde(default)]
+    #[validate(range(max = 60000, message = "must be at most 60000"))]
+    delay_ms: u64,
+    #[serde(default)]
+    fail_times: u32,
+}
+
+impl DemoJob {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            delay_ms: 0,
+            fail_times: 0,
+        }
+    }
+}
+
+impl Job for DemoJob {
+    const KIND: &'static str = "demo";
+
+    async fn run(self, ctx: JobContext) -> anyhow::Result<()> {
+        tokio::time::sleep(Duration::from_millis(self.delay_ms)).await;
+        if ctx.attempt <= self.fail_times {
+            anyhow::bail!(
+                "failing on purpose ({} of {})",
+                ctx.attempt,
+                self.fail_times
+            );
+        }
+        info!("📨 Demo job #{}: {}", ctx.id, self.message);
+        ctx.state.events().emit(&DemoJobDone {
+            id: ctx.id,
+            message: self.message,
+        });
+        Ok(())
+    }
+}
+
+/// Published when a [`DemoJob`] finishes.
+#[derive(Serialize, Deserialize)]
+pub struct DemoJobDone {
+    id: i64,
+    message: String,
+}
+
+impl Topic for DemoJobDone {
+    const KIND: &'static str = "job.demo";
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobQueued {
+    id: i64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/jobs/demo",
+    tag = "jobs",
+    request_body = DemoJob,
+    responses(
+        (status = 202, description = "Job queued", body = JobQueued),
+        (status = 422, description = "Invalid job")
+    )
+)]
+pub async fn enqueue_demo_handler(
+    State(state): State<AppState>,
+    ValidatedJson(job): ValidatedJson<DemoJob>,
+) -> AppResult<(StatusCode, Json<JobQueued>)> {
+    let id = state
+        .jobs()
+        .enqueue(job)
+        .await
+        .map_err(AppError::internal)?;
+    Ok((StatusCode::ACCEPTED, Json(JobQueued { id })))
+}
+
+#[derive(Serialize)]
+pub struct JobsReport {
+    workers: usize,
+    counts: BTreeMap<JobStatus, u64>,
+    /// The latest jobs, newest first.
+    jobs: Vec<JobRecord>,
+}
+
+/// `/debug/jobs`: queue counts and the latest jobs.
+pub async fn debug_handler(State(state): State<AppState>) -> AppResult<Json<JobsReport>> {
+    let jobs = state.jobs();
+    Ok(Json(JobsReport {
+        workers: jobs.config.workers,
+        counts: jobs.store.counts().await.map_err(AppError::internal)?,
+        jobs: jobs
+            .store
+            .list(LIST_LIMIT)
+            .await
+            .map_err(AppError::internal)?,
+    }))
+}
@@ -0,0 +1,146 @@
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs};
+use tracing::debug;
+
+use crate::config::CookieKeysConfig;
+
+/// `version=secret,...` on top of the configured keys.
+const KEYS_ENV: &str = "COOKIE_KEYS";
+
+/// What a sealed value held, and whether it should be sealed again.
+pub struct Opened {
+    pub plaintext: Vec<u8>,
+    /// Sealed with a key older than the newest; sealing it again moves it
+    /// onto the newest before the old key is dropped.
+    pub stale: bool,
+}
+
+/// Versioned AES-256-GCM keys for cookie values. A sealed value is
+/// `<version>.<nonce and ciphertext, base64url>`, made with the newest key;
+/// any key in the ring opens one. Values from before keys had versions
+/// have no prefix and open with version `0`.
+pub struct Keyring {
+    /// Newest first.
+    keys: Vec<(u32, LessSafeKey)>,
+    ephemeral: bool,
+}
+
+impl Keyring {
+    pub fn new(config: &CookieKeysConfig) -> anyhow::Result<Self> {
+        let mut secrets = BTreeMap::new();
+        let mut add = |version: &str, secret: String, source: &str| {
+            let version: u32 = version
+                .trim()
+                .parse()
+                .with_context(|| format!("{}: key version {:?} isn't a number", source, version))?;
+            if secret.is_empty() {
+                anyhow::bail!("{}: key {} is empty", source, version);
+            }
+            secrets.insert(version, secret);
+            Ok(())
+        };
+        if let Ok(secret) = std::env::var("SESSION_SECRET")
+            && !secret.is_empty()
+        {
+            add("0", secret, "SESSION_SECRET")?;
+        }
+        for (version, secret) in &config.keys {
+            add(version, secret.clone(), "cookie_keys.keys")?;
+        }
+        if let Some(path) = &config.keyring_path {
+            let source = path.display().to_string();
+            let keys: BTreeMap<String, String> = fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice(&json)?))
+                .with_context(|| format!("failed to read the keyring {}", source))?;
+            for (version, secret) in keys {
+                add(&version, secret, &source)?;
+            }
+        }
+        if let Ok(list) = std::env::var(KEYS_ENV) {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let Some((version, secret)) = entry.split_once('=') else {
+                    anyhow::bail!("{}: expected version=secret, got {:?}", KEYS_ENV, entry);
+                };
+                add(version, secret.trim().to_string(), KEYS_ENV)?;
+            }
+        }
+
+        let ephemeral = secrets.is_empty();
+        if ephemeral {
+            let mut secret = [0; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secrets.insert(0, hex::encode(secret));
+        }
+        let keys = secrets
+            .into_iter()
+            .rev()
+            .map(|(version, secret)| {
+                // Any length of secret makes a 256-bit key.
+                let key = UnboundKey::new(&AES_256_GCM, &Sha256::digest(secret))
+                    .map_err(|_| anyhow::anyhow!("failed to make cookie key {}", version))?;
+                Ok((version, LessSafeKey::new(key)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        debug!(
+            "🔑 Cookie key versions {:?}; sealing with {}",
+            keys.iter().map(|(v, _)| v).collect::<Vec<_>>(),
+            keys[0].0
+        );
+        Ok(Self { keys, ephemeral })
+    }
+
+    /// Whether the only key was made up at startup, so nothing sealed
+    /// survives a restart.
+    pub fn ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
+    /// `plaintext` encrypted with the newest key, so the browser can
+    /// neither read nor forge it. `purpose` (e.g. the cookie's name) is
+    /// bound in, so a value sealed for one cookie won't open as another.
+    pub fn seal(&self, purpose: &str, plaintext: &[u8]) -> anyhow::Result<String> {
+        let (version, key) = &self.keys[0];
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(purpose),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow::anyhow!("failed to encrypt the {} cookie", purpose))?;
+        Ok(format!(
+            "{}.{}",
+            version,
+            URL_SAFE_NO_PAD.encode([&nonce[..], &sealed].concat())
+        ))
+    }
+
+    /// What `value` holds, if one of our keys sealed it for `purpose`.
+    pub fn open(&self, purpose: &str, value: &str) -> Option<Opened> {
+        let (version, value) = match value.split_once('.') {
+            Some((version, value)) => (version.parse().ok()?, value),
+            None => (0, value),
+        };
+        let key = self.keys.iter().find(|(v, _)| *v == version)?;
+        let mut value = URL_SAFE_NO_PAD.decode(value).ok()?;
+        if value.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = value.split_at_mut(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let plaintext = key
+            .1
+            .open_in_place(nonce, Aad::from(purpose), sealed)
+            .ok()?;
+        Some(Opened {
+            plaintext: plaintext.to_vec(),
+            stale: version != self.keys[0].0,
+        })
+    }
+}
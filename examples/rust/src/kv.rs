@@ -0,0 +1,440 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf, sync::Mutex, time::Duration};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    config::{KvBackend, KvConfig},
+    error::{AppError, AppResult},
+    state::AppState,
+    tenancy::Tenant,
+};
+
+const MAX_KEY_LEN: usize = 200;
+
+/// A year; longer than any prototype needs.
+const MAX_TTL_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Where `/api/kv` keeps its values. Values are arbitrary JSON; a `ttl`
+/// makes one expire.
+pub trait KvStore: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<serde_json::Value>>>;
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Whether there was a value to delete.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+    /// Live keys, sorted.
+    fn keys(&self) -> BoxFuture<'_, anyhow::Result<Vec<String>>>;
+}
+
+/// The store picked by `kv.backend`.
+pub fn open(
+    config: &KvConfig,
+    #[cfg(feature = "redis")] redis: std::sync::Arc<crate::cache::Redis>,
+) -> anyhow::Result<Box<dyn KvStore>> {
+    Ok(match config.backend {
+        KvBackend::Memory => Box::new(MemoryStore::default()),
+        KvBackend::File => Box::new(FileStore::new(config.dir.clone())),
+        #[cfg(feature = "redis")]
+        KvBackend::Redis => Box::new(RedisStore(redis)),
+        #[cfg(not(feature = "redis"))]
+        KvBackend::Redis => anyhow::bail!("kv.backend \"redis\" needs the `redis` feature"),
+    })
+}
+
+/// A value with its expiry, as the memory and file backends keep it.
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    value: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Entry {
+    fn new(value: serde_json::Value, ttl: Option<Duration>) -> anyhow::Result<Self> {
+        let expires_at = ttl
+            .map(|ttl| anyhow::Ok(chrono::Utc::now() + chrono::Duration::from_std(ttl)?))
+            .transpose()?;
+        Ok(Self { value, expires_at })
+    }
+
+    fn expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now())
+    }
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl KvStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<serde_json::Value>>> {
+        Box::pin(async move {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some(entry) if entry.expired() => {
+                    entries.remove(key);
+                    Ok(None)
+                }
+                entry => Ok(entry.map(|entry| entry.value.clone())),
+            }
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let entry = Entry::new(value, ttl)?;
+            self.entries.lock().unwrap().insert(key.to_string(), entry);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let entry = self.entries.lock().unwrap().remove(key);
+            Ok(entry.is_some_and(|entry| !entry.expired()))
+        })
+    }
+
+    fn keys(&self) -> BoxFuture<'_, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, entry| !entry.expired());
+            Ok(entries.keys().cloned().collect())
+        })
+    }
+}
+
+/// One `<key>.json` per key. Keys are restricted to characters that are
+/// safe in file names (see [`check_key`]), except `:`, which Windows doesn't
+/// allow and is written as `%3A`.
+struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key.replace(':', "%3A")))
+    }
+
+    async fn read(&self, key: &str) -> anyhow::Result<Option<Entry>> {
+        let path = self.path(key);
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let entry: Entry = serde_json::from_slice(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        if entry.expired() {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl KvStore for FileStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<serde_json::Value>>> {
+        Box::pin(async move { Ok(self.read(key).await?.map(|entry| entry.value)) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let entry = Entry::new(value, ttl)?;
+            tokio::fs::create_dir_all(&self.dir).await?;
+            // Written aside and renamed so readers never see half a file.
+            let path = self.path(key);
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, serde_json::to_vec_pretty(&entry)?).await?;
+            tokio::fs::rename(&tmp, &path)
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let existed = self.read(key).await?.is_some();
+            match tokio::fs::remove_file(self.path(key)).await {
+                Ok(()) => Ok(existed),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn keys(&self) -> BoxFuture<'_, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut dir = match tokio::fs::read_dir(&self.dir).await {
+                Ok(dir) => dir,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+            let mut keys = Vec::new();
+            while let Some(file) = dir.next_entry().await? {
+                let name = file.file_name();
+                let Some(key) = name.to_str().and_then(|n| n.strip_suffix(".json")) else {
+                    continue;
+                };
+                let key = key.replace("%3A", ":");
+                if check_key(&key).is_ok() && self.read(&key).await?.is_some() {
+                    keys.push(key);
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
+
+/// Values live under `<project>:kv:<key>`; Redis handles expiry.
+#[cfg(feature = "redis")]
+struct RedisStore(std::sync::Arc<crate::cache::Redis>);
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    const PREFIX: &'static str = "{{.ProjectName}}:kv:";
+
+    fn key(key: &str) -> String {
+        format!("{}{}", Self::PREFIX, key)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl KvStore for RedisStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<serde_json::Value>>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let json: Option<String> = self.0.connection().await?.get(Self::key(key)).await?;
+            Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let mut connection = self.0.connection().await?;
+            let json = value.to_string();
+            match ttl {
+                Some(ttl) => {
+                    connection
+                        .set_ex::<_, _, ()>(Self::key(key), json, ttl.as_secs())
+                        .await?
+                }
+                None => connection.set::<_, _, ()>(Self::key(key), json).await?,
+            }
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<bool>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let deleted: u64 = self.0.connection().await?.del(Self::key(key)).await?;
+            Ok(deleted > 0)
+        })
+    }
+
+    fn keys(&self) -> BoxFuture<'_, anyhow::Result<Vec<String>>> {
+        use futures::StreamExt;
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let mut connection = self.0.connection().await?;
+            let pattern = format!("{}*", Self::PREFIX);
+            let keys: Vec<String> = connection.scan_match(&pattern).await?.collect().await;
+            let mut keys: Vec<String> = keys
+                .into_iter()
+                .filter_map(|key| key.strip_prefix(Self::PREFIX).map(str::to_string))
+                .collect();
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
+
+/// Keys are 1 to 200 of `A-Z a-z 0-9 _ - . :`, not starting with a dot, so
+/// every backend can store them as-is.
+fn check_key(key: &str) -> AppResult<()> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && !key.starts_with('.')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'));
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::bad_request(format!(
+            "Keys are 1 to {} of A-Z a-z 0-9 _ - . : and can't start with a dot",
+            MAX_KEY_LEN
+        )))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct KvKeys {
+    keys: Vec<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PutParams {
+    /// Seconds until the value expires, at most a year; omit to keep it.
+    ttl: Option<u64>,
+    /// Comma-separated cache tags; invalidating any of them deletes the key.
+    tags: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/kv",
+    tag = "kv",
+    responses((status = 200, description = "Every live key, sorted", body = KvKeys))
+)]
+pub async fn list_handler(
+    State(state): State<AppState>,
+    tenant: Tenant,
+) -> AppResult<Json<KvKeys>> {
+    let keys = state.kv().keys().await.map_err(AppError::internal)?;
+    let keys = keys
+        .iter()
+        .filter_map(|key| tenant.unkey(key))
+        .map(str::to_string)
+        .collect();
+    Ok(Json(KvKeys { keys }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/kv/{key}",
+    tag = "kv",
+    params(("key" = String, Path, description = "Key")),
+    responses(
+        (status = 200, description = "The stored JSON value", body = Object),
+        (status = 400, description = "Invalid key"),
+        (status = 404, description = "No such key, or it expired")
+    )
+)]
+pub async fn get_handler(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    tenant: Tenant,
+) -> AppResult<Json<serde_json::Value>> {
+    check_key(&key)?;
+    state
+        .kv()
+        .get(&tenant.key(&key))
+        .await
+        .map_err(AppError::internal)?
+        .map(Json)
+        .ok_or_else(|| AppError::not_found(format!("No value for key '{}'", key)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/kv/{key}",
+    tag = "kv",
+    params(("key" = String, Path, description = "Key"), PutParams),
+    request_body(content = Object, description = "Any JSON value"),
+    responses(
+        (status = 204, description = "Value stored"),
+        (status = 400, description = "Invalid key or ttl")
+    )
+)]
+pub async fn put_handler(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    tenant: Tenant,
+    Query(params): Query<PutParams>,
+    Json(value): Json<serde_json::Value>,
+) -> AppResult<StatusCode> {
+    check_key(&key)?;
+    let ttl = match params.ttl {
+        Some(ttl) if ttl == 0 || ttl > MAX_TTL_SECS => {
+            return Err(AppError::bad_request(format!(
+                "ttl must be 1 to {} seconds",
+                MAX_TTL_SECS
+            )))
+        }
+        ttl => ttl.map(Duration::from_secs),
+    };
+    let key = tenant.key(&key);
+    state
+        .kv()
+        .put(&key, value, ttl)
+        .await
+        .map_err(AppError::internal)?;
+    let tags = params.tags.as_deref().unwrap_or_default().split(',');
+    for tag in tags.map(str::trim).filter(|tag| !tag.is_empty()) {
+        state.response_cache().tag_kv(tag, &key);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/kv/{key}",
+    tag = "kv",
+    params(("key" = String, Path, description = "Key")),
+    responses(
+        (status = 204, description = "Value deleted"),
+        (status = 400, description = "Invalid key"),
+        (status = 404, description = "No such key")
+    )
+)]
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    tenant: Tenant,
+) -> AppResult<StatusCode> {
+    check_key(&key)?;
+    if state
+        .kv()
+        .delete(&tenant.key(&key))
+        .await
+        .map_err(AppError::internal)?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found(format!("No value for key '{}'", key)))
+    }
+}
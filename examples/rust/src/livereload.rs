@@ -0,0 +1,203 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{path::Path, sync::Mutex, time::Duration};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::{security_headers, state::AppState};
+
+/// Editors touch a file several times per save; wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// HTML bodies larger than this are passed through untouched.
+const MAX_INJECT_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Reloads the page when told to, and after the server comes back from a
+/// restart (the socket drops, then reconnects).
+const CLIENT_SCRIPT: &str = r#"<script>
+(function () {
+  var url = (location.protocol === "https:" ? "wss://" : "ws://") + location.host + "/__nsm/reload";
+  var dropped = false;
+  function connect() {
+    var ws = new WebSocket(url);
+    ws.onopen = function () { if (dropped) location.reload(); };
+    ws.onmessage = function (e) { if (e.data === "reload") location.reload(); };
+    ws.onclose = function () { dropped = true; setTimeout(connect, 1000); };
+  }
+  connect();
+})();
+</script>
+"#;
+
+/// Browser live reload for debug builds under NSM: a watcher on the given
+/// directories tells every `/__nsm/reload` socket to refresh the page.
+pub struct LiveReload {
+    tx: broadcast::Sender<()>,
+    // Dropping the watcher stops it.
+    _watcher: Mutex<RecommendedWatcher>,
+}
+
+impl LiveReload {
+    /// Enabled for debug builds when running under NSM.
+    pub fn enabled() -> bool {
+        cfg!(debug_assertions) && crate::nsm_enabled()
+    }
+
+    /// Starts watching `dirs` (missing ones are skipped). Must be called from
+    /// within the Tokio runtime.
+    pub fn start(dirs: &[&str]) -> anyhow::Result<Self> {
+        let (tx, _) = broadcast::channel(16);
+        let (changes_tx, changes_rx) = mpsc::unbounded_channel();
+
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res
+                    && !matches!(event.kind, EventKind::Access(_))
+                    && event.paths.iter().any(|p| !is_editor_noise(p))
+                {
+                    let _ = changes_tx.send(());
+                }
+            })?;
+        for dir in dirs.iter().map(Path::new).filter(|d| d.is_dir()) {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+        tokio::spawn(debounce(changes_rx, tx.clone()));
+
+        info!("🔄 Live reload watching {}", dirs.join(", "));
+        Ok(Self {
+            tx,
+            _watcher: Mutex::new(watcher),
+        })
+    }
+}
+
+/// Swap files, backups and dotfiles don't warrant a reload.
+fn is_editor_noise(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    name.starts_with('.') || name.ends_with('~') || name.ends_with(".swp") || name.ends_with(".tmp")
+}
+
+/// Collapses a burst of file events into one reload, sent once things have
+/// been quiet for `DEBOUNCE`.
+async fn debounce(mut changes: mpsc::UnboundedReceiver<()>, tx: broadcast::Sender<()>) {
+    while changes.recv().await.is_some() {
+        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, changes.recv()).await {}
+        debug!("Files changed, reloading browsers");
+        let _ = tx.send(());
+    }
+}
+
+/// `/__nsm/reload`: sends `reload` whenever a watched file changes.
+pub async fn reload_ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState) {
+    let Some(live_reload) = state.live_reload() else {
+        return;
+    };
+    let mut reloads = live_reload.tx.subscribe();
+    let shutdown = state.shutdown_token();
+
+    loop {
+        tokio::select! {
+            reload = reloads.recv() => {
+                if matches!(reload, Err(broadcast::error::RecvError::Closed))
+                    || socket.send(Message::Text("reload".into())).await.is_err()
+                {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    break;
+                }
+            }
+            _ = shutdown.cancelled() => {
+                let _ = socket
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "server restarting".into(),
+                    })))
+                    .await;
+                break;
+            }
+        }
+    }
+}
+
+/// Adds the reload script to HTML responses while live reload is on.
+pub async fn inject_script(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if state.live_reload().is_none() {
+        return next.run(req).await;
+    }
+
+    // Page loads are asked for uncompressed so the HTML can be patched.
+    let navigation = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"));
+    if navigation {
+        req.headers_mut().remove(header::ACCEPT_ENCODING);
+    }
+
+    let res = next.run(req).await;
+    let is_html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    let patchable = is_html
+        && !res.headers().contains_key(header::CONTENT_ENCODING)
+        && !matches!(
+            res.status(),
+            StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+        )
+        && res
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|len| len <= MAX_INJECT_BYTES);
+    if !patchable {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_INJECT_BYTES as usize).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Live reload: failed to buffer HTML response: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let html = String::from_utf8_lossy(&bytes);
+    let script = CLIENT_SCRIPT.replacen(
+        "<script>",
+        &format!("<script nonce=\"{}\">", security_headers::nonce()),
+        1,
+    );
+    let patched = match html.to_ascii_lowercase().rfind("</body>") {
+        Some(at) => format!("{}{}{}", &html[..at], script, &html[at..]),
+        None => format!("{}{}", html, script),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(patched))
+}
@@ -1,14 +1,140 @@
+#[cfg(feature = "database")]
+mod accounts;
+mod admin;
+#[cfg(feature = "database")]
+mod apikeys;
+mod assets;
+#[cfg(feature = "database")]
+mod audit;
+mod auth;
+mod basic_auth;
+mod bench;
+mod brute_force;
+mod build_info;
+#[cfg(feature = "redis")]
+mod cache;
+mod capture;
+mod chaos;
+mod cli;
+mod client_addr;
+mod config;
+#[cfg(feature = "nats")]
+mod consumer;
+mod dashboard;
+#[cfg(feature = "database")]
+mod db;
+#[cfg(feature = "email")]
+mod email;
+mod error;
+mod events;
+#[cfg(feature = "database")]
+mod export;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod har;
+mod health;
+mod hooks;
+#[cfg(feature = "database")]
+mod import;
+mod introspection;
+mod jobs;
+mod keyring;
+mod kv;
+mod livereload;
+mod metadata;
+mod mocks;
+mod mtls;
+#[cfg(feature = "database")]
+mod notes;
+mod oidc;
+mod openapi;
+mod outbound;
+#[cfg(feature = "database")]
+mod outbox;
+mod pagination;
+#[cfg(feature = "database")]
+mod passwords;
+mod preflight;
+mod preview;
+mod privileged;
+mod ratelimit;
+mod rbac;
+mod redaction;
+mod reload;
+mod request_log;
+mod response_cache;
+mod routes;
+mod rpc;
+#[cfg(feature = "s3")]
+mod s3;
+mod scheduler;
+#[cfg(feature = "search")]
+mod search;
+mod security_headers;
+#[cfg(feature = "database")]
+mod seeds;
+mod selfcheck;
+mod sessions;
+mod signed_urls;
+mod signing;
+#[cfg(feature = "database")]
+mod soft_delete;
+mod state;
+mod static_files;
+mod static_keys;
+mod stats;
+mod streaming;
+mod templates;
+mod tenancy;
+#[cfg(feature = "database")]
+mod two_factor;
+#[cfg(feature = "database")]
+mod tx;
+mod uploads;
+mod upstream;
+mod validation;
+mod watch;
+mod watchdog;
+mod ws;
+
+#[cfg(all(feature = "console", not(tokio_unstable)))]
+compile_error!("the `console` feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+#[cfg(feature = "database")]
+use axum::handler::Handler;
 use axum::{
-    extract::Query,
-    http::{HeaderMap, StatusCode},
-    response::{Html, Json},
-    routing::{get, post},
-    Router,
+    extract::{DefaultBodyLimit, Query, Request, State},
+    http::HeaderMap,
+    middleware,
+    response::{Html, IntoResponse, Json, Response},
 };
+use clap::Parser;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, net::SocketAddr};
-use tower_http::{cors::CorsLayer, services::ServeDir};
-use tracing::{info, warn};
+use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+};
+use tracing::{error, info, warn};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    error::{AppError, AppResult},
+    hooks::Hook,
+    routes::{Route, Routes},
+    state::{config_profile, AppState},
+    validation::ValidatedJson,
+};
+
+const PROJECT_NAME: &str = "{{.ProjectName}}";
+const DEFAULT_DOMAIN: &str = "{{.Domain}}";
+
+/// Written by NSM with the ports it leased to this project.
+const NSM_PORTS_FILE: &str = ".nsm-ports.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 struct NSMConfig {
@@ -27,51 +153,96 @@ impl Default for NSMConfig {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
 struct AppInfo {
     name: String,
     version: String,
     domain: String,
     nsm_enabled: bool,
     timestamp: chrono::DateTime<chrono::Utc>,
+    #[cfg_attr(feature = "graphql", graphql(skip))]
     headers: Option<HashMap<String, String>>,
 }
 
-#[derive(Serialize)]
-struct HealthResponse {
-    status: String,
-    timestamp: chrono::DateTime<chrono::Utc>,
-    uptime: String,
+fn nsm_enabled() -> bool {
+    std::env::var("NSM_ENABLED").unwrap_or_default() == "true"
+}
+
+/// The domain NSM routes to us (`NSM_DOMAIN`), or the one the project was
+/// generated with.
+fn domain() -> String {
+    std::env::var("NSM_DOMAIN").unwrap_or_else(|_| DEFAULT_DOMAIN.to_string())
 }
 
 fn load_nsm_config() -> NSMConfig {
-    match fs::read_to_string(".nsm-ports.json") {
-        Ok(contents) => match serde_json::from_str(&contents) {
-            Ok(config) => {
-                info!("🔧 NSM: Using HTTP port {}", config.http);
-                config
-            }
-            Err(e) => {
-                warn!("NSM: Failed to parse configuration: {}", e);
-                NSMConfig::default()
-            }
-        },
-        Err(_) => NSMConfig::default(),
+    match read_nsm_config() {
+        Ok(Some(config)) => {
+            info!("🔧 NSM: Using HTTP port {}", config.http);
+            config
+        }
+        Ok(None) => NSMConfig::default(),
+        Err(e) => {
+            warn!("NSM: Failed to parse configuration: {}", e);
+            NSMConfig::default()
+        }
     }
 }
 
-async fn home_handler() -> Html<&'static str> {
-    Html(include_str!("../templates/index.html"))
+/// The NSM port lease, or `None` when we weren't started by NSM.
+fn read_nsm_config() -> anyhow::Result<Option<NSMConfig>> {
+    match fs::read_to_string(NSM_PORTS_FILE) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
 
-async fn api_info_handler(
-    Query(params): Query<HashMap<String, String>>,
-    headers: HeaderMap,
-) -> Json<AppInfo> {
+/// Context for `templates/index.html`.
+#[derive(Serialize)]
+struct IndexContext<'a> {
+    project_name: &'a str,
+    domain: String,
+    nsm_enabled: bool,
+    version: &'a str,
+    routes: &'a [routes::RouteInfo],
+    /// Whether the demo works on `/api/notes` rather than `/api/echo`.
+    notes_demo: bool,
+}
+
+async fn home_handler(State(state): State<AppState>) -> AppResult<Html<String>> {
+    let context = IndexContext {
+        project_name: PROJECT_NAME,
+        domain: domain(),
+        nsm_enabled: nsm_enabled(),
+        version: build_info::BUILD_INFO.version,
+        routes: state.routes(),
+        notes_demo: cfg!(feature = "database"),
+    };
+
+    Ok(Html(state.templates().render("index.html", &context)?))
+}
+
+/// Query parameters accepted by `/api/info`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct InfoParams {
+    /// Include the request headers in the response when present.
+    debug: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/info",
+    tag = "demo",
+    params(InfoParams),
+    responses((status = 200, description = "Application metadata", body = AppInfo))
+)]
+async fn api_info_handler(Query(params): Query<InfoParams>, headers: HeaderMap) -> Json<AppInfo> {
     let mut header_map = HashMap::new();
-    
+
     // Include debug headers if requested
-    if params.get("debug").is_some() {
+    if params.debug.is_some() {
         for (name, value) in headers.iter() {
             if let Ok(value_str) = value.to_str() {
                 header_map.insert(name.to_string(), value_str.to_string());
@@ -79,89 +250,996 @@ async fn api_info_handler(
         }
     }
 
-    let nsm_enabled = std::env::var("NSM_ENABLED").unwrap_or_default() == "true";
-
-    Json(AppInfo {
-        name: "{{.ProjectName}}".to_string(),
-        version: "1.0.0".to_string(),
-        domain: "{{.Domain}}".to_string(),
-        nsm_enabled,
-        timestamp: chrono::Utc::now(),
-        headers: if header_map.is_empty() { None } else { Some(header_map) },
-    })
+    Json(app_info(if header_map.is_empty() {
+        None
+    } else {
+        Some(header_map)
+    }))
 }
 
-async fn health_handler() -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "healthy".to_string(),
+fn app_info(headers: Option<HashMap<String, String>>) -> AppInfo {
+    AppInfo {
+        name: PROJECT_NAME.to_string(),
+        version: build_info::BUILD_INFO.version.to_string(),
+        domain: domain(),
+        nsm_enabled: nsm_enabled(),
         timestamp: chrono::Utc::now(),
-        uptime: "running".to_string(),
-    })
+        headers,
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, Validate)]
 struct EchoRequest {
+    #[validate(length(min = 1, max = 4096, message = "must be 1 to 4096 characters"))]
+    #[schema(min_length = 1, max_length = 4096)]
     message: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
 struct EchoResponse {
     echo: String,
     timestamp: chrono::DateTime<chrono::Utc>,
     id: String,
 }
 
-async fn echo_handler(Json(payload): Json<EchoRequest>) -> Json<EchoResponse> {
-    Json(EchoResponse {
-        echo: payload.message,
+#[utoipa::path(
+    post,
+    path = "/api/echo",
+    tag = "demo",
+    request_body = EchoRequest,
+    responses(
+        (status = 200, description = "The message, echoed back", body = EchoResponse),
+        (status = 422, description = "The message is empty or too long")
+    )
+)]
+async fn echo_handler(ValidatedJson(payload): ValidatedJson<EchoRequest>) -> Json<EchoResponse> {
+    Json(echo(payload.message))
+}
+
+fn echo(message: String) -> EchoResponse {
+    EchoResponse {
+        echo: message,
         timestamp: chrono::Utc::now(),
         id: uuid::Uuid::new_v4().to_string(),
-    })
+    }
 }
 
-async fn not_found() -> (StatusCode, Json<serde_json::Value>) {
-    (
-        StatusCode::NOT_FOUND,
-        Json(serde_json::json!({
-            "error": "Not Found",
-            "message": "The requested resource was not found",
-            "timestamp": chrono::Utc::now()
-        })),
-    )
+async fn not_found() -> AppError {
+    AppError::not_found("The requested resource was not found")
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            std::env::var("RUST_LOG")
-                .unwrap_or_else(|_| "{{.ProjectName | replace "_" "-"}}=debug,tower_http=debug".into()),
+/// Router fallback: SPA deep links get `index.html` when enabled, everything
+/// else the JSON 404.
+async fn fallback(State(state): State<AppState>, req: Request) -> Response {
+    if let Some(mock) = state.mocks().lookup(req.method(), req.uri().path()) {
+        return mock.respond(req).await;
+    }
+    if let Some(spa) = state.spa()
+        && spa.matches(req.method(), req.uri().path())
+        && let Some(res) = spa.serve(req).await
+    {
+        return res;
+    }
+    not_found().await.into_response()
+}
+
+/// Every route the app serves. Register new routes here so they show up in
+/// `/api/routes`.
+fn app_routes(state: &AppState) -> Routes<AppState> {
+    // Leave room for multipart framing on top of the per-file limit.
+    let upload_body_limit = state.uploads().max_bytes() as usize + 1024 * 1024;
+
+    let routes = Routes::default()
+        .add(Route::new("/").get(home_handler).describe("Landing page"))
+        .add(
+            Route::new("/api/info")
+                .get(api_info_handler)
+                .describe("Application metadata"),
+        )
+        .add(
+            Route::new("/api/health")
+                .get(health::health_handler)
+                .describe("Health with dependency checks"),
+        )
+        .add(
+            Route::new("/api/version")
+                .get(build_info::version_handler)
+                .describe("Build metadata"),
+        )
+        .add(
+            Route::new("/api/routes")
+                .get(routes::routes_handler)
+                .describe("This route table"),
+        )
+        .add(
+            Route::new("/livez")
+                .get(health::livez_handler)
+                .describe("Liveness probe"),
+        )
+        .add(
+            Route::new("/readyz")
+                .get(health::readyz_handler)
+                .describe("Readiness probe"),
+        )
+        .add(
+            Route::new("/api/echo")
+                .post(echo_handler)
+                .describe("Echo a message back"),
+        )
+        .nest_service(
+            "/static",
+            static_files::static_service(static_files::STATIC_DIR, state.clone()),
+            "Static files",
+        )
+        .add(
+            Route::new("/static-manifest.json")
+                .get(assets::manifest_handler)
+                .describe("Fingerprinted static asset URLs"),
+        )
+        .add(
+            Route::new("/api/openapi.json")
+                .get(openapi::openapi_handler)
+                .describe("OpenAPI 3 document"),
+        )
+        .add(
+            Route::new("/api/events")
+                .get(events::sse_handler)
+                .post(events::publish_handler)
+                .describe("Server-sent events stream / publish an event"),
+        )
+        .add(
+            Route::new("/api/poll")
+                .get(events::poll_handler)
+                .describe("Long-poll for events (?since=&timeout=&topics=)"),
+        )
+        .add(
+            Route::new("/api/upload")
+                .post(uploads::upload_handler)
+                .map(|r| r.layer(DefaultBodyLimit::max(upload_body_limit)))
+                .describe("Multipart file upload"),
+        )
+        .add(
+            Route::new("/api/upload/url")
+                .get(uploads::upload_url_handler)
+                .describe("Signed upload URL (?expires_in=&bind_client=)"),
+        )
+        .add(
+            Route::new("/api/uploads")
+                .get(uploads::list_handler)
+                .describe("List uploads (?page=&limit=&cursor=&sort=&q=&content_type=)"),
+        )
+        .add(
+            Route::new("/api/uploads/:id")
+                .get(uploads::download_handler)
+                .describe("Download an uploaded file"),
+        )
+        .add(
+            Route::new("/api/uploads/:id/url")
+                .get(uploads::download_url_handler)
+                .describe("Presigned or signed download URL (?expires_in=&bind_client=)"),
+        )
+        .add(
+            Route::new("/admin/uploads/quarantine")
+                .get(uploads::quarantine_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Uploads held back as suspicious (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/uploads/:id/release")
+                .post(uploads::release_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Release a quarantined upload (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/uploads/:id")
+                .delete(uploads::delete_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Delete an upload (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/api/stream/ndjson")
+                .get(streaming::ndjson_handler)
+                .describe("Chunked NDJSON stream (?count=&delay_ms=)"),
+        )
+        .add(
+            Route::new("/api/stream/bytes")
+                .get(streaming::bytes_handler)
+                .describe("Large binary download (?size=)"),
+        )
+        .add(
+            Route::new("/api/session")
+                .get(sessions::session_handler)
+                .describe("Current session (demo: counts visits)"),
+        )
+        .add(
+            Route::new("/api/session/login")
+                .post(sessions::login_handler)
+                .describe(if cfg!(feature = "database") {
+                    "Log in to an account from /api/session/signup"
+                } else {
+                    "Demo login; any username, password \"password\""
+                }),
+        )
+        .add(
+            Route::new("/api/session/me")
+                .get(sessions::me_handler)
+                .auth()
+                .describe("Who the session is logged in as"),
+        )
+        .add(
+            Route::new("/api/session/logout")
+                .post(sessions::logout_handler)
+                .describe("End the session"),
+        )
+        .add(
+            Route::new("/api/me")
+                .get(auth::me_handler)
+                .auth()
+                .describe("Claims of the bearer JWT"),
+        )
+        .add(
+            Route::new("/api/jobs/demo")
+                .post(jobs::enqueue_demo_handler)
+                .describe("Queue a demo background job"),
+        )
+        .add(
+            Route::new("/api/kv")
+                .get(kv::list_handler)
+                .describe("Key-value store keys"),
+        )
+        .add(
+            Route::new("/api/kv/:key")
+                .get(kv::get_handler)
+                .put(kv::put_handler)
+                .delete(kv::delete_handler)
+                .describe("Key-value store for prototypes (?ttl= seconds, ?tags= on PUT)"),
+        )
+        .add(
+            Route::new("/admin/cache/invalidate")
+                .post(response_cache::invalidate_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Drop cached responses and KV keys by tag (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/maintenance")
+                .get(admin::maintenance_handler)
+                .put(admin::set_maintenance_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Maintenance mode status / toggle (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/rate-limits")
+                .get(ratelimit::usage_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Requests and limits left today by principal (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/privileged-log/export")
+                .get(privileged::export_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe(
+                    "Privileged action log as NDJSON (?since=&actor=&outcome=, Bearer ADMIN_TOKEN)",
+                ),
+        )
+        .add(
+            Route::new("/ws")
+                .get(ws::ws_handler)
+                .describe("WebSocket echo/broadcast (?mode=echo|broadcast&topics=)"),
+        )
+        .add(
+            Route::new("/docs")
+                .get(openapi::docs_handler)
+                .describe("Swagger UI"),
         )
-        .init();
+        .add(
+            Route::new("/favicon.ico")
+                .get(metadata::favicon_handler)
+                .describe("Site icon"),
+        )
+        .add(
+            Route::new("/robots.txt")
+                .get(metadata::robots_handler)
+                .describe("Crawler rules"),
+        )
+        .add(
+            Route::new("/oauth/introspect")
+                .post(introspection::introspect_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Whether a token is live, and whose (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/oauth/revoke")
+                .post(introspection::revoke_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Revoke an API key by value (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/security.txt")
+                .get(metadata::legacy_security_txt_handler)
+                .describe("Redirect to /.well-known/security.txt"),
+        )
+        .add(
+            Route::new("/.well-known/security.txt")
+                .get(metadata::security_txt_handler)
+                .describe("Where to report vulnerabilities (RFC 9116)"),
+        )
+        .add(
+            Route::new("/.well-known/change-password")
+                .get(metadata::change_password_handler)
+                .describe("Redirect to the password change page"),
+        )
+        .add(
+            Route::new("/.well-known/health")
+                .get(health::health_handler)
+                .describe("Alias for /api/health"),
+        )
+        .add(
+            Route::new("/.well-known/:name")
+                .get(metadata::well_known_handler)
+                .describe("Configured well-known documents"),
+        );
+
+    let routes = routes.add(
+        Route::new("/rpc")
+            .post(rpc::rpc_handler)
+            .map(|r| r.layer(axum::Extension(Arc::new(rpc::registry()))))
+            .describe("JSON-RPC 2.0 (single and batch calls)"),
+    );
+
+    let routes = if cfg!(debug_assertions) {
+        routes
+            .add(
+                Route::new("/__nsm/dashboard")
+                    .get(dashboard::dashboard_handler)
+                    .describe("Dev dashboard (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/requests")
+                    .get(capture::list_handler)
+                    .describe("Captured requests, newest first (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/requests/export.har")
+                    .get(har::export_handler)
+                    .describe("Captured requests as a HAR 1.2 file (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/requests/:id/replay")
+                    .post(capture::replay_handler)
+                    .describe("Re-issue a captured request (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/stats")
+                    .get(stats::stats_handler)
+                    .describe(
+                        "Runtime, memory, connection, pool, consumer and fd stats (debug builds)",
+                    ),
+            )
+            .add(
+                Route::new("/debug/token")
+                    .post(auth::token_handler)
+                    .describe("Issue an HS256 JWT {sub, scope, ttl_secs} (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/jobs")
+                    .get(jobs::debug_handler)
+                    .describe("Background job counts and latest jobs (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/tasks")
+                    .get(scheduler::tasks_handler)
+                    .describe("Scheduled tasks with their last and next runs (debug builds)"),
+            )
+    } else {
+        routes
+    };
+
+    let routes = if state.dev_oidc().is_some() {
+        routes
+            .add(
+                Route::new("/__nsm/oidc/.well-known/openid-configuration")
+                    .get(oidc::discovery_handler)
+                    .describe("Dev OIDC provider discovery document (debug builds)"),
+            )
+            .add(
+                Route::new("/__nsm/oidc/jwks.json")
+                    .get(oidc::jwks_handler)
+                    .describe("Dev OIDC provider signing keys (debug builds)"),
+            )
+            .add(
+                Route::new("/__nsm/oidc/authorize")
+                    .get(oidc::authorize_page_handler)
+                    .post(oidc::authorize_handler)
+                    .describe("Dev OIDC sign-in: pick a configured user (debug builds)"),
+            )
+            .add(
+                Route::new("/__nsm/oidc/token")
+                    .post(oidc::token_handler)
+                    .describe("Dev OIDC token endpoint, authorization_code + PKCE (debug builds)"),
+            )
+            .add(
+                Route::new("/__nsm/oidc/userinfo")
+                    .get(oidc::userinfo_handler)
+                    .auth()
+                    .describe("Dev OIDC userinfo (debug builds)"),
+            )
+    } else {
+        routes
+    };
+
+    let routes = if state.signed_requests().enabled() {
+        routes.add(
+            Route::new("/hooks/events")
+                .post(signing::events_handler)
+                .auth()
+                .describe("Republish a signed event {kind, data}, e.g. another service's outbox"),
+        )
+    } else {
+        routes
+    };
+
+    let routes = if !state.config().upstreams.is_empty() {
+        let proxy = upstream::proxy_handler;
+        routes.add(
+            Route::new("/upstream/:name/*path")
+                .get(proxy)
+                .post(proxy)
+                .put(proxy)
+                .patch(proxy)
+                .delete(proxy)
+                .describe("Record-and-replay proxy to configured upstream APIs"),
+        )
+    } else {
+        routes
+    };
+
+    let routes = if !state.config().chaos.rules.is_empty() {
+        routes.add(
+            Route::new("/__nsm/chaos")
+                .get(chaos::status_handler)
+                .put(chaos::toggle_handler)
+                .describe("Chaos injection status and toggle"),
+        )
+    } else {
+        routes
+    };
+
+    let routes = if state.live_reload().is_some() {
+        routes.add(
+            Route::new("/__nsm/reload")
+                .get(livereload::reload_ws_handler)
+                .describe("Browser live reload (debug builds under NSM)"),
+        )
+    } else {
+        routes
+    };
+
+    #[cfg(feature = "graphql")]
+    let routes = {
+        let schema = graphql::schema(state.clone());
+        let route = Route::new("/graphql").post(graphql::graphql_handler);
+        let route = if cfg!(debug_assertions) {
+            route.get(graphql::graphiql_handler)
+        } else {
+            route
+        };
+        routes.add(
+            route
+                .map(|r| r.layer(axum::Extension(schema)))
+                .describe("GraphQL (GraphiQL playground in debug builds)"),
+        )
+    };
+
+    #[cfg(feature = "grpc")]
+    let routes = grpc::add_services(routes, state);
+
+    #[cfg(feature = "database")]
+    let routes = routes
+        .add(
+            Route::new("/api/session/signup")
+                .post(sessions::signup_handler)
+                .describe("Create an account {username, password} and log in"),
+        )
+        .add(
+            Route::new("/api/session/2fa")
+                .get(two_factor::status_handler)
+                .auth()
+                .describe("Whether the account uses TOTP two-factor"),
+        )
+        .add(
+            Route::new("/api/session/2fa/setup")
+                .post(two_factor::setup_handler)
+                .auth()
+                .describe("Start turning two-factor on: a new TOTP secret and otpauth:// URL"),
+        )
+        .add(
+            Route::new("/api/session/2fa/qr")
+                .get(two_factor::qr_handler)
+                .auth()
+                .describe("The secret being set up as an SVG QR code"),
+        )
+        .add(
+            Route::new("/api/session/2fa/enable")
+                .post(two_factor::enable_handler)
+                .auth()
+                .describe("Confirm a code {code} to turn two-factor on; returns recovery codes"),
+        )
+        .add(
+            Route::new("/api/session/2fa/verify")
+                .post(two_factor::verify_handler)
+                .auth()
+                .describe(
+                    "Send a TOTP or recovery code {code} to finish logging in, or to step up",
+                ),
+        )
+        .add(
+            Route::new("/api/session/2fa/recovery-codes")
+                .post(two_factor::recovery_codes_handler)
+                .auth()
+                .describe("Replace the recovery codes (needs a recent code)"),
+        )
+        .add(
+            Route::new("/api/session/2fa/disable")
+                .post(two_factor::disable_handler)
+                .auth()
+                .describe("Turn two-factor off (needs a recent code)"),
+        )
+        .add(
+            Route::new("/api/notes")
+                .get(notes::list_handler)
+                .post(notes::create_handler)
+                .describe("Notes (SQLite demo)"),
+        )
+        .add(
+            Route::new("/api/notes/export")
+                .get(notes::export_handler)
+                .describe("Stream every note as NDJSON or CSV (?format=&q=)"),
+        )
+        .add(
+            Route::new("/api/notes/import")
+                .post(notes::import_handler)
+                .describe("Bulk-create notes from streamed NDJSON or CSV"),
+        )
+        .add(
+            Route::new("/api/key")
+                .get(apikeys::current_handler)
+                .auth()
+                .describe("The API key this request was made with, and its limits"),
+        )
+        .add(
+            Route::new("/admin/api-keys")
+                .get(apikeys::list_handler)
+                .post(apikeys::create_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("List or issue API keys (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/api-keys/:id")
+                .delete(apikeys::revoke_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Revoke an API key (Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/admin/audit")
+                .get(audit::list_handler)
+                .map(|r| r.layer(middleware::from_fn(admin::require_token)))
+                .auth()
+                .describe("Audit log (?entity=&entity_id=&actor=&action=, Bearer ADMIN_TOKEN)"),
+        )
+        .add(
+            Route::new("/api/notes/:id")
+                .get(notes::get_handler)
+                .put(notes::update_handler)
+                .delete(notes::delete_handler.layer(rbac::require_permission("notes:delete")))
+                .permission("notes:delete")
+                .describe("One note (SQLite demo); deleting needs notes:delete"),
+        )
+        .add(
+            Route::new("/api/notes/:id/restore")
+                .post(notes::restore_handler)
+                .describe("Take a deleted note out of the trash"),
+        );
+    #[cfg(feature = "search")]
+    let routes = routes.add(
+        Route::new("/api/search")
+            .get(search::search_handler)
+            .describe("Full-text search over notes (?q=&page=&limit=)"),
+    );
+    #[cfg(feature = "database")]
+    let routes = if cfg!(debug_assertions) {
+        routes
+            .add(
+                Route::new("/debug/outbox")
+                    .get(outbox::debug_handler)
+                    .describe("Outbox delivery counts and latest messages (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/outbox/:id/retry")
+                    .post(outbox::retry_handler)
+                    .describe("Re-send a dead-lettered outbox message (debug builds)"),
+            )
+    } else {
+        routes
+    };
+
+    #[cfg(feature = "email")]
+    let routes = if cfg!(debug_assertions) {
+        routes
+            .add(
+                Route::new("/debug/emails")
+                    .get(email::list_handler)
+                    .post(email::send_handler)
+                    .describe("Recently sent emails; POST {to} sends a test (debug builds)"),
+            )
+            .add(
+                Route::new("/debug/emails/:id")
+                    .get(email::show_handler)
+                    .describe("One sent email's HTML (debug builds)"),
+            )
+    } else {
+        routes
+    };
+
+    // Mocks are answered by the fallback, so they're listed but not routed.
+    state
+        .mocks()
+        .table()
+        .into_iter()
+        .fold(routes, |routes, (path, method)| {
+            routes.fallback_route(path, vec![method], "Mock from mocks/*.yaml")
+        })
+}
+
+/// Allows the origins in the `cors` config, which SIGHUP can change.
+fn cors_layer(state: &AppState) -> CorsLayer {
+    let state = state.clone();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| state.cors_allows(origin))
+        }))
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(Any)
+}
+
+/// Resolves once SIGINT/SIGTERM arrives or watch mode asks for a restart.
+/// Readiness is flipped to draining first and, when actually stopping with
+/// `SHUTDOWN_DRAIN_DELAY_MS` set, we keep serving for that long so the NSM
+/// proxy can stop routing new requests to us.
+async fn shutdown_signal(state: AppState, restart: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+        _ = restart.cancelled() => {
+            // The listener is handed to the new build, so there's no gap to
+            // cover with a drain delay.
+            info!("🔁 New build ready, draining connections before restart");
+            state.begin_drain();
+            return;
+        }
+    }
+
+    info!("🛑 Shutdown signal received, draining connections");
+    state.begin_drain();
+
+    let drain_delay = std::env::var("SHUTDOWN_DRAIN_DELAY_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_default();
+    if !drain_delay.is_zero() {
+        tokio::time::sleep(drain_delay).await;
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    // Before the runtime spawns its workers: this touches the environment.
+    watch::take_handoff();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run())
+}
+
+async fn run() -> anyhow::Result<()> {
+    match cli::Cli::parse().command() {
+        cli::Command::Serve(args) => serve(args).await,
+        cli::Command::Routes { json } => cli::routes(json),
+        cli::Command::Config => cli::config(),
+        cli::Command::Check => cli::check(),
+        cli::Command::Doctor { json } => cli::doctor(json).await,
+        cli::Command::Bench(args) => cli::bench(args).await,
+        cli::Command::Version { json } => cli::version(json),
+        cli::Command::PreviewTemplates { port } => preview::serve(port).await,
+        cli::Command::Admin { command } => admin::client(command).await,
+        #[cfg(feature = "database")]
+        cli::Command::Migrate { command } => db::migrate(command).await,
+        #[cfg(feature = "database")]
+        cli::Command::Db { command } => db::snapshots(command).await,
+        #[cfg(feature = "database")]
+        cli::Command::Seed => seeds::command().await,
+        cli::Command::Completions { shell } => cli::completions(shell),
+        cli::Command::Manpage { out_dir } => cli::manpage(out_dir),
+    }
+}
+
+async fn serve(args: cli::ServeArgs) -> anyhow::Result<()> {
+    let app_config = config::AppConfig::load()?;
+    args.init_tracing(&app_config);
 
     let config = load_nsm_config();
+    let state = AppState::new(config_profile(), app_config)?;
+    health::register_env_checks(state.health());
+    #[cfg(feature = "database")]
+    db::init(&state).await?;
+    #[cfg(feature = "redis")]
+    cache::init(&state);
+    #[cfg(feature = "s3")]
+    s3::init(&state).await;
 
     // Build our application with routes
-    let app = Router::new()
-        .route("/", get(home_handler))
-        .route("/api/info", get(api_info_handler))
-        .route("/api/health", get(health_handler))
-        .route("/api/echo", post(echo_handler))
-        .nest_service("/static", ServeDir::new("static"))
-        .layer(CorsLayer::permissive())
-        .fallback(not_found);
+    let (router, route_table) = app_routes(&state).into_parts();
+    state.set_routes(route_table);
+
+    let app = router.fallback(fallback);
+    // Innermost, so the response it commits on is the handler's own.
+    #[cfg(feature = "database")]
+    let app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        tx::manage_transactions,
+    ));
+    // Inside sessions, so a cached response never carries a session cookie.
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_cache::cache_responses,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ratelimit::limit_principals,
+        ))
+        // Inside sessions, for the session's user.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rbac::resolve_principal,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            sessions::manage_sessions,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            tenancy::resolve_tenant,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error::error_pages,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            livereload::inject_script,
+        ))
+        // Outside live reload, which would otherwise buffer a truncated page
+        // back into a whole one.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            chaos::inject_faults,
+        ));
+    let app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        auth::authenticate,
+    ));
+    #[cfg(feature = "database")]
+    let app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        apikeys::enforce_api_keys,
+    ));
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            signing::verify_signatures,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            static_keys::require_static_key,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            basic_auth::require_basic_auth,
+        ))
+        // Outermost of the auth layers, which let a signed link through.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            signed_urls::verify_signed_urls,
+        ));
+    // Feeds the dashboard's recent requests.
+    let app = if cfg!(debug_assertions) {
+        app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            capture::capture_requests,
+        ))
+    } else {
+        app
+    };
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ratelimit::limit_requests,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin::reject_during_maintenance,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            privileged::log_privileged,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            security_headers::add_security_headers,
+        ))
+        .layer(cors_layer(&state))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_log::log_requests,
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state.clone());
+    state.set_app(app.clone());
 
     let addr: SocketAddr = format!("{}:{}", config.host, config.http).parse()?;
+    let mtls = mtls::Mtls::new(&state.config().mtls, addr)?;
+
+    if args.banner() {
+        info!("🚀 Rust server starting on {}", addr);
+        info!("🌐 Domain: {}", domain());
+        info!(
+            "📡 NSM: {}",
+            if nsm_enabled() { "Enabled" } else { "Disabled" }
+        );
+        info!("🦀 Framework: Axum");
+        #[cfg(feature = "console")]
+        info!(
+            "🔬 tokio-console: 127.0.0.1:{}",
+            state.config().console.port
+        );
+        info!(
+            "🏷️  Build: v{} ({}, {})",
+            build_info::BUILD_INFO.version,
+            build_info::BUILD_INFO.short_sha(),
+            build_info::BUILD_INFO.profile
+        );
+        if args.log_format.resolve() != cli::LogFormat::Json {
+            println!();
+        }
+    } else {
+        info!("Listening on {}", addr);
+    }
+    if state.chaos().is_enabled() {
+        warn!(
+            "🐒 Chaos injection enabled ({} rules); toggle with PUT /__nsm/chaos",
+            state.config().chaos.rules.len()
+        );
+    }
+
+    // A watch-mode restart carries on the same run, so on_start and
+    // on_ready don't fire again.
+    let inherited = watch::inherited_listener()?;
+    let fresh_start = inherited.is_none();
+    let hooks = state.config().hooks.clone();
+    if fresh_start {
+        hooks::run(Hook::Start, hooks.clone(), addr).await;
+    }
 
-    info!("🚀 Rust server starting on {}", addr);
-    info!("🌐 Domain: {{.Domain}}");
-    info!("📡 NSM: {}", if std::env::var("NSM_ENABLED").unwrap_or_default() == "true" { "Enabled" } else { "Disabled" });
-    info!("🦀 Framework: Axum");
-    println!();
+    let listener = match inherited {
+        Some(listener) => listener,
+        None => std::net::TcpListener::bind(addr)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    // Watch mode keeps a duplicate of the socket to hand to the next build.
+    let (handoff, restart) = if args.watch {
+        (Some(listener.try_clone()?), watch::spawn()?)
+    } else {
+        (None, CancellationToken::new())
+    };
+
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let local_addr = listener.local_addr()?;
+    reload::spawn_sighup_handler(state.clone())?;
+    watchdog::spawn(state.clone())?;
+    admin::spawn(state.clone())?;
+    jobs::start(&state).await?;
+    #[cfg(feature = "nats")]
+    consumer::start(&state).await?;
+    scheduler::start(&state);
+    #[cfg(feature = "database")]
+    outbox::start(&state);
+    #[cfg(feature = "search")]
+    search::start(&state);
+    state.mark_ready();
+
+    // The mTLS listener, if any, stops along with the main one.
+    let connections = stats::CountConnections::new(app, state.clone());
+    let stop = CancellationToken::new();
+    let shutdown = {
+        let (shutdown, stop) = (shutdown_signal(state, restart.clone()), stop.clone());
+        async move {
+            shutdown.await;
+            stop.cancel();
+        }
+    };
+    let server = async {
+        axum::serve(listener, connections.clone())
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    };
+    let mtls = async {
+        match mtls {
+            Some(mtls) => mtls.serve(connections.clone(), stop).await,
+            None => Ok(()),
+        }
+    };
+    // A failed self-check drops the server straight away: nothing has been
+    // served yet, so there is nothing to drain.
+    let ready = async {
+        if !args.no_self_check {
+            selfcheck::run(local_addr).await.inspect_err(|e| {
+                error!(
+                    "❌ Startup self-check failed (skip with --no-self-check): {:#}",
+                    e
+                );
+            })?;
+        }
+        if fresh_start {
+            tokio::spawn(hooks::run(Hook::Ready, hooks.clone(), local_addr));
+        }
+        anyhow::Ok(())
+    };
+    tokio::try_join!(server, mtls, ready)?;
+
+    if restart.is_cancelled()
+        && let Some(listener) = handoff
+    {
+        return Err(watch::exec_restart(listener));
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    admin::cleanup();
+    hooks::run(Hook::Shutdown, hooks, local_addr).await;
+    info!("👋 Server stopped");
 
     Ok(())
 }
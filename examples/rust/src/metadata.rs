@@ -0,0 +1,135 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use std::{fmt::Write, path::PathBuf};
+
+use crate::{
+    error::{AppError, AppResult},
+    state::AppState,
+    static_files::STATIC_DIR,
+};
+
+/// Browsers re-request the icon on every page load; let them cache the answer.
+const FAVICON_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// How far ahead an unset `Expires` is put.
+const SECURITY_TXT_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::days(365);
+
+/// `/favicon.ico`: the configured icon, `static/favicon.ico`, or an empty
+/// `204` so browser noise doesn't show up as 404s in the log.
+pub async fn favicon_handler(State(state): State<AppState>) -> AppResult<Response> {
+    let path = state
+        .config()
+        .metadata
+        .favicon
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(STATIC_DIR).join("favicon.ico"));
+
+    let body = match tokio::fs::read(&path).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((
+                StatusCode::NO_CONTENT,
+                [(header::CACHE_CONTROL, FAVICON_CACHE_CONTROL)],
+            )
+                .into_response());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let content_type = mime_guess::from_path(&path)
+        .first_or_octet_stream()
+        .to_string();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, FAVICON_CACHE_CONTROL.to_string()),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+pub async fn robots_handler(State(state): State<AppState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        state.config().metadata.robots_txt.clone(),
+    )
+        .into_response()
+}
+
+/// `/.well-known/<name>` from the `metadata.well_known` config section.
+pub async fn well_known_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> AppResult<Response> {
+    let doc = state
+        .config()
+        .metadata
+        .well_known
+        .get(&name)
+        .ok_or_else(|| AppError::not_found(format!("No well-known document named {}", name)))?;
+
+    Ok((
+        [(header::CONTENT_TYPE, doc.content_type.clone())],
+        doc.body.clone(),
+    )
+        .into_response())
+}
+
+/// `/.well-known/security.txt`, from the `metadata.security_txt` config
+/// section, with its fields in RFC 9116 order.
+pub async fn security_txt_handler(State(state): State<AppState>) -> AppResult<Response> {
+    let config = &state.config().metadata.security_txt;
+    if !config.enabled || config.contact.is_empty() {
+        return Err(AppError::not_found("No security.txt here"));
+    }
+    let expires = config
+        .expires
+        .unwrap_or_else(|| chrono::Utc::now() + SECURITY_TXT_EXPIRY);
+
+    let mut body = String::new();
+    let mut field = |name: &str, values: &[String]| {
+        for value in values {
+            let _ = writeln!(body, "{}: {}", name, value);
+        }
+    };
+    field("Contact", &config.contact);
+    field(
+        "Expires",
+        &[expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)],
+    );
+    field("Encryption", &config.encryption);
+    field("Acknowledgments", &config.acknowledgments);
+    if !config.preferred_languages.is_empty() {
+        field(
+            "Preferred-Languages",
+            &[config.preferred_languages.join(", ")],
+        );
+    }
+    field("Canonical", &config.canonical);
+    field("Policy", &config.policy);
+    field("Hiring", &config.hiring);
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response())
+}
+
+/// `/security.txt`, the file's old home, points at the current one.
+pub async fn legacy_security_txt_handler() -> Redirect {
+    Redirect::permanent("/.well-known/security.txt")
+}
+
+/// `/.well-known/change-password`: where password managers send users to
+/// change theirs, when `metadata.security_txt.change_password` says.
+pub async fn change_password_handler(State(state): State<AppState>) -> AppResult<Redirect> {
+    let location = state
+        .config()
+        .metadata
+        .security_txt
+        .change_password
+        .as_deref()
+        .ok_or_else(|| AppError::not_found("No password change page here"))?;
+    Ok(Redirect::to(location))
+}
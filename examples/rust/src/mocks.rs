@@ -0,0 +1,378 @@
+use anyhow::{bail, Context};
+use axum::{
+    body::Body,
+    extract::{Query, Request},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tera::Tera;
+use tracing::{debug, warn};
+
+use crate::{
+    error::{AppError, AppResult},
+    templates::template_error,
+};
+
+/// Default directory for mock definitions, relative to the working
+/// directory. Override with `APP_MOCKS_DIR`.
+pub const MOCKS_DIR: &str = "mocks";
+
+/// Request bodies larger than this aren't exposed to response templates.
+const MOCK_BODY_LIMIT: usize = 1024 * 1024;
+
+/// One entry of a `mocks/*.yaml` file:
+///
+/// ```yaml
+/// - method: GET
+///   path: /api/users/:id
+///   status: 200
+///   latency_ms: 150
+///   headers:
+///     x-total-count: "1"
+///   body:
+///     id: 42
+///     name: Ada
+/// ```
+///
+/// A string `body` is rendered as a Tera template and served as text;
+/// anything else is served as JSON, with each string in it rendered as a
+/// template. Templates see `method`, `path`, `params` (so `:id` above is
+/// `params.id`), `query`, `headers` and the request `body` (parsed when it's
+/// JSON).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockSpec {
+    #[serde(default = "default_method")]
+    method: String,
+    path: String,
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    latency_ms: u64,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+enum MockBody {
+    Empty,
+    /// Name of the template holding the body.
+    Text(String),
+    /// The body with every string replaced by the name of its template.
+    Json(serde_json::Value),
+}
+
+struct Mock {
+    method: Method,
+    status: StatusCode,
+    latency: Duration,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: MockBody,
+}
+
+/// Everything loaded from the mocks directory in one go, so a reload swaps
+/// routes and templates together.
+#[derive(Default)]
+struct MockSet {
+    mocks: Vec<Mock>,
+    /// Path pattern -> indices into `mocks`, one per method.
+    router: matchit::Router<Vec<usize>>,
+    /// `(path, method)` in file order, for the route table.
+    table: Vec<(String, &'static str)>,
+    tera: Tera,
+}
+
+/// Routes defined declaratively in `mocks/*.yaml`, served from the fallback
+/// so real handlers always win. Debug builds re-read the directory on every
+/// request that reaches them.
+pub struct Mocks {
+    dir: PathBuf,
+    set: RwLock<Arc<MockSet>>,
+    hot_reload: bool,
+}
+
+/// Mock methods as the `'static` names the route table uses.
+fn static_method(method: &str) -> Option<&'static str> {
+    let methods = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+    methods.into_iter().find(|m| m.eq_ignore_ascii_case(method))
+}
+
+/// Registers each string in `value` as a template, replacing it with the
+/// template's name.
+fn compile_json(tera: &mut Tera, name: &str, value: &mut serde_json::Value) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::String(s) => {
+            tera.add_raw_template(name, s)?;
+            *s = name.to_string();
+        }
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                compile_json(tera, &format!("{}/{}", name, i), item)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                compile_json(tera, &format!("{}/{}", name, key), field)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn render_json(
+    tera: &Tera,
+    value: &serde_json::Value,
+    context: &tera::Context,
+) -> AppResult<serde_json::Value> {
+    Ok(match value {
+        serde_json::Value::String(name) => {
+            serde_json::Value::String(tera.render(name, context).map_err(template_error)?)
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(
+            items
+                .iter()
+                .map(|item| render_json(tera, item, context))
+                .collect::<AppResult<_>>()?,
+        ),
+        serde_json::Value::Object(fields) => serde_json::Value::Object(
+            fields
+                .iter()
+                .map(|(key, field)| Ok((key.clone(), render_json(tera, field, context)?)))
+                .collect::<AppResult<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+impl MockSet {
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut set = Self::default();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(set);
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext == "yaml" || ext == "yml")
+            })
+            .collect();
+        files.sort();
+
+        let mut by_path: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for file in files {
+            let contents = fs::read_to_string(&file)
+                .with_context(|| format!("failed to read mock file {}", file.display()))?;
+            let specs: Vec<MockSpec> = serde_yaml::from_str(&contents)
+                .with_context(|| format!("failed to parse mock file {}", file.display()))?;
+            for (i, spec) in specs.into_iter().enumerate() {
+                let name = format!("{}#{}", file.display(), i);
+                let mock = set
+                    .compile(&name, &spec)
+                    .with_context(|| format!("invalid mock {} ({})", name, spec.path))?;
+                let indices = by_path.entry(spec.path.clone()).or_default();
+                if indices.iter().any(|&j| set.mocks[j].method == mock.method) {
+                    bail!("{}: {} {} is already mocked", name, mock.method, spec.path);
+                }
+                indices.push(set.mocks.len());
+                set.table.push((
+                    spec.path,
+                    static_method(mock.method.as_str()).unwrap_or("GET"),
+                ));
+                set.mocks.push(mock);
+            }
+        }
+        for (path, indices) in by_path {
+            set.router
+                .insert(path.clone(), indices)
+                .with_context(|| format!("invalid mock path {}", path))?;
+        }
+        Ok(set)
+    }
+
+    fn compile(&mut self, name: &str, spec: &MockSpec) -> anyhow::Result<Mock> {
+        let method = static_method(&spec.method)
+            .with_context(|| format!("unsupported method {}", spec.method))?;
+        let headers = spec
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((name.parse()?, value.parse()?)))
+            .collect::<anyhow::Result<_>>()?;
+        let body = match spec.body.clone() {
+            None | Some(serde_json::Value::Null) => MockBody::Empty,
+            Some(serde_json::Value::String(text)) => {
+                self.tera.add_raw_template(name, &text)?;
+                MockBody::Text(name.to_string())
+            }
+            Some(mut value) => {
+                compile_json(&mut self.tera, name, &mut value)?;
+                MockBody::Json(value)
+            }
+        };
+        Ok(Mock {
+            method: method.parse()?,
+            status: StatusCode::from_u16(spec.status)?,
+            latency: Duration::from_millis(spec.latency_ms),
+            headers,
+            body,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct MockContext {
+    method: String,
+    path: String,
+    params: BTreeMap<String, String>,
+    query: BTreeMap<String, String>,
+    headers: BTreeMap<String, String>,
+    body: serde_json::Value,
+}
+
+/// A request that a mock answers.
+pub struct MockMatch {
+    set: Arc<MockSet>,
+    index: usize,
+    params: BTreeMap<String, String>,
+}
+
+impl Mocks {
+    pub fn load(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        let set = MockSet::load(&dir)?;
+        if !set.mocks.is_empty() {
+            debug!("Loaded {} mocks from {}", set.mocks.len(), dir.display());
+        }
+        Ok(Self {
+            dir,
+            set: RwLock::new(Arc::new(set)),
+            hot_reload: cfg!(debug_assertions),
+        })
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::load(std::env::var("APP_MOCKS_DIR").unwrap_or_else(|_| MOCKS_DIR.to_string()))
+    }
+
+    /// Mocked `(path, method)` pairs as of startup.
+    pub fn table(&self) -> Vec<(String, &'static str)> {
+        self.set.read().unwrap().table.clone()
+    }
+
+    /// The mock for this request, if any. A broken edit keeps the previous
+    /// mocks in place.
+    pub fn lookup(&self, method: &Method, path: &str) -> Option<MockMatch> {
+        if self.hot_reload {
+            match MockSet::load(&self.dir) {
+                Ok(set) => *self.set.write().unwrap() = Arc::new(set),
+                Err(e) => warn!("Mocks: keeping previous definitions: {:#}", e),
+            }
+        }
+        let set = self.set.read().unwrap().clone();
+        let matched = set.router.at(path).ok()?;
+        let index = *matched
+            .value
+            .iter()
+            .find(|&&i| set.mocks[i].method == method)?;
+        let params = matched
+            .params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Some(MockMatch { set, index, params })
+    }
+}
+
+impl MockMatch {
+    pub async fn respond(self, req: Request) -> Response {
+        match self.render(req).await {
+            Ok(res) => res,
+            Err(e) => e.into_response(),
+        }
+    }
+
+    async fn render(self, req: Request) -> AppResult<Response> {
+        let mock = &self.set.mocks[self.index];
+        let (parts, body) = req.into_parts();
+        let query = Query::<BTreeMap<String, String>>::try_from_uri(&parts.uri)
+            .map(|q| q.0)
+            .unwrap_or_default();
+        let headers = parts
+            .headers
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+        let body = axum::body::to_bytes(body, MOCK_BODY_LIMIT)
+            .await
+            .map_err(|e| AppError::bad_request(e.to_string()))?;
+        let body =
+            serde_json::from_slice(&body).unwrap_or_else(|_| {
+                match String::from_utf8_lossy(&body).into_owned() {
+                    text if text.is_empty() => serde_json::Value::Null,
+                    text => serde_json::Value::String(text),
+                }
+            });
+        let context = tera::Context::from_serialize(MockContext {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            params: self.params,
+            query,
+            headers,
+            body,
+        })
+        .map_err(AppError::internal)?;
+
+        let (content_type, body) = match &mock.body {
+            MockBody::Empty => (None, Body::empty()),
+            MockBody::Text(name) => (
+                Some("text/plain; charset=utf-8"),
+                Body::from(
+                    self.set
+                        .tera
+                        .render(name, &context)
+                        .map_err(template_error)?,
+                ),
+            ),
+            MockBody::Json(value) => {
+                let value = render_json(&self.set.tera, value, &context)?;
+                (
+                    Some("application/json"),
+                    Body::from(serde_json::to_vec(&value).map_err(AppError::internal)?),
+                )
+            }
+        };
+
+        if !mock.latency.is_zero() {
+            tokio::time::sleep(mock.latency).await;
+        }
+
+        let mut res = Response::new(body);
+        *res.status_mut() = mock.status;
+        if let Some(content_type) = content_type {
+            res.headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        for (name, value) in &mock.headers {
+            res.headers_mut().insert(name.clone(), value.clone());
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::Context;
+use axum::body::Body;
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider, pki_types::CertificateDer, server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+use crate::{
+    auth::Verified,
+    config::MtlsConfig,
+    stats::{CountConnections, CountedConnection},
+};
+
+/// Clients that haven't finished the handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who a verified client certificate says the caller is, in the extensions
+/// of every request over its connection. Those requests also carry a
+/// [`Verified`] with the identity as `sub`, so [`crate::auth::RequireAuth`]
+/// takes a certificate as readily as a token.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientCert {
+    /// The identity from `mtls.identities`, else the common name.
+    pub name: String,
+    /// e.g. `O=mkcert development certificate, CN=nsm-proxy`.
+    pub subject: String,
+    /// Its common name and subject alternative names, spelled as in
+    /// `mtls.identities`.
+    pub names: Vec<String>,
+    /// SHA-256 of the certificate, in hex.
+    pub fingerprint: String,
+    /// When it expires, in unix seconds.
+    pub not_after: i64,
+}
+
+/// The mTLS listener: our certificate, the CA clients must chain to and the
+/// identity each certificate name maps to.
+pub struct Mtls {
+    acceptor: TlsAcceptor,
+    addr: SocketAddr,
+    identities: BTreeMap<String, String>,
+}
+
+impl Mtls {
+    /// `None` unless `mtls.enabled`. `http` is where the plain listener
+    /// goes, for the default port.
+    pub fn new(config: &MtlsConfig, http: SocketAddr) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let path = |configured: &Option<PathBuf>, field: &str, env: &str| {
+            configured
+                .clone()
+                .or_else(|| std::env::var_os(env).map(PathBuf::from))
+                .with_context(|| format!("mtls is enabled; set mtls.{} or {}", field, env))
+        };
+        let cert_path = path(&config.cert_path, "cert_path", "NSM_CERT_PATH")?;
+        let key_path = path(&config.key_path, "key_path", "NSM_KEY_PATH")?;
+        let ca_path = match &config.client_ca {
+            Some(path) => path.clone(),
+            None => crate::preflight::mkcert_ca_root()
+                .context("mtls.client_ca is unset and mkcert's CA wasn't found")?
+                .join("rootCA.pem"),
+        };
+
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(&ca_path)? {
+            roots
+                .add(cert)
+                .with_context(|| format!("{}: unusable CA certificate", ca_path.display()))?;
+        }
+        let key = fs::File::open(&key_path)
+            .and_then(|file| rustls_pemfile::private_key(&mut BufReader::new(file)))
+            .with_context(|| format!("failed to read {}", key_path.display()))?
+            .with_context(|| format!("no private key in {}", key_path.display()))?;
+
+        let provider = Arc::new(default_provider());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+        let verifier = if config.optional {
+            verifier.allow_unauthenticated()
+        } else {
+            verifier
+        };
+        let mut tls = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier.build()?)
+            .with_single_cert(read_certs(&cert_path)?, key)?;
+        tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Some(Self {
+            acceptor: TlsAcceptor::from(Arc::new(tls)),
+            addr: SocketAddr::new(http.ip(), config.port.unwrap_or(http.port() + 1)),
+            identities: config.identities.clone(),
+        }))
+    }
+
+    /// Maps a certificate rustls has already verified to its identity.
+    fn identify(&self, der: &CertificateDer<'_>) -> ClientCert {
+        let fingerprint = hex::encode(Sha256::digest(der));
+        let (subject, names, not_after) = match parse_x509_certificate(der) {
+            Ok((_, cert)) => {
+                let mut names: Vec<String> = cert
+                    .subject()
+                    .iter_common_name()
+                    .filter_map(|cn| cn.as_str().ok())
+                    .map(|cn| format!("CN={}", cn))
+                    .collect();
+                if let Ok(Some(san)) = cert.subject_alternative_name() {
+                    names.extend(san.value.general_names.iter().filter_map(spell));
+                }
+                let not_after = cert.validity().not_after.timestamp();
+                (cert.subject().to_string(), names, not_after)
+            }
+            Err(e) => {
+                warn!("🔐 Client certificate {} doesn't parse: {}", fingerprint, e);
+                (String::new(), Vec::new(), 0)
+            }
+        };
+        let name = names
+            .iter()
+            .find_map(|name| self.identities.get(name).cloned())
+            .or_else(|| {
+                let (_, value) = names.first()?.split_once(['=', ':'])?;
+                Some(value.to_string())
+            })
+            .unwrap_or_else(|| format!("sha256:{}", &fingerprint[..16]));
+        ClientCert {
+            name,
+            subject,
+            names,
+            fingerprint,
+            not_after,
+        }
+    }
+
+    /// Serves `app` over TLS until `stop` is cancelled, then lets open
+    /// connections finish the requests they're on.
+    pub async fn serve(self, app: CountConnections, stop: CancellationToken) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.addr)
+            .await
+            .with_context(|| format!("mtls: cannot bind {}", self.addr))?;
+        info!("🔐 mTLS listening on {}", self.addr);
+
+        let mtls = Arc::new(self);
+        let connections = TaskTracker::new();
+        loop {
+            let (tcp, remote) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("🔐 mTLS accept failed: {}", e);
+                        continue;
+                    }
+                },
+                _ = stop.cancelled() => break,
+            };
+            let (mtls, app, stop) = (mtls.clone(), app.connection(remote), stop.clone());
+            connections.spawn(async move {
+                if let Err(e) = mtls.handle(tcp, app, stop).await {
+                    debug!("🔐 mTLS connection from {}: {:#}", remote, e);
+                }
+            });
+        }
+        connections.close();
+        connections.wait().await;
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        tcp: TcpStream,
+        app: CountedConnection,
+        stop: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(tcp))
+            .await
+            .context("handshake timed out")??;
+        let cert = tls
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|der| self.identify(der));
+        if let Some(cert) = &cert {
+            debug!("🔐 {} connected as {}", cert.subject, cert.name);
+        }
+        let verified = cert.as_ref().map(Verified::from_client_cert);
+
+        let service = tower::service_fn(move |req: axum::http::Request<Incoming>| {
+            let mut req = req.map(Body::new);
+            if let (Some(cert), Some(verified)) = (&cert, &verified) {
+                req.extensions_mut().insert(cert.clone());
+                req.extensions_mut().insert(verified.clone());
+            }
+            app.clone().oneshot(req)
+        });
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let conn = builder
+            .serve_connection_with_upgrades(TokioIo::new(tls), TowerToHyperService::new(service));
+        tokio::pin!(conn);
+        let served = tokio::select! {
+            served = conn.as_mut() => served,
+            _ = stop.cancelled() => {
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        };
+        served.map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+/// A subject alternative name as `mtls.identities` spells it; other kinds
+/// are left out.
+fn spell(name: &GeneralName<'_>) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) => Some(format!("DNS:{}", name)),
+        GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+        GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+        _ => None,
+    }
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = fs::File::open(path)
+        .and_then(|file| {
+            rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()
+        })
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates in {}", path.display());
+    }
+    Ok(certs)
+}
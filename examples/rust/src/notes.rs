@@ -0,0 +1,675 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use validator::Validate;
+
+use crate::{
+    audit::{self, Actor},
+    error::{AppError, AppResult},
+    export::{ExportFormat, ExportRow},
+    import::ImportReport,
+    pagination::{Page, Pagination, PaginationParams},
+    response_cache::{CacheFor, Invalidate},
+    soft_delete::{self, SoftDelete, LIVE},
+    state::AppState,
+    tenancy::Tenant,
+    tx::Tx,
+    validation::ValidatedJson,
+};
+
+/// The notes demo's part of the OpenAPI document, merged in when the
+/// `database` feature is on.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_handler,
+        export_handler,
+        import_handler,
+        create_handler,
+        get_handler,
+        update_handler,
+        delete_handler,
+        restore_handler
+    ),
+    components(schemas(
+        Note,
+        NoteInput,
+        ExportFormat,
+        ImportReport,
+        crate::import::RowError,
+        crate::pagination::NotePage
+    )),
+    tags((name = "notes", description = "SQLite-backed CRUD demo"))
+)]
+pub struct NotesApi;
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct Note {
+    id: i64,
+    title: String,
+    body: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    /// Starts at 1 and goes up with every update.
+    version: i64,
+    /// Set while the note is in the trash.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SoftDelete for Note {
+    const TABLE: &'static str = "notes";
+}
+
+impl Note {
+    /// Strong, since a version always has the same content.
+    fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.id, self.version)
+    }
+}
+
+impl ExportRow for Note {
+    const CSV_HEADER: &'static [&'static str] =
+        &["id", "title", "body", "created_at", "updated_at", "version"];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.title.clone(),
+            self.body.clone(),
+            self.created_at.to_rfc3339(),
+            self.updated_at.to_rfc3339(),
+            self.version.to_string(),
+        ]
+    }
+
+    fn cursor(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct NoteInput {
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"))]
+    #[schema(min_length = 1, max_length = 200)]
+    title: String,
+    #[serde(default)]
+    #[validate(length(max = 10000, message = "must be at most 10000 characters"))]
+    #[schema(max_length = 10000)]
+    body: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NoteFilter {
+    /// Case-insensitive substring of the title or body.
+    q: Option<String>,
+    /// List deleted notes (the trash) instead.
+    #[serde(default)]
+    deleted: bool,
+}
+
+impl NoteFilter {
+    /// The `WHERE` condition, with `like_pattern` bound as `?1` and the
+    /// tenant as `?2`.
+    fn sql(&self) -> String {
+        format!(
+            "tenant = ?2 AND {} AND (?1 IS NULL OR title LIKE ?1 ESCAPE '\\' OR body LIKE ?1 ESCAPE '\\')",
+            if self.deleted {
+                "deleted_at IS NOT NULL"
+            } else {
+                LIVE
+            }
+        )
+    }
+
+    /// `q` as a `LIKE` pattern.
+    fn like_pattern(&self) -> Option<String> {
+        self.q.as_deref().filter(|q| !q.is_empty()).map(|q| {
+            let escaped = q
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// `ndjson` (the default) or `csv`.
+    #[param(inline)]
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Cache tag of note listings; every write invalidates it.
+const NOTES_TAG: &str = "notes";
+
+/// How long a listing is served from the cache, barring writes.
+const LIST_CACHE_SECS: u64 = 30;
+
+fn not_found(id: i64) -> AppError {
+    AppError::not_found(format!("No note with id {}", id))
+}
+
+/// The tenant's note `id`, unless it's missing or deleted.
+async fn fetch_live<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    tenant: &Tenant,
+    id: i64,
+) -> AppResult<Note> {
+    sqlx::query_as(&format!(
+        "SELECT * FROM notes WHERE id = ? AND tenant = ? AND {}",
+        LIVE
+    ))
+    .bind(id)
+    .bind(tenant.id())
+    .fetch_optional(db)
+    .await
+    .map_err(AppError::internal)?
+    .ok_or_else(|| not_found(id))
+}
+
+/// Versions of note `id` that `If-Match` accepts, or `None` when any
+/// version will do (no header, or `*`).
+fn if_match(headers: &HeaderMap, id: i64) -> Option<Vec<i64>> {
+    let value = headers.get(header::IF_MATCH)?.to_str().unwrap_or_default();
+    if value.trim() == "*" {
+        return None;
+    }
+    let prefix = format!("{}-", id);
+    Some(
+        value
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .strip_prefix(prefix.as_str())?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Weak comparison, as `If-None-Match` asks for.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    value
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// After a conditional write changed nothing: 404 if the note is gone,
+/// otherwise it has moved past the version the client had.
+async fn write_failed(tx: &mut Tx, tenant: &Tenant, id: i64) -> AppError {
+    let exists = sqlx::query(&format!(
+        "SELECT 1 FROM notes WHERE id = ? AND tenant = ? AND {}",
+        LIVE
+    ))
+    .bind(id)
+    .bind(tenant.id())
+    .fetch_optional(&mut **tx)
+    .await;
+    match exists {
+        Ok(Some(_)) => AppError::new(
+            StatusCode::PRECONDITION_FAILED,
+            format!("Note {} has changed; fetch it again", id),
+        ),
+        Ok(None) => not_found(id),
+        Err(e) => AppError::internal(e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/notes",
+    tag = "notes",
+    params(PaginationParams, NoteFilter),
+    responses(
+        (status = 200, description = "One page of notes, newest first unless sorted", body = NotePage),
+        (status = 400, description = "Invalid paging, sort or filter")
+    )
+)]
+pub async fn list_handler(
+    State(state): State<AppState>,
+    pagination: Pagination,
+    tenant: Tenant,
+    Query(filter): Query<NoteFilter>,
+) -> AppResult<(CacheFor, Json<Page<Note>>)> {
+    let order = match pagination.sort(&["created_at", "updated_at", "title"])? {
+        Some(sort) => {
+            let direction = if sort.descending { "DESC" } else { "ASC" };
+            let column = match sort.field.as_str() {
+                "title" => "title COLLATE NOCASE",
+                field => field,
+            };
+            format!("{} {}, id {}", column, direction, direction)
+        }
+        None => "created_at DESC, id DESC".to_string(),
+    };
+    let pattern = filter.like_pattern();
+    let (total,): (i64,) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FROM notes WHERE {}",
+        filter.sql()
+    ))
+    .bind(&pattern)
+    .bind(tenant.id())
+    .fetch_one(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    let notes = sqlx::query_as(&format!(
+        "SELECT * FROM notes WHERE {} ORDER BY {} LIMIT ?3 OFFSET ?4",
+        filter.sql(),
+        order
+    ))
+    .bind(&pattern)
+    .bind(tenant.id())
+    .bind(pagination.limit as i64)
+    .bind(pagination.offset as i64)
+    .fetch_all(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    Ok((
+        CacheFor::secs(LIST_CACHE_SECS).tag(NOTES_TAG),
+        Json(pagination.paged(notes, total as usize)),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/notes/export",
+    tag = "notes",
+    params(ExportParams, NoteFilter),
+    responses(
+        (status = 200, description = "Every matching note, oldest first, streamed in chunks",
+         content_type = ["application/x-ndjson", "text/csv"]),
+        (status = 400, description = "Unknown format")
+    )
+)]
+pub async fn export_handler(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(params): Query<ExportParams>,
+    Query(filter): Query<NoteFilter>,
+) -> AppResult<Response> {
+    let pattern = filter.like_pattern();
+    let sql = format!(
+        "SELECT * FROM notes WHERE {} AND id > ?3 ORDER BY id LIMIT ?4",
+        filter.sql()
+    );
+    crate::export::stream_rows("notes", params.format, move |after, limit| {
+        let (db, sql, pattern) = (state.db().clone(), sql.clone(), pattern.clone());
+        let tenant = tenant.id().to_string();
+        async move {
+            sqlx::query_as::<_, Note>(&sql)
+                .bind(pattern)
+                .bind(tenant)
+                .bind(after)
+                .bind(limit)
+                .fetch_all(&db)
+                .await
+        }
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/notes/import",
+    tag = "notes",
+    request_body(
+        content = String,
+        content_type = "application/x-ndjson",
+        description = "Notes as NDJSON, or as text/csv with a header row. Fields other than title and body are ignored, so an export imports as is."
+    ),
+    responses(
+        (status = 200, description = "What was imported, and why the rest wasn't", body = ImportReport),
+        (status = 400, description = "Unreadable body"),
+        (status = 413, description = "A record is too long"),
+        (status = 415, description = "Neither CSV nor NDJSON")
+    )
+)]
+pub async fn import_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    tenant: Tenant,
+    headers: HeaderMap,
+    body: Body,
+) -> AppResult<(Invalidate, Json<ImportReport>)> {
+    let report = crate::import::import_rows("notes", &headers, body, |batch| {
+        insert_batch(&state, &actor, &tenant, batch)
+    })
+    .await?;
+    Ok((Invalidate::tags([NOTES_TAG]), Json(report)))
+}
+
+/// Inserts `inputs` in one transaction, with a `note.created` event and an
+/// audit entry each.
+async fn insert_batch(
+    state: &AppState,
+    actor: &Actor,
+    tenant: &Tenant,
+    inputs: Vec<NoteInput>,
+) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let mut tx = state.db().begin().await?;
+    for input in inputs {
+        let note: Note = sqlx::query_as(
+            "INSERT INTO notes (tenant, title, body, created_at, updated_at) \
+             VALUES (?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(tenant.id())
+        .bind(input.title)
+        .bind(input.body)
+        .bind(now)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+        state
+            .outbox()
+            .record_in(&mut tx, Some(tenant), "note.created", &note)
+            .await?;
+        audit::record(&mut tx, actor, "create", "note", note.id, None, Some(&note)).await?;
+    }
+    tx.commit().await?;
+    state.outbox().wake();
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/notes",
+    tag = "notes",
+    request_body = NoteInput,
+    responses(
+        (status = 201, description = "Note created", body = Note,
+         headers(("ETag" = String), ("Location" = String))),
+        (status = 422, description = "Invalid title or body")
+    )
+)]
+pub async fn create_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    tenant: Tenant,
+    mut tx: Tx,
+    ValidatedJson(input): ValidatedJson<NoteInput>,
+) -> AppResult<Response> {
+    let now = chrono::Utc::now();
+    let note: Note = sqlx::query_as(
+        "INSERT INTO notes (tenant, title, body, created_at, updated_at) \
+         VALUES (?, ?, ?, ?, ?) RETURNING *",
+    )
+    .bind(tenant.id())
+    .bind(input.title)
+    .bind(input.body)
+    .bind(now)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(AppError::internal)?;
+    state
+        .outbox()
+        .record(&mut tx, Some(&tenant), "note.created", &note)
+        .await
+        .map_err(AppError::internal)?;
+    audit::record(
+        &mut tx,
+        &actor,
+        "create",
+        "note",
+        note.id,
+        None,
+        Some(&note),
+    )
+    .await
+    .map_err(AppError::internal)?;
+    Ok((
+        StatusCode::CREATED,
+        Invalidate::tags([NOTES_TAG]),
+        [
+            (header::ETAG, note.etag()),
+            (header::LOCATION, format!("/api/notes/{}", note.id)),
+        ],
+        Json(note),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/notes/{id}",
+    tag = "notes",
+    params(
+        ("id" = i64, Path, description = "Note id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag from an earlier read")
+    ),
+    responses(
+        (status = 200, description = "The note", body = Note, headers(("ETag" = String))),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+        (status = 404, description = "No such note")
+    )
+)]
+pub async fn get_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let note = fetch_live(state.db(), &tenant, id).await?;
+    let etag = note.etag();
+    if if_none_match(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(note)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/notes/{id}",
+    tag = "notes",
+    params(
+        ("id" = i64, Path, description = "Note id"),
+        ("If-Match" = Option<String>, Header, description = "Only update this version")
+    ),
+    request_body = NoteInput,
+    responses(
+        (status = 200, description = "The updated note", body = Note, headers(("ETag" = String))),
+        (status = 404, description = "No such note"),
+        (status = 412, description = "The note has changed since the ETag in If-Match"),
+        (status = 422, description = "Invalid title or body")
+    )
+)]
+pub async fn update_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    actor: Actor,
+    tenant: Tenant,
+    mut tx: Tx,
+    ValidatedJson(input): ValidatedJson<NoteInput>,
+) -> AppResult<Response> {
+    let before = fetch_live(&mut *tx, &tenant, id).await?;
+    let versions = if_match(&headers, id).map(|versions| serde_json::json!(versions).to_string());
+    let note: Option<Note> = sqlx::query_as(&format!(
+        "UPDATE notes SET title = ?, body = ?, updated_at = ?, version = version + 1 \
+         WHERE id = ? AND {} AND (?5 IS NULL OR version IN (SELECT value FROM json_each(?5))) \
+         RETURNING *",
+        LIVE
+    ))
+    .bind(input.title)
+    .bind(input.body)
+    .bind(chrono::Utc::now())
+    .bind(id)
+    .bind(versions)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(AppError::internal)?;
+    let Some(note) = note else {
+        return Err(write_failed(&mut tx, &tenant, id).await);
+    };
+    state
+        .outbox()
+        .record(&mut tx, Some(&tenant), "note.updated", &note)
+        .await
+        .map_err(AppError::internal)?;
+    audit::record(
+        &mut tx,
+        &actor,
+        "update",
+        "note",
+        id,
+        Some(&before),
+        Some(&note),
+    )
+    .await
+    .map_err(AppError::internal)?;
+    Ok((
+        Invalidate::tags([NOTES_TAG]),
+        [(header::ETAG, note.etag())],
+        Json(note),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/notes/{id}",
+    tag = "notes",
+    params(
+        ("id" = i64, Path, description = "Note id"),
+        ("If-Match" = Option<String>, Header, description = "Only delete this version")
+    ),
+    responses(
+        (status = 204, description = "Note moved to the trash"),
+        (status = 404, description = "No such note"),
+        (status = 412, description = "The note has changed since the ETag in If-Match")
+    )
+)]
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    actor: Actor,
+    tenant: Tenant,
+    mut tx: Tx,
+) -> AppResult<(Invalidate, StatusCode)> {
+    let before = fetch_live(&mut *tx, &tenant, id).await?;
+    if if_match(&headers, id).is_some_and(|versions| !versions.contains(&before.version)) {
+        return Err(write_failed(&mut tx, &tenant, id).await);
+    }
+    let note: Note = soft_delete::soft_delete(&mut tx, id)
+        .await
+        .map_err(AppError::internal)?
+        .ok_or_else(|| not_found(id))?;
+    state
+        .outbox()
+        .record(&mut tx, Some(&tenant), "note.deleted", serde_json::json!({ "id": id }))
+        .await
+        .map_err(AppError::internal)?;
+    audit::record(
+        &mut tx,
+        &actor,
+        "delete",
+        "note",
+        id,
+        Some(&before),
+        Some(&note),
+    )
+    .await
+    .map_err(AppError::internal)?;
+    Ok((Invalidate::tags([NOTES_TAG]), StatusCode::NO_CONTENT))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/notes/{id}/restore",
+    tag = "notes",
+    params(("id" = i64, Path, description = "Note id")),
+    responses(
+        (status = 200, description = "The note, out of the trash", body = Note, headers(("ETag" = String))),
+        (status = 404, description = "No such note in the trash")
+    )
+)]
+pub async fn restore_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    actor: Actor,
+    tenant: Tenant,
+    mut tx: Tx,
+) -> AppResult<Response> {
+    let gone = || AppError::not_found(format!("No deleted note with id {}", id));
+    let before: Note = sqlx::query_as(
+        "SELECT * FROM notes WHERE id = ? AND tenant = ? AND deleted_at IS NOT NULL",
+    )
+    .bind(id)
+    .bind(tenant.id())
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(AppError::internal)?
+    .ok_or_else(gone)?;
+    let note: Note = soft_delete::restore(&mut tx, id)
+        .await
+        .map_err(AppError::internal)?
+        .ok_or_else(gone)?;
+    state
+        .outbox()
+        .record(&mut tx, Some(&tenant), "note.restored", &note)
+        .await
+        .map_err(AppError::internal)?;
+    audit::record(
+        &mut tx,
+        &actor,
+        "restore",
+        "note",
+        id,
+        Some(&before),
+        Some(&note),
+    )
+    .await
+    .map_err(AppError::internal)?;
+    Ok((
+        Invalidate::tags([NOTES_TAG]),
+        [(header::ETAG, note.etag())],
+        Json(note),
+    )
+        .into_response())
+}
+
+/// Removes notes that have been in the trash for more than `days` days,
+/// for the `purge-deleted-notes` task.
+pub async fn purge_deleted(state: &AppState, days: u64) -> anyhow::Result<()> {
+    let before = chrono::Utc::now() - chrono::Days::new(days);
+    let actor = Actor::system("purge-deleted-notes");
+    let mut tx = state.db().begin().await?;
+    let purged: Vec<Note> = soft_delete::purge(&mut tx, before).await?;
+    for note in &purged {
+        audit::record(&mut tx, &actor, "purge", "note", note.id, Some(note), None).await?;
+    }
+    tx.commit().await?;
+    if !purged.is_empty() {
+        state
+            .response_cache()
+            .invalidate(state.kv(), &[NOTES_TAG.to_string()])
+            .await;
+        tracing::info!(
+            "🗑️ Purged {} notes deleted over {} days ago",
+            purged.len(),
+            days
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,460 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Json, Redirect, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::RngCore;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+use crate::{
+    config::{DevOidcConfig, DevOidcUser},
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// Where the provider lives; its issuer is this path on whatever host the
+/// request came in on.
+const OIDC_PATH: &str = "/__nsm/oidc";
+
+/// The signing key, kept across restarts so tokens already handed out stay
+/// valid.
+const KEY_FILE: &str = ".nsm-oidc-key";
+
+/// How long a code may wait before being exchanged.
+const CODE_TTL: Duration = Duration::from_secs(60);
+
+/// What a code stands for until it's exchanged.
+struct Grant {
+    user: DevOidcUser,
+    client_id: String,
+    redirect_uri: String,
+    scope: String,
+    nonce: Option<String>,
+    code_challenge: Option<(String, String)>,
+    issued: Instant,
+}
+
+/// A minimal OpenID Connect provider for debug builds: discovery, JWKS, the
+/// authorization code flow (with PKCE) behind a pick-a-user page, and
+/// `userinfo`. Tokens are ES256, signed with a key generated on first use.
+pub struct DevOidc {
+    config: DevOidcConfig,
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Value,
+    codes: Mutex<HashMap<String, Grant>>,
+}
+
+impl DevOidc {
+    pub fn new(config: &DevOidcConfig) -> anyhow::Result<Self> {
+        let pkcs8 = load_or_generate_key()?;
+        let pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &pkcs8,
+            &SystemRandom::new(),
+        )
+        .map_err(|e| anyhow::anyhow!("{}: invalid key: {}", KEY_FILE, e))?;
+        // An uncompressed point: 0x04, then x and y.
+        let public = pair.public_key().as_ref();
+        let (x, y) = (
+            URL_SAFE_NO_PAD.encode(&public[1..33]),
+            URL_SAFE_NO_PAD.encode(&public[33..65]),
+        );
+        let kid = hex::encode(&Sha256::digest(public)[..8]);
+        Ok(Self {
+            config: config.clone(),
+            encoding: EncodingKey::from_ec_der(&pkcs8),
+            decoding: DecodingKey::from_ec_components(&x, &y)?,
+            jwk: json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "use": "sig",
+                "alg": "ES256",
+                "kid": kid,
+                "x": x,
+                "y": y,
+            }),
+            kid,
+            codes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The key id and key its tokens verify with, for `Auth`.
+    pub fn verifier(&self) -> (String, DecodingKey) {
+        (self.kid.clone(), self.decoding.clone())
+    }
+
+    fn sign(&self, claims: &Value) -> AppResult<String> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.kid.clone());
+        jsonwebtoken::encode(&header, claims, &self.encoding).map_err(AppError::internal)
+    }
+
+    fn user(&self, sub: &str) -> Option<&DevOidcUser> {
+        self.config.users.iter().find(|user| user.sub == sub)
+    }
+}
+
+fn load_or_generate_key() -> anyhow::Result<Vec<u8>> {
+    if let Ok(saved) = std::fs::read_to_string(KEY_FILE) {
+        return URL_SAFE_NO_PAD
+            .decode(saved.trim())
+            .with_context(|| format!("{}: not a saved key", KEY_FILE));
+    }
+    let pkcs8 =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|e| anyhow::anyhow!("failed to generate a signing key: {}", e))?;
+    std::fs::write(KEY_FILE, URL_SAFE_NO_PAD.encode(pkcs8.as_ref()))
+        .with_context(|| format!("failed to write {}", KEY_FILE))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(KEY_FILE, std::fs::Permissions::from_mode(0o600))?;
+    }
+    info!("🔑 Generated a dev OIDC signing key in {}", KEY_FILE);
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+fn provider(state: &AppState) -> AppResult<&DevOidc> {
+    state
+        .dev_oidc()
+        .ok_or_else(|| AppError::not_found("The dev OIDC provider is off"))
+}
+
+/// `http://host:port/__nsm/oidc`, as the client reached us.
+fn issuer(headers: &HeaderMap) -> String {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    format!(
+        "{}://{}{}",
+        header("x-forwarded-proto").unwrap_or("http"),
+        header("host").unwrap_or("localhost"),
+        OIDC_PATH
+    )
+}
+
+/// An OAuth 2 error response (RFC 6749 §5.2).
+fn oauth_error(status: StatusCode, error: &str, description: &str) -> Response {
+    let body = json!({ "error": error, "error_description": description });
+    (status, [(header::CACHE_CONTROL, "no-store")], Json(body)).into_response()
+}
+
+/// `GET /__nsm/oidc/.well-known/openid-configuration`
+pub async fn discovery_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> AppResult<Json<Value>> {
+    provider(&state)?;
+    let issuer = issuer(&headers);
+    Ok(Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "jwks_uri": format!("{}/jwks.json", issuer),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "scopes_supported": ["openid", "profile", "email"],
+        "token_endpoint_auth_methods_supported": ["none", "client_secret_post", "client_secret_basic"],
+        "code_challenge_methods_supported": ["S256", "plain"],
+        "claims_supported": ["sub", "name", "email", "email_verified", "nonce"],
+    })))
+}
+
+/// `GET /__nsm/oidc/jwks.json`
+pub async fn jwks_handler(State(state): State<AppState>) -> AppResult<Json<Value>> {
+    let oidc = provider(&state)?;
+    Ok(Json(json!({ "keys": [oidc.jwk] })))
+}
+
+#[derive(Deserialize)]
+pub struct AuthorizeParams {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+impl AuthorizeParams {
+    fn check(&self) -> AppResult<()> {
+        if self.response_type != "code" {
+            return Err(AppError::bad_request(
+                "Only response_type=code is supported",
+            ));
+        }
+        if !self.redirect_uri.starts_with("http://") && !self.redirect_uri.starts_with("https://") {
+            return Err(AppError::bad_request("redirect_uri must be an http(s) URL"));
+        }
+        Ok(())
+    }
+}
+
+/// Context for `templates/oidc_login.html`.
+#[derive(Serialize)]
+struct LoginContext<'a> {
+    project_name: &'a str,
+    client_id: &'a str,
+    redirect_uri: &'a str,
+    users: &'a [DevOidcUser],
+    /// The authorize request, carried through the form as hidden fields.
+    params: BTreeMap<&'static str, String>,
+}
+
+/// `GET /__nsm/oidc/authorize`: the sign-in page, listing the configured
+/// users.
+pub async fn authorize_page_handler(
+    State(state): State<AppState>,
+    Query(params): Query<AuthorizeParams>,
+) -> AppResult<Html<String>> {
+    let oidc = provider(&state)?;
+    params.check()?;
+    let hidden = [
+        ("response_type", Some(&params.response_type)),
+        ("client_id", Some(&params.client_id)),
+        ("redirect_uri", Some(&params.redirect_uri)),
+        ("scope", Some(&params.scope)),
+        ("state", params.state.as_ref()),
+        ("nonce", params.nonce.as_ref()),
+        ("code_challenge", params.code_challenge.as_ref()),
+        (
+            "code_challenge_method",
+            params.code_challenge_method.as_ref(),
+        ),
+    ];
+    let context = LoginContext {
+        project_name: crate::PROJECT_NAME,
+        client_id: &params.client_id,
+        redirect_uri: &params.redirect_uri,
+        users: &oidc.config.users,
+        params: hidden
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?.clone())))
+            .collect(),
+    };
+    Ok(Html(state.templates().render("oidc_login.html", &context)?))
+}
+
+#[derive(Deserialize)]
+pub struct SignIn {
+    sub: String,
+    #[serde(flatten)]
+    params: AuthorizeParams,
+}
+
+/// `POST /__nsm/oidc/authorize`: signs in as the picked user and sends the
+/// browser back to the client with a code.
+pub async fn authorize_handler(
+    State(state): State<AppState>,
+    Form(input): Form<SignIn>,
+) -> AppResult<Redirect> {
+    let oidc = provider(&state)?;
+    let params = input.params;
+    params.check()?;
+    let user = oidc
+        .user(&input.sub)
+        .ok_or_else(|| AppError::bad_request(format!("No user {:?}", input.sub)))?;
+    let code_challenge = match (params.code_challenge, params.code_challenge_method) {
+        (Some(challenge), method) => {
+            let method = method.unwrap_or_else(|| "plain".to_string());
+            if method != "S256" && method != "plain" {
+                return Err(AppError::bad_request(format!(
+                    "Unsupported code_challenge_method {:?}",
+                    method
+                )));
+            }
+            Some((challenge, method))
+        }
+        (None, _) => None,
+    };
+
+    let mut code = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut code);
+    let code = URL_SAFE_NO_PAD.encode(code);
+    {
+        let mut codes = oidc.codes.lock().unwrap();
+        codes.retain(|_, grant| grant.issued.elapsed() < CODE_TTL);
+        codes.insert(
+            code.clone(),
+            Grant {
+                user: user.clone(),
+                client_id: params.client_id,
+                redirect_uri: params.redirect_uri.clone(),
+                scope: params.scope,
+                nonce: params.nonce,
+                code_challenge,
+                issued: Instant::now(),
+            },
+        );
+    }
+    info!("🔑 Dev OIDC: signed in as {}", user.sub);
+
+    let encode = |v: &str| utf8_percent_encode(v, NON_ALPHANUMERIC).to_string();
+    let mut location = format!(
+        "{}{}code={}",
+        params.redirect_uri,
+        if params.redirect_uri.contains('?') {
+            '&'
+        } else {
+            '?'
+        },
+        encode(&code)
+    );
+    if let Some(state) = &params.state {
+        location.push_str(&format!("&state={}", encode(state)));
+    }
+    Ok(Redirect::to(&location))
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: String,
+    redirect_uri: Option<String>,
+    client_id: Option<String>,
+    code_verifier: Option<String>,
+}
+
+/// `POST /__nsm/oidc/token`: exchanges a code for an ID token and an
+/// access token. Client secrets aren't checked; PKCE is, when the code was
+/// issued with a challenge.
+pub async fn token_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(input): Form<TokenRequest>,
+) -> Response {
+    let Some(oidc) = state.dev_oidc() else {
+        return AppError::not_found("The dev OIDC provider is off").into_response();
+    };
+    if input.grant_type != "authorization_code" {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unsupported_grant_type",
+            "Only authorization_code is supported",
+        );
+    }
+    let grant = oidc.codes.lock().unwrap().remove(&input.code);
+    let Some(grant) = grant.filter(|grant| grant.issued.elapsed() < CODE_TTL) else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Unknown, used or expired code",
+        );
+    };
+    if input.client_id.is_some_and(|id| id != grant.client_id)
+        || input
+            .redirect_uri
+            .is_some_and(|uri| uri != grant.redirect_uri)
+    {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "client_id or redirect_uri doesn't match the authorize request",
+        );
+    }
+    if let Some((challenge, method)) = &grant.code_challenge {
+        let verified = input
+            .code_verifier
+            .is_some_and(|verifier| match method.as_str() {
+                "S256" => URL_SAFE_NO_PAD.encode(Sha256::digest(&verifier)) == *challenge,
+                _ => verifier == *challenge,
+            });
+        if !verified {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "code_verifier doesn't match the code_challenge",
+            );
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let ttl = oidc.config.token_ttl_secs;
+    let user = &grant.user;
+    let mut claims = json!({
+        "iss": issuer(&headers),
+        "sub": user.sub,
+        "iat": now,
+        "exp": now + ttl as i64,
+        "name": user.name,
+        "email": user.email,
+        "email_verified": true,
+    });
+    for (name, value) in &user.claims {
+        claims[name] = value.clone();
+    }
+    let mut id_token = claims.clone();
+    id_token["aud"] = grant.client_id.as_str().into();
+    id_token["auth_time"] = now.into();
+    if let Some(nonce) = &grant.nonce {
+        id_token["nonce"] = nonce.as_str().into();
+    }
+    let mut access_token = claims;
+    access_token["scope"] = grant.scope.as_str().into();
+    if let Some(audience) = &state.config().auth.audience {
+        access_token["aud"] = audience.as_str().into();
+    }
+
+    let tokens = oidc
+        .sign(&id_token)
+        .and_then(|id_token| Ok((id_token, oidc.sign(&access_token)?)));
+    match tokens {
+        Ok((id_token, access_token)) => (
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(json!({
+                "access_token": access_token,
+                "id_token": id_token,
+                "token_type": "Bearer",
+                "expires_in": ttl,
+                "scope": grant.scope,
+            })),
+        )
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `GET /__nsm/oidc/userinfo`: the access token's claims.
+pub async fn userinfo_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let claims = match token {
+        Some(token) => state.auth().verify(token.trim()).await,
+        None => Err(crate::auth::AuthError::Missing),
+    };
+    match claims {
+        Ok(mut claims) => {
+            if let Some(claims) = claims.as_object_mut() {
+                for name in ["iss", "aud", "iat", "exp", "scope"] {
+                    claims.remove(name);
+                }
+            }
+            Json(claims).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
@@ -0,0 +1,119 @@
+use axum::response::{Html, Json};
+use utoipa::OpenApi;
+
+use crate::security_headers::{self, ContentSecurityPolicy};
+
+/// Where the Swagger UI assets come from.
+const SWAGGER_CDN: &str = "https://unpkg.com";
+
+/// The OpenAPI 3 document. Add new handlers to `paths` and their
+/// request/response types to `schemas` to have them documented.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "{{.ProjectName}}", description = "{{.Description}}"),
+    paths(
+        crate::api_info_handler,
+        crate::echo_handler,
+        crate::health::health_handler,
+        crate::health::livez_handler,
+        crate::health::readyz_handler,
+        crate::build_info::version_handler,
+        crate::routes::routes_handler,
+        crate::events::sse_handler,
+        crate::events::publish_handler,
+        crate::events::poll_handler,
+        crate::uploads::upload_handler,
+        crate::uploads::list_handler,
+        crate::uploads::download_handler,
+        crate::uploads::download_url_handler,
+        crate::uploads::upload_url_handler,
+        crate::streaming::ndjson_handler,
+        crate::streaming::bytes_handler,
+        crate::sessions::session_handler,
+        crate::sessions::login_handler,
+        crate::sessions::logout_handler,
+        crate::sessions::me_handler,
+        crate::auth::me_handler,
+        crate::jobs::enqueue_demo_handler,
+        crate::kv::list_handler,
+        crate::kv::get_handler,
+        crate::kv::put_handler,
+        crate::kv::delete_handler,
+    ),
+    components(schemas(
+        crate::AppInfo,
+        crate::EchoRequest,
+        crate::EchoResponse,
+        crate::health::HealthResponse,
+        crate::health::CheckResult,
+        crate::health::CheckStatus,
+        crate::health::ProbeResponse,
+        crate::build_info::BuildInfo,
+        crate::routes::RouteInfo,
+        crate::events::Event,
+        crate::events::PublishRequest,
+        crate::events::PollResponse,
+        crate::uploads::UploadMetadata,
+        crate::uploads::DownloadUrl,
+        crate::pagination::UploadPage,
+        crate::sessions::SessionInfo,
+        crate::sessions::LoginRequest,
+        crate::sessions::Me,
+        crate::auth::Claims,
+        crate::jobs::DemoJob,
+        crate::jobs::JobQueued,
+        crate::kv::KvKeys,
+    )),
+    tags(
+        (name = "system", description = "Health, build and introspection endpoints"),
+        (name = "events", description = "Live updates"),
+        (name = "files", description = "File uploads and downloads"),
+        (name = "session", description = "Cookie sessions and a login demo"),
+        (name = "auth", description = "Bearer JWTs"),
+        (name = "jobs", description = "Background jobs"),
+        (name = "kv", description = "Schemaless key-value store for prototyping"),
+        (name = "demo", description = "Example endpoints")
+    )
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_handler() -> Json<utoipa::openapi::OpenApi> {
+    #[allow(unused_mut)]
+    let mut doc = ApiDoc::openapi();
+    #[cfg(feature = "database")]
+    doc.merge(crate::notes::NotesApi::openapi());
+    #[cfg(feature = "database")]
+    doc.merge(crate::sessions::AccountsApi::openapi());
+    #[cfg(feature = "search")]
+    doc.merge(crate::search::SearchApi::openapi());
+    Json(doc)
+}
+
+/// Swagger UI pointed at `/api/openapi.json`. Assets come from the
+/// swagger-ui-dist CDN so the build doesn't have to download them, which
+/// the page's policy allows.
+pub async fn docs_handler() -> (ContentSecurityPolicy, Html<String>) {
+    let policy = ContentSecurityPolicy::new()
+        .script_src(SWAGGER_CDN)
+        .style_src(SWAGGER_CDN)
+        .img_src(SWAGGER_CDN);
+    let page = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <title>{{.ProjectName}} - API Docs</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script nonce="%NONCE%">
+        window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+</body>
+</html>"##;
+    (
+        policy,
+        Html(page.replace("%NONCE%", &security_headers::nonce())),
+    )
+}
@@ -0,0 +1,274 @@
+use axum::{body::Bytes, http::Request};
+use futures::future::BoxFuture;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper_rustls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{
+        connect::{
+            dns::{GaiResolver, Name},
+            Connect, HttpConnector,
+        },
+        Client,
+    },
+    rt::TokioExecutor,
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::Service;
+use tracing::warn;
+
+use crate::config::OutboundConfig;
+
+/// An address range, e.g. `10.0.4.0/24`; a bare address is a range of one.
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (s.parse().ok()?, None),
+        };
+        let bits = if matches!(addr, IpAddr::V4(_)) {
+            32
+        } else {
+            128
+        };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let shift = |bits: u32| bits.saturating_sub(self.prefix as u32);
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let shift = shift(32);
+                shift == 32 || u32::from(net) >> shift == u32::from(ip) >> shift
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let shift = shift(128);
+                shift == 128 || u128::from(net) >> shift == u128::from(ip) >> shift
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Which addresses outbound requests may reach.
+struct Guard {
+    block_private: bool,
+    block_loopback: bool,
+    allowed_hosts: Vec<String>,
+    allowed_ranges: Vec<Cidr>,
+}
+
+impl Guard {
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    /// Why `ip` is off limits, if it is.
+    fn blocks(&self, ip: IpAddr) -> Option<&'static str> {
+        // `::ffff:169.254.169.254` is the metadata service all the same.
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        if self.allowed_ranges.iter().any(|range| range.contains(ip)) {
+            return None;
+        }
+        let reason = match ip {
+            IpAddr::V4(v4) => v4_reason(v4),
+            IpAddr::V6(v6) => v6_reason(v6),
+        }?;
+        match reason {
+            "loopback" if !self.block_loopback => None,
+            "loopback" => Some(reason),
+            _ if self.block_private => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+fn v4_reason(ip: Ipv4Addr) -> Option<&'static str> {
+    let [a, b, ..] = ip.octets();
+    if ip.is_loopback() {
+        Some("loopback")
+    } else if ip.is_link_local() {
+        // 169.254.169.254 is where the cloud metadata services live.
+        Some("link-local")
+    } else if ip.is_private() {
+        Some("private")
+    } else if a == 100 && (64..128).contains(&b) {
+        Some("shared (CGNAT)")
+    } else if ip.is_unspecified() || ip.is_broadcast() || a == 0 {
+        Some("unroutable")
+    } else {
+        None
+    }
+}
+
+fn v6_reason(ip: Ipv6Addr) -> Option<&'static str> {
+    let first = ip.segments()[0];
+    if ip.is_loopback() {
+        Some("loopback")
+    } else if first & 0xffc0 == 0xfe80 {
+        Some("link-local")
+    } else if first & 0xfe00 == 0xfc00 {
+        // Unique local, including AWS's fd00:ec2::254 metadata address.
+        Some("private")
+    } else if ip.is_unspecified() {
+        Some("unroutable")
+    } else {
+        None
+    }
+}
+
+/// Resolves names as usual, then drops the addresses the guard blocks, so
+/// the connection goes to an address that was checked; a name that only
+/// resolves to blocked ones can't be reached.
+#[derive(Clone)]
+struct GuardedResolver {
+    inner: GaiResolver,
+    guard: Arc<Guard>,
+}
+
+impl Service<Name> for GuardedResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let guard = self.guard.clone();
+        let host = name.as_str().to_string();
+        let resolving = self.inner.call(name);
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving.await?.collect();
+            if guard.allows_host(&host) {
+                return Ok(addrs.into_iter());
+            }
+            let mut blocked = None;
+            let allowed: Vec<SocketAddr> = addrs
+                .into_iter()
+                .filter(|addr| match guard.blocks(addr.ip()) {
+                    Some(reason) => {
+                        blocked = Some((addr.ip(), reason));
+                        false
+                    }
+                    None => true,
+                })
+                .collect();
+            if allowed.is_empty()
+                && let Some((ip, reason)) = blocked
+            {
+                warn!(
+                    "🚧 Outbound request to {} ({}) blocked: {} address",
+                    host, ip, reason
+                );
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} resolves to a {} address ({})", host, reason, ip),
+                ));
+            }
+            Ok(allowed.into_iter())
+        })
+    }
+}
+
+/// A pooled HTTP(S) client trusting the system's root certificates.
+pub type HttpsClient<R = GaiResolver> = Client<HttpsConnector<HttpConnector<R>>, Full<Bytes>>;
+
+/// The client for fixed destinations the operator configured, e.g. the JWKS
+/// URL or the S3 endpoint. Anything a request can point somewhere goes
+/// through [`OutboundClient`] instead.
+pub fn https_client() -> anyhow::Result<HttpsClient> {
+    https_client_over(HttpConnector::new())
+}
+
+fn https_client_over<R>(mut http: HttpConnector<R>) -> anyhow::Result<HttpsClient<R>>
+where
+    HttpsConnector<HttpConnector<R>>: Connect + Clone + Send + Sync + 'static,
+{
+    http.enforce_http(false);
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http);
+    Ok(Client::builder(TokioExecutor::new()).build(https))
+}
+
+/// The HTTP(S) client for URLs that come from users or config, e.g. the
+/// outbox's webhooks. It won't reach private, link-local or cloud metadata
+/// addresses unless `outbound.allow` lists them, whether the URL names the
+/// address or a host that resolves to it. Cheap to clone.
+#[derive(Clone)]
+pub struct OutboundClient {
+    client: HttpsClient<GuardedResolver>,
+    guard: Arc<Guard>,
+}
+
+impl OutboundClient {
+    pub fn new(config: &OutboundConfig) -> anyhow::Result<Self> {
+        let mut allowed_hosts = Vec::new();
+        let mut allowed_ranges = Vec::new();
+        for entry in &config.allow {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                anyhow::bail!("outbound.allow: empty entry");
+            }
+            match Cidr::parse(entry) {
+                Some(range) => allowed_ranges.push(range),
+                None if entry.contains('/') => {
+                    anyhow::bail!("outbound.allow: {:?} is not a valid range", entry)
+                }
+                None => allowed_hosts.push(entry.to_string()),
+            }
+        }
+        let guard = Arc::new(Guard {
+            block_private: config.block_private,
+            block_loopback: config.block_loopback,
+            allowed_hosts,
+            allowed_ranges,
+        });
+
+        let client = https_client_over(HttpConnector::new_with_resolver(GuardedResolver {
+            inner: GaiResolver::new(),
+            guard: guard.clone(),
+        }))?;
+        Ok(Self { client, guard })
+    }
+
+    /// Sends `req`, unless its URL is a blocked address. Names are checked
+    /// once resolved.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub async fn request(
+        &self,
+        req: Request<Full<Bytes>>,
+    ) -> anyhow::Result<hyper::Response<Incoming>> {
+        // Addresses in the URL never reach the resolver.
+        let host = req.uri().host().unwrap_or_default();
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>()
+            && !self.guard.allows_host(host)
+            && let Some(reason) = self.guard.blocks(ip)
+        {
+            warn!("🚧 Outbound request to {} blocked: {} address", ip, reason);
+            anyhow::bail!("{} is a {} address", ip, reason);
+        }
+        Ok(self.client.request(req).await?)
+    }
+}
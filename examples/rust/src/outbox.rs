@@ -0,0 +1,472 @@
+use anyhow::{anyhow, Context};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, Method, Request, StatusCode, Uri},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{OutboxConfig, WebhookConfig},
+    error::{AppError, AppResult},
+    events::TopicFilter,
+    outbound::OutboundClient,
+    state::AppState,
+    tenancy::Tenant,
+    tx::Tx,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The destination that publishes to the live event hub, which SSE,
+/// WebSocket and long-poll clients read (and Redis relays, when on).
+const EVENTS_DESTINATION: &str = "events";
+
+/// How often the idle dispatcher looks for retries that have come due.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Deliveries attempted together.
+const BATCH_SIZE: i64 = 50;
+
+/// Messages listed by `/debug/outbox`.
+const LIST_LIMIT: i64 = 100;
+
+const SIGNATURE_HEADER: &str = "x-outbox-signature";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Waiting for `next_attempt_at`.
+    Pending,
+    Delivered,
+    /// Out of attempts; `POST /debug/outbox/{id}/retry` sends it again.
+    Dead,
+}
+
+impl OutboxStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+/// One event on its way to one destination.
+#[derive(Serialize)]
+pub struct OutboxMessage {
+    pub id: i64,
+    /// `events`, or a webhook URL.
+    pub destination: String,
+    pub kind: String,
+    pub payload: Value,
+    /// Whose data the payload is; see [`crate::events::Event::tenant`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct OutboxRow {
+    id: i64,
+    destination: String,
+    kind: String,
+    payload: String,
+    tenant: Option<String>,
+    status: String,
+    attempts: i64,
+    next_attempt_at: i64,
+    last_error: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl TryFrom<OutboxRow> for OutboxMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(row: OutboxRow) -> anyhow::Result<Self> {
+        let time = |ms| DateTime::from_timestamp_millis(ms).unwrap_or_default();
+        Ok(Self {
+            id: row.id,
+            destination: row.destination,
+            kind: row.kind,
+            payload: serde_json::from_str(&row.payload)?,
+            tenant: row.tenant,
+            status: serde_json::from_value(Value::String(row.status))?,
+            attempts: row.attempts.try_into()?,
+            next_attempt_at: time(row.next_attempt_at),
+            last_error: row.last_error,
+            created_at: time(row.created_at),
+            updated_at: time(row.updated_at),
+        })
+    }
+}
+
+/// What a webhook receives. `id` is stable across retries, so receivers
+/// can drop the duplicates at-least-once delivery allows.
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    id: i64,
+    kind: &'a str,
+    data: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<&'a str>,
+    created_at: DateTime<Utc>,
+}
+
+struct Webhook {
+    uri: Uri,
+    topics: TopicFilter,
+    config: WebhookConfig,
+}
+
+/// Events written alongside data changes and delivered once the change has
+/// committed. Record them with [`Outbox::record`] on the request's [`Tx`].
+pub struct Outbox {
+    db: SqlitePool,
+    config: OutboxConfig,
+    webhooks: Vec<Webhook>,
+    /// Wakes the dispatcher when a transaction with events commits.
+    recorded: Arc<Notify>,
+}
+
+impl Outbox {
+    pub fn new(config: &OutboxConfig, db: SqlitePool) -> anyhow::Result<Self> {
+        let webhooks = config
+            .webhooks
+            .iter()
+            .map(|webhook| {
+                let uri: Uri = webhook
+                    .url
+                    .parse()
+                    .with_context(|| format!("outbox webhook {}: invalid url", webhook.url))?;
+                if uri.host().is_none() {
+                    anyhow::bail!("outbox webhook {}: url needs a host", webhook.url);
+                }
+                Ok(Webhook {
+                    uri,
+                    topics: TopicFilter::new(webhook.events.iter().cloned()),
+                    config: webhook.clone(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            db,
+            config: config.clone(),
+            webhooks,
+            recorded: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Adds a `kind` event for the event hub and every webhook that wants
+    /// it. Nothing is sent unless `tx` commits. With a `tenant`, only that
+    /// tenant's live subscribers get it.
+    pub async fn record(
+        &self,
+        tx: &mut Tx,
+        tenant: Option<&Tenant>,
+        kind: &str,
+        data: impl Serialize,
+    ) -> anyhow::Result<()> {
+        self.record_in(tx, tenant, kind, data).await?;
+        let recorded = self.recorded.clone();
+        tx.after_commit(move || recorded.notify_one());
+        Ok(())
+    }
+
+    /// [`record`](Self::record) in a transaction the caller manages; call
+    /// [`wake`](Self::wake) once it has committed.
+    pub async fn record_in(
+        &self,
+        conn: &mut SqliteConnection,
+        tenant: Option<&Tenant>,
+        kind: &str,
+        data: impl Serialize,
+    ) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&data)?;
+        let now = Utc::now().timestamp_millis();
+        let destinations = std::iter::once(EVENTS_DESTINATION).chain(
+            self.webhooks
+                .iter()
+                .filter(|webhook| webhook.topics.matches(kind))
+                .map(|webhook| webhook.config.url.as_str()),
+        );
+        for destination in destinations {
+            sqlx::query(
+                "INSERT INTO outbox (destination, kind, payload, tenant, status, \
+                 next_attempt_at, created_at, updated_at) \
+                 VALUES (?, ?, ?, ?, 'pending', ?, ?, ?)",
+            )
+            .bind(destination)
+            .bind(kind)
+            .bind(&payload)
+            .bind(tenant.map(Tenant::id))
+            .bind(now)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Delivers what was recorded now rather than at the next poll.
+    pub fn wake(&self) {
+        self.recorded.notify_one();
+    }
+
+    /// Wait before the attempt after `attempt`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.config
+                .backoff_ms
+                .saturating_mul(factor)
+                .min(self.config.max_backoff_ms),
+        )
+    }
+
+    async fn due(&self) -> anyhow::Result<Vec<OutboxMessage>> {
+        let rows: Vec<OutboxRow> = sqlx::query_as(
+            "SELECT * FROM outbox WHERE status = 'pending' AND next_attempt_at <= ? \
+             ORDER BY id LIMIT ?",
+        )
+        .bind(Utc::now().timestamp_millis())
+        .bind(BATCH_SIZE)
+        .fetch_all(&self.db)
+        .await?;
+        rows.into_iter().map(OutboxMessage::try_from).collect()
+    }
+
+    async fn deliver(&self, state: &AppState, message: OutboxMessage) {
+        let result = if message.destination == EVENTS_DESTINATION {
+            state.events().publish_to(
+                message.tenant.clone(),
+                message.kind.as_str(),
+                message.payload.clone(),
+            );
+            Ok(())
+        } else {
+            self.post(state.outbound(), &message).await
+        };
+
+        let attempts = message.attempts + 1;
+        let mut next_attempt_at = message.next_attempt_at;
+        let (status, error) = match result {
+            Ok(()) => {
+                debug!(
+                    "📤 Outbox #{} ({}) delivered to {}",
+                    message.id, message.kind, message.destination
+                );
+                (OutboxStatus::Delivered, None)
+            }
+            Err(e) if attempts >= self.config.max_attempts => {
+                error!(
+                    "📤 Outbox #{} ({}) to {} dead-lettered after {} attempts: {:#}",
+                    message.id, message.kind, message.destination, attempts, e
+                );
+                (OutboxStatus::Dead, Some(format!("{:#}", e)))
+            }
+            Err(e) => {
+                let wait = self.backoff(attempts);
+                warn!(
+                    "📤 Outbox #{} ({}) to {} failed, retrying in {:?}: {:#}",
+                    message.id, message.kind, message.destination, wait, e
+                );
+                next_attempt_at = Utc::now() + wait;
+                (OutboxStatus::Pending, Some(format!("{:#}", e)))
+            }
+        };
+
+        let result = sqlx::query(
+            "UPDATE outbox SET status = ?, attempts = ?, next_attempt_at = ?, \
+             last_error = COALESCE(?, last_error), updated_at = ? WHERE id = ?",
+        )
+        .bind(status.as_str())
+        .bind(attempts)
+        .bind(next_attempt_at.timestamp_millis())
+        .bind(error)
+        .bind(Utc::now().timestamp_millis())
+        .bind(message.id)
+        .execute(&self.db)
+        .await;
+        if let Err(e) = result {
+            error!("Failed to update outbox #{}: {}", message.id, e);
+        }
+    }
+
+    /// Through `client`, so a webhook can't be pointed at internal
+    /// addresses.
+    async fn post(&self, client: &OutboundClient, message: &OutboxMessage) -> anyhow::Result<()> {
+        let webhook = self
+            .webhooks
+            .iter()
+            .find(|webhook| webhook.config.url == message.destination)
+            .ok_or_else(|| anyhow!("no longer in outbox.webhooks"))?;
+        let body = serde_json::to_vec(&WebhookBody {
+            id: message.id,
+            kind: &message.kind,
+            data: &message.payload,
+            tenant: message.tenant.as_deref(),
+            created_at: message.created_at,
+        })?;
+
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(webhook.uri.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, "{{.ProjectName}}-outbox");
+        if let Some(secret) = &webhook.config.secret {
+            let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+                .expect("HMAC takes keys of any length");
+            mac.update(&body);
+            req = req.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", hex::encode(mac.finalize().into_bytes())),
+            );
+            let path = webhook.uri.path_and_query().map_or("/", |p| p.as_str());
+            req = req.header(
+                crate::signing::SIGNATURE_HEADER,
+                crate::signing::sign(
+                    crate::PROJECT_NAME,
+                    secret.as_bytes(),
+                    &Method::POST,
+                    path,
+                    &body,
+                ),
+            );
+        }
+        let req = req.body(Full::new(Bytes::from(body)))?;
+
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let res = tokio::time::timeout(timeout, client.request(req))
+            .await
+            .map_err(|_| anyhow!("timed out after {:?}", timeout))??;
+        if !res.status().is_success() {
+            anyhow::bail!("HTTP {}", res.status());
+        }
+        Ok(())
+    }
+}
+
+/// Starts the dispatcher, which delivers recorded events until shutdown.
+/// With several instances on one database an event may go out more than
+/// once; delivery is at-least-once either way.
+pub fn start(state: &AppState) {
+    info!(
+        "📤 Outbox: event hub and {} webhooks",
+        state.outbox().webhooks.len()
+    );
+    tokio::spawn(dispatch(state.clone()));
+}
+
+async fn dispatch(state: AppState) {
+    let shutdown = state.shutdown_token();
+    let outbox = state.outbox();
+    while !shutdown.is_cancelled() {
+        let due = outbox.due().await.unwrap_or_else(|e| {
+            error!("Failed to read the outbox: {:#}", e);
+            Vec::new()
+        });
+        if due.is_empty() {
+            tokio::select! {
+                _ = outbox.recorded.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = shutdown.cancelled() => {}
+            }
+            continue;
+        }
+        futures::future::join_all(
+            due.into_iter()
+                .map(|message| outbox.deliver(&state, message)),
+        )
+        .await;
+    }
+}
+
+#[derive(Serialize)]
+pub struct OutboxReport {
+    webhooks: Vec<String>,
+    counts: BTreeMap<OutboxStatus, u64>,
+    /// The latest messages, newest first.
+    messages: Vec<OutboxMessage>,
+}
+
+/// `/debug/outbox`: delivery counts and the latest messages.
+pub async fn debug_handler(State(state): State<AppState>) -> AppResult<Json<OutboxReport>> {
+    let outbox = state.outbox();
+    let counts: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM outbox GROUP BY status")
+            .fetch_all(&outbox.db)
+            .await
+            .map_err(AppError::internal)?;
+    let rows: Vec<OutboxRow> = sqlx::query_as("SELECT * FROM outbox ORDER BY id DESC LIMIT ?")
+        .bind(LIST_LIMIT)
+        .fetch_all(&outbox.db)
+        .await
+        .map_err(AppError::internal)?;
+    Ok(Json(OutboxReport {
+        webhooks: outbox
+            .webhooks
+            .iter()
+            .map(|webhook| webhook.config.url.clone())
+            .collect(),
+        counts: counts
+            .into_iter()
+            .filter_map(|(status, count)| {
+                let status = serde_json::from_value(Value::String(status)).ok()?;
+                Some((status, count.try_into().ok()?))
+            })
+            .collect(),
+        messages: rows
+            .into_iter()
+            .map(OutboxMessage::try_from)
+            .collect::<anyhow::Result<_>>()
+            .map_err(AppError::internal)?,
+    }))
+}
+
+/// `/debug/outbox/{id}/retry`: sends a dead-lettered message again, with a
+/// fresh set of attempts.
+pub async fn retry_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> AppResult<StatusCode> {
+    let outbox = state.outbox();
+    let now = Utc::now().timestamp_millis();
+    let result = sqlx::query(
+        "UPDATE outbox SET status = 'pending', attempts = 0, next_attempt_at = ?, \
+         updated_at = ? WHERE id = ? AND status = 'dead'",
+    )
+    .bind(now)
+    .bind(now)
+    .bind(id)
+    .execute(&outbox.db)
+    .await
+    .map_err(AppError::internal)?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found(format!(
+            "No dead-lettered outbox message with id {}",
+            id
+        )));
+    }
+    outbox.recorded.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,165 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::AppError, uploads::UploadMetadata};
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+/// Query parameters understood by every paginated list endpoint.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaginationParams {
+    /// 1-based page number. Ignored when `cursor` is given.
+    page: Option<usize>,
+    /// Items per page (default 20, max 100).
+    limit: Option<usize>,
+    /// Opaque `next_cursor` from a previous page.
+    cursor: Option<String>,
+    /// Field to sort by; prefix with `-` for descending.
+    sort: Option<String>,
+}
+
+/// Validated paging and sorting for a list request. Rejects out-of-range
+/// values with a 400 instead of silently clamping them.
+pub struct Pagination {
+    pub offset: usize,
+    pub limit: usize,
+    pub sort: Option<Sort>,
+}
+
+pub struct Sort {
+    pub field: String,
+    pub descending: bool,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Pagination {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<PaginationParams>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::bad_request(e.body_text()))?;
+
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(AppError::bad_request(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+
+        let offset = match (params.cursor, params.page) {
+            (Some(cursor), _) => decode_cursor(&cursor)
+                .ok_or_else(|| AppError::bad_request("Invalid pagination cursor"))?,
+            (None, Some(0)) => return Err(AppError::bad_request("page starts at 1")),
+            (None, Some(page)) => (page - 1)
+                .checked_mul(limit)
+                .ok_or_else(|| AppError::bad_request("page is out of range"))?,
+            (None, None) => 0,
+        };
+
+        let sort = params
+            .sort
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.strip_prefix('-') {
+                Some(field) => Sort {
+                    field: field.to_string(),
+                    descending: true,
+                },
+                None => Sort {
+                    field: s,
+                    descending: false,
+                },
+            });
+
+        Ok(Self {
+            offset,
+            limit,
+            sort,
+        })
+    }
+}
+
+impl Pagination {
+    /// The requested sort, checked against the fields an endpoint supports.
+    pub fn sort(&self, allowed: &[&str]) -> Result<Option<&Sort>, AppError> {
+        match &self.sort {
+            Some(sort) if !allowed.contains(&sort.field.as_str()) => {
+                Err(AppError::bad_request(format!(
+                    "Cannot sort by {}; expected one of {}",
+                    sort.field,
+                    allowed.join(", ")
+                )))
+            }
+            sort => Ok(sort.as_ref()),
+        }
+    }
+
+    /// Cuts one page out of an already filtered and sorted list.
+    pub fn page<T>(&self, items: Vec<T>) -> Page<T> {
+        let total = items.len();
+        let items = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .collect();
+        self.paged(items, total)
+    }
+
+    /// Wraps a page the query already cut out with `offset` and `limit`;
+    /// `total` counts the matches across all pages.
+    pub fn paged<T>(&self, items: Vec<T>, total: usize) -> Page<T> {
+        let end = self.offset.saturating_add(items.len());
+        Page {
+            items,
+            total,
+            page: self.offset / self.limit + 1,
+            limit: self.limit,
+            next_cursor: (end < total).then(|| encode_cursor(end)),
+        }
+    }
+}
+
+/// Standard list envelope.
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(not(feature = "database"), aliases(UploadPage = Page<UploadMetadata>))]
+#[cfg_attr(
+    all(feature = "database", not(feature = "search")),
+    aliases(UploadPage = Page<UploadMetadata>, NotePage = Page<crate::notes::Note>)
+)]
+#[cfg_attr(
+    feature = "search",
+    aliases(
+        UploadPage = Page<UploadMetadata>,
+        NotePage = Page<crate::notes::Note>,
+        SearchPage = Page<crate::search::SearchHit>
+    )
+)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Matching items across all pages.
+    pub total: usize,
+    pub page: usize,
+    pub limit: usize,
+    /// Pass as `?cursor=` to fetch the next page; absent on the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+// Cursors are offsets today; keeping them opaque leaves room for keyset
+// pagination without breaking clients.
+fn encode_cursor(offset: usize) -> String {
+    hex::encode(format!("o:{}", offset))
+}
+
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    let raw = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+    raw.strip_prefix("o:")?.parse().ok()
+}
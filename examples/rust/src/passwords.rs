@@ -0,0 +1,177 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, Version,
+};
+use std::collections::BTreeMap;
+
+use crate::config::PasswordsConfig;
+
+/// `version=pepper,...` on top of the configured peppers.
+const PEPPERS_ENV: &str = "PASSWORD_PEPPERS";
+
+/// How a password check went.
+pub struct Verdict {
+    pub matches: bool,
+    /// The hash is out of date (older parameters, an older pepper, or
+    /// bcrypt); hash the password again while it's at hand.
+    pub rehash: bool,
+}
+
+impl Verdict {
+    fn no() -> Self {
+        Self {
+            matches: false,
+            rehash: false,
+        }
+    }
+}
+
+/// Hashes account passwords with argon2id, using the parameters from
+/// `passwords` and the newest pepper as argon2's secret. A hash records its
+/// parameters and, as `keyid`, the pepper's version, so any configured
+/// pepper checks it and [`Passwords::verify`] can tell when it's due to be
+/// redone. bcrypt hashes from before still check. Hashing is slow on
+/// purpose; call these off the async workers.
+pub struct Passwords {
+    params: Params,
+    /// Newest first.
+    peppers: Vec<(u32, Vec<u8>)>,
+    /// Checked against when a login names no account, so it takes as long
+    /// as one with a wrong password and doesn't tell which usernames exist.
+    dummy: String,
+}
+
+impl Passwords {
+    pub fn new(config: &PasswordsConfig) -> anyhow::Result<Self> {
+        let mut peppers = BTreeMap::new();
+        let mut add = |version: &str, pepper: String, source: &str| {
+            let version: u32 = version.trim().parse().with_context(|| {
+                format!("{}: pepper version {:?} isn't a number", source, version)
+            })?;
+            if pepper.is_empty() {
+                anyhow::bail!("{}: pepper {} is empty", source, version);
+            }
+            peppers.insert(version, pepper.into_bytes());
+            Ok(())
+        };
+        for (version, pepper) in &config.peppers {
+            add(version, pepper.clone(), "passwords.peppers")?;
+        }
+        if let Ok(list) = std::env::var(PEPPERS_ENV) {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let Some((version, pepper)) = entry.split_once('=') else {
+                    anyhow::bail!("{}: expected version=pepper, got {:?}", PEPPERS_ENV, entry);
+                };
+                add(version, pepper.trim().to_string(), PEPPERS_ENV)?;
+            }
+        }
+        let peppers: Vec<_> = peppers.into_iter().rev().collect();
+
+        let mut params = ParamsBuilder::new();
+        params
+            .m_cost(config.memory_kib)
+            .t_cost(config.iterations)
+            .p_cost(config.parallelism);
+        if let Some((version, _)) = peppers.first() {
+            params.keyid(KeyId::new(version.to_string().as_bytes()).map_err(anyhow::Error::msg)?);
+        }
+        let params = params
+            .build()
+            .map_err(|e| anyhow::anyhow!("passwords: {}", e))?;
+
+        let mut passwords = Self {
+            params,
+            peppers,
+            dummy: String::new(),
+        };
+        passwords.dummy = passwords.hash("not a real password")?;
+        Ok(passwords)
+    }
+
+    fn argon2<'a>(
+        &'a self,
+        pepper: Option<&'a [u8]>,
+        params: Params,
+    ) -> anyhow::Result<Argon2<'a>> {
+        match pepper {
+            Some(pepper) => {
+                Argon2::new_with_secret(pepper, Algorithm::Argon2id, Version::V0x13, params)
+                    .map_err(anyhow::Error::msg)
+            }
+            None => Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params)),
+        }
+    }
+
+    /// A PHC string (`$argon2id$v=19$m=...`) for `password`.
+    pub fn hash(&self, password: &str) -> anyhow::Result<String> {
+        let pepper = self.peppers.first().map(|(_, p)| p.as_slice());
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(self
+            .argon2(pepper, self.params.clone())?
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(anyhow::Error::msg)?
+            .to_string())
+    }
+
+    /// `password` against `hash`, or against a dummy hash when there's no
+    /// account, so that takes as long as a wrong password.
+    pub fn verify(&self, password: &str, hash: Option<&str>) -> anyhow::Result<Verdict> {
+        let Some(hash) = hash else {
+            self.check(password, &self.dummy)?;
+            return Ok(Verdict::no());
+        };
+        if hash.starts_with("$2") {
+            let matches = bcrypt::verify(password, hash)?;
+            return Ok(Verdict {
+                matches,
+                rehash: matches,
+            });
+        }
+        let (matches, current) = self.check(password, hash)?;
+        Ok(Verdict {
+            matches,
+            rehash: matches && !current,
+        })
+    }
+
+    /// Whether `password` matches an argon2 `hash`, with the pepper it
+    /// names, and whether the hash was made the way new ones are.
+    fn check(&self, password: &str, hash: &str) -> anyhow::Result<(bool, bool)> {
+        let parsed = PasswordHash::new(hash).map_err(anyhow::Error::msg)?;
+        let params = Params::try_from(&parsed).map_err(anyhow::Error::msg)?;
+        let current = parsed.algorithm == Algorithm::Argon2id.ident()
+            && params.m_cost() == self.params.m_cost()
+            && params.t_cost() == self.params.t_cost()
+            && params.p_cost() == self.params.p_cost()
+            && params.keyid() == self.params.keyid();
+        let pepper = match params.keyid() {
+            [] => None,
+            keyid => {
+                let version: u32 = std::str::from_utf8(keyid)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .context("a password hash names an unreadable pepper version")?;
+                let (_, pepper) = self
+                    .peppers
+                    .iter()
+                    .find(|(v, _)| *v == version)
+                    .with_context(|| {
+                        format!("password pepper {} is no longer configured", version)
+                    })?;
+                Some(pepper.as_slice())
+            }
+        };
+        // The algorithm and parameters come from the hash; only the pepper
+        // is ours.
+        let matches = match self
+            .argon2(pepper, self.params.clone())?
+            .verify_password(password.as_bytes(), &parsed)
+        {
+            Ok(()) => true,
+            Err(argon2::password_hash::Error::Password) => false,
+            Err(e) => anyhow::bail!("{}", e),
+        };
+        Ok((matches, current))
+    }
+}
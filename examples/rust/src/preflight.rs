@@ -0,0 +1,352 @@
+use serde::Serialize;
+use std::{
+    fs,
+    io::{BufReader, ErrorKind},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::{
+    config::AppConfig, static_files::STATIC_DIR, templates::TEMPLATES_DIR, NSMConfig,
+    NSM_PORTS_FILE,
+};
+
+/// Environment `doctor` expects. NSM sets these when it starts the project;
+/// add the variables your own code depends on.
+const REQUIRED_ENV: &[&str] = &["NSM_ENABLED", "NSM_PROJECT_NAME", "NSM_DOMAIN"];
+
+/// Bound on each network probe, so `doctor` never hangs.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    /// What to do about a failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn skip(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Skip,
+            message: message.into(),
+            hint: None,
+        }
+    }
+}
+
+/// Outcome of a set of checks; `ok` unless one of them failed.
+#[derive(Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl From<Vec<Check>> for Report {
+    fn from(checks: Vec<Check>) -> Self {
+        Self {
+            ok: checks.iter().all(|c| c.status != Status::Fail),
+            checks,
+        }
+    }
+}
+
+impl Report {
+    pub fn print(&self, json: bool) -> anyhow::Result<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+        for check in &self.checks {
+            let mark = match check.status {
+                Status::Pass => "✅",
+                Status::Fail => "❌",
+                Status::Skip => "➖",
+            };
+            println!("{} {}: {}", mark, check.name, check.message);
+            if let Some(hint) = &check.hint {
+                println!("   → {}", hint);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything that would stop `serve` from starting, checked without
+/// starting it.
+pub fn check() -> Report {
+    let (ports, mut checks) = match crate::read_nsm_config() {
+        Ok(Some(ports)) => {
+            let check = Check::pass("nsm_ports", format!("{} is valid", NSM_PORTS_FILE));
+            (Some(ports), vec![check])
+        }
+        Ok(None) => {
+            let check = Check::skip(
+                "nsm_ports",
+                format!("no {}, using defaults", NSM_PORTS_FILE),
+            );
+            (None, vec![check])
+        }
+        Err(e) => {
+            let check = Check::fail(
+                "nsm_ports",
+                format!("{}: {:#}", NSM_PORTS_FILE, e),
+                "Restart the project with nsm so it rewrites the file",
+            );
+            (None, vec![check])
+        }
+    };
+
+    checks.push(check_config());
+    checks.push(check_port(ports));
+    checks.push(check_tls());
+    checks.push(check_dir("static_dir", STATIC_DIR));
+    checks.push(check_dir("templates_dir", TEMPLATES_DIR));
+    checks.into()
+}
+
+fn check_config() -> Check {
+    let path = AppConfig::path();
+    match AppConfig::load() {
+        Ok(_) if Path::new(&path).exists() => Check::pass("config", format!("{} is valid", path)),
+        Ok(_) => Check::pass("config", format!("no {}, using defaults", path)),
+        Err(e) => Check::fail(
+            "config",
+            format!("{:#}", e),
+            "Fix the file or point APP_CONFIG somewhere else",
+        ),
+    }
+}
+
+/// The HTTP port has to be bindable: either the one NSM leased to us in
+/// `.nsm-ports.json` or the generated default.
+fn check_port(leased: Option<NSMConfig>) -> Check {
+    let source = if leased.is_some() {
+        "leased by NSM"
+    } else {
+        "default"
+    };
+    let ports = leased.unwrap_or_default();
+    let addr = format!("{}:{}", ports.host, ports.http);
+
+    match TcpListener::bind(&addr) {
+        Ok(_) => Check::pass("port", format!("{} is free ({})", addr, source)),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => Check::fail(
+            "port",
+            format!("{} is already in use ({})", addr, source),
+            "Stop the other process, or let nsm pick a new port by restarting the project",
+        ),
+        Err(e) => Check::fail(
+            "port",
+            format!("cannot bind {}: {}", addr, e),
+            "Check the host in .nsm-ports.json and that the port isn't privileged",
+        ),
+    }
+}
+
+/// With `NSM_HTTPS_ENABLED`, the cert and key NSM points us at must exist
+/// and contain PEM data we can use.
+fn check_tls() -> Check {
+    if std::env::var("NSM_HTTPS_ENABLED").as_deref() != Ok("true") {
+        return Check::skip("tls", "HTTPS is not enabled");
+    }
+    let hint = "Restart the project with nsm to regenerate its certificate";
+
+    let (Ok(cert_path), Ok(key_path)) = (
+        std::env::var("NSM_CERT_PATH"),
+        std::env::var("NSM_KEY_PATH"),
+    ) else {
+        return Check::fail(
+            "tls",
+            "NSM_CERT_PATH and NSM_KEY_PATH must both be set",
+            hint,
+        );
+    };
+
+    let certs = fs::File::open(&cert_path).and_then(|file| {
+        rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()
+    });
+    match certs {
+        Ok(certs) if certs.is_empty() => {
+            return Check::fail("tls", format!("no certificates in {}", cert_path), hint);
+        }
+        Ok(_) => {}
+        Err(e) => return Check::fail("tls", format!("{}: {}", cert_path, e), hint),
+    }
+
+    let key = fs::File::open(&key_path)
+        .and_then(|file| rustls_pemfile::private_key(&mut BufReader::new(file)));
+    match key {
+        Ok(Some(_)) => Check::pass("tls", format!("{} and {} parse", cert_path, key_path)),
+        Ok(None) => Check::fail("tls", format!("no private key in {}", key_path), hint),
+        Err(e) => Check::fail("tls", format!("{}: {}", key_path, e), hint),
+    }
+}
+
+fn check_dir(name: &'static str, dir: &str) -> Check {
+    if Path::new(dir).is_dir() {
+        Check::pass(name, format!("{}/ exists", dir))
+    } else {
+        Check::fail(
+            name,
+            format!("{}/ is missing", dir),
+            format!("Run from the project root, or create {}/", dir),
+        )
+    }
+}
+
+/// Probes the environment NSM sets up around us: its proxy, the mkcert CA,
+/// DNS for our domain and the variables it passes down.
+pub async fn doctor() -> Report {
+    let domain = crate::domain();
+    let (proxy, dns) = tokio::join!(check_proxy(), check_dns(&domain));
+    vec![proxy, check_ca(), dns, check_env()].into()
+}
+
+/// NSM's proxy listens on the HTTPS port it leased to us.
+pub async fn check_proxy() -> Check {
+    let hint = "Start the project with nsm, e.g. `nsm -t rust`";
+    let ports = match crate::read_nsm_config() {
+        Ok(Some(ports)) => ports,
+        Ok(None) => {
+            return Check::fail(
+                "nsm",
+                format!("no {}; NSM isn't managing this project", NSM_PORTS_FILE),
+                hint,
+            );
+        }
+        Err(e) => return Check::fail("nsm", format!("{}: {:#}", NSM_PORTS_FILE, e), hint),
+    };
+
+    let addr = format!("127.0.0.1:{}", ports.https);
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(&addr)).await {
+        Ok(Ok(_)) => Check::pass("nsm", format!("proxy is listening on {}", addr)),
+        Ok(Err(e)) => Check::fail("nsm", format!("proxy at {}: {}", addr, e), hint),
+        Err(_) => Check::fail("nsm", format!("proxy at {} timed out", addr), hint),
+    }
+}
+
+/// The mkcert root has to be in the OS trust store, or browsers reject the
+/// proxy's certificate.
+fn check_ca() -> Check {
+    let hint = "Install mkcert and run `nsm-setup install` to trust its CA";
+    let Some(ca_root) = mkcert_ca_root() else {
+        return Check::fail("ca", "mkcert not found and CAROOT not set", hint);
+    };
+
+    let ca_path = ca_root.join("rootCA.pem");
+    let ca = fs::File::open(&ca_path).and_then(|file| {
+        rustls_pemfile::certs(&mut BufReader::new(file))
+            .next()
+            .transpose()
+    });
+    let ca = match ca {
+        Ok(Some(ca)) => ca,
+        Ok(None) => {
+            return Check::fail(
+                "ca",
+                format!("no certificate in {}", ca_path.display()),
+                hint,
+            );
+        }
+        Err(e) => return Check::fail("ca", format!("{}: {}", ca_path.display(), e), hint),
+    };
+
+    let native = rustls_native_certs::load_native_certs();
+    if native.certs.contains(&ca) {
+        Check::pass("ca", format!("{} is trusted", ca_path.display()))
+    } else {
+        Check::fail(
+            "ca",
+            format!("{} is not in the system trust store", ca_path.display()),
+            "Run `mkcert -install`",
+        )
+    }
+}
+
+pub fn mkcert_ca_root() -> Option<PathBuf> {
+    if let Ok(root) = std::env::var("CAROOT") {
+        return Some(root.into());
+    }
+    let output = std::process::Command::new("mkcert")
+        .arg("-CAROOT")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().into())
+}
+
+/// Our domain should resolve to this machine via NSM's dnsmasq setup.
+async fn check_dns(domain: &str) -> Check {
+    let tld = domain.rsplit('.').next().unwrap_or(domain);
+    let hint = format!(
+        "Run `nsm-setup tld add {}` to route .{} to localhost",
+        tld, tld
+    );
+
+    match tokio::time::timeout(PROBE_TIMEOUT, lookup_host((domain, 443))).await {
+        Ok(Ok(addrs)) => {
+            let addrs: Vec<_> = addrs.map(|a| a.ip()).collect();
+            if addrs.iter().any(|ip| ip.is_loopback()) {
+                Check::pass("dns", format!("{} resolves to this machine", domain))
+            } else {
+                Check::fail(
+                    "dns",
+                    format!("{} resolves to {:?}, not this machine", domain, addrs),
+                    hint,
+                )
+            }
+        }
+        Ok(Err(e)) => Check::fail("dns", format!("{} doesn't resolve: {}", domain, e), hint),
+        Err(_) => Check::fail("dns", format!("resolving {} timed out", domain), hint),
+    }
+}
+
+fn check_env() -> Check {
+    let missing: Vec<_> = REQUIRED_ENV
+        .iter()
+        .filter(|name| std::env::var_os(name).is_none_or(|v| v.is_empty()))
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        Check::pass("env", format!("{} are set", REQUIRED_ENV.join(", ")))
+    } else {
+        Check::fail(
+            "env",
+            format!("missing {}", missing.join(", ")),
+            "Start the project through nsm, or export them yourself",
+        )
+    }
+}
@@ -0,0 +1,140 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use std::{fs, io::ErrorKind, net::SocketAddr};
+use tera::escape_html;
+
+use crate::{
+    build_info::BUILD_INFO,
+    config::AppConfig,
+    livereload,
+    state::{config_profile, AppState},
+    static_files::{self, STATIC_DIR},
+    templates::{describe_error, TEMPLATES_DIR},
+};
+
+/// Default port for `preview-templates`, next to the app's usual one.
+pub const PREVIEW_PORT: u16 = 3001;
+
+/// Serves every template under `templates/` rendered with sample context,
+/// plus `/static` so pages look as they do in the app. Pages reload in the
+/// browser whenever a template, sample or static file changes.
+pub async fn serve(port: u16) -> anyhow::Result<()> {
+    let state = AppState::for_preview(config_profile(), AppConfig::load()?)?;
+    let app = Router::new()
+        .route("/", get(index_handler))
+        .route("/templates/*name", get(template_handler))
+        .route("/__nsm/reload", get(livereload::reload_ws_handler))
+        .nest_service(
+            "/static",
+            static_files::static_service(STATIC_DIR, state.clone()),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            livereload::inject_script,
+        ))
+        .with_state(state.clone());
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("🎨 Previewing {}/ on http://{}", TEMPLATES_DIR, addr);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            // Closes the live reload sockets, which would otherwise hold
+            // the shutdown open.
+            state.begin_drain();
+        })
+        .await?;
+    Ok(())
+}
+
+/// Sample context for `name`: `templates/index.json` for `index.html`,
+/// layered over the values every page of the app gets.
+fn sample_context(name: &str) -> anyhow::Result<(serde_json::Value, bool)> {
+    let mut context = serde_json::json!({
+        "project_name": crate::PROJECT_NAME,
+        "version": BUILD_INFO.version,
+        "domain": crate::domain(),
+        "nsm_enabled": crate::nsm_enabled(),
+    });
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let path = format!("{}/{}.json", TEMPLATES_DIR, stem);
+    let sample = match fs::read_to_string(&path) {
+        Ok(sample) => sample,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((context, false)),
+        Err(e) => return Err(e.into()),
+    };
+    let sample: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&sample)
+        .map_err(|e| anyhow::anyhow!("{} is not a JSON object: {}", path, e))?;
+    if let Some(context) = context.as_object_mut() {
+        context.extend(sample);
+    }
+    Ok((context, true))
+}
+
+/// Shown in place of a page that fails to render, so the browser keeps its
+/// live reload connection and picks up the fix.
+fn error_page(name: &str, message: &str) -> Response {
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{name} failed</title></head>\
+         <body style=\"font-family: system-ui, sans-serif; margin: 2rem\">\
+         <p><a href=\"/\">&larr; All templates</a></p><h1>{name} failed to render</h1>\
+         <pre style=\"white-space: pre-wrap; color: #b00020\">{message}</pre></body></html>",
+        name = escape_html(name),
+        message = escape_html(message),
+    );
+    (StatusCode::INTERNAL_SERVER_ERROR, Html(page)).into_response()
+}
+
+/// `GET /`: every template, with whether it has a sample context.
+async fn index_handler(State(state): State<AppState>) -> Response {
+    let names = match state.templates().names() {
+        Ok(names) => names,
+        Err(e) => return error_page(TEMPLATES_DIR, &describe_error(&e)),
+    };
+    let mut items = String::new();
+    for name in &names {
+        let note = match sample_context(name) {
+            Ok((_, true)) => "",
+            Ok((_, false)) => " <small>(no sample context)</small>",
+            Err(_) => " <small>(invalid sample context)</small>",
+        };
+        items.push_str(&format!(
+            "<li><a href=\"/templates/{name}\">{name}</a>{note}</li>",
+            name = escape_html(name),
+        ));
+    }
+    if names.is_empty() {
+        items.push_str(&format!("<li>No templates in {}/</li>", TEMPLATES_DIR));
+    }
+    Html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Templates - {project}</title></head>\
+         <body style=\"font-family: system-ui, sans-serif; margin: 2rem\">\
+         <h1>🎨 {project} templates</h1><ul>{items}</ul>\
+         <p><small>Sample context comes from <code>{dir}/&lt;name&gt;.json</code>.</small></p>\
+         </body></html>",
+        project = crate::PROJECT_NAME,
+        dir = TEMPLATES_DIR,
+    ))
+    .into_response()
+}
+
+/// `GET /templates/<name>`: the template rendered with its sample context.
+async fn template_handler(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    let context = match sample_context(&name)
+        .and_then(|(context, _)| Ok(tera::Context::from_value(context)?))
+    {
+        Ok(context) => context,
+        Err(e) => return error_page(&name, &format!("{:#}", e)),
+    };
+    match state.templates().render_context(&name, &context) {
+        Ok(page) => Html(page).into_response(),
+        Err(e) => error_page(&name, &describe_error(&e)),
+    }
+}
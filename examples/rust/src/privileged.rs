@@ -0,0 +1,315 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::PathBuf,
+    sync::Mutex,
+    time::Instant,
+};
+use tokio::io::AsyncBufReadExt;
+use tracing::warn;
+
+use crate::{
+    config::PrivilegedLogConfig,
+    error::{AppError, AppResult},
+    rbac::Principal,
+    state::AppState,
+};
+
+/// JSON bodies up to this size are logged as parameters; larger ones only
+/// by size.
+const MAX_LOGGED_BODY: usize = 64 * 1024;
+
+/// Body and query fields whose values are never written down.
+const SECRET_FIELDS: &[&str] = &["password", "secret", "token"];
+
+/// One privileged action, as a line of the log.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    at: DateTime<Utc>,
+    /// Named as in the audit log, `admin-token` for `ADMIN_TOKEN` or
+    /// `console` for the admin console.
+    actor: String,
+    /// e.g. `PUT /admin/maintenance` or `console: maintenance on`.
+    action: String,
+    params: Value,
+    /// `ok`, `denied` (401, 403, 429) or `failed`.
+    outcome: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<f64>,
+}
+
+/// The privileged action log: an append-only file apart from the access log,
+/// one JSON object per line.
+pub struct PrivilegedLog {
+    path: PathBuf,
+    paths: Vec<String>,
+    file: Option<Mutex<File>>,
+}
+
+impl PrivilegedLog {
+    pub fn new(config: &PrivilegedLogConfig) -> anyhow::Result<Self> {
+        let file = if config.enabled {
+            if let Some(dir) = config.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            let mut options = OpenOptions::new();
+            options.create(true).append(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let file = options.open(&config.path).map_err(|e| {
+                anyhow::anyhow!(
+                    "privileged_log: cannot open {}: {}",
+                    config.path.display(),
+                    e
+                )
+            })?;
+            Some(Mutex::new(file))
+        } else {
+            None
+        };
+        Ok(Self {
+            path: config.path.clone(),
+            paths: config.paths.clone(),
+            file,
+        })
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        self.file.is_some()
+            && self
+                .paths
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    fn append(&self, entry: &Entry) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut line = serde_json::to_vec(entry).unwrap_or_default();
+        line.push(b'\n');
+        // One write per entry, so concurrent entries never interleave.
+        if let Err(e) = file.lock().unwrap().write_all(&line) {
+            warn!("🛡️  Privileged log write failed: {}", e);
+        }
+    }
+
+    /// Records an admin console command that changes something.
+    pub fn console(&self, command: &str, outcome: &str) {
+        self.append(&Entry {
+            at: Utc::now(),
+            actor: "console".to_string(),
+            action: format!("console: {}", command.trim()),
+            params: Value::Null,
+            outcome: outcome.to_string(),
+            status: None,
+            request_id: None,
+            client: None,
+            latency_ms: None,
+        });
+    }
+}
+
+/// `value` with the fields named in `SECRET_FIELDS` blanked, at any depth.
+fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(field, value)| {
+                    let secret = SECRET_FIELDS
+                        .iter()
+                        .any(|s| field.to_ascii_lowercase().contains(s));
+                    let value = if secret {
+                        Value::String("<redacted>".to_string())
+                    } else {
+                        redact(value)
+                    };
+                    (field, value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact).collect()),
+        other => other,
+    }
+}
+
+/// The query string, and the body when it's JSON and small enough.
+fn params(uri: &Uri, headers: &HeaderMap, body: &Bytes) -> Value {
+    let mut params = Map::new();
+    if let Ok(Query(query)) = Query::<BTreeMap<String, String>>::try_from_uri(uri)
+        && !query.is_empty()
+    {
+        params.insert("query".to_string(), redact(json!(query)));
+    }
+    if !body.is_empty() {
+        let json = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("json"));
+        match serde_json::from_slice::<Value>(body) {
+            Ok(value) if json => {
+                params.insert("body".to_string(), redact(value));
+            }
+            _ => {
+                params.insert("body_bytes".to_string(), body.len().into());
+            }
+        }
+    }
+    Value::Object(params)
+}
+
+/// What's known of a privileged request before it's handled.
+struct Pending {
+    started: Instant,
+    action: String,
+    params: Value,
+    admin_token: bool,
+    request_id: Option<String>,
+    client: String,
+}
+
+impl Pending {
+    fn finish(self, log: &PrivilegedLog, res: Response) -> Response {
+        let status = res.status();
+        let outcome = match status.as_u16() {
+            _ if status.is_success() || status.is_redirection() => "ok",
+            401 | 403 | 429 => "denied",
+            _ => "failed",
+        };
+        let principal = res
+            .extensions()
+            .get::<Principal>()
+            .and_then(Principal::name);
+        let actor = match principal {
+            Some(name) => name.to_string(),
+            None if self.admin_token => "admin-token".to_string(),
+            None => "anonymous".to_string(),
+        };
+        log.append(&Entry {
+            at: Utc::now(),
+            actor,
+            action: self.action,
+            params: self.params,
+            outcome: outcome.to_string(),
+            status: Some(status.as_u16()),
+            request_id: self.request_id,
+            client: Some(self.client),
+            latency_ms: Some(self.started.elapsed().as_secs_f64() * 1000.0),
+        });
+        res
+    }
+}
+
+/// Writes every request under `privileged_log.paths` to the privileged log,
+/// whether it got through or not: who (the request's principal, named as in
+/// the audit log), what (method and path, with the query and a JSON body as
+/// parameters, secrets blanked), how it went and its request id. Outside
+/// the rate limiter and maintenance mode, so the requests they turn away are
+/// on record too.
+pub async fn log_privileged(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let log = state.privileged_log();
+    if !log.covers(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let headers = req.headers();
+    let mut pending = Pending {
+        started: Instant::now(),
+        action: format!("{} {}", req.method(), req.uri().path()),
+        params: Value::Null,
+        admin_token: crate::admin::has_admin_token(headers),
+        request_id: headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        client: state.trusted_proxies().client(headers, req.extensions()),
+    };
+
+    let (parts, body) = req.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_LOGGED_BODY).await else {
+        // What was read is gone, so the request can't be passed on whole.
+        pending.params = params(&parts.uri, &parts.headers, &Bytes::new());
+        let res = AppError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large");
+        return pending.finish(log, res.into_response());
+    };
+    pending.params = params(&parts.uri, &parts.headers, &body);
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    pending.finish(log, res)
+}
+
+#[derive(Deserialize)]
+pub struct ExportFilter {
+    since: Option<DateTime<Utc>>,
+    actor: Option<String>,
+    outcome: Option<String>,
+}
+
+impl ExportFilter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.since.is_none_or(|since| entry.at >= since)
+            && self
+                .actor
+                .as_ref()
+                .is_none_or(|actor| *actor == entry.actor)
+            && self.outcome.as_ref().is_none_or(|o| *o == entry.outcome)
+    }
+}
+
+/// `GET /admin/privileged-log/export`: the log as NDJSON, oldest first,
+/// filtered by any of `since` (RFC 3339), `actor` and `outcome`.
+pub async fn export_handler(
+    State(state): State<AppState>,
+    Query(filter): Query<ExportFilter>,
+) -> AppResult<Response> {
+    let log = state.privileged_log();
+    if log.file.is_none() {
+        return Err(AppError::not_found("The privileged log is turned off"));
+    }
+    let file = tokio::fs::File::open(&log.path)
+        .await
+        .map_err(AppError::internal)?;
+    let lines = tokio::io::BufReader::new(file).lines();
+    let stream = futures::stream::unfold((lines, filter), |(mut lines, filter)| async move {
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some((Err(e), (lines, filter))),
+            };
+            let Ok(entry) = serde_json::from_str::<Entry>(&line) else {
+                continue;
+            };
+            if filter.matches(&entry) {
+                return Some((Ok(Bytes::from(line + "\n")), (lines, filter)));
+            }
+        }
+    });
+    let filename = format!("privileged-{}.ndjson", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
@@ -0,0 +1,431 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Mutex, RwLock},
+    time::Instant,
+};
+
+use crate::{
+    config::{PrincipalLimit, RateLimitConfig},
+    error::AppError,
+    mtls::ClientCert,
+    rbac::Principal,
+    sessions::{cookie, SESSION_COOKIE},
+    state::AppState,
+};
+
+/// Buckets beyond this many clients are pruned of full (idle) ones.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// How fast a bucket refills, and how many tokens it holds.
+#[derive(Clone, Copy)]
+struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+impl Rate {
+    /// `None` when `requests_per_second` turns limiting off.
+    fn new(requests_per_second: f64, burst: Option<u32>) -> Option<Self> {
+        (requests_per_second > 0.0).then(|| Self {
+            per_second: requests_per_second,
+            burst: burst.map_or(requests_per_second.ceil(), f64::from).max(1.0),
+        })
+    }
+}
+
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.rate.per_second;
+        (self.tokens + refilled).min(self.rate.burst)
+    }
+}
+
+/// Where a bucket stands after a request, as told by the `X-RateLimit-*`
+/// headers.
+#[derive(Clone, Copy)]
+struct Standing {
+    limit: f64,
+    remaining: f64,
+    /// Seconds until the bucket is full again.
+    reset: f64,
+    /// Set when the request was refused: seconds until a token is available.
+    wait: Option<f64>,
+}
+
+impl Standing {
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("x-ratelimit-limit", self.limit),
+            ("x-ratelimit-remaining", self.remaining.floor()),
+            ("x-ratelimit-reset", self.reset.ceil()),
+        ];
+        for (name, value) in values {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from(value as u64),
+            );
+        }
+    }
+}
+
+/// Takes a token from `key`'s bucket, creating it full if it's new.
+fn take(buckets: &mut HashMap<String, Bucket>, key: &str, rate: Rate) -> Standing {
+    let now = Instant::now();
+    if buckets.len() >= MAX_TRACKED_CLIENTS {
+        buckets.retain(|_, b| b.tokens_at(now) < b.rate.burst);
+    }
+    let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+        rate,
+        tokens: rate.burst,
+        updated: now,
+    });
+    bucket.tokens = bucket.tokens_at(now);
+    bucket.updated = now;
+    let wait = if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        None
+    } else {
+        Some((1.0 - bucket.tokens) / rate.per_second)
+    };
+    Standing {
+        limit: rate.burst,
+        remaining: bucket.tokens,
+        reset: (rate.burst - bucket.tokens) / rate.per_second,
+        wait,
+    }
+}
+
+/// A principal's requests on one UTC day.
+#[derive(Default)]
+struct Usage {
+    day: NaiveDate,
+    requests: u64,
+    /// Turned away by the rate limit or the quota; these don't count
+    /// towards the quota.
+    throttled: u64,
+}
+
+/// What a request cost a principal with limits of its own.
+struct Charge {
+    bucket: Option<Standing>,
+    /// The daily quota and what's left of it, when there is one.
+    quota: Option<(u64, u64)>,
+    over_quota: bool,
+}
+
+impl Charge {
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        if let Some(bucket) = &self.bucket {
+            bucket.set_headers(headers);
+        }
+        if let Some((limit, remaining)) = self.quota {
+            let values = [
+                ("x-quota-limit", limit),
+                ("x-quota-remaining", remaining),
+                ("x-quota-reset", until_reset().ceil() as u64),
+            ];
+            for (name, value) in values {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+        }
+    }
+}
+
+/// Token buckets keyed by client address, and by principal for those with
+/// limits of their own, along with their requests today. Usage is counted
+/// per instance and starts over on restart.
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    principals: Mutex<HashMap<String, Bucket>>,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config.clone()),
+            buckets: Mutex::default(),
+            principals: Mutex::default(),
+            usage: Mutex::default(),
+        }
+    }
+
+    /// Swaps in new limits; every client and principal starts again with a
+    /// full bucket. Today's usage carries over.
+    pub fn set_config(&self, config: &RateLimitConfig) {
+        *self.config.write().unwrap() = config.clone();
+        self.buckets.lock().unwrap().clear();
+        self.principals.lock().unwrap().clear();
+    }
+
+    /// Takes a token for `client`, or says how many seconds until one is
+    /// available. For the API key tiers, which need the `database` feature.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub fn acquire(&self, client: &str) -> Result<(), f64> {
+        match self.take_client(client).and_then(|s| s.wait) {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    /// `None` when client limiting is off.
+    fn take_client(&self, client: &str) -> Option<Standing> {
+        let config = self.config.read().unwrap();
+        let rate = Rate::new(config.requests_per_second, config.burst)?;
+        Some(take(&mut self.buckets.lock().unwrap(), client, rate))
+    }
+
+    fn limits_principals(&self) -> bool {
+        !self.config.read().unwrap().principals.is_empty()
+    }
+
+    /// Counts a request against `principal`'s bucket and quota; `None` when
+    /// it has no limits of its own.
+    fn charge(&self, principal: &str) -> Option<Charge> {
+        let config = self.config.read().unwrap();
+        let limit = config
+            .principals
+            .get(principal)
+            .or_else(|| config.principals.get("*"))?;
+        let bucket = Rate::new(limit.requests_per_second, limit.burst)
+            .map(|rate| take(&mut self.principals.lock().unwrap(), principal, rate));
+
+        let today = Utc::now().date_naive();
+        let mut usage = self.usage.lock().unwrap();
+        if usage.len() >= MAX_TRACKED_CLIENTS {
+            usage.retain(|_, u| u.day == today);
+        }
+        let usage = usage.entry(principal.to_string()).or_default();
+        if usage.day != today {
+            *usage = Usage {
+                day: today,
+                ..Usage::default()
+            };
+        }
+        let throttled = bucket.is_some_and(|b| b.wait.is_some());
+        let over_quota = !throttled && limit.daily_quota > 0 && usage.requests >= limit.daily_quota;
+        if throttled || over_quota {
+            usage.throttled += 1;
+        } else {
+            usage.requests += 1;
+        }
+        Some(Charge {
+            bucket,
+            quota: (limit.daily_quota > 0).then(|| {
+                (
+                    limit.daily_quota,
+                    limit.daily_quota.saturating_sub(usage.requests),
+                )
+            }),
+            over_quota,
+        })
+    }
+
+    /// Today's usage of every principal with limits of its own that's
+    /// listed by name or has made requests today, by name.
+    fn report(&self) -> Vec<PrincipalUsage> {
+        let config = self.config.read().unwrap();
+        let today = Utc::now().date_naive();
+        let usage = self.usage.lock().unwrap();
+        let buckets = self.principals.lock().unwrap();
+        let now = Instant::now();
+
+        let names: BTreeSet<&String> = config
+            .principals
+            .keys()
+            .filter(|name| *name != "*")
+            .chain(usage.iter().filter(|(_, u)| u.day == today).map(|(n, _)| n))
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let limit = config
+                    .principals
+                    .get(name)
+                    .or_else(|| config.principals.get("*"))?;
+                let (requests, throttled) = usage
+                    .get(name)
+                    .filter(|u| u.day == today)
+                    .map_or((0, 0), |u| (u.requests, u.throttled));
+                let tokens = Rate::new(limit.requests_per_second, limit.burst).map(|rate| {
+                    buckets
+                        .get(name)
+                        .map_or(rate.burst, |bucket| bucket.tokens_at(now))
+                        .floor()
+                });
+                Some(PrincipalUsage {
+                    principal: name.clone(),
+                    limit: limit.clone(),
+                    tokens,
+                    requests,
+                    throttled,
+                    quota_remaining: (limit.daily_quota > 0)
+                        .then(|| limit.daily_quota.saturating_sub(requests)),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Seconds until quotas reset at midnight UTC.
+pub fn until_reset() -> f64 {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + Days::new(1)).and_time(chrono::NaiveTime::MIN);
+    (midnight.and_utc() - now).num_milliseconds() as f64 / 1000.0
+}
+
+/// Probes and NSM's own endpoints are never limited.
+fn exempt(path: &str) -> bool {
+    path == "/livez" || path == "/readyz" || path.starts_with("/__nsm/")
+}
+
+/// Whether a request says who it's from, so it may turn out to be a
+/// principal with limits of its own.
+fn carries_credentials(req: &Request) -> bool {
+    let headers = req.headers();
+    headers.contains_key(header::AUTHORIZATION)
+        || headers.contains_key("x-api-key")
+        || cookie(headers, SESSION_COOKIE).is_some()
+        || req.extensions().get::<ClientCert>().is_some()
+}
+
+/// A request [`limit_requests`] left to [`limit_principals`], with the
+/// client to charge if it turns out anonymous after all.
+#[derive(Clone)]
+struct Deferred {
+    client: String,
+}
+
+/// Runs the request if `standing` allows it, answering `429` with
+/// `Retry-After` otherwise; the bucket's headers go on either way.
+async fn within(standing: Option<Standing>, req: Request, next: Next) -> Response {
+    let Some(standing) = standing else {
+        return next.run(req).await;
+    };
+    let mut res = match standing.wait {
+        Some(wait) => too_many_requests("Rate limit exceeded", wait),
+        None => next.run(req).await,
+    };
+    standing.set_headers(res.headers_mut());
+    res
+}
+
+/// Limits requests per client, answering `429` with `Retry-After`. Clients
+/// are told apart by address (see [`crate::client_addr`]). Responses
+/// carry `X-RateLimit-Limit`, `X-RateLimit-Remaining` and
+/// `X-RateLimit-Reset` (seconds until the bucket is full). Once
+/// `rate_limit.principals` lists any, requests with credentials are left to
+/// [`limit_principals`], which knows who they're from; those turned away by
+/// the auth layers in between aren't limited. Probes and NSM's own
+/// endpoints are never limited.
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if exempt(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let client = state
+        .trusted_proxies()
+        .client(req.headers(), req.extensions());
+
+    let limiter = state.rate_limiter();
+    if limiter.limits_principals() && carries_credentials(&req) {
+        req.extensions_mut().insert(Deferred { client });
+        return next.run(req).await;
+    }
+    within(limiter.take_client(&client), req, next).await
+}
+
+/// Holds principals listed in `rate_limit.principals` to their own bucket
+/// and daily quota, with the `X-RateLimit-*` headers for the bucket and
+/// `X-Quota-Limit`, `X-Quota-Remaining` and `X-Quota-Reset` for the quota.
+/// Other requests [`limit_requests`] left here are charged to their client
+/// as usual. Inside [`crate::rbac::resolve_principal`], for the name.
+pub async fn limit_principals(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if exempt(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let limiter = state.rate_limiter();
+    let name = req
+        .extensions()
+        .get::<Principal>()
+        .and_then(Principal::name)
+        .map(str::to_string);
+    if let Some(charge) = name.as_deref().and_then(|name| limiter.charge(name)) {
+        let mut res = match (charge.bucket.and_then(|b| b.wait), charge.quota) {
+            (Some(wait), _) => too_many_requests("Rate limit exceeded for this principal", wait),
+            (None, Some((quota, _))) if charge.over_quota => too_many_requests(
+                format!(
+                    "Daily quota of {} requests used up; it resets at midnight UTC",
+                    quota
+                ),
+                until_reset(),
+            ),
+            _ => next.run(req).await,
+        };
+        charge.set_headers(res.headers_mut());
+        return res;
+    }
+    match req.extensions().get::<Deferred>() {
+        Some(deferred) => {
+            let standing = limiter.take_client(&deferred.client);
+            within(standing, req, next).await
+        }
+        None => next.run(req).await,
+    }
+}
+
+#[derive(Serialize)]
+pub struct PrincipalUsage {
+    principal: String,
+    limit: PrincipalLimit,
+    /// Left in its bucket now, when it has a rate limit.
+    tokens: Option<f64>,
+    requests: u64,
+    throttled: u64,
+    quota_remaining: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    day: NaiveDate,
+    resets_in_secs: u64,
+    principals: Vec<PrincipalUsage>,
+}
+
+/// `GET /admin/rate-limits`: today's requests by principal, for those with
+/// limits of their own, and how much of their bucket and quota is left.
+pub async fn usage_handler(State(state): State<AppState>) -> Json<UsageReport> {
+    Json(UsageReport {
+        day: Utc::now().date_naive(),
+        resets_in_secs: until_reset().ceil() as u64,
+        principals: state.rate_limiter().report(),
+    })
+}
+
+/// A `429` saying to come back in `wait` seconds.
+pub fn too_many_requests(message: impl Into<String>, wait: f64) -> Response {
+    let mut res = AppError::new(StatusCode::TOO_MANY_REQUESTS, message).into_response();
+    res.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(wait.ceil().max(1.0) as u64),
+    );
+    res
+}
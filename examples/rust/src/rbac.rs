@@ -0,0 +1,239 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    convert::Infallible,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+use crate::{
+    auth::{verify_request, AuthError, Verified},
+    basic_auth::BasicUser,
+    config::RbacConfig,
+    mtls::ClientCert,
+    sessions::Session,
+    state::AppState,
+    static_keys::StaticKey,
+};
+
+/// Who a request is from, and what their roles let them do. Put in the
+/// request's extensions by [`resolve_principal`].
+#[derive(Clone, Debug)]
+// Checked by `require_permission`, which the stock routes only use in the
+// notes demo, which needs the `database` feature.
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+pub struct Principal {
+    /// As in the audit log, e.g. `jwt:alice`; `None` when anonymous.
+    name: Option<String>,
+    permissions: BTreeSet<String>,
+}
+
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+impl Principal {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether the roles grant `permission`, directly or through `*` or a
+    /// `prefix:*` wildcard.
+    pub fn can(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| {
+            granted == "*"
+                || granted == permission
+                || granted
+                    .strip_suffix('*')
+                    .is_some_and(|prefix| prefix.ends_with(':') && permission.starts_with(prefix))
+        })
+    }
+}
+
+/// The role definitions, checked at startup.
+pub struct Rbac {
+    config: RbacConfig,
+}
+
+impl Rbac {
+    pub fn new(config: &RbacConfig) -> anyhow::Result<Self> {
+        let assigned = config
+            .principals
+            .values()
+            .chain([&config.authenticated_roles, &config.anonymous_roles])
+            .flatten();
+        for role in assigned {
+            if !config.roles.contains_key(role) {
+                anyhow::bail!("rbac: no role {:?} in rbac.roles", role);
+            }
+        }
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    fn principal(&self, name: Option<String>, token_roles: Vec<String>) -> Principal {
+        let config = &self.config;
+        let roles = match &name {
+            Some(name) => config
+                .authenticated_roles
+                .iter()
+                .chain(config.principals.get(name).into_iter().flatten())
+                .chain(&token_roles)
+                .collect::<Vec<_>>(),
+            None => config.anonymous_roles.iter().collect(),
+        };
+        Principal {
+            name,
+            permissions: roles
+                .into_iter()
+                .filter_map(|role| config.roles.get(role))
+                .flatten()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The roles a token lists in `rbac.roles_claim`.
+fn token_roles(verified: &Verified, claim: &str) -> Vec<String> {
+    match verified.claim(claim) {
+        Some(Value::Array(roles)) => roles
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::String(roles)) => roles.split_whitespace().map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Works out the request's [`Principal`] for [`require_permission`]: the
+/// API key, static key, Basic auth user or client certificate it came with,
+/// otherwise its bearer token, otherwise the session's user. A bearer token
+/// that doesn't verify counts for nothing here; `auth.required_for` is what
+/// turns it away.
+pub async fn resolve_principal(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let extensions = req.extensions();
+    #[cfg(feature = "database")]
+    let api_key = extensions
+        .get::<crate::apikeys::ApiKey>()
+        .map(|key| format!("api-key:{}", key.id));
+    #[cfg(not(feature = "database"))]
+    let api_key = None;
+    let mut verified = extensions.get::<Verified>().cloned();
+    let mut name = api_key
+        .or_else(|| {
+            Some(format!(
+                "static-key:{}",
+                extensions.get::<StaticKey>()?.name
+            ))
+        })
+        .or_else(|| Some(format!("basic:{}", extensions.get::<BasicUser>()?.name)))
+        .or_else(|| Some(format!("cert:{}", extensions.get::<ClientCert>()?.name)));
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .is_some_and(|v| v.as_bytes().starts_with(b"Bearer "));
+    if name.is_none() && verified.is_none() && bearer {
+        verified = verify_request(&state, req.headers()).await.ok();
+        if let Some(verified) = &verified {
+            req.extensions_mut().insert(verified.clone());
+        }
+    }
+    let subject = verified
+        .as_ref()
+        .and_then(|v| v.subject().map(str::to_string));
+    name = name
+        .or_else(|| Some(format!("jwt:{}", subject?)))
+        .or_else(|| {
+            let user: String = req.extensions().get::<Session>()?.get("user")?;
+            Some(format!("user:{}", user))
+        });
+
+    let rbac = state.rbac();
+    let roles = verified
+        .filter(|_| name.as_deref().is_some_and(|n| n.starts_with("jwt:")))
+        .map(|verified| token_roles(&verified, &rbac.config.roles_claim))
+        .unwrap_or_default();
+    let principal = rbac.principal(name, roles);
+    req.extensions_mut().insert(principal.clone());
+    // Also on the response, for the privileged log outside.
+    let mut res = next.run(req).await;
+    res.extensions_mut().insert(principal);
+    res
+}
+
+/// A route layer letting through only requests whose principal has
+/// `permission`: anonymous ones get a `401`, the rest a `403`, both as
+/// `application/problem+json`. Attach it to a whole route with
+/// `.map(|r| r.layer(require_permission("notes:read")))`, or to one method
+/// with `.delete(handler.layer(require_permission("notes:delete")))`.
+/// Responses cached with `CacheFor` are served before it runs, so don't
+/// cache guarded routes.
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+pub fn require_permission(permission: &'static str) -> RequirePermission {
+    RequirePermission(permission)
+}
+
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+#[derive(Clone)]
+pub struct RequirePermission(&'static str);
+
+impl<S> Layer<S> for RequirePermission {
+    type Service = PermissionGuard<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PermissionGuard {
+            inner,
+            permission: self.0,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+#[derive(Clone)]
+pub struct PermissionGuard<S> {
+    inner: S,
+    permission: &'static str,
+}
+
+impl<S> Service<Request> for PermissionGuard<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let denied = match req.extensions().get::<Principal>() {
+            Some(principal) if principal.can(self.permission) => None,
+            Some(Principal { name: Some(_), .. }) => {
+                Some(AuthError::Forbidden(self.permission.to_string()))
+            }
+            // Missing only on routes outside `resolve_principal`.
+            _ => Some(AuthError::Missing),
+        };
+        match denied {
+            None => Box::pin(self.inner.call(req)),
+            Some(e) => {
+                let res = e.into_response();
+                Box::pin(async move { Ok(res) })
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use axum::http::HeaderMap;
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    capture::{header_list, Header},
+    config::RedactionConfig,
+};
+
+/// What a masked value reads as.
+pub const MASK: &str = "<redacted>";
+
+/// `Authorization` schemes; the credential is the word after.
+const AUTH_SCHEMES: &[&str] = &["basic", "bearer", "digest", "token"];
+
+/// Masks credentials before they're written anywhere a person or a log
+/// collector reads: the log output (the access log included) and the debug
+/// capture buffer. Which headers and fields count as secret comes from
+/// `redaction`.
+pub struct Redactor {
+    headers: Vec<String>,
+    fields: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Self {
+        let lower = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect()
+        };
+        Self {
+            headers: lower(&config.headers),
+            fields: lower(&config.fields),
+        }
+    }
+
+    fn secret_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    fn secret_field(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.fields
+            .iter()
+            .any(|field| name.contains(field.as_str()))
+    }
+
+    /// `headers` as the capture buffer lists them, secret ones masked.
+    pub fn header_list(&self, headers: &HeaderMap) -> Vec<Header> {
+        let mut list = header_list(headers);
+        for header in &mut list {
+            if self.secret_header(&header.name) {
+                header.value = MASK.to_string();
+            }
+        }
+        list
+    }
+
+    /// `value` with secret fields masked, at any depth.
+    pub fn json(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(field, value)| {
+                        let value = if self.secret_field(&field) {
+                            Value::String(MASK.to_string())
+                        } else {
+                            self.json(value)
+                        };
+                        (field, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.json(v)).collect()),
+            other => other,
+        }
+    }
+
+    /// `a=1&password=2` with secret values masked.
+    fn form(&self, form: &str) -> String {
+        form.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _))
+                    if self.secret_field(&percent_decode_str(name).decode_utf8_lossy()) =>
+                {
+                    format!("{}={}", name, MASK)
+                }
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// A URI with secret query parameters masked.
+    pub fn uri(&self, uri: &str) -> String {
+        match uri.split_once('?') {
+            Some((path, query)) => format!("{}?{}", path, self.form(query)),
+            None => uri.to_string(),
+        }
+    }
+
+    /// A body with secrets masked: JSON and form bodies field by field,
+    /// anything else (JSON cut short by the capture limit, say) as
+    /// [`Redactor::text`] does.
+    pub fn body(&self, content_type: Option<&str>, body: &str) -> String {
+        let content_type = content_type.unwrap_or_default();
+        if content_type.contains("json")
+            && let Ok(value) = serde_json::from_str::<Value>(body)
+        {
+            return self.json(value).to_string();
+        }
+        if content_type.starts_with("application/x-www-form-urlencoded") {
+            return self.form(body);
+        }
+        self.text(body)
+    }
+
+    /// Free-form text, such as a log record, with the value masked wherever
+    /// a secret header or field name is followed by `=` or `:`. That covers
+    /// `password=...` fields, `"token": "..."` in JSON and `Debug` output,
+    /// and `authorization: Bearer ...`; a secret logged without its name
+    /// can't be told apart.
+    pub fn text(&self, text: &str) -> String {
+        let lower = text.to_ascii_lowercase();
+        let mut spans = Vec::new();
+        for name in self.headers.iter().chain(&self.fields) {
+            for (at, _) in lower.match_indices(name.as_str()) {
+                spans.extend(value_span(text, at + name.len()));
+            }
+        }
+        if spans.is_empty() {
+            return text.to_string();
+        }
+        spans.sort_unstable();
+
+        let mut out = String::with_capacity(text.len());
+        let mut copied = 0;
+        for (start, end) in spans {
+            // A name inside a value that's already masked.
+            if start < copied {
+                continue;
+            }
+            out.push_str(&text[copied..start]);
+            out.push_str(MASK);
+            copied = end;
+        }
+        out.push_str(&text[copied..]);
+        out
+    }
+}
+
+/// Skips spaces and ANSI styling, which `fmt` puts between a field's name
+/// and its value.
+fn skip_noise(text: &str, mut at: usize) -> usize {
+    let bytes = text.as_bytes();
+    loop {
+        match bytes.get(at) {
+            Some(b' ') => at += 1,
+            Some(0x1b) => match bytes[at..].iter().position(u8::is_ascii_alphabetic) {
+                Some(len) => at += len + 1,
+                None => return text.len(),
+            },
+            _ => return at,
+        }
+    }
+}
+
+/// Where the value of a name ending at `at` lies, without its quotes; `None`
+/// when what follows isn't a value (`password_hash`, `tokens were...`).
+fn value_span(text: &str, at: usize) -> Option<(usize, usize)> {
+    let rest = |at: usize| &text[at..];
+    // The name's closing quote, escaped when it's JSON inside a string.
+    let mut at = at
+        + ["\\\"", "\""]
+            .iter()
+            .find(|quote| rest(at).starts_with(*quote))
+            .map_or(0, |quote| quote.len());
+    at = skip_noise(text, at);
+    if !rest(at).starts_with(['=', ':']) {
+        return None;
+    }
+    at = skip_noise(text, at + 1);
+    // `Some("...")` from `Debug`.
+    if rest(at).starts_with("Some(") {
+        at += "Some(".len();
+    }
+
+    for quote in ["\\\"", "\""] {
+        if let Some(value) = rest(at).strip_prefix(quote) {
+            let start = at + quote.len();
+            let len = closing_quote(value, quote)?;
+            return (len > 0).then_some((start, start + len));
+        }
+    }
+    let word = |at: usize| {
+        rest(at)
+            .find(|c: char| c.is_whitespace() || ",;&)}]\"\x1b".contains(c))
+            .unwrap_or(rest(at).len())
+    };
+    let mut len = word(at);
+    // `Bearer abc`: the credential is the second word.
+    if AUTH_SCHEMES
+        .iter()
+        .any(|scheme| rest(at)[..len].eq_ignore_ascii_case(scheme))
+        && rest(at + len).starts_with(' ')
+    {
+        at += len + 1;
+        len = word(at);
+    }
+    (len > 0).then_some((at, at + len))
+}
+
+/// Length of a quoted value up to its closing `quote`, skipping escapes.
+fn closing_quote(value: &str, quote: &str) -> Option<usize> {
+    if quote == "\"" {
+        let mut escaped = false;
+        return value.char_indices().find_map(|(i, c)| match c {
+            _ if escaped => {
+                escaped = false;
+                None
+            }
+            '\\' => {
+                escaped = true;
+                None
+            }
+            '"' => Some(i),
+            _ => None,
+        });
+    }
+    value.find(quote)
+}
+
+/// Standard output with secrets masked from each record, for the log
+/// formats. `fmt` writes a record in one go, so a secret is never split
+/// across writes.
+#[derive(Clone)]
+pub struct RedactedStdout(pub Arc<Redactor>);
+
+impl RedactedStdout {
+    pub fn print(&self, text: &str) {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(self.0.text(text).as_bytes());
+    }
+}
+
+impl Write for RedactedStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        io::stdout()
+            .lock()
+            .write_all(self.0.text(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RedactedStdout {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
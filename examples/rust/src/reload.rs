@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{cli, config::AppConfig, state::AppState};
+
+/// Top-level config keys applied to the running process; changes anywhere
+/// else only take effect after a restart.
+const HOT_RELOADABLE: &[&str] = &["log_level", "cors", "rate_limit"];
+
+/// The config as last read from disk, to diff the next reload against.
+pub struct ConfigSnapshot(Mutex<serde_json::Value>);
+
+impl ConfigSnapshot {
+    pub fn new(config: &AppConfig) -> Self {
+        Self(Mutex::new(serde_json::to_value(config).unwrap_or_default()))
+    }
+}
+
+/// Re-reads the config file and applies what changed. A config that fails
+/// to load or validate leaves everything as it was.
+pub fn reload(state: &AppState) -> anyhow::Result<()> {
+    let config = AppConfig::load()?;
+    let next = serde_json::to_value(&config)?;
+    let mut snapshot = state.config_snapshot().0.lock().unwrap();
+
+    let (Some(before), Some(after)) = (snapshot.as_object(), next.as_object()) else {
+        anyhow::bail!("config is not an object");
+    };
+    let changed: Vec<&str> = after
+        .keys()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(String::as_str)
+        .collect();
+    if changed.is_empty() {
+        info!("🔄 Config unchanged");
+        return Ok(());
+    }
+
+    // The log filter is the only change that can still be rejected, so it
+    // goes first.
+    let mut applied = Vec::new();
+    if changed.contains(&"log_level") {
+        if cli::reload_log_filter(config.log_level.as_deref())? {
+            applied.push("log_level");
+        } else {
+            warn!("log_level changed but RUST_LOG is set, which takes precedence");
+        }
+    }
+    if changed.contains(&"cors") {
+        state.set_cors(config.cors.clone());
+        applied.push("cors");
+    }
+    if changed.contains(&"rate_limit") {
+        state.rate_limiter().set_config(&config.rate_limit);
+        applied.push("rate_limit");
+    }
+
+    if !applied.is_empty() {
+        info!("🔄 Reloaded {}", applied.join(", "));
+    }
+    let restart: Vec<&str> = changed
+        .into_iter()
+        .filter(|key| !HOT_RELOADABLE.contains(key))
+        .collect();
+    if !restart.is_empty() {
+        warn!("♻️  Restart to apply changes to {}", restart.join(", "));
+    }
+    *snapshot = next;
+    Ok(())
+}
+
+/// Reloads the config on every SIGHUP, e.g. `kill -HUP <pid>` after NSM
+/// edits the project's settings.
+#[cfg(unix)]
+pub fn spawn_sighup_handler(state: AppState) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!(
+                "🔄 SIGHUP received, reloading config from {}",
+                AppConfig::path()
+            );
+            if let Err(e) = reload(&state) {
+                error!("Config reload failed, keeping the current config: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_handler(_state: AppState) -> anyhow::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,332 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+use tracing::{
+    field::{Field, Visit},
+    info, info_span,
+    span::{Attributes, Id},
+    Event, Instrument, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{mtls::ClientCert, redaction::RedactedStdout, state::AppState};
+
+/// Name of the span every request runs in.
+const REQUEST_SPAN: &str = "request";
+
+/// The path column of the dev format is padded to this width.
+const PATH_WIDTH: usize = 32;
+
+/// A request still waiting for its response to start.
+#[derive(Clone)]
+pub struct InFlightRequest {
+    pub method: String,
+    pub path: String,
+    pub request_id: String,
+    pub started: Instant,
+}
+
+/// Requests currently being handled, for the admin console.
+#[derive(Default)]
+pub struct InFlightRequests {
+    next_id: AtomicU64,
+    requests: Mutex<BTreeMap<u64, InFlightRequest>>,
+}
+
+impl InFlightRequests {
+    /// Oldest first.
+    pub fn list(&self) -> Vec<InFlightRequest> {
+        self.requests.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Removes its request from the in-flight list when dropped.
+struct InFlightGuard {
+    state: AppState,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.state
+            .in_flight()
+            .requests
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+    }
+}
+
+/// Runs the request inside a `request` span and logs its status and latency
+/// once the response is ready, so every format can tie events to a request.
+/// Until then the request is listed as in flight.
+pub async fn log_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let span = info_span!(
+        REQUEST_SPAN,
+        method = %req.method(),
+        path = %req.uri().path(),
+        request_id,
+        key = tracing::field::Empty,
+        user = tracing::field::Empty,
+    );
+    if let Some(cert) = req.extensions().get::<ClientCert>() {
+        span.record("user", cert.name.as_str());
+    }
+    let started = Instant::now();
+
+    let in_flight = state.in_flight();
+    let id = in_flight.next_id.fetch_add(1, Ordering::Relaxed);
+    in_flight.requests.lock().unwrap().insert(
+        id,
+        InFlightRequest {
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            request_id,
+            started,
+        },
+    );
+    let _guard = InFlightGuard {
+        state: state.clone(),
+        id,
+    };
+
+    async move {
+        let res = next.run(req).await;
+        let latency = started.elapsed().as_secs_f64() * 1000.0;
+        info!(
+            status = res.status().as_u16(),
+            latency_ms = (latency * 10.0).round() / 10.0,
+            "finished"
+        );
+        res
+    }
+    .instrument(span)
+    .await
+}
+
+/// Field values of an event or span, rendered the way `fmt` does.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+    method: String,
+    path: String,
+    request_id: String,
+    status: Option<u64>,
+    latency_ms: Option<f64>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            "method" => self.method = format!("{:?}", value),
+            "path" => self.path = format!("{:?}", value),
+            name => {
+                let _ = write!(self.rest, " {}={:?}", name, value);
+            }
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "request_id" => self.request_id = value.to_string(),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "status" => self.status = Some(value),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        match field.name() {
+            "latency_ms" => self.latency_ms = Some(value),
+            _ => self.record_debug(field, &value),
+        }
+    }
+}
+
+/// What the dev format keeps for a request until its span closes.
+struct RequestGroup {
+    started: chrono::DateTime<chrono::Local>,
+    fields: Fields,
+    lines: Vec<String>,
+}
+
+/// Development log format: a request's events are held back until it
+/// finishes, then printed together under one line with its method, path,
+/// colored status and right-aligned latency. Events outside a request print
+/// straight away. Secrets are masked on the way out.
+pub struct DevFormat {
+    ansi: bool,
+    stdout: RedactedStdout,
+}
+
+impl DevFormat {
+    pub fn new(ansi: bool, stdout: RedactedStdout) -> Self {
+        Self { ansi, stdout }
+    }
+
+    fn paint(&self, color: &str, text: impl fmt::Display) -> String {
+        if self.ansi {
+            format!("\x1b[{}m{}\x1b[0m", color, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn level(&self, level: &Level) -> String {
+        let color = match *level {
+            Level::ERROR => "31",
+            Level::WARN => "33",
+            Level::INFO => "32",
+            Level::DEBUG => "34",
+            Level::TRACE => "35",
+        };
+        self.paint(color, format_args!("{:>5}", level))
+    }
+
+    fn time(&self, time: chrono::DateTime<chrono::Local>) -> String {
+        self.paint("2", time.format("%H:%M:%S%.3f"))
+    }
+
+    fn event_line(&self, event: &Event<'_>) -> String {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        format!(
+            "{} {} {}{}",
+            self.level(meta.level()),
+            self.paint("2", meta.target()),
+            fields.message,
+            self.paint("2", fields.rest),
+        )
+    }
+
+    fn request_line(&self, group: &RequestGroup) -> String {
+        let fields = &group.fields;
+        let status = match fields.status {
+            Some(status) => {
+                let color = match status {
+                    500.. => "1;31",
+                    400.. => "33",
+                    300.. => "36",
+                    _ => "32",
+                };
+                self.paint(color, status)
+            }
+            None => self.paint("2", "---"),
+        };
+        let latency = match fields.latency_ms {
+            Some(ms) => format!("{:>8.1}ms", ms),
+            None => format!("{:>10}", ""),
+        };
+        let id: String = fields.request_id.chars().take(8).collect();
+        format!(
+            "{} {} {:<PATH_WIDTH$} {} {} {}",
+            self.time(group.started),
+            self.paint("1", format_args!("{:<7}", fields.method)),
+            fields.path,
+            status,
+            if fields.latency_ms.is_some_and(|ms| ms >= 500.0) {
+                self.paint("33", latency)
+            } else {
+                latency
+            },
+            self.paint("2", id),
+        )
+    }
+
+    fn print(&self, text: &str) {
+        self.stdout.print(text);
+    }
+}
+
+impl<S> Layer<S> for DevFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != REQUEST_SPAN {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(RequestGroup {
+            started: chrono::Local::now(),
+            fields,
+            lines: Vec::new(),
+        });
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let request = ctx.event_scope(event).and_then(|mut scope| {
+            scope.find(|span| span.extensions().get::<RequestGroup>().is_some())
+        });
+        let Some(request) = request else {
+            self.print(&format!(
+                "{} {}\n",
+                self.time(chrono::Local::now()),
+                self.event_line(event)
+            ));
+            return;
+        };
+
+        let mut extensions = request.extensions_mut();
+        let Some(group) = extensions.get_mut::<RequestGroup>() else {
+            return;
+        };
+        // The request's own summary becomes the group's first line.
+        if event.metadata().target() == module_path!() {
+            event.record(&mut group.fields);
+            return;
+        }
+        let line = format!(
+            "{} {}",
+            self.time(chrono::Local::now()),
+            self.event_line(event)
+        );
+        group.lines.push(line);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(group) = span.extensions_mut().remove::<RequestGroup>() else {
+            return;
+        };
+        let mut text = self.request_line(&group);
+        text.push('\n');
+        for line in &group.lines {
+            let _ = writeln!(text, "  {} {}", self.paint("2", "│"), line);
+        }
+        self.print(&text);
+    }
+}
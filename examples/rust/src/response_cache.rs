@@ -0,0 +1,292 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponseParts, Json, Response, ResponseParts},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::{AppError, AppResult},
+    kv::KvStore,
+    state::AppState,
+    tenancy::Tenant,
+};
+
+/// Responses kept at once; past this, new ones aren't cached until some
+/// expire.
+const MAX_ENTRIES: usize = 1000;
+
+/// Bodies larger than this aren't cached.
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+/// `HIT` or `MISS` on responses from handlers that opted in.
+const CACHE_HEADER: &str = "x-cache";
+
+/// Returned by a `GET` handler to have its `200` cached for `ttl`, until
+/// then served without calling the handler. The cache is keyed by tenant
+/// and URI only, so don't use it for responses that depend on who's asking.
+#[derive(Clone)]
+pub struct CacheFor {
+    ttl: Duration,
+    tags: Vec<String>,
+}
+
+// Used by the notes demo, which needs the `database` feature.
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+impl CacheFor {
+    pub fn secs(secs: u64) -> Self {
+        Self {
+            ttl: Duration::from_secs(secs),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Drops the response when `tag` is invalidated.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+}
+
+impl IntoResponseParts for CacheFor {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+/// Returned by a write handler to say what its change makes stale. Applied
+/// once the handler succeeds, after its transaction has committed; a failed
+/// write invalidates nothing.
+#[derive(Clone, Default)]
+pub struct Invalidate {
+    tags: Vec<String>,
+    keys: Vec<String>,
+}
+
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+impl Invalidate {
+    /// Cached responses and KV keys with any of `tags`.
+    pub fn tags<T: Into<String>>(tags: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            tags: tags.into_iter().map(Into::into).collect(),
+            keys: Vec::new(),
+        }
+    }
+
+    /// KV key `key` as well, already scoped to the tenant.
+    #[allow(dead_code)]
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+}
+
+impl IntoResponseParts for Invalidate {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    expires: Instant,
+    tags: Vec<String>,
+}
+
+/// What an invalidation removed.
+#[derive(Serialize, Default)]
+pub struct Invalidated {
+    responses: usize,
+    kv_keys: usize,
+}
+
+/// Cached `GET` responses, and which KV keys carry which tags. Both live in
+/// memory, so a restart empties the cache and forgets KV tags; tagged KV
+/// values themselves stay until they expire or are deleted.
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    kv_tags: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl ResponseCache {
+    fn get(&self, key: &str) -> Option<Response> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+        let mut res = Response::new(Body::from(entry.body.clone()));
+        *res.status_mut() = entry.status;
+        *res.headers_mut() = entry.headers.clone();
+        Some(res)
+    }
+
+    fn insert(&self, key: String, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    /// Puts KV key `key` under `tag`, so invalidating the tag deletes it.
+    pub fn tag_kv(&self, tag: &str, key: &str) {
+        self.kv_tags
+            .lock()
+            .unwrap()
+            .entry(tag.to_string())
+            .or_default()
+            .insert(key.to_string());
+    }
+
+    /// Drops the responses cached under any of `tags`, and deletes the KV
+    /// keys tagged with them from `kv`.
+    pub async fn invalidate(&self, kv: &dyn KvStore, tags: &[String]) -> Invalidated {
+        let mut invalidated = Invalidated::default();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.len();
+            entries.retain(|_, entry| !entry.tags.iter().any(|tag| tags.contains(tag)));
+            invalidated.responses = before - entries.len();
+        }
+        let keys: HashSet<String> = {
+            let mut kv_tags = self.kv_tags.lock().unwrap();
+            tags.iter()
+                .filter_map(|tag| kv_tags.remove(tag))
+                .flatten()
+                .collect()
+        };
+        for key in keys {
+            match kv.delete(&key).await {
+                Ok(true) => invalidated.kv_keys += 1,
+                Ok(false) => {}
+                Err(e) => warn!("Failed to delete KV key '{}' on invalidation: {:#}", key, e),
+            }
+        }
+        if invalidated.responses > 0 || invalidated.kv_keys > 0 {
+            debug!(
+                "🧹 Invalidated {:?}: {} responses, {} KV keys",
+                tags, invalidated.responses, invalidated.kv_keys
+            );
+        }
+        invalidated
+    }
+}
+
+/// Serves and fills the response cache for `GET` handlers that return
+/// [`CacheFor`], and applies the [`Invalidate`] of successful writes.
+pub async fn cache_responses(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let cache = state.response_cache();
+    if req.method() != Method::GET {
+        let res = next.run(req).await;
+        let invalidate = res.extensions().get::<Invalidate>();
+        if let Some(invalidate) = invalidate.filter(|_| res.status().is_success()) {
+            cache.invalidate(state.kv(), &invalidate.tags).await;
+            for key in &invalidate.keys {
+                if let Err(e) = state.kv().delete(key).await {
+                    warn!("Failed to delete KV key '{}' on invalidation: {:#}", key, e);
+                }
+            }
+        }
+        return res;
+    }
+
+    let uri = req.uri().to_string();
+    let key = match req.extensions().get::<Tenant>() {
+        Some(tenant) => tenant.key(&uri),
+        None => uri,
+    };
+    if let Some(mut hit) = cache.get(&key) {
+        hit.headers_mut().insert(
+            HeaderName::from_static(CACHE_HEADER),
+            HeaderValue::from_static("HIT"),
+        );
+        return hit;
+    }
+    let res = next.run(req).await;
+    let Some(cache_for) = res.extensions().get::<CacheFor>().cloned() else {
+        return res;
+    };
+    let cacheable = res.status() == StatusCode::OK
+        && !res.headers().contains_key(header::SET_COOKIE)
+        && res
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|len| len <= MAX_BODY_BYTES);
+    if !cacheable {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES as usize).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Response cache: failed to buffer a response: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    cache.insert(
+        key,
+        Entry {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            expires: Instant::now() + cache_for.ttl,
+            tags: cache_for.tags,
+        },
+    );
+    parts.headers.insert(
+        HeaderName::from_static(CACHE_HEADER),
+        HeaderValue::from_static("MISS"),
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[derive(Deserialize)]
+pub struct InvalidateRequest {
+    tags: Vec<String>,
+}
+
+/// `POST /admin/cache/invalidate`: drops everything tagged with any of
+/// `tags`, cached responses and KV keys alike.
+pub async fn invalidate_handler(
+    State(state): State<AppState>,
+    Json(input): Json<InvalidateRequest>,
+) -> AppResult<Json<Invalidated>> {
+    if input.tags.is_empty() {
+        return Err(AppError::bad_request("Name at least one tag"));
+    }
+    let invalidated = state
+        .response_cache()
+        .invalidate(state.kv(), &input.tags)
+        .await;
+    info!(
+        "🧹 Flushed cache tags {:?}: {} responses, {} KV keys",
+        input.tags, invalidated.responses, invalidated.kv_keys
+    );
+    Ok(Json(invalidated))
+}
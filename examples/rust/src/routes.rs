@@ -0,0 +1,208 @@
+use axum::{extract::State, handler::Handler, response::Json, routing::MethodRouter, Router};
+use serde::Serialize;
+use std::convert::Infallible;
+use utoipa::ToSchema;
+
+use crate::state::AppState;
+
+/// One row of the route table served by `/api/routes`.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct RouteInfo {
+    pub path: String,
+    pub methods: Vec<&'static str>,
+    pub auth: bool,
+    /// The RBAC permission (see `rbac::require_permission`) some or all of
+    /// the route's methods need.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<&'static str>,
+}
+
+/// A route under construction: the axum handler plus the metadata we need to
+/// describe it, since a `MethodRouter` can't be introspected after the fact.
+pub struct Route<S> {
+    path: String,
+    methods: Vec<&'static str>,
+    handler: MethodRouter<S>,
+    auth: bool,
+    permission: Option<&'static str>,
+    description: Option<&'static str>,
+}
+
+// Not every verb is used by the stock routes; they're here for the handlers
+// you add.
+macro_rules! method {
+    ($name:ident, $method:literal) => {
+        #[allow(dead_code)]
+        pub fn $name<H, T>(mut self, handler: H) -> Self
+        where
+            H: Handler<T, S>,
+            T: 'static,
+        {
+            self.handler = self.handler.$name(handler);
+            self.methods.push($method);
+            self
+        }
+    };
+}
+
+impl<S> Route<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            methods: Vec::new(),
+            handler: MethodRouter::new(),
+            auth: false,
+            permission: None,
+            description: None,
+        }
+    }
+
+    method!(get, "GET");
+    method!(post, "POST");
+    method!(put, "PUT");
+    method!(patch, "PATCH");
+    method!(delete, "DELETE");
+
+    /// Marks the route as requiring authentication in the route table.
+    pub fn auth(mut self) -> Self {
+        self.auth = true;
+        self
+    }
+
+    /// Records the permission the route's `require_permission` layer checks,
+    /// which also marks it as requiring authentication.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub fn permission(mut self, permission: &'static str) -> Self {
+        self.permission = Some(permission);
+        self.auth()
+    }
+
+    /// Applies a transformation to the underlying `MethodRouter`, e.g. to
+    /// attach a route-specific layer.
+    pub fn map(mut self, f: impl FnOnce(MethodRouter<S>) -> MethodRouter<S>) -> Self {
+        self.handler = f(self.handler);
+        self
+    }
+
+    pub fn describe(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+}
+
+/// Wraps `Router` and records every route added through it, so the route
+/// table always matches what is actually served.
+pub struct Routes<S> {
+    router: Router<S>,
+    table: Vec<RouteInfo>,
+}
+
+impl<S> Default for Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self {
+            router: Router::new(),
+            table: Vec::new(),
+        }
+    }
+}
+
+impl<S> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn add(mut self, route: Route<S>) -> Self {
+        self.router = self.router.route(&route.path, route.handler);
+        self.table.push(RouteInfo {
+            path: route.path,
+            methods: route.methods,
+            auth: route.auth,
+            permission: route.permission,
+            description: route.description,
+        });
+        self
+    }
+
+    /// Mounts a tower service (e.g. `ServeDir`) under `path`.
+    pub fn nest_service<T>(mut self, path: &str, service: T, description: &'static str) -> Self
+    where
+        T: tower::Service<axum::extract::Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: axum::response::IntoResponse,
+        T::Future: Send + 'static,
+    {
+        self.router = self.router.nest_service(path, service);
+        self.table.push(RouteInfo {
+            path: format!("{}/*", path.trim_end_matches('/')),
+            methods: vec!["GET", "HEAD"],
+            auth: false,
+            permission: None,
+            description: Some(description),
+        });
+        self
+    }
+
+    /// Routes `path` (usually ending in a wildcard) to a tower service as-is,
+    /// without stripping a prefix the way `nest_service` does.
+    #[allow(dead_code)]
+    pub fn route_service<T>(
+        mut self,
+        path: &str,
+        methods: &[&'static str],
+        service: T,
+        description: &'static str,
+    ) -> Self
+    where
+        T: tower::Service<axum::extract::Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: axum::response::IntoResponse,
+        T::Future: Send + 'static,
+    {
+        self.router = self.router.route_service(path, service);
+        self.table.push(RouteInfo {
+            path: path.to_string(),
+            methods: methods.to_vec(),
+            auth: false,
+            permission: None,
+            description: Some(description),
+        });
+        self
+    }
+
+    /// Lists a route the fallback answers (e.g. a mock) without registering
+    /// a handler for it.
+    pub fn fallback_route(
+        mut self,
+        path: String,
+        methods: Vec<&'static str>,
+        description: &'static str,
+    ) -> Self {
+        self.table.push(RouteInfo {
+            path,
+            methods,
+            auth: false,
+            permission: None,
+            description: Some(description),
+        });
+        self
+    }
+
+    pub fn into_parts(self) -> (Router<S>, Vec<RouteInfo>) {
+        (self.router, self.table)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/routes",
+    tag = "system",
+    responses((status = 200, description = "Registered routes", body = [RouteInfo]))
+)]
+pub async fn routes_handler(State(state): State<AppState>) -> Json<Vec<RouteInfo>> {
+    Json(state.routes().to_vec())
+}
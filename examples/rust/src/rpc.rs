@@ -0,0 +1,273 @@
+use axum::{
+    body::Bytes,
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use futures::future::{join_all, BoxFuture};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, future::Future, sync::Arc};
+use validator::Validate;
+
+use crate::{state::AppState, validation::field_errors, EchoRequest};
+
+/// Standard JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Batches larger than this are rejected outright.
+const MAX_BATCH: usize = 100;
+
+#[derive(Serialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: impl Serialize) -> Self {
+        self.data = serde_json::to_value(data).ok();
+        self
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+type MethodFn =
+    Arc<dyn Fn(AppState, Value) -> BoxFuture<'static, Result<Value, RpcError>> + Send + Sync>;
+
+/// Maps method names to handlers. Register typed handlers with `method`;
+/// params are deserialized from either the positional or named form.
+#[derive(Default, Clone)]
+pub struct RpcRegistry {
+    methods: BTreeMap<&'static str, MethodFn>,
+}
+
+impl RpcRegistry {
+    pub fn method<P, R, F, Fut>(mut self, name: &'static str, handler: F) -> Self
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize,
+        F: Fn(AppState, P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RpcError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.methods.insert(
+            name,
+            Arc::new(move |state, params| {
+                let handler = handler.clone();
+                Box::pin(async move {
+                    let params = serde_json::from_value(params)
+                        .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+                    let result = handler(state, params).await?;
+                    serde_json::to_value(result)
+                        .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+                })
+            }),
+        );
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.methods.keys().copied().collect()
+    }
+
+    async fn call(&self, state: AppState, call: Value) -> Option<RpcResponse> {
+        // Anything unparseable as a request still gets an answer, with a null
+        // id as the spec requires.
+        let request: RpcRequest = match serde_json::from_value(call) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(RpcResponse::error(
+                    Value::Null,
+                    RpcError::new(INVALID_REQUEST, e.to_string()),
+                ));
+            }
+        };
+        let id = request.id.clone();
+        let outcome = self.dispatch(state, request).await;
+
+        // Notifications (no id) never get a response, even on error.
+        let id = id?;
+        Some(match outcome {
+            Ok(result) => RpcResponse::result(id, result),
+            Err(error) => RpcResponse::error(id, error),
+        })
+    }
+
+    async fn dispatch(&self, state: AppState, request: RpcRequest) -> Result<Value, RpcError> {
+        if request.jsonrpc != "2.0" {
+            return Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+        }
+        if matches!(&request.id, Some(id) if !(id.is_string() || id.is_number() || id.is_null())) {
+            return Err(RpcError::new(
+                INVALID_REQUEST,
+                "id must be a string, number or null",
+            ));
+        }
+        let params = match request.params {
+            None => Value::Null,
+            Some(params @ (Value::Array(_) | Value::Object(_))) => params,
+            Some(_) => {
+                return Err(RpcError::new(
+                    INVALID_REQUEST,
+                    "params must be an array or object",
+                ))
+            }
+        };
+        let method = self.methods.get(request.method.as_str()).ok_or_else(|| {
+            RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {}", request.method),
+            )
+        })?;
+        method(state, params).await
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    // `Some(Value::Null)` for `"id": null`, `None` when absent (notification).
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+impl RpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    fn error(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// Params for methods that take none; accepts omitted, `[]` or `{}`.
+pub struct Nothing;
+
+impl<'de> Deserialize<'de> for Nothing {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Option::<Value>::deserialize(deserializer)? {
+            None | Some(Value::Null) => Ok(Nothing),
+            Some(Value::Array(a)) if a.is_empty() => Ok(Nothing),
+            Some(Value::Object(o)) if o.is_empty() => Ok(Nothing),
+            Some(_) => Err(serde::de::Error::custom("this method takes no params")),
+        }
+    }
+}
+
+/// The built-in methods, mirroring the REST endpoints.
+pub fn registry() -> RpcRegistry {
+    let registry = RpcRegistry::default()
+        .method("echo", |_, params: EchoRequest| async move {
+            params.validate().map_err(|errors| {
+                RpcError::invalid_params("Validation failed").with_data(field_errors(&errors))
+            })?;
+            Ok(crate::echo(params.message))
+        })
+        .method("info", |_, Nothing| async { Ok(crate::app_info(None)) })
+        .method("health", |state: AppState, Nothing| async move {
+            Ok(crate::health::health_report(&state).await.1)
+        })
+        .method("version", |_, Nothing| async {
+            Ok(crate::build_info::BUILD_INFO)
+        });
+
+    let mut names = registry.names();
+    names.push("system.listMethods");
+    names.sort_unstable();
+    registry.method("system.listMethods", move |_, Nothing| {
+        let names = names.clone();
+        async move { Ok(names) }
+    })
+}
+
+/// `POST /rpc`: a single call or a batch. Notifications get no response
+/// entry; a request made only of notifications gets `204 No Content`.
+pub async fn rpc_handler(
+    State(state): State<AppState>,
+    Extension(registry): Extension<Arc<RpcRegistry>>,
+    body: Bytes,
+) -> Response {
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("Parse error: {}", e));
+            return Json(RpcResponse::error(Value::Null, error)).into_response();
+        }
+    };
+
+    match payload {
+        Value::Array(calls) if calls.is_empty() || calls.len() > MAX_BATCH => {
+            let error = RpcError::new(
+                INVALID_REQUEST,
+                format!("Batch must contain 1 to {} calls", MAX_BATCH),
+            );
+            Json(RpcResponse::error(Value::Null, error)).into_response()
+        }
+        Value::Array(calls) => {
+            let responses: Vec<_> = join_all(
+                calls
+                    .into_iter()
+                    .map(|call| registry.call(state.clone(), call)),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+            if responses.is_empty() {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                Json(responses).into_response()
+            }
+        }
+        call => match registry.call(state, call).await {
+            Some(response) => Json(response).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    }
+}
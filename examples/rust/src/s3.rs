@@ -0,0 +1,562 @@
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    http::{header, Method, Request, Response, StatusCode, Uri},
+};
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, TryStreamExt};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+use tokio::io::AsyncWrite;
+use tokio_util::io::StreamReader;
+use tracing::{info, warn};
+
+use crate::{
+    health::HealthCheck,
+    outbound::{self, HttpsClient},
+    state::AppState,
+    uploads::{BoxReader, BoxWriter, StorageBackend, UploadMetadata},
+};
+
+/// A local MinIO with its stock credentials, e.g.
+/// `docker run -p 9000:9000 -p 9001:9001 minio/minio server /data --console-address :9001`.
+pub const DEFAULT_S3_ENDPOINT: &str = "http://127.0.0.1:9000";
+pub const DEFAULT_S3_BUCKET: &str = "app-data";
+const DEFAULT_S3_REGION: &str = "us-east-1";
+const DEFAULT_S3_ACCESS_KEY: &str = "minioadmin";
+const DEFAULT_S3_SECRET_KEY: &str = "minioadmin";
+
+/// SigV4 caps presigned URLs at a week.
+const MAX_PRESIGN_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Where the upload backend keeps its objects in the bucket.
+const UPLOADS_PREFIX: &str = "uploads/";
+
+/// `sha256("")`, the payload hash of every bodiless request.
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Everything but RFC 3986's unreserved characters, as SigV4 encodes them.
+const QUERY_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+/// The same, keeping the `/` between path segments.
+const PATH_ENCODE: &AsciiSet = &QUERY_ENCODE.remove(b'/');
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn other(message: impl Into<String>) -> io::Error {
+    io::Error::other(message.into())
+}
+
+/// Client for an S3-compatible store, addressed path-style
+/// (`endpoint/bucket/key`) as MinIO expects. Configured by `S3_ENDPOINT`,
+/// `S3_BUCKET`, `S3_REGION`, `S3_ACCESS_KEY` and `S3_SECRET_KEY`; the
+/// defaults fit a local MinIO.
+pub struct S3 {
+    client: HttpsClient,
+    /// `scheme://host[:port]`, without a trailing slash.
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3 {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let url = env_or("S3_ENDPOINT", DEFAULT_S3_ENDPOINT);
+        let uri: Uri = url
+            .parse()
+            .with_context(|| format!("invalid S3_ENDPOINT {}", url))?;
+        let Some(host) = uri.authority().map(|a| a.to_string()) else {
+            anyhow::bail!("S3_ENDPOINT {} needs a host", url);
+        };
+        Ok(Self {
+            client: outbound::https_client()?,
+            endpoint: format!("{}://{}", uri.scheme_str().unwrap_or("http"), host),
+            host,
+            bucket: env_or("S3_BUCKET", DEFAULT_S3_BUCKET),
+            region: env_or("S3_REGION", DEFAULT_S3_REGION),
+            access_key: env_or("S3_ACCESS_KEY", DEFAULT_S3_ACCESS_KEY),
+            secret_key: env_or("S3_SECRET_KEY", DEFAULT_S3_SECRET_KEY),
+        })
+    }
+
+    /// Creates the bucket unless it's there already. Returns whether it
+    /// was created.
+    pub async fn ensure_bucket(&self) -> io::Result<bool> {
+        if self.bucket_exists().await? {
+            return Ok(false);
+        }
+        check(self.send(Method::PUT, "", Vec::new(), Bytes::new()).await?).await?;
+        Ok(true)
+    }
+
+    pub async fn bucket_exists(&self) -> io::Result<bool> {
+        let res = self
+            .send(Method::HEAD, "", Vec::new(), Bytes::new())
+            .await?;
+        match res.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(other(format!("HEAD bucket {}: {}", self.bucket, status))),
+        }
+    }
+
+    pub async fn put(&self, key: &str, body: Bytes) -> io::Result<()> {
+        check(self.send(Method::PUT, key, Vec::new(), body).await?).await?;
+        Ok(())
+    }
+
+    /// Streams the object; a missing one is `ErrorKind::NotFound`.
+    pub async fn get(&self, key: &str) -> io::Result<BoxReader> {
+        let res = check(
+            self.send(Method::GET, key, Vec::new(), Bytes::new())
+                .await?,
+        )
+        .await?;
+        let stream = TryStreamExt::map_err(res.into_body().into_data_stream(), io::Error::other);
+        Ok(Box::pin(StreamReader::new(stream)))
+    }
+
+    /// Succeeds for a missing object too.
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        check(
+            self.send(Method::DELETE, key, Vec::new(), Bytes::new())
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Every key under `prefix`, a page of a thousand at a time.
+    pub async fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let mut query = vec![
+                ("list-type".to_string(), "2".to_string()),
+                ("prefix".to_string(), prefix.to_string()),
+            ];
+            if let Some(token) = token.take() {
+                query.push(("continuation-token".to_string(), token));
+            }
+            let res = check(self.send(Method::GET, "", query, Bytes::new()).await?).await?;
+            let body = res
+                .into_body()
+                .collect()
+                .await
+                .map_err(io::Error::other)?
+                .to_bytes();
+            let xml = String::from_utf8_lossy(&body);
+            keys.extend(xml_values(&xml, "Key"));
+            if xml_values(&xml, "IsTruncated").first().map(String::as_str) != Some("true") {
+                return Ok(keys);
+            }
+            token = xml_values(&xml, "NextContinuationToken").into_iter().next();
+            if token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// A URL that fetches `key` without credentials until `expires_in` has
+    /// passed. `response` overrides response headers, e.g.
+    /// `response-content-disposition`.
+    pub fn presigned_get(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        response: &[(&str, &str)],
+    ) -> String {
+        let query = response
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.presign(Method::GET, key, expires_in, query)
+    }
+
+    /// A URL that stores the body of a `PUT` as `key`, for handlers that
+    /// let the browser upload straight to the bucket.
+    #[allow(dead_code)]
+    pub fn presigned_put(&self, key: &str, expires_in: Duration) -> String {
+        self.presign(Method::PUT, key, expires_in, Vec::new())
+    }
+
+    fn presign(
+        &self,
+        method: Method,
+        key: &str,
+        expires_in: Duration,
+        mut query: Vec<(String, String)>,
+    ) -> String {
+        let now = Utc::now();
+        let path = self.path(key);
+        let expires = expires_in.min(MAX_PRESIGN_EXPIRY).as_secs().max(1);
+        query.extend([
+            (
+                "X-Amz-Algorithm".to_string(),
+                "AWS4-HMAC-SHA256".to_string(),
+            ),
+            (
+                "X-Amz-Credential".to_string(),
+                format!("{}/{}", self.access_key, self.scope(now)),
+            ),
+            ("X-Amz-Date".to_string(), amz_date(now)),
+            ("X-Amz-Expires".to_string(), expires.to_string()),
+            ("X-Amz-SignedHeaders".to_string(), "host".to_string()),
+        ]);
+        let query = canonical_query(query);
+        let signature = self.signature(
+            now,
+            &method,
+            &path,
+            &query,
+            &[("host", self.host.as_str())],
+            "UNSIGNED-PAYLOAD",
+        );
+        format!(
+            "{}{}?{}&X-Amz-Signature={}",
+            self.endpoint, path, query, signature
+        )
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: Vec<(String, String)>,
+        body: Bytes,
+    ) -> io::Result<Response<Body>> {
+        let now = Utc::now();
+        let path = self.path(key);
+        let query = canonical_query(query);
+        let payload_hash = if body.is_empty() {
+            EMPTY_SHA256.to_string()
+        } else {
+            hex::encode(Sha256::digest(&body))
+        };
+        let date = amz_date(now);
+        let headers = [
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", date.as_str()),
+        ];
+        let signature = self.signature(now, &method, &path, &query, &headers, &payload_hash);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            self.scope(now),
+            signed_headers(&headers),
+            signature
+        );
+
+        let uri = if query.is_empty() {
+            format!("{}{}", self.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.endpoint, path, query)
+        };
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, authorization);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let req = req.body(Full::new(body)).map_err(io::Error::other)?;
+        self.client
+            .request(req)
+            .await
+            .map(|res| res.map(Body::new))
+            .map_err(|e| other(format!("S3 request to {} failed: {}", self.endpoint, e)))
+    }
+
+    fn signature(
+        &self,
+        now: DateTime<Utc>,
+        method: &Method,
+        path: &str,
+        query: &str,
+        headers: &[(&str, &str)],
+        payload_hash: &str,
+    ) -> String {
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            path,
+            query,
+            canonical_headers,
+            signed_headers(headers),
+            payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date(now),
+            self.scope(now),
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [
+            now.format("%Y%m%d").to_string().as_str(),
+            &self.region,
+            "s3",
+            "aws4_request",
+        ]
+        .iter()
+        .fold(
+            format!("AWS4{}", self.secret_key).into_bytes(),
+            |key, part| hmac(&key, part),
+        );
+        hex::encode(hmac(&key, &string_to_sign))
+    }
+
+    fn scope(&self, now: DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), self.region)
+    }
+
+    /// `/bucket/key`, encoded once as SigV4 wants for S3.
+    fn path(&self, key: &str) -> String {
+        let path = if key.is_empty() {
+            format!("/{}", self.bucket)
+        } else {
+            format!("/{}/{}", self.bucket, key)
+        };
+        utf8_percent_encode(&path, PATH_ENCODE).to_string()
+    }
+}
+
+fn amz_date(now: DateTime<Utc>) -> String {
+    now.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn signed_headers(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn canonical_query(mut query: Vec<(String, String)>) -> String {
+    query.sort();
+    query
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(name, QUERY_ENCODE),
+                utf8_percent_encode(value, QUERY_ENCODE)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Fails non-2xx responses with S3's error code, mapping 404 to
+/// `ErrorKind::NotFound`.
+async fn check(res: Response<Body>) -> io::Result<Response<Body>> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res
+        .into_body()
+        .collect()
+        .await
+        .map(|body| body.to_bytes())
+        .unwrap_or_default();
+    let code = xml_values(&String::from_utf8_lossy(&body), "Code")
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+    let kind = if status == StatusCode::NOT_FOUND {
+        io::ErrorKind::NotFound
+    } else {
+        io::ErrorKind::Other
+    };
+    Err(io::Error::new(kind, format!("S3 {} {}", status, code)))
+}
+
+/// The text of every `<tag>` element. S3's responses are flat enough that
+/// this is all the XML parsing they need.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Uploads stored under `uploads/` in the bucket. Objects are buffered and
+/// sent in one `PUT` when the writer shuts down, which the upload size limit
+/// keeps small.
+pub struct S3Backend {
+    s3: Arc<S3>,
+}
+
+impl S3Backend {
+    pub fn new(s3: Arc<S3>) -> Self {
+        Self { s3 }
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn writer(&self, key: &str) -> BoxFuture<'_, io::Result<BoxWriter>> {
+        let writer = PutWriter {
+            s3: self.s3.clone(),
+            key: format!("{}{}", UPLOADS_PREFIX, key),
+            buf: Vec::new(),
+            put: None,
+        };
+        Box::pin(async move { Ok(Box::pin(writer) as BoxWriter) })
+    }
+
+    fn reader(&self, key: &str) -> BoxFuture<'_, io::Result<BoxReader>> {
+        let key = format!("{}{}", UPLOADS_PREFIX, key);
+        Box::pin(async move { self.s3.get(&key).await })
+    }
+
+    fn remove(&self, key: &str) -> BoxFuture<'_, io::Result<()>> {
+        let key = format!("{}{}", UPLOADS_PREFIX, key);
+        Box::pin(async move { self.s3.delete(&key).await })
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        Box::pin(async move {
+            Ok(self
+                .s3
+                .list(UPLOADS_PREFIX)
+                .await?
+                .into_iter()
+                .filter_map(|key| key.strip_prefix(UPLOADS_PREFIX).map(str::to_string))
+                .collect())
+        })
+    }
+
+    fn download_url(&self, metadata: &UploadMetadata, expires_in: Duration) -> Option<String> {
+        let disposition = format!("attachment; filename=\"{}\"", metadata.filename);
+        Some(self.s3.presigned_get(
+            &format!("{}{}", UPLOADS_PREFIX, metadata.id),
+            expires_in,
+            &[
+                ("response-content-type", &metadata.content_type),
+                ("response-content-disposition", &disposition),
+            ],
+        ))
+    }
+}
+
+struct PutWriter {
+    s3: Arc<S3>,
+    key: String,
+    buf: Vec<u8>,
+    put: Option<BoxFuture<'static, io::Result<()>>>,
+}
+
+impl AsyncWrite for PutWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.put.is_some() {
+            return Poll::Ready(Err(other("write after shutdown")));
+        }
+        self.buf.extend_from_slice(data);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let put = this.put.get_or_insert_with(|| {
+            let (s3, key) = (this.s3.clone(), std::mem::take(&mut this.key));
+            let body = Bytes::from(std::mem::take(&mut this.buf));
+            Box::pin(async move { s3.put(&key, body).await })
+        });
+        put.as_mut().poll(cx)
+    }
+}
+
+/// Creates the bucket if needed and adds object storage to
+/// `/api/health`. An unreachable store is logged, not fatal: the app starts
+/// and uploads fail until it's up.
+pub async fn init(state: &AppState) {
+    let s3 = state.s3();
+    match s3.ensure_bucket().await {
+        Ok(created) => info!(
+            "🪣 Object storage: bucket {} at {}{}",
+            s3.bucket,
+            s3.endpoint,
+            if created { " (created)" } else { "" }
+        ),
+        Err(e) => warn!(
+            "🪣 Object storage at {} unavailable; uploads will fail until it's up: {}",
+            s3.endpoint, e
+        ),
+    }
+    state.health().register(S3Check(state.clone()));
+}
+
+/// Non-critical: only uploads need it.
+struct S3Check(AppState);
+
+impl HealthCheck for S3Check {
+    fn name(&self) -> &str {
+        "object_storage"
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            match self.0.s3().bucket_exists().await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err("bucket is missing".to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,268 @@
+use axum::{extract::State, response::Json};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{events::Topic, jobs::DemoJob, state::AppState};
+
+/// Applies to tasks registered without their own `timeout`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+type TaskFn = Arc<dyn Fn(AppState) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+/// When a task fires.
+enum Trigger {
+    /// Six fields, seconds first, in UTC: `0 30 3 * * *` is 03:30 daily.
+    Cron(Box<cron::Schedule>),
+    /// Fixed period, first run one period after startup.
+    Every(Duration),
+}
+
+impl Trigger {
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Cron(schedule) => schedule.after(&after).next(),
+            Self::Every(period) => Some(after + chrono::Duration::from_std(*period).ok()?),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Cron(schedule) => format!("cron {}", schedule),
+            Self::Every(period) => format!("every {:?}", period),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct TaskStatus {
+    running: bool,
+    runs: u64,
+    failures: u64,
+    /// Times it came due while the previous run was still going.
+    skipped: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_run_at: Option<DateTime<Utc>>,
+}
+
+struct Task {
+    name: &'static str,
+    trigger: Trigger,
+    timeout: Duration,
+    run: TaskFn,
+    status: Mutex<TaskStatus>,
+}
+
+/// Recurring tasks. Register them in [`tasks`].
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Arc<Task>>,
+}
+
+impl Scheduler {
+    /// Runs `task` on a six-field cron expression (seconds first, UTC).
+    /// Panics on an invalid expression, which is a bug in [`tasks`].
+    pub fn cron<F, Fut>(self, name: &'static str, expression: &str, task: F) -> Self
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let schedule = cron::Schedule::from_str(expression).unwrap_or_else(|e| {
+            panic!(
+                "task {}: invalid cron expression {:?}: {}",
+                name, expression, e
+            )
+        });
+        self.add(name, Trigger::Cron(Box::new(schedule)), task)
+    }
+
+    /// Runs `task` every `period`.
+    pub fn every<F, Fut>(self, name: &'static str, period: Duration, task: F) -> Self
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.add(name, Trigger::Every(period), task)
+    }
+
+    /// Overrides the last task's timeout; runs past it are cancelled and
+    /// count as failures.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        if let Some(task) = self.tasks.last_mut().and_then(Arc::get_mut) {
+            task.timeout = timeout;
+        }
+        self
+    }
+
+    fn add<F, Fut>(mut self, name: &'static str, trigger: Trigger, task: F) -> Self
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.tasks.push(Arc::new(Task {
+            name,
+            trigger,
+            timeout: DEFAULT_TIMEOUT,
+            run: Arc::new(move |state| Box::pin(task(state))),
+            status: Mutex::new(TaskStatus::default()),
+        }));
+        self
+    }
+}
+
+/// Published every minute by the `heartbeat` task.
+#[derive(Serialize, Deserialize)]
+pub struct Heartbeat {
+    uptime_seconds: u64,
+}
+
+impl Topic for Heartbeat {
+    const KIND: &'static str = "scheduler.heartbeat";
+}
+
+/// Every recurring task the app runs.
+pub fn tasks() -> Scheduler {
+    let scheduler = Scheduler::default()
+        .every("heartbeat", Duration::from_secs(60), |state| async move {
+            state.events().emit(&Heartbeat {
+                uptime_seconds: state.uptime().as_secs(),
+            });
+            Ok(())
+        })
+        .cron("nightly-demo-job", "0 0 3 * * *", |state| async move {
+            state
+                .jobs()
+                .enqueue(DemoJob::new("nightly run from the scheduler"))
+                .await?;
+            Ok(())
+        })
+        .timeout(Duration::from_secs(30));
+    #[cfg(feature = "database")]
+    let scheduler = scheduler.cron("purge-deleted-notes", "0 30 3 * * *", |state| async move {
+        match state.config().database.trash_retention_days {
+            0 => Ok(()),
+            days => crate::notes::purge_deleted(&state, days).await,
+        }
+    });
+    scheduler
+}
+
+/// Starts a timer per task, except those in `scheduler.disabled`. Timers
+/// stop when shutdown begins.
+pub fn start(state: &AppState) {
+    let disabled = &state.config().scheduler.disabled;
+    for task in &state.scheduler().tasks {
+        if disabled.iter().any(|name| name == task.name) {
+            continue;
+        }
+        tokio::spawn(schedule(state.clone(), task.clone()));
+    }
+}
+
+async fn schedule(state: AppState, task: Arc<Task>) {
+    let shutdown = state.shutdown_token();
+    let mut after = Utc::now();
+    while let Some(next) = task.trigger.next_after(after) {
+        task.status.lock().unwrap().next_run_at = Some(next);
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.cancelled() => return,
+        }
+        after = next;
+
+        {
+            let mut status = task.status.lock().unwrap();
+            if status.running {
+                status.skipped += 1;
+                warn!(
+                    "⏰ Task {} is still running from {}; skipping this run",
+                    task.name,
+                    status
+                        .last_run_at
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_default()
+                );
+                continue;
+            }
+            status.running = true;
+            status.last_run_at = Some(Utc::now());
+        }
+        let span = info_span!("task", name = task.name);
+        tokio::spawn(run(state.clone(), task.clone()).instrument(span));
+    }
+}
+
+async fn run(state: AppState, task: Arc<Task>) {
+    let started = Instant::now();
+    // Spawned so a panic fails the run rather than leaving it marked running.
+    let handle = tokio::spawn((task.run)(state).in_current_span());
+    let abort = handle.abort_handle();
+    let result = match tokio::time::timeout(task.timeout, handle).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(anyhow::anyhow!("{}", e)),
+        Err(_) => {
+            abort.abort();
+            Err(anyhow::anyhow!("timed out after {:?}", task.timeout))
+        }
+    };
+
+    let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+    let mut status = task.status.lock().unwrap();
+    status.running = false;
+    status.runs += 1;
+    status.last_duration_ms = Some(elapsed);
+    match result {
+        Ok(()) => {
+            status.last_error = None;
+            info!("⏰ Task {} ran in {:.1}ms", task.name, elapsed);
+        }
+        Err(e) => {
+            status.failures += 1;
+            status.last_error = Some(format!("{:#}", e));
+            error!("⏰ Task {} failed: {:#}", task.name, e);
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TaskInfo {
+    name: &'static str,
+    schedule: String,
+    timeout_secs: u64,
+    enabled: bool,
+    #[serde(flatten)]
+    status: TaskStatus,
+}
+
+/// `/debug/tasks`: every scheduled task with its last and next run.
+pub async fn tasks_handler(State(state): State<AppState>) -> Json<Vec<TaskInfo>> {
+    let disabled = &state.config().scheduler.disabled;
+    Json(
+        state
+            .scheduler()
+            .tasks
+            .iter()
+            .map(|task| TaskInfo {
+                name: task.name,
+                schedule: task.trigger.describe(),
+                timeout_secs: task.timeout.as_secs(),
+                enabled: !disabled.iter().any(|name| name == task.name),
+                status: task.status.lock().unwrap().clone(),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,312 @@
+use axum::{
+    extract::{Query, State},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Mutex};
+use tantivy::{
+    collector::{Count, TopDocs},
+    doc,
+    query::{BooleanQuery, Occur, QueryParser, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, Value, FAST, INDEXED, STORED, STRING, TEXT},
+    snippet::SnippetGenerator,
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term,
+};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tracing::{error, info};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::{AppError, AppResult},
+    events::Event,
+    pagination::{Page, Pagination, PaginationParams},
+    state::AppState,
+    tenancy::Tenant,
+};
+
+/// Heap the index writer may use before flushing a segment.
+const WRITER_MEMORY: usize = 50_000_000;
+
+/// Longest body excerpt in a hit.
+const SNIPPET_CHARS: usize = 200;
+
+/// Matches in the title count for this much more than in the body.
+const TITLE_BOOST: f32 = 2.0;
+
+/// The search part of the OpenAPI document, merged in when the `search`
+/// feature is on.
+#[derive(OpenApi)]
+#[openapi(
+    paths(search_handler),
+    components(schemas(SearchHit, crate::pagination::SearchPage)),
+    tags((name = "search", description = "Full-text search over notes"))
+)]
+pub struct SearchApi;
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchHit {
+    id: i64,
+    title: String,
+    score: f32,
+    /// The title with matching terms in `<b>`, HTML-escaped; absent when
+    /// only the body matched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_html: Option<String>,
+    /// The best-matching excerpt of the body, marked up the same way;
+    /// empty when only the title matched.
+    snippet_html: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct NoteDoc {
+    id: i64,
+    tenant: String,
+    title: String,
+    body: String,
+}
+
+struct Fields {
+    id: Field,
+    tenant: Field,
+    title: Field,
+    body: Field,
+}
+
+/// An in-memory tantivy index over the notes, rebuilt from the database at
+/// startup and kept current from `note.*` events.
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+
+impl SearchIndex {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut schema = Schema::builder();
+        let fields = Fields {
+            id: schema.add_i64_field("id", INDEXED | STORED | FAST),
+            tenant: schema.add_text_field("tenant", STRING),
+            title: schema.add_text_field("title", TEXT | STORED),
+            body: schema.add_text_field("body", TEXT | STORED),
+        };
+        let index = Index::create_in_ram(schema.build());
+        // Reloaded by hand after each commit, so a search right after
+        // indexing sees it.
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let writer = Mutex::new(index.writer(WRITER_MEMORY)?);
+        Ok(Self {
+            index,
+            reader,
+            writer,
+            fields,
+        })
+    }
+
+    /// Replaces the notes in `ids`; ids missing from `notes` were deleted.
+    /// Blocks, so call it from `spawn_blocking`.
+    fn update(&self, ids: Option<&BTreeSet<i64>>, notes: Vec<NoteDoc>) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        match ids {
+            Some(ids) => {
+                for id in ids {
+                    writer.delete_term(Term::from_field_i64(self.fields.id, *id));
+                }
+            }
+            None => {
+                writer.delete_all_documents()?;
+            }
+        }
+        for note in notes {
+            writer.add_document(doc!(
+                self.fields.id => note.id,
+                self.fields.tenant => note.tenant,
+                self.fields.title => note.title,
+                self.fields.body => note.body,
+            ))?;
+        }
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    /// One page of `tenant`'s hits for `q`, best first, with the total
+    /// number of matches. Blocks, so call it from `spawn_blocking`.
+    fn search(
+        &self,
+        tenant: &str,
+        q: &str,
+        offset: usize,
+        limit: usize,
+    ) -> tantivy::Result<(Vec<SearchHit>, usize)> {
+        let searcher = self.reader.searcher();
+        let mut parser =
+            QueryParser::for_index(&self.index, vec![self.fields.title, self.fields.body]);
+        parser.set_field_boost(self.fields.title, TITLE_BOOST);
+        // Lenient, so stray quotes or colons in what people type don't
+        // turn into errors.
+        let (query, _) = parser.parse_query_lenient(q);
+        let tenant = TermQuery::new(
+            Term::from_field_text(self.fields.tenant, tenant),
+            IndexRecordOption::Basic,
+        );
+        let query = BooleanQuery::new(vec![(Occur::Must, query), (Occur::Must, Box::new(tenant))]);
+        let (top, total) = searcher.search(
+            &query,
+            &(TopDocs::with_limit(limit).and_offset(offset), Count),
+        )?;
+
+        let titles = SnippetGenerator::create(&searcher, &query, self.fields.title)?;
+        let mut bodies = SnippetGenerator::create(&searcher, &query, self.fields.body)?;
+        bodies.set_max_num_chars(SNIPPET_CHARS);
+        let hits = top
+            .into_iter()
+            .map(|(score, address)| {
+                let doc: TantivyDocument = searcher.doc(address)?;
+                let text = |field| {
+                    doc.get_first(field)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let title_snippet = titles.snippet_from_doc(&doc);
+                Ok(SearchHit {
+                    id: doc
+                        .get_first(self.fields.id)
+                        .and_then(|value| value.as_i64())
+                        .unwrap_or_default(),
+                    title: text(self.fields.title),
+                    score,
+                    title_html: (!title_snippet.highlighted().is_empty())
+                        .then(|| title_snippet.to_html()),
+                    snippet_html: bodies.snippet_from_doc(&doc).to_html(),
+                })
+            })
+            .collect::<tantivy::Result<_>>()?;
+        Ok((hits, total))
+    }
+}
+
+/// Loads `ids` from the database (every note when `None`) and indexes
+/// them. Returns how many notes were indexed.
+async fn reindex(state: &AppState, ids: Option<BTreeSet<i64>>) -> anyhow::Result<usize> {
+    let notes: Vec<NoteDoc> = match &ids {
+        Some(ids) => {
+            sqlx::query_as(
+                "SELECT id, tenant, title, body FROM notes \
+                 WHERE id IN (SELECT value FROM json_each(?)) AND deleted_at IS NULL",
+            )
+            .bind(serde_json::to_string(ids)?)
+            .fetch_all(state.db())
+            .await?
+        }
+        None => {
+            sqlx::query_as("SELECT id, tenant, title, body FROM notes WHERE deleted_at IS NULL")
+                .fetch_all(state.db())
+                .await?
+        }
+    };
+    let count = notes.len();
+    let index = state.search().clone();
+    tokio::task::spawn_blocking(move || index.update(ids.as_ref(), notes)).await??;
+    Ok(count)
+}
+
+/// Starts the indexer: a full build, then the notes named by each batch of
+/// `note.*` events. Missed events (the subscriber lagged) mean another full
+/// build.
+pub fn start(state: &AppState) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let shutdown = state.shutdown_token();
+        // Subscribed before the build, so changes made during it aren't lost.
+        let mut events = state.events().subscribe();
+        match reindex(&state, None).await {
+            Ok(count) => info!("🔎 Search: indexed {} notes", count),
+            Err(e) => error!("Failed to build the search index: {:#}", e),
+        }
+
+        loop {
+            let first = tokio::select! {
+                event = events.recv() => event,
+                _ = shutdown.cancelled() => return,
+            };
+            let mut ids = BTreeSet::new();
+            let mut rebuild = false;
+            match first {
+                Ok(event) => ids.extend(note_id(&event)),
+                Err(RecvError::Lagged(_)) => rebuild = true,
+                Err(RecvError::Closed) => return,
+            }
+            // Take whatever else is queued too, so a burst is one commit.
+            loop {
+                match events.try_recv() {
+                    Ok(event) => ids.extend(note_id(&event)),
+                    Err(TryRecvError::Lagged(_)) => rebuild = true,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Closed) => return,
+                }
+            }
+
+            let result = if rebuild {
+                reindex(&state, None).await
+            } else if !ids.is_empty() {
+                reindex(&state, Some(ids)).await
+            } else {
+                continue;
+            };
+            if let Err(e) = result {
+                error!("Failed to update the search index: {:#}", e);
+            }
+        }
+    });
+}
+
+/// The note a `note.*` event is about.
+fn note_id(event: &Event) -> Option<i64> {
+    if !event.kind.starts_with("note.") {
+        return None;
+    }
+    event.data.get("id")?.as_i64()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// Words to look for in titles and bodies. Supports `"exact phrases"`,
+    /// `title:word`, `+required` and `-excluded` terms.
+    q: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    tag = "search",
+    params(SearchParams, PaginationParams),
+    responses(
+        (status = 200, description = "One page of matching notes, most relevant first", body = SearchPage),
+        (status = 400, description = "Missing query, or invalid paging")
+    )
+)]
+pub async fn search_handler(
+    State(state): State<AppState>,
+    pagination: Pagination,
+    tenant: Tenant,
+    Query(params): Query<SearchParams>,
+) -> AppResult<Json<Page<SearchHit>>> {
+    // Hits come in relevance order only.
+    pagination.sort(&[])?;
+    if params.q.trim().is_empty() {
+        return Err(AppError::bad_request("q must not be empty"));
+    }
+    let index = state.search().clone();
+    let (offset, limit) = (pagination.offset, pagination.limit);
+    let (hits, total) =
+        tokio::task::spawn_blocking(move || index.search(tenant.id(), &params.q, offset, limit))
+            .await
+            .map_err(AppError::internal)?
+            .map_err(AppError::internal)?;
+    Ok(Json(pagination.paged(hits, total)))
+}
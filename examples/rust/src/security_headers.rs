@@ -0,0 +1,243 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponseParts, Response, ResponseParts},
+};
+use rand::RngCore;
+use std::{convert::Infallible, fmt};
+
+use crate::{config::SecurityHeadersConfig, state::AppState};
+
+tokio::task_local! {
+    /// The nonce of the request being handled, for templates to put on their
+    /// inline scripts and styles.
+    static NONCE: String;
+}
+
+/// The current request's CSP nonce, or an empty string outside a request
+/// (e.g. in the template previewer).
+pub fn nonce() -> String {
+    NONCE.try_with(String::clone).unwrap_or_default()
+}
+
+/// A Content-Security-Policy, built up a source at a time, e.g.
+/// `ContentSecurityPolicy::new().script_src("https://unpkg.com")`. The
+/// app-wide policy is one of these; a handler that needs more returns its
+/// own alongside the response, as in `(policy, Html(page))`, and its sources
+/// are added to the app-wide ones for that response only.
+#[derive(Clone, Debug, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+}
+
+impl ContentSecurityPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `source` to `directive`, e.g. `("img-src", "data:")`. Keywords
+    /// keep their quotes: `'self'`, `'none'`.
+    pub fn source(mut self, directive: &str, source: impl Into<String>) -> Self {
+        let source = source.into();
+        match self
+            .directives
+            .iter_mut()
+            .find(|(name, _)| name == directive)
+        {
+            Some((_, sources)) => {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+            None => self.directives.push((directive.to_string(), vec![source])),
+        }
+        self
+    }
+
+    pub fn default_src(self, source: impl Into<String>) -> Self {
+        self.source("default-src", source)
+    }
+
+    pub fn script_src(self, source: impl Into<String>) -> Self {
+        self.source("script-src", source)
+    }
+
+    pub fn style_src(self, source: impl Into<String>) -> Self {
+        self.source("style-src", source)
+    }
+
+    pub fn connect_src(self, source: impl Into<String>) -> Self {
+        self.source("connect-src", source)
+    }
+
+    pub fn img_src(self, source: impl Into<String>) -> Self {
+        self.source("img-src", source)
+    }
+
+    pub fn font_src(self, source: impl Into<String>) -> Self {
+        self.source("font-src", source)
+    }
+
+    pub fn frame_ancestors(self, source: impl Into<String>) -> Self {
+        self.source("frame-ancestors", source)
+    }
+
+    /// Every source of `other` added to this policy.
+    pub fn merge(self, other: &ContentSecurityPolicy) -> Self {
+        other
+            .directives
+            .iter()
+            .flat_map(|(name, sources)| sources.iter().map(move |s| (name, s)))
+            .fold(self, |policy, (name, source)| {
+                policy.source(name, source.clone())
+            })
+    }
+
+    /// Lets inline `<script>` and `<style>` elements carrying `nonce` run.
+    pub fn nonce(self, nonce: &str) -> Self {
+        let source = format!("'nonce-{}'", nonce);
+        self.script_src(source.clone()).style_src(source)
+    }
+}
+
+impl fmt::Display for ContentSecurityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, sources)) in self.directives.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{} {}", name, sources.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned with a response, adds its sources to the app-wide policy.
+impl IntoResponseParts for ContentSecurityPolicy {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+/// The app-wide policy and the other headers, worked out at startup.
+pub struct SecurityHeaders {
+    enabled: bool,
+    policy: ContentSecurityPolicy,
+    policy_header: HeaderName,
+    referrer_policy: Option<HeaderValue>,
+    live_reload: bool,
+}
+
+impl SecurityHeaders {
+    /// `live_reload` allows the reload script's websocket.
+    pub fn new(config: &SecurityHeadersConfig, live_reload: bool) -> anyhow::Result<Self> {
+        // Scripts, styles and assets from our own origin (`/static`, with
+        // fingerprinted names or not); inline ones only with the nonce.
+        let mut policy = ContentSecurityPolicy::new()
+            .default_src("'self'")
+            .script_src("'self'")
+            .style_src("'self'")
+            .img_src("'self'")
+            .img_src("data:")
+            .font_src("'self'")
+            .connect_src("'self'")
+            .source("object-src", "'none'")
+            .source("base-uri", "'self'")
+            .frame_ancestors("'self'");
+        for (directive, sources) in &config.csp {
+            if directive.is_empty()
+                || !directive
+                    .bytes()
+                    .all(|b| b.is_ascii_alphabetic() || b == b'-')
+            {
+                anyhow::bail!("security_headers.csp: {:?} is not a directive", directive);
+            }
+            for source in sources {
+                policy = policy.source(directive, source.clone());
+            }
+        }
+        if let Some(uri) = &config.report_uri {
+            policy = policy.source("report-uri", uri.clone());
+        }
+        // Checked once here, so building the header per request can't fail
+        // on anything but a handler's own sources.
+        HeaderValue::try_from(policy.to_string())
+            .map_err(|_| anyhow::anyhow!("security_headers.csp: sources must be visible ASCII"))?;
+
+        let referrer_policy = (!config.referrer_policy.is_empty())
+            .then(|| HeaderValue::try_from(config.referrer_policy.as_str()))
+            .transpose()?;
+        Ok(Self {
+            enabled: config.enabled,
+            policy,
+            policy_header: if config.report_only {
+                header::CONTENT_SECURITY_POLICY_REPORT_ONLY
+            } else {
+                header::CONTENT_SECURITY_POLICY
+            },
+            referrer_policy,
+            live_reload,
+        })
+    }
+}
+
+/// Adds `Content-Security-Policy`, `X-Content-Type-Options: nosniff` and
+/// `Referrer-Policy` to responses that don't set their own. The policy is
+/// the app-wide one from [`SecurityHeaders`], plus whatever sources the
+/// handler returned, plus a fresh nonce, which templates put on their inline
+/// `<script>` and `<style>` elements with the `csp_nonce()` function. While
+/// live reload is on, the reload websocket on this host is allowed
+/// as well.
+pub async fn add_security_headers(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let headers = state.security_headers();
+    if !headers.enabled {
+        return next.run(req).await;
+    }
+    let host = req
+        .headers()
+        .get("x-forwarded-host")
+        .or_else(|| req.headers().get(header::HOST))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let nonce = hex::encode(bytes);
+    let mut res = NONCE.scope(nonce.clone(), next.run(req)).await;
+
+    let mut policy = headers.policy.clone().nonce(&nonce);
+    if let Some(extra) = res.extensions_mut().remove::<ContentSecurityPolicy>() {
+        policy = policy.merge(&extra);
+    }
+    if headers.live_reload
+        && let Some(host) = host
+    {
+        policy = policy
+            .connect_src(format!("ws://{}", host))
+            .connect_src(format!("wss://{}", host));
+    }
+
+    let res_headers = res.headers_mut();
+    if !res_headers.contains_key(&headers.policy_header)
+        && let Ok(value) = HeaderValue::try_from(policy.to_string())
+    {
+        res_headers.insert(headers.policy_header.clone(), value);
+    }
+    res_headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    if let Some(referrer_policy) = &headers.referrer_policy {
+        res_headers
+            .entry(header::REFERRER_POLICY)
+            .or_insert(referrer_policy.clone());
+    }
+    res
+}
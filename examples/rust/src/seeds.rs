@@ -0,0 +1,204 @@
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::db;
+
+/// Where `seed` and `database.seed_on_start` look, relative to the working
+/// directory.
+const SEEDS_DIR: &str = "seeds";
+
+/// What one seeding pass did.
+#[derive(Default)]
+pub struct Seeded {
+    pub applied: Vec<String>,
+    /// Files already applied before, unchanged.
+    pub skipped: usize,
+    /// Files whose contents no longer match what was applied; they aren't
+    /// run again, since re-running inserts would duplicate rows.
+    pub changed: Vec<String>,
+}
+
+/// Applies every file in `seeds/` that hasn't been applied to this database
+/// yet, in name order, each in its own transaction. `.sql` files run as
+/// they are; a `.json` file maps table names to arrays of rows, e.g.
+/// `{"notes": [{"title": "Hello", "body": "..."}]}`. Applied files are
+/// recorded in the `seeds` table, so running this again is a no-op.
+pub async fn run(pool: &SqlitePool) -> anyhow::Result<Seeded> {
+    let mut seeded = Seeded::default();
+    for path in seed_files()? {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let checksum = hex::encode(Sha256::digest(contents.as_bytes()));
+
+        let applied: Option<(String,)> = sqlx::query_as("SELECT checksum FROM seeds WHERE name = ?")
+            .bind(&name)
+            .fetch_optional(pool)
+            .await?;
+        match applied {
+            Some((was,)) if was == checksum => {
+                seeded.skipped += 1;
+                continue;
+            }
+            Some(_) => {
+                seeded.changed.push(name);
+                continue;
+            }
+            None => {}
+        }
+
+        let mut tx = pool.begin().await?;
+        apply(&mut tx, &path, &contents)
+            .await
+            .with_context(|| format!("seed {} failed", name))?;
+        sqlx::query("INSERT INTO seeds (name, checksum, applied_at) VALUES (?, ?, ?)")
+            .bind(&name)
+            .bind(&checksum)
+            .bind(chrono::Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        seeded.applied.push(name);
+    }
+    Ok(seeded)
+}
+
+/// `database.seed_on_start`: seeds after the startup migrations, logging
+/// rather than printing.
+pub async fn on_start(pool: &SqlitePool) -> anyhow::Result<()> {
+    let seeded = run(pool).await?;
+    if !seeded.applied.is_empty() {
+        info!("🌱 Seeded {}", seeded.applied.join(", "));
+    }
+    for name in &seeded.changed {
+        warn!(
+            "🌱 Seed {} changed since it was applied; not re-running it",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// `seed`: migrates first, so it works on a fresh database.
+pub async fn command() -> anyhow::Result<()> {
+    let pool = db::connect()?;
+    db::migrate_quietly(&pool).await?;
+    let seeded = run(&pool).await?;
+    for name in &seeded.applied {
+        println!("Applied {}", name);
+    }
+    for name in &seeded.changed {
+        println!(
+            "Skipped {}: changed since it was applied (restore a snapshot to re-seed)",
+            name
+        );
+    }
+    if seeded.applied.is_empty() && seeded.changed.is_empty() {
+        println!(
+            "{} already has every seed in {}/ ({} files)",
+            db::database_url(),
+            SEEDS_DIR,
+            seeded.skipped
+        );
+    }
+    Ok(())
+}
+
+/// The `.sql` and `.json` files in `seeds/`, sorted by name. A missing
+/// directory just means there's nothing to seed.
+fn seed_files() -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(SEEDS_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}/", SEEDS_DIR)),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("sql" | "json")
+                )
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+async fn apply(
+    tx: &mut Transaction<'_, Sqlite>,
+    path: &Path,
+    contents: &str,
+) -> anyhow::Result<()> {
+    if path.extension().is_some_and(|e| e == "sql") {
+        sqlx::raw_sql(contents).execute(&mut **tx).await?;
+        return Ok(());
+    }
+
+    let tables: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(contents).context("expected {\"table\": [rows...]}")?;
+    for (table, rows) in tables {
+        let rows = rows
+            .as_array()
+            .with_context(|| format!("{} should be an array of rows", table))?;
+        for row in rows {
+            let row = row
+                .as_object()
+                .with_context(|| format!("rows of {} should be objects", table))?;
+            insert(tx, &table, row).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Inserts one JSON row. Table and column names come from the seed file,
+/// so they're checked and quoted rather than trusted.
+async fn insert(
+    tx: &mut Transaction<'_, Sqlite>,
+    table: &str,
+    row: &serde_json::Map<String, serde_json::Value>,
+) -> anyhow::Result<()> {
+    let columns = row
+        .keys()
+        .map(|column| identifier(column))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        identifier(table)?,
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    );
+    let mut query = sqlx::query(&sql);
+    for value in row.values() {
+        query = match value {
+            serde_json::Value::Null => query.bind(None::<String>),
+            serde_json::Value::Bool(b) => query.bind(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => query.bind(i),
+                None => query.bind(n.as_f64()),
+            },
+            serde_json::Value::String(s) => query.bind(s.clone()),
+            // Nested values are stored as JSON text.
+            other => query.bind(other.to_string()),
+        };
+    }
+    query.execute(&mut **tx).await?;
+    Ok(())
+}
+
+fn identifier(name: &str) -> anyhow::Result<String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        anyhow::bail!("{:?} isn't a plain table or column name", name);
+    }
+    Ok(format!("\"{}\"", name))
+}
@@ -0,0 +1,64 @@
+use anyhow::{bail, Context};
+use axum::{body::Bytes, http::Request};
+use http_body_util::{BodyExt, Empty};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tracing::info;
+
+/// Marks the self-check's own requests, which chaos injection leaves alone.
+pub const SELF_CHECK_HEADER: &str = "x-nsm-self-check";
+
+/// Requested in order once the listener is up; each must answer `2xx`.
+const CHECK_PATHS: &[&str] = &["/api/health", "/readyz"];
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body text kept in the error for a failed check.
+const BODY_EXCERPT: usize = 200;
+
+/// Where to reach a listener bound to `addr` from this machine.
+pub fn loopback(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, addr.port())
+}
+
+/// Requests the health endpoints through the real listener and the full
+/// middleware stack, so a broken stack fails at startup rather than on the
+/// first user request.
+pub async fn run(addr: SocketAddr) -> anyhow::Result<()> {
+    let addr = loopback(addr);
+    let client: Client<HttpConnector, Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build_http();
+
+    for path in CHECK_PATHS {
+        let req = Request::get(format!("http://{}{}", addr, path))
+            .header(SELF_CHECK_HEADER, "1")
+            .body(Empty::new())?;
+        let res = tokio::time::timeout(CHECK_TIMEOUT, client.request(req))
+            .await
+            .with_context(|| format!("GET {} timed out after {:?}", path, CHECK_TIMEOUT))?
+            .with_context(|| format!("GET {} failed", path))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.into_body().collect().await.map(|b| b.to_bytes());
+            let body = body
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            let excerpt: String = body.chars().take(BODY_EXCERPT).collect();
+            bail!("GET {} returned {}: {}", path, status, excerpt.trim());
+        }
+    }
+    info!("✅ Self-check passed ({})", CHECK_PATHS.join(", "));
+    Ok(())
+}
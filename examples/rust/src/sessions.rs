@@ -0,0 +1,670 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    client_addr::ClientAddr,
+    config::{SessionBackend, SessionConfig},
+    error::{AppError, AppResult},
+    keyring::Keyring,
+    state::AppState,
+    validation::ValidatedJson,
+};
+
+pub const SESSION_COOKIE: &str = "session";
+
+/// What the demo login accepts, with any username, in builds without
+/// accounts.
+#[cfg(not(feature = "database"))]
+const DEMO_PASSWORD: &str = "password";
+
+/// A session's values, by key.
+pub type SessionData = BTreeMap<String, serde_json::Value>;
+
+/// A session as the stores keep it.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    data: SessionData,
+    /// Unix seconds.
+    expires_at: i64,
+}
+
+/// Where sessions live between requests. Expired sessions load as `None`.
+trait SessionStore: Send + Sync {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Record>>>;
+
+    fn save<'a>(&'a self, id: &'a str, record: Record) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Record>>> {
+        Box::pin(async move {
+            let records = self.records.lock().unwrap();
+            Ok(records
+                .get(id)
+                .filter(|record| record.expires_at > now())
+                .map(|record| Record {
+                    data: record.data.clone(),
+                    expires_at: record.expires_at,
+                }))
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: Record) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut records = self.records.lock().unwrap();
+            let now = now();
+            records.retain(|_, record| record.expires_at > now);
+            records.insert(id.to_string(), record);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.records.lock().unwrap().remove(id);
+            Ok(())
+        })
+    }
+}
+
+/// Sessions under `<project>:session:<id>`; Redis drops them when they
+/// expire.
+#[cfg(feature = "redis")]
+struct RedisStore(Arc<crate::cache::Redis>);
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    fn key(id: &str) -> String {
+        format!("{{.ProjectName}}:session:{}", id)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl SessionStore for RedisStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Record>>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let json: Option<String> = self.0.connection().await?.get(Self::key(id)).await?;
+            Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: Record) -> BoxFuture<'a, anyhow::Result<()>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            let ttl = (record.expires_at - now()).max(1) as u64;
+            self.0
+                .connection()
+                .await?
+                .set_ex::<_, _, ()>(Self::key(id), serde_json::to_string(&record)?, ttl)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        use redis::AsyncCommands;
+
+        Box::pin(async move {
+            self.0
+                .connection()
+                .await?
+                .del::<_, ()>(Self::key(id))
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+/// The `sessions` table from `migrations/`.
+#[cfg(feature = "database")]
+struct SqliteStore(sqlx::SqlitePool);
+
+#[cfg(feature = "database")]
+impl SessionStore for SqliteStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Record>>> {
+        Box::pin(async move {
+            let row: Option<(String, i64)> = sqlx::query_as(
+                "SELECT data, expires_at FROM sessions WHERE id = ? AND expires_at > ?",
+            )
+            .bind(id)
+            .bind(now())
+            .fetch_optional(&self.0)
+            .await?;
+            row.map(|(data, expires_at)| {
+                Ok(Record {
+                    data: serde_json::from_str(&data)?,
+                    expires_at,
+                })
+            })
+            .transpose()
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: Record) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+                .bind(now())
+                .execute(&self.0)
+                .await?;
+            sqlx::query(
+                "INSERT INTO sessions (id, data, expires_at) VALUES (?, ?, ?) \
+                 ON CONFLICT (id) DO UPDATE SET data = excluded.data, expires_at = excluded.expires_at",
+            )
+            .bind(id)
+            .bind(serde_json::to_string(&record.data)?)
+            .bind(record.expires_at)
+            .execute(&self.0)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM sessions WHERE id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// The configured store plus the keys that encrypt session cookies.
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    keyring: Keyring,
+    ttl_secs: i64,
+    secure: bool,
+    domain: Option<String>,
+}
+
+impl Sessions {
+    pub fn new(
+        config: &SessionConfig,
+        keyring: Keyring,
+        #[cfg(feature = "database")] db: sqlx::SqlitePool,
+        #[cfg(feature = "redis")] redis: Arc<crate::cache::Redis>,
+    ) -> anyhow::Result<Self> {
+        let store: Box<dyn SessionStore> = match config.backend {
+            SessionBackend::Memory => Box::new(MemoryStore::default()),
+            #[cfg(feature = "redis")]
+            SessionBackend::Redis => Box::new(RedisStore(redis)),
+            #[cfg(not(feature = "redis"))]
+            SessionBackend::Redis => {
+                anyhow::bail!("sessions.backend \"redis\" needs the `redis` feature")
+            }
+            #[cfg(feature = "database")]
+            SessionBackend::Sqlite => Box::new(SqliteStore(db)),
+            #[cfg(not(feature = "database"))]
+            SessionBackend::Sqlite => {
+                anyhow::bail!("sessions.backend \"sqlite\" needs the `database` feature")
+            }
+        };
+        if keyring.ephemeral() && config.backend != SessionBackend::Memory {
+            warn!("SESSION_SECRET and cookie_keys are unset; sessions won't survive a restart");
+        }
+        let domain = match &config.cookie_domain {
+            Some(domain) => Some(domain.clone()),
+            None if crate::nsm_enabled() => Some(crate::domain()),
+            None => None,
+        };
+        Ok(Self {
+            store,
+            keyring,
+            ttl_secs: i64::try_from(config.ttl_secs).unwrap_or(i64::MAX / 2),
+            secure: config.secure_cookie,
+            domain: domain.filter(|domain| !domain.is_empty()),
+        })
+    }
+
+    /// The cookie value for `id`, encrypted with the newest cookie key so
+    /// the browser can neither read the id nor forge one.
+    fn seal(&self, id: &str) -> anyhow::Result<String> {
+        self.keyring.seal(SESSION_COOKIE, id.as_bytes())
+    }
+
+    /// The id in a cookie value, if we sealed it, and whether it was sealed
+    /// with an older key.
+    fn open(&self, value: &str) -> Option<(String, bool)> {
+        let opened = self.keyring.open(SESSION_COOKIE, value)?;
+        Some((String::from_utf8(opened.plaintext).ok()?, opened.stale))
+    }
+
+    fn set_cookie(&self, res: &mut Response, value: &str, max_age: i64) {
+        let mut cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            SESSION_COOKIE, value, max_age
+        );
+        if let Some(domain) = &self.domain {
+            cookie.push_str("; Domain=");
+            cookie.push_str(domain);
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            res.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    /// Writes back what the handler did to the session and sets or clears
+    /// the cookie to match.
+    async fn finish(&self, session: &Session, res: &mut Response) -> anyhow::Result<()> {
+        let (id, stale_id, data, destroyed, dirty) = {
+            let mut inner = session.0.lock().unwrap();
+            (
+                inner.id.clone(),
+                inner.stale_id.take(),
+                std::mem::take(&mut inner.data),
+                inner.destroyed,
+                inner.changed || inner.refresh,
+            )
+        };
+        if let Some(stale_id) = &stale_id {
+            self.store.delete(stale_id).await?;
+        }
+        if destroyed {
+            if let Some(id) = &id {
+                self.store.delete(id).await?;
+            }
+            if id.is_some() || stale_id.is_some() {
+                self.set_cookie(res, "", 0);
+            }
+            return Ok(());
+        }
+        // Nothing to keep; don't hand out a cookie for an empty session.
+        if !dirty || (id.is_none() && data.is_empty()) {
+            return Ok(());
+        }
+
+        let id = id.unwrap_or_else(new_id);
+        let record = Record {
+            data,
+            expires_at: now().saturating_add(self.ttl_secs),
+        };
+        self.store.save(&id, record).await?;
+        self.set_cookie(res, &self.seal(&id)?, self.ttl_secs);
+        Ok(())
+    }
+}
+
+/// 256 random bits.
+fn new_id() -> String {
+    let mut id = [0; 32];
+    rand::thread_rng().fill_bytes(&mut id);
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+/// The value of cookie `name`, if the request sent it.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+struct SessionState {
+    /// `None` until the session is first saved.
+    id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    /// Past the halfway point of its lifetime, or its cookie was sealed with
+    /// an older key; saving again extends it and seals a new cookie.
+    refresh: bool,
+    /// Left behind by `regenerate`, deleted once the response is ready.
+    stale_id: Option<String>,
+    destroyed: bool,
+}
+
+/// The current request's session. Changes are saved once the handler
+/// returns, by [`manage_sessions`]. Values are anything serde can handle.
+#[derive(Clone)]
+pub struct Session(Arc<Mutex<SessionState>>);
+
+impl Session {
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let inner = self.0.lock().unwrap();
+        serde_json::from_value(inner.data.get(key)?.clone()).ok()
+    }
+
+    pub fn insert(&self, key: &str, value: impl Serialize) {
+        let Ok(value) = serde_json::to_value(value) else {
+            return;
+        };
+        let mut inner = self.0.lock().unwrap();
+        inner.data.insert(key.to_string(), value);
+        inner.changed = true;
+    }
+
+    // Used by two-factor login, which needs the `database` feature.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub fn remove(&self, key: &str) {
+        let mut inner = self.0.lock().unwrap();
+        if inner.data.remove(key).is_some() {
+            inner.changed = true;
+        }
+    }
+
+    /// Moves the data to a fresh id. Call it whenever privileges change
+    /// (login, logout, sudo), so an id planted in the browser beforehand is
+    /// worthless afterwards.
+    pub fn regenerate(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.stale_id = inner.id.take();
+        inner.changed = true;
+    }
+
+    /// Deletes the session and clears the cookie.
+    pub fn destroy(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.data.clear();
+        inner.destroyed = true;
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Session {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| AppError::internal("Session used on a route without manage_sessions"))
+    }
+}
+
+/// Loads the session named by the cookie for the handler's [`Session`]
+/// extractor, then saves it afterwards. A cookie that doesn't decrypt or an
+/// unknown id starts an empty session; nothing is stored until a value is
+/// set.
+pub async fn manage_sessions(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let sessions = state.sessions();
+    let opened = cookie(req.headers(), SESSION_COOKIE).and_then(|value| sessions.open(value));
+    let (id, stale_key) = opened.map_or((None, false), |(id, stale)| (Some(id), stale));
+    let record = match &id {
+        Some(id) => sessions.store.load(id).await.unwrap_or_else(|e| {
+            error!("Failed to load session: {:#}", e);
+            None
+        }),
+        None => None,
+    };
+    let session = Session(Arc::new(Mutex::new(match record {
+        Some(record) => SessionState {
+            // A cookie sealed with an older key is sealed again with the
+            // newest, so it outlives the old key.
+            refresh: stale_key || record.expires_at - now() < sessions.ttl_secs / 2,
+            id,
+            data: record.data,
+            changed: false,
+            stale_id: None,
+            destroyed: false,
+        },
+        None => SessionState {
+            id: None,
+            data: SessionData::new(),
+            changed: false,
+            refresh: false,
+            stale_id: None,
+            destroyed: false,
+        },
+    })));
+    req.extensions_mut().insert(session.clone());
+
+    let mut res = next.run(req).await;
+    if let Err(e) = sessions.finish(&session, &mut res).await {
+        error!("Failed to save session: {:#}", e);
+    }
+    res
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionInfo {
+    /// Who logged in, if anyone.
+    user: Option<String>,
+    /// Requests to `/api/session` in this session, this one included.
+    visits: u64,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct LoginRequest {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
+    #[schema(min_length = 1, max_length = 64)]
+    username: String,
+    /// Without the `database` feature there are no accounts, and any
+    /// username logs in with `password`.
+    password: String,
+}
+
+#[cfg(feature = "database")]
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct SignupRequest {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
+    #[schema(min_length = 1, max_length = 64)]
+    username: String,
+    #[validate(length(min = 8, max = 1024, message = "must be 8 to 1024 characters"))]
+    #[schema(min_length = 8, max_length = 1024)]
+    password: String,
+}
+
+pub fn session_info(session: &Session) -> SessionInfo {
+    SessionInfo {
+        user: session.get("user"),
+        visits: session.get("visits").unwrap_or(0),
+    }
+}
+
+/// Signs `username` in on a fresh session id.
+pub fn log_in(session: &Session, username: String) {
+    session.regenerate();
+    session.insert("user", username);
+    session.insert("logged_in_at", Utc::now());
+}
+
+/// Counts visits, so the session is visible before logging in.
+#[utoipa::path(
+    get,
+    path = "/api/session",
+    tag = "session",
+    responses((status = 200, description = "The current session", body = SessionInfo))
+)]
+pub async fn session_handler(session: Session) -> Json<SessionInfo> {
+    let visits: u64 = session.get("visits").unwrap_or(0);
+    session.insert("visits", visits + 1);
+    Json(session_info(&session))
+}
+
+/// `password` against the account's, which is rehashed if it's out of
+/// date. `None` when it's wrong; otherwise whether the account also wants a
+/// two-factor code.
+#[cfg(feature = "database")]
+async fn check_password(
+    state: &AppState,
+    username: &str,
+    password: String,
+) -> AppResult<Option<bool>> {
+    Ok(crate::accounts::authenticate(state, username, password)
+        .await?
+        .map(|account| account.two_factor))
+}
+
+#[cfg(not(feature = "database"))]
+async fn check_password(
+    _state: &AppState,
+    _username: &str,
+    password: String,
+) -> AppResult<Option<bool>> {
+    Ok((password == DEMO_PASSWORD).then_some(false))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/session/login",
+    tag = "session",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in; the session has a new id", body = SessionInfo),
+        (status = 202, description = "Password accepted; the account wants a code at /api/session/2fa/verify"),
+        (status = 401, description = "Wrong username or password, or a CAPTCHA is needed (X-Captcha-Required)"),
+        (status = 422, description = "Invalid username"),
+        (status = 429, description = "Too many failed logins from this client or for this account")
+    )
+)]
+#[cfg_attr(not(feature = "database"), allow(unused_variables))]
+pub async fn login_handler(
+    State(state): State<AppState>,
+    session: Session,
+    ClientAddr(client): ClientAddr,
+    headers: HeaderMap,
+    ValidatedJson(login): ValidatedJson<LoginRequest>,
+) -> Result<Response, Response> {
+    let guard = state.brute_force();
+    guard.admit(&client, &login.username, &headers).await?;
+    let checked = check_password(&state, &login.username, login.password)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let Some(two_factor) = checked else {
+        guard.failed(&client, &login.username);
+        return Err(
+            AppError::new(StatusCode::UNAUTHORIZED, "Wrong username or password").into_response(),
+        );
+    };
+    // The account's failures are only forgotten once the code is right too.
+    #[cfg(feature = "database")]
+    if two_factor {
+        return Ok(crate::two_factor::challenge(&session, login.username));
+    }
+    guard.succeeded(&login.username);
+    log_in(&session, login.username);
+    Ok(Json(session_info(&session)).into_response())
+}
+
+/// Creates an account and logs in as it.
+#[cfg(feature = "database")]
+#[utoipa::path(
+    post,
+    path = "/api/session/signup",
+    tag = "session",
+    request_body = SignupRequest,
+    responses(
+        (status = 201, description = "Signed up and logged in", body = SessionInfo),
+        (status = 409, description = "The username is taken"),
+        (status = 422, description = "Invalid username or password")
+    )
+)]
+pub async fn signup_handler(
+    State(state): State<AppState>,
+    session: Session,
+    ValidatedJson(signup): ValidatedJson<SignupRequest>,
+) -> AppResult<(StatusCode, Json<SessionInfo>)> {
+    let created = crate::accounts::create(&state, &signup.username, signup.password).await?;
+    if created.is_none() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            "That username is taken",
+        ));
+    }
+    log_in(&session, signup.username);
+    Ok((StatusCode::CREATED, Json(session_info(&session))))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Me {
+    user: String,
+    logged_in_at: DateTime<Utc>,
+}
+
+/// Who the session is logged in as.
+#[utoipa::path(
+    get,
+    path = "/api/session/me",
+    tag = "session",
+    responses(
+        (status = 200, description = "The logged-in user", body = Me),
+        (status = 401, description = "Not logged in")
+    )
+)]
+pub async fn me_handler(session: Session) -> AppResult<Json<Me>> {
+    let (Some(user), Some(logged_in_at)) = (session.get("user"), session.get("logged_in_at"))
+    else {
+        return Err(AppError::new(StatusCode::UNAUTHORIZED, "Not logged in"));
+    };
+    Ok(Json(Me { user, logged_in_at }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/session/logout",
+    tag = "session",
+    responses((status = 204, description = "Session ended"))
+)]
+pub async fn logout_handler(session: Session) -> StatusCode {
+    session.destroy();
+    StatusCode::NO_CONTENT
+}
+
+/// The account endpoints, which need the `database` feature.
+#[cfg(feature = "database")]
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        signup_handler,
+        crate::two_factor::status_handler,
+        crate::two_factor::setup_handler,
+        crate::two_factor::qr_handler,
+        crate::two_factor::enable_handler,
+        crate::two_factor::verify_handler,
+        crate::two_factor::recovery_codes_handler,
+        crate::two_factor::disable_handler
+    ),
+    components(schemas(
+        SignupRequest,
+        crate::two_factor::Challenge,
+        crate::two_factor::TwoFactorStatus,
+        crate::two_factor::Setup,
+        crate::two_factor::CodeRequest,
+        crate::two_factor::RecoveryCodes
+    ))
+)]
+pub struct AccountsApi;
@@ -0,0 +1,289 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    config::SignedRequestsConfig,
+    error::{AppError, AppResult},
+    events::Event,
+    state::AppState,
+};
+
+/// `key=<name>, ts=<unix secs>, nonce=<random>, sig=<hex HMAC-SHA256>`.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// `name=secret,...` on top of the configured keys.
+const KEYS_ENV: &str = "REQUEST_SIGNING_KEYS";
+
+/// Signed bodies larger than this are refused.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Nonces remembered at once; past this, signed requests are turned away
+/// until some expire. Dropping live ones instead would let their requests
+/// be replayed.
+const MAX_NONCES: usize = 100_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The name of the key a request was signed with, once verified.
+#[derive(Clone, Debug)]
+pub struct SignedBy {
+    pub key: String,
+}
+
+/// What gets signed: the timestamp, nonce, method, path with query and the
+/// body's SHA-256, one per line.
+fn string_to_sign(ts: i64, nonce: &str, method: &Method, path: &str, body: &[u8]) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        ts,
+        nonce,
+        method,
+        path,
+        hex::encode(Sha256::digest(body))
+    )
+}
+
+fn mac(secret: &[u8], message: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// The `X-Signature` value for a request, signed now with a fresh nonce.
+/// For clients of other NSM services; the outbox signs its webhooks with it.
+#[cfg_attr(not(feature = "database"), allow(dead_code))]
+pub fn sign(key: &str, secret: &[u8], method: &Method, path: &str, body: &[u8]) -> String {
+    let ts = chrono::Utc::now().timestamp();
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+    let message = string_to_sign(ts, &nonce, method, path, body);
+    let sig = hex::encode(mac(secret, &message).finalize().into_bytes());
+    format!("key={}, ts={}, nonce={}, sig={}", key, ts, nonce, sig)
+}
+
+/// The parts of an `X-Signature` value.
+struct Signature<'a> {
+    key: &'a str,
+    ts: i64,
+    nonce: &'a str,
+    sig: Vec<u8>,
+}
+
+fn parse(value: &str) -> Option<Signature<'_>> {
+    let fields: HashMap<&str, &str> = value
+        .split(',')
+        .filter_map(|field| field.trim().split_once('='))
+        .collect();
+    Some(Signature {
+        key: fields.get("key")?,
+        ts: fields.get("ts")?.parse().ok()?,
+        nonce: fields.get("nonce").filter(|n| !n.is_empty())?,
+        sig: hex::decode(fields.get("sig")?).ok()?,
+    })
+}
+
+/// The shared secrets, and the nonces seen lately.
+pub struct SignedRequests {
+    keys: BTreeMap<String, Vec<u8>>,
+    protect: Vec<String>,
+    max_skew: Duration,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl SignedRequests {
+    pub fn new(config: &SignedRequestsConfig) -> anyhow::Result<Self> {
+        let mut keys = config.keys.clone();
+        if let Ok(list) = std::env::var(KEYS_ENV) {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let Some((name, secret)) = entry.split_once('=') else {
+                    anyhow::bail!("{}: expected name=secret, got {:?}", KEYS_ENV, entry);
+                };
+                keys.insert(name.trim().to_string(), secret.trim().to_string());
+            }
+        }
+        if let Some((name, _)) = keys.iter().find(|(_, secret)| secret.is_empty()) {
+            anyhow::bail!("request signing key {:?} is empty", name);
+        }
+        Ok(Self {
+            keys: keys
+                .into_iter()
+                .map(|(name, secret)| (name, secret.into_bytes()))
+                .collect(),
+            protect: config.protect.clone(),
+            max_skew: Duration::from_secs(config.max_skew_secs),
+            nonces: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether any keys are configured.
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    fn protects(&self, path: &str) -> bool {
+        self.enabled()
+            && self
+                .protect
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    /// Records `nonce` for `key`, or says why it can't be used. Nonces are
+    /// kept until their timestamp is too old to be accepted anyway.
+    fn first_use(&self, key: &str, nonce: &str) -> Result<(), AppError> {
+        let now = Instant::now();
+        let mut nonces = self.nonces.lock().unwrap();
+        let id = format!("{}:{}", key, nonce);
+        if nonces.get(&id).is_some_and(|expires| *expires > now) {
+            return Err(AppError::new(StatusCode::UNAUTHORIZED, "Replayed request"));
+        }
+        if nonces.len() >= MAX_NONCES {
+            nonces.retain(|_, expires| *expires > now);
+            if nonces.len() >= MAX_NONCES {
+                warn!(
+                    "🔏 {} signed requests within the allowed skew; refusing more until some expire",
+                    MAX_NONCES
+                );
+                return Err(AppError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Too many signed requests; try again shortly",
+                ));
+            }
+        }
+        nonces.insert(id, now + self.max_skew * 2);
+        Ok(())
+    }
+
+    /// Checks `value` against the request; the name of its key if it holds.
+    fn verify(
+        &self,
+        value: &str,
+        method: &Method,
+        path: &str,
+        body: &[u8],
+    ) -> Result<String, AppError> {
+        let unauthorized = |message: &str| AppError::new(StatusCode::UNAUTHORIZED, message);
+        let signature = parse(value).ok_or_else(|| unauthorized("Malformed X-Signature"))?;
+        let secret = self
+            .keys
+            .get(signature.key)
+            .ok_or_else(|| unauthorized("Unknown signing key"))?;
+        let skew = chrono::Utc::now().timestamp().abs_diff(signature.ts);
+        if skew > self.max_skew.as_secs() {
+            return Err(unauthorized("Signature timestamp is too far from now"));
+        }
+        let message = string_to_sign(signature.ts, signature.nonce, method, path, body);
+        mac(secret, &message)
+            .verify_slice(&signature.sig)
+            .map_err(|_| unauthorized("Bad signature"))?;
+        // Only after the signature holds, so forged requests can't use up
+        // nonces.
+        self.first_use(signature.key, signature.nonce)?;
+        Ok(signature.key.to_string())
+    }
+}
+
+/// Answers `401` for requests under `signed_requests.protect` without a
+/// valid `X-Signature`: one made with a configured key over this method,
+/// path and body, within `signed_requests.max_skew_secs` of now, and with a
+/// nonce not used before. The key's name is on the request's log lines and
+/// in its extensions as a [`SignedBy`]. Each instance remembers its own
+/// nonces, so behind several instances a replay could land on another one
+/// until the timestamp runs out.
+pub async fn verify_signatures(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let signed = state.signed_requests();
+    if !signed.protects(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let unauthorized = |message: &str| AppError::new(StatusCode::UNAUTHORIZED, message);
+    let Some(value) = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+    else {
+        return unauthorized("A signed request is required; send X-Signature").into_response();
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => {
+            return AppError::new(StatusCode::PAYLOAD_TOO_LARGE, "Signed body is too large")
+                .into_response()
+        }
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |p| p.as_str());
+    match signed.verify(&value, &parts.method, path, &body) {
+        Ok(key) => {
+            tracing::Span::current().record("key", key.as_str());
+            parts.extensions.insert(SignedBy { key });
+            next.run(Request::from_parts(parts, Body::from(body))).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// What `/hooks/events` takes: an event, as the outbox delivers them.
+#[derive(Deserialize)]
+pub struct InboundEvent {
+    kind: String,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[derive(Serialize)]
+pub struct Relayed {
+    event: Event,
+    signed_by: String,
+}
+
+/// `POST /hooks/events`: an event from another service, e.g. its outbox,
+/// republished to our own subscribers.
+pub async fn events_handler(
+    State(state): State<AppState>,
+    signed_by: Option<axum::Extension<SignedBy>>,
+    Json(input): Json<InboundEvent>,
+) -> AppResult<(StatusCode, Json<Relayed>)> {
+    // Unsigned when `signed_requests.protect` leaves this path out.
+    let Some(axum::Extension(signed_by)) = signed_by else {
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "A signed request is required; send X-Signature",
+        ));
+    };
+    if input.kind.is_empty() {
+        return Err(AppError::bad_request("kind can't be empty"));
+    }
+    let event = state.events().publish(input.kind, input.data);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(Relayed {
+            event,
+            signed_by: signed_by.key,
+        }),
+    ))
+}
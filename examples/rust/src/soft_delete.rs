@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use sqlx::{sqlite::SqliteRow, FromRow, SqliteConnection};
+
+/// The condition for rows that haven't been deleted; queries for live rows
+/// add it to their `WHERE`.
+pub const LIVE: &str = "deleted_at IS NULL";
+
+/// A model whose rows are hidden rather than removed when deleted. Its
+/// table needs an `id` primary key and a nullable `deleted_at`.
+pub trait SoftDelete: for<'r> FromRow<'r, SqliteRow> + Send + Unpin {
+    const TABLE: &'static str;
+}
+
+/// Marks live row `id` deleted and returns it as it is now, or `None` if
+/// there's no such live row.
+pub async fn soft_delete<T: SoftDelete>(
+    conn: &mut SqliteConnection,
+    id: i64,
+) -> sqlx::Result<Option<T>> {
+    sqlx::query_as(&format!(
+        "UPDATE {} SET deleted_at = ? WHERE id = ? AND {} RETURNING *",
+        T::TABLE,
+        LIVE
+    ))
+    .bind(Utc::now())
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Brings back deleted row `id`, or returns `None` if there's no such
+/// deleted row.
+pub async fn restore<T: SoftDelete>(
+    conn: &mut SqliteConnection,
+    id: i64,
+) -> sqlx::Result<Option<T>> {
+    sqlx::query_as(&format!(
+        "UPDATE {} SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL RETURNING *",
+        T::TABLE
+    ))
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Removes rows deleted before `before` for good, returning them.
+pub async fn purge<T: SoftDelete>(
+    conn: &mut SqliteConnection,
+    before: DateTime<Utc>,
+) -> sqlx::Result<Vec<T>> {
+    sqlx::query_as(&format!(
+        "DELETE FROM {} WHERE deleted_at < ? RETURNING *",
+        T::TABLE
+    ))
+    .bind(before)
+    .fetch_all(conn)
+    .await
+}
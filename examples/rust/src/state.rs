@@ -0,0 +1,565 @@
+use axum::Router;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    assets::AssetManifest,
+    auth::Auth,
+    basic_auth::BasicAuth,
+    brute_force::BruteForce,
+    capture::CaptureBuffer,
+    chaos::Chaos,
+    client_addr::TrustedProxies,
+    config::{AppConfig, CorsConfig},
+    events::EventHub,
+    health::HealthRegistry,
+    jobs::Jobs,
+    keyring::Keyring,
+    kv::{self, KvStore},
+    livereload::LiveReload,
+    mocks::Mocks,
+    oidc::DevOidc,
+    outbound::OutboundClient,
+    privileged::PrivilegedLog,
+    ratelimit::RateLimiter,
+    rbac::Rbac,
+    redaction::Redactor,
+    reload::ConfigSnapshot,
+    request_log::InFlightRequests,
+    response_cache::ResponseCache,
+    routes::RouteInfo,
+    scheduler::{self, Scheduler},
+    security_headers::SecurityHeaders,
+    sessions::Sessions,
+    signed_urls::UrlSigner,
+    signing::SignedRequests,
+    static_files::{SpaFallback, STATIC_DIR},
+    static_keys::StaticKeys,
+    stats::ConnectionCounter,
+    templates::{Templates, TEMPLATES_DIR},
+    uploads::UploadStore,
+    upstream::Upstreams,
+    watchdog::Watchdog,
+};
+
+/// Shared application state handed to every handler.
+#[derive(Clone)]
+pub struct AppState {
+    inner: Arc<AppStateInner>,
+}
+
+struct AppStateInner {
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: Instant,
+    profile: String,
+    config: AppConfig,
+    /// What the last SIGHUP read, and the settings it can change.
+    config_snapshot: ConfigSnapshot,
+    cors: RwLock<CorsConfig>,
+    rate_limiter: RateLimiter,
+    ready: AtomicBool,
+    shutdown: CancellationToken,
+    health: HealthRegistry,
+    events: EventHub,
+    uploads: UploadStore,
+    spa: Option<SpaFallback>,
+    assets: Arc<AssetManifest>,
+    templates: Templates,
+    live_reload: Option<LiveReload>,
+    capture: CaptureBuffer,
+    redactor: Redactor,
+    chaos: Chaos,
+    mocks: Mocks,
+    upstreams: Upstreams,
+    connections: ConnectionCounter,
+    watchdog: Watchdog,
+    in_flight: InFlightRequests,
+    maintenance: AtomicBool,
+    #[cfg(feature = "database")]
+    db: sqlx::SqlitePool,
+    #[cfg(feature = "database")]
+    pool_monitor: crate::db::PoolMonitor,
+    #[cfg(feature = "database")]
+    outbox: crate::outbox::Outbox,
+    #[cfg(feature = "database")]
+    api_keys: crate::apikeys::ApiKeys,
+    #[cfg(feature = "database")]
+    passwords: crate::passwords::Passwords,
+    #[cfg(feature = "redis")]
+    redis: Arc<crate::cache::Redis>,
+    #[cfg(feature = "s3")]
+    s3: Arc<crate::s3::S3>,
+    #[cfg(feature = "email")]
+    mailer: crate::email::Mailer,
+    #[cfg(feature = "search")]
+    search: Arc<crate::search::SearchIndex>,
+    #[cfg(feature = "nats")]
+    consumers: crate::consumer::Consumers,
+    auth: Auth,
+    dev_oidc: Option<DevOidc>,
+    static_keys: StaticKeys,
+    basic_auth: BasicAuth,
+    brute_force: BruteForce,
+    rbac: Rbac,
+    signed_requests: SignedRequests,
+    outbound: OutboundClient,
+    trusted_proxies: TrustedProxies,
+    url_signer: UrlSigner,
+    privileged_log: PrivilegedLog,
+    security_headers: SecurityHeaders,
+    kv: Box<dyn KvStore>,
+    response_cache: ResponseCache,
+    sessions: Sessions,
+    jobs: Jobs,
+    scheduler: Scheduler,
+    routes: OnceLock<Vec<RouteInfo>>,
+    app: OnceLock<Router>,
+}
+
+impl AppState {
+    pub fn new(profile: String, config: AppConfig) -> anyhow::Result<Self> {
+        Self::build(profile, config, false)
+    }
+
+    /// For `preview-templates`: templates are re-read and browsers reloaded
+    /// on every change, whatever the build type.
+    pub fn for_preview(profile: String, config: AppConfig) -> anyhow::Result<Self> {
+        Self::build(profile, config, true)
+    }
+
+    fn build(profile: String, config: AppConfig, preview: bool) -> anyhow::Result<Self> {
+        let assets = Arc::new(if config.static_files.fingerprint {
+            AssetManifest::build(STATIC_DIR)
+        } else {
+            AssetManifest::default()
+        });
+        let chaos = Chaos::new(&config.chaos);
+        let upstreams = Upstreams::new(&config.upstreams)?;
+        let config_snapshot = ConfigSnapshot::new(&config);
+        let cors = RwLock::new(config.cors.clone());
+        let rate_limiter = RateLimiter::new(&config.rate_limit);
+        #[cfg(feature = "redis")]
+        let redis = Arc::new(crate::cache::Redis::new()?);
+        let kv = kv::open(
+            &config.kv,
+            #[cfg(feature = "redis")]
+            redis.clone(),
+        )?;
+        #[cfg(feature = "s3")]
+        let s3 = Arc::new(crate::s3::S3::from_env()?);
+        let uploads = UploadStore::from_env(
+            #[cfg(feature = "s3")]
+            s3.clone(),
+        )?;
+        #[cfg(feature = "email")]
+        let mailer = crate::email::Mailer::new(&config.email)?;
+        #[cfg(feature = "database")]
+        let db = crate::db::connect()?;
+        let sessions = Sessions::new(
+            &config.sessions,
+            Keyring::new(&config.cookie_keys)?,
+            #[cfg(feature = "database")]
+            db.clone(),
+            #[cfg(feature = "redis")]
+            redis.clone(),
+        )?;
+        #[cfg(feature = "database")]
+        let outbox = crate::outbox::Outbox::new(&config.outbox, db.clone())?;
+        #[cfg(feature = "database")]
+        let api_keys = crate::apikeys::ApiKeys::new(&config.api_keys);
+        #[cfg(feature = "database")]
+        let passwords = crate::passwords::Passwords::new(&config.passwords)?;
+        let dev_oidc = if cfg!(debug_assertions) && config.dev_oidc.enabled {
+            Some(DevOidc::new(&config.dev_oidc)?)
+        } else {
+            None
+        };
+        let auth = Auth::new(&config.auth, dev_oidc.as_ref().map(DevOidc::verifier))?;
+        let static_keys = StaticKeys::new(&config.static_api_keys)?;
+        let basic_auth = BasicAuth::new(&config.basic_auth)?;
+        let redactor = Redactor::new(&config.redaction);
+        let brute_force = BruteForce::new(&config.brute_force)?;
+        let rbac = Rbac::new(&config.rbac)?;
+        let signed_requests = SignedRequests::new(&config.signed_requests)?;
+        let outbound = OutboundClient::new(&config.outbound)?;
+        let trusted_proxies = TrustedProxies::new(&config.proxy)?;
+        let url_signer = UrlSigner::new(&config.signed_urls);
+        let privileged_log = PrivilegedLog::new(&config.privileged_log)?;
+        let jobs = Jobs::new(
+            &config.jobs,
+            #[cfg(feature = "database")]
+            db.clone(),
+        );
+        let templates = Templates::load(TEMPLATES_DIR, assets.clone())?;
+        let templates = if preview {
+            templates.always_reload()
+        } else {
+            templates
+        };
+        let live_reload = if LiveReload::enabled() || preview {
+            Some(LiveReload::start(&[TEMPLATES_DIR, STATIC_DIR])?)
+        } else {
+            None
+        };
+        let security_headers =
+            SecurityHeaders::new(&config.security_headers, live_reload.is_some())?;
+
+        Ok(Self {
+            inner: Arc::new(AppStateInner {
+                started_at: chrono::Utc::now(),
+                started: Instant::now(),
+                profile,
+                config,
+                config_snapshot,
+                cors,
+                rate_limiter,
+                ready: AtomicBool::new(false),
+                shutdown: CancellationToken::new(),
+                health: HealthRegistry::default(),
+                events: EventHub::default(),
+                uploads,
+                spa: SpaFallback::from_env(STATIC_DIR),
+                templates,
+                assets,
+                live_reload,
+                capture: CaptureBuffer::default(),
+                redactor,
+                chaos,
+                mocks: Mocks::from_env()?,
+                upstreams,
+                connections: ConnectionCounter::default(),
+                watchdog: Watchdog::default(),
+                in_flight: InFlightRequests::default(),
+                maintenance: AtomicBool::new(false),
+                #[cfg(feature = "database")]
+                db,
+                #[cfg(feature = "database")]
+                pool_monitor: crate::db::PoolMonitor::default(),
+                #[cfg(feature = "database")]
+                outbox,
+                #[cfg(feature = "database")]
+                api_keys,
+                #[cfg(feature = "database")]
+                passwords,
+                #[cfg(feature = "redis")]
+                redis,
+                #[cfg(feature = "s3")]
+                s3,
+                #[cfg(feature = "email")]
+                mailer,
+                #[cfg(feature = "search")]
+                search: Arc::new(crate::search::SearchIndex::new()?),
+                #[cfg(feature = "nats")]
+                consumers: crate::consumer::registry(),
+                auth,
+                dev_oidc,
+                static_keys,
+                basic_auth,
+                brute_force,
+                rbac,
+                signed_requests,
+                outbound,
+                trusted_proxies,
+                url_signer,
+                privileged_log,
+                security_headers,
+                kv,
+                response_cache: ResponseCache::default(),
+                sessions,
+                jobs,
+                scheduler: scheduler::tasks(),
+                routes: OnceLock::new(),
+                app: OnceLock::new(),
+            }),
+        })
+    }
+
+    pub fn started_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.inner.started_at
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.inner.profile
+    }
+
+    pub fn config(&self) -> &AppConfig {
+        &self.inner.config
+    }
+
+    pub fn config_snapshot(&self) -> &ConfigSnapshot {
+        &self.inner.config_snapshot
+    }
+
+    pub fn cors_allows(&self, origin: &str) -> bool {
+        self.inner.cors.read().unwrap().allows(origin)
+    }
+
+    pub fn set_cors(&self, cors: CorsConfig) {
+        *self.inner.cors.write().unwrap() = cors;
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.inner.rate_limiter
+    }
+
+    pub fn health(&self) -> &HealthRegistry {
+        &self.inner.health
+    }
+
+    pub fn events(&self) -> &EventHub {
+        &self.inner.events
+    }
+
+    pub fn uploads(&self) -> &UploadStore {
+        &self.inner.uploads
+    }
+
+    pub fn spa(&self) -> Option<&SpaFallback> {
+        self.inner.spa.as_ref()
+    }
+
+    pub fn assets(&self) -> &AssetManifest {
+        &self.inner.assets
+    }
+
+    pub fn templates(&self) -> &Templates {
+        &self.inner.templates
+    }
+
+    pub fn live_reload(&self) -> Option<&LiveReload> {
+        self.inner.live_reload.as_ref()
+    }
+
+    pub fn capture(&self) -> &CaptureBuffer {
+        &self.inner.capture
+    }
+
+    pub fn redactor(&self) -> &Redactor {
+        &self.inner.redactor
+    }
+
+    pub fn chaos(&self) -> &Chaos {
+        &self.inner.chaos
+    }
+
+    pub fn mocks(&self) -> &Mocks {
+        &self.inner.mocks
+    }
+
+    pub fn upstreams(&self) -> &Upstreams {
+        &self.inner.upstreams
+    }
+
+    pub fn connections(&self) -> &ConnectionCounter {
+        &self.inner.connections
+    }
+
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.inner.watchdog
+    }
+
+    pub fn in_flight(&self) -> &InFlightRequests {
+        &self.inner.in_flight
+    }
+
+    /// Maintenance mode answers `503` to everything but probes and NSM's own
+    /// endpoints.
+    pub fn set_maintenance(&self, on: bool) {
+        self.inner.maintenance.store(on, Ordering::SeqCst);
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.inner.maintenance.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "database")]
+    pub fn db(&self) -> &sqlx::SqlitePool {
+        &self.inner.db
+    }
+
+    #[cfg(feature = "database")]
+    pub fn api_keys(&self) -> &crate::apikeys::ApiKeys {
+        &self.inner.api_keys
+    }
+
+    #[cfg(feature = "database")]
+    pub fn passwords(&self) -> &crate::passwords::Passwords {
+        &self.inner.passwords
+    }
+
+    #[cfg(feature = "database")]
+    pub fn pool_monitor(&self) -> &crate::db::PoolMonitor {
+        &self.inner.pool_monitor
+    }
+
+    #[cfg(feature = "database")]
+    pub fn outbox(&self) -> &crate::outbox::Outbox {
+        &self.inner.outbox
+    }
+
+    #[cfg(feature = "search")]
+    pub fn search(&self) -> &Arc<crate::search::SearchIndex> {
+        &self.inner.search
+    }
+
+    #[cfg(feature = "nats")]
+    pub fn consumers(&self) -> &crate::consumer::Consumers {
+        &self.inner.consumers
+    }
+
+    #[cfg(feature = "email")]
+    pub fn mailer(&self) -> &crate::email::Mailer {
+        &self.inner.mailer
+    }
+
+    #[cfg(feature = "s3")]
+    pub fn s3(&self) -> &crate::s3::S3 {
+        &self.inner.s3
+    }
+
+    #[cfg(feature = "redis")]
+    pub fn redis(&self) -> &crate::cache::Redis {
+        &self.inner.redis
+    }
+
+    pub fn auth(&self) -> &Auth {
+        &self.inner.auth
+    }
+
+    /// The dev OIDC provider, in debug builds unless turned off.
+    pub fn dev_oidc(&self) -> Option<&DevOidc> {
+        self.inner.dev_oidc.as_ref()
+    }
+
+    pub fn static_keys(&self) -> &StaticKeys {
+        &self.inner.static_keys
+    }
+
+    pub fn basic_auth(&self) -> &BasicAuth {
+        &self.inner.basic_auth
+    }
+
+    pub fn brute_force(&self) -> &BruteForce {
+        &self.inner.brute_force
+    }
+
+    pub fn rbac(&self) -> &Rbac {
+        &self.inner.rbac
+    }
+
+    pub fn signed_requests(&self) -> &SignedRequests {
+        &self.inner.signed_requests
+    }
+
+    /// For requests to URLs from users or config.
+    #[cfg_attr(not(feature = "database"), allow(dead_code))]
+    pub fn outbound(&self) -> &OutboundClient {
+        &self.inner.outbound
+    }
+
+    /// Works out where requests came from; see [`TrustedProxies::client`].
+    pub fn trusted_proxies(&self) -> &TrustedProxies {
+        &self.inner.trusted_proxies
+    }
+
+    pub fn url_signer(&self) -> &UrlSigner {
+        &self.inner.url_signer
+    }
+
+    pub fn privileged_log(&self) -> &PrivilegedLog {
+        &self.inner.privileged_log
+    }
+
+    pub fn security_headers(&self) -> &SecurityHeaders {
+        &self.inner.security_headers
+    }
+
+    pub fn kv(&self) -> &dyn KvStore {
+        self.inner.kv.as_ref()
+    }
+
+    pub fn response_cache(&self) -> &ResponseCache {
+        &self.inner.response_cache
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.inner.sessions
+    }
+
+    pub fn jobs(&self) -> &Jobs {
+        &self.inner.jobs
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.inner.scheduler
+    }
+
+    /// Records the route table once the router has been built.
+    pub fn set_routes(&self, routes: Vec<RouteInfo>) {
+        let _ = self.inner.routes.set(routes);
+    }
+
+    pub fn routes(&self) -> &[RouteInfo] {
+        self.inner
+            .routes
+            .get()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Records the fully layered app, so requests can be re-dispatched
+    /// in-process (e.g. replays).
+    pub fn set_app(&self, app: Router) {
+        let _ = self.inner.app.set(app);
+    }
+
+    pub fn app(&self) -> Option<Router> {
+        self.inner.app.get().cloned()
+    }
+
+    /// Marks startup as complete so `/readyz` starts reporting ready.
+    pub fn mark_ready(&self) {
+        self.inner.ready.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::SeqCst)
+    }
+
+    /// Flags the process as shutting down; readiness fails from here on and
+    /// long-lived connections (WebSockets) are asked to close.
+    pub fn begin_drain(&self) {
+        self.inner.shutdown.cancel();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.inner.shutdown.is_cancelled()
+    }
+
+    /// Cancelled once graceful shutdown begins.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.inner.shutdown.clone()
+    }
+}
+
+/// Resolves the active config profile from `APP_PROFILE`, falling back to
+/// the build type.
+pub fn config_profile() -> String {
+    std::env::var("APP_PROFILE").unwrap_or_else(|_| {
+        if cfg!(debug_assertions) {
+            "development".to_string()
+        } else {
+            "production".to_string()
+        }
+    })
+}
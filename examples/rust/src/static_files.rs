@@ -0,0 +1,538 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use std::{
+    io::SeekFrom,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tower::{ServiceBuilder, ServiceExt};
+use tower_http::{
+    compression::CompressionLayer,
+    services::{ServeDir, ServeFile},
+};
+
+use crate::{error::AppResult, state::AppState};
+
+/// More ranges than this in one request is treated as abuse and answered with
+/// the full file instead.
+const MAX_RANGES: usize = 16;
+
+/// Directory served under `/static`.
+pub const STATIC_DIR: &str = "static";
+
+/// Characters left unescaped in listing links.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Fingerprinted URLs change whenever the content does.
+const IMMUTABLE: HeaderValue = HeaderValue::from_static("public, max-age=31536000, immutable");
+
+struct StaticRoot {
+    root: PathBuf,
+    state: AppState,
+}
+
+/// `ServeDir` for `root`, wrapped so that range requests behave the way
+/// browsers' media elements and PDF viewers expect: `If-Range` is honoured and
+/// multi-range requests get a `multipart/byteranges` response. Single ranges
+/// are left to `ServeDir`.
+///
+/// Precompressed `.br`/`.gz` siblings (as emitted by frontend bundlers) are
+/// preferred when the client accepts them; other compressible files are
+/// compressed on the fly. Range responses are never compressed.
+///
+/// With `static.directory_listing` enabled, directories without an
+/// `index.html` get an HTML listing instead of a 404.
+pub fn static_service(
+    root: impl Into<PathBuf>,
+    state: AppState,
+) -> impl tower::Service<
+    Request,
+    Response = Response,
+    Error = std::convert::Infallible,
+    Future = impl Send + 'static,
+> + Clone
+       + Send
+       + 'static {
+    let root = root.into();
+    let state = Arc::new(StaticRoot {
+        root: root.clone(),
+        state,
+    });
+
+    ServiceBuilder::new()
+        .layer(middleware::map_response(vary_on_encoding))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            fingerprint_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            cache_control_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            directory_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(state, range_middleware))
+        .layer(CompressionLayer::new().br(true).gzip(true))
+        .service(ServeDir::new(root).precompressed_br().precompressed_gzip())
+}
+
+async fn range_middleware(
+    State(root): State<Arc<StaticRoot>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD)
+        || !req.headers().contains_key(header::RANGE)
+    {
+        return next.run(req).await;
+    }
+
+    let Some(path) = resolve_path(&root.root, req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let Ok(metadata) = tokio::fs::metadata(&path).await else {
+        return next.run(req).await;
+    };
+    if !metadata.is_file() {
+        return next.run(req).await;
+    }
+    let modified = metadata.modified().ok();
+
+    // A stale `If-Range` validator means the client's partial copy is out of
+    // date: ignore `Range` and send the whole file.
+    if let Some(if_range) = req.headers().get(header::IF_RANGE)
+        && !if_range_matches(if_range, modified)
+    {
+        req.headers_mut().remove(header::RANGE);
+        return next.run(req).await;
+    }
+
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !range.contains(',') {
+        return next.run(req).await;
+    }
+
+    let len = metadata.len();
+    match parse_ranges(&range, len) {
+        Some(ranges) if ranges.is_empty() => unsatisfiable(len),
+        Some(ranges) if ranges.len() <= MAX_RANGES => {
+            multipart_response(path, ranges, len, modified, req.method() == Method::HEAD)
+        }
+        _ => {
+            req.headers_mut().remove(header::RANGE);
+            next.run(req).await
+        }
+    }
+}
+
+/// Serves fingerprinted paths from the asset manifest as their logical file,
+/// cached forever.
+async fn fingerprint_middleware(
+    State(root): State<Arc<StaticRoot>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let path = percent_encoding::percent_decode_str(req.uri().path()).decode_utf8_lossy();
+    let Some(logical) = root.state.assets().resolve(path.trim_start_matches('/')) else {
+        return next.run(req).await;
+    };
+
+    let encoded = logical
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut path_and_query = format!("/{}", encoded);
+    if let Some(query) = req.uri().query() {
+        path_and_query = format!("{}?{}", path_and_query, query);
+    }
+    let Ok(uri) = Uri::builder().path_and_query(path_and_query).build() else {
+        return next.run(req).await;
+    };
+    *req.uri_mut() = uri;
+
+    let mut res = next.run(req).await;
+    if res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED {
+        res.headers_mut().insert(header::CACHE_CONTROL, IMMUTABLE);
+    }
+    res
+}
+
+/// Applies the first matching `static.cache_control` policy to successful
+/// responses.
+async fn cache_control_middleware(
+    State(root): State<Arc<StaticRoot>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = percent_encoding::percent_decode_str(req.uri().path())
+        .decode_utf8_lossy()
+        .trim_start_matches('/')
+        .to_string();
+    let mut res = next.run(req).await;
+
+    let cacheable = res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED;
+    if cacheable
+        && !res.headers().contains_key(header::CACHE_CONTROL)
+        && let Some(policy) = root
+            .state
+            .config()
+            .static_files
+            .cache_control
+            .iter()
+            .find(|p| p.matches(&path))
+    {
+        res.headers_mut()
+            .insert(header::CACHE_CONTROL, policy.value.clone());
+    }
+    res
+}
+
+/// Directory requests: `/dir` redirects to `/dir/` (`ServeDir` would drop the
+/// `/static` prefix from the `Location`), and `/dir/` gets a listing when
+/// enabled and there's no `index.html`.
+async fn directory_middleware(
+    State(root): State<Arc<StaticRoot>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.run(req).await;
+    }
+    let Some(dir) = resolve_path(&root.root, req.uri().path()) else {
+        return next.run(req).await;
+    };
+    if !tokio::fs::metadata(&dir).await.is_ok_and(|m| m.is_dir()) {
+        return next.run(req).await;
+    }
+
+    let original = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| req.uri().clone());
+    let mut path = original.path().to_string();
+    if !path.ends_with('/') {
+        path.push('/');
+        if let Some(query) = original.query() {
+            path = format!("{}?{}", path, query);
+        }
+        return Redirect::temporary(&path).into_response();
+    }
+
+    let has_index = tokio::fs::try_exists(dir.join("index.html"))
+        .await
+        .unwrap_or(false);
+    if has_index || !root.state.config().static_files.directory_listing {
+        return next.run(req).await;
+    }
+    let has_parent = req.uri().path() != "/";
+
+    match render_listing(&root.state, &dir, &path, has_parent).await {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// One row of `templates/listing.html`.
+#[derive(Serialize)]
+struct ListingEntry {
+    name: String,
+    href: String,
+    is_dir: bool,
+    size: String,
+    modified: String,
+}
+
+/// Context for `templates/listing.html`.
+#[derive(Serialize)]
+struct ListingContext<'a> {
+    project_name: &'a str,
+    path: &'a str,
+    has_parent: bool,
+    entries: Vec<ListingEntry>,
+}
+
+async fn render_listing(
+    state: &AppState,
+    dir: &Path,
+    path: &str,
+    has_parent: bool,
+) -> AppResult<String> {
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        // Follow symlinks, like `ServeDir` does when serving them.
+        let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+        let is_dir = metadata.is_dir();
+
+        entries.push(ListingEntry {
+            href: format!(
+                "{}{}{}",
+                path,
+                utf8_percent_encode(&name, PATH_SEGMENT),
+                if is_dir { "/" } else { "" }
+            ),
+            size: format_size(metadata.len()),
+            modified: metadata
+                .modified()
+                .map(|t| {
+                    chrono::DateTime::<chrono::Utc>::from(t)
+                        .format("%Y-%m-%d %H:%M:%S UTC")
+                        .to_string()
+                })
+                .unwrap_or_default(),
+            name,
+            is_dir,
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let context = ListingContext {
+        project_name: crate::PROJECT_NAME,
+        path,
+        has_parent,
+        entries,
+    };
+    state.templates().render("listing.html", &context)
+}
+
+/// `1536` -> `1.5 KiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Precompressed variants are picked by `Accept-Encoding`, so caches must key
+/// on it even when `ServeDir` served the `.br`/`.gz` file itself.
+async fn vary_on_encoding(mut res: Response) -> Response {
+    let already = res
+        .headers()
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.to_ascii_lowercase().contains("accept-encoding"));
+    if !already {
+        res.headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    res
+}
+
+/// Maps a request path onto `root`, refusing anything that could escape it.
+fn resolve_path(root: &Path, uri_path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(uri_path)
+        .decode_utf8()
+        .ok()?;
+    let mut path = root.to_path_buf();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn if_range_matches(if_range: &HeaderValue, modified: Option<SystemTime>) -> bool {
+    // Static files don't carry ETags, so an entity-tag validator never matches.
+    let (Ok(value), Some(modified)) = (if_range.to_str(), modified) else {
+        return false;
+    };
+    let Ok(since) = httpdate::parse_http_date(value) else {
+        return false;
+    };
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+    secs(since).is_some() && secs(since) == secs(modified)
+}
+
+/// Parses `bytes=a-b, c-, -n` into inclusive `(start, end)` pairs, dropping
+/// unsatisfiable ranges. `None` means the header is malformed.
+fn parse_ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+
+    for part in spec.split(',').map(str::trim) {
+        let (start, end) = part.split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                (suffix > 0 && len > 0).then(|| (len.saturating_sub(suffix), len - 1))
+            }
+            (start, "") => {
+                let start: u64 = start.parse().ok()?;
+                (start < len).then(|| (start, len - 1))
+            }
+            (start, end) => {
+                let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+                if start > end {
+                    return None;
+                }
+                (start < len).then(|| (start, end.min(len - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+
+    Some(ranges)
+}
+
+fn unsatisfiable(len: u64) -> Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+    )
+        .into_response()
+}
+
+fn multipart_response(
+    path: PathBuf,
+    ranges: Vec<(u64, u64)>,
+    len: u64,
+    modified: Option<SystemTime>,
+    head_only: bool,
+) -> Response {
+    let boundary = uuid::Uuid::new_v4().simple().to_string();
+    let content_type = format!("multipart/byteranges; boundary={}", boundary);
+    let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+    let mime = mime_guess::from_path(&path)
+        .first_or_octet_stream()
+        .to_string();
+
+    // Parts after the first are separated from the previous body by CRLF.
+    let part_header = move |i: usize, start: u64, end: u64| {
+        Bytes::from(format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            if i > 0 { "\r\n" } else { "" },
+            boundary,
+            mime,
+            start,
+            end,
+            len
+        ))
+    };
+
+    let content_length = ranges
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| part_header(i, start, end).len() as u64 + (end - start + 1))
+        .sum::<u64>()
+        + closing.len() as u64;
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(value) =
+        modified.and_then(|m| HeaderValue::from_str(&httpdate::fmt_http_date(m)).ok())
+    {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+
+    if head_only {
+        return (StatusCode::PARTIAL_CONTENT, headers).into_response();
+    }
+
+    let parts = stream::iter(ranges.into_iter().enumerate())
+        .then(move |(i, (start, end))| {
+            let path = path.clone();
+            async move {
+                let mut file = tokio::fs::File::open(&path).await?;
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok::<_, std::io::Error>((i, start, end, file.take(end - start + 1)))
+            }
+        })
+        .map_ok(move |(i, start, end, reader)| {
+            stream::once(future::ready(Ok(part_header(i, start, end))))
+                .chain(ReaderStream::new(reader))
+        })
+        .try_flatten()
+        .chain(stream::once(future::ready(Ok(closing))));
+
+    (
+        StatusCode::PARTIAL_CONTENT,
+        headers,
+        Body::from_stream(parts),
+    )
+        .into_response()
+}
+
+/// Serves `index.html` for unknown GET paths under `prefix`, so client-side
+/// routed SPAs survive deep links and reloads. Paths under `/api` and paths
+/// that look like files (`/app/main.js`) still get the JSON 404.
+pub struct SpaFallback {
+    prefix: String,
+    index: PathBuf,
+}
+
+impl SpaFallback {
+    /// Enabled by `SPA_FALLBACK_PREFIX`, e.g. `/` or `/app`.
+    pub fn from_env(static_root: impl AsRef<Path>) -> Option<Self> {
+        let prefix = std::env::var("SPA_FALLBACK_PREFIX").ok()?;
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        Some(Self {
+            prefix,
+            index: static_root.as_ref().join("index.html"),
+        })
+    }
+
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        let under_prefix = self.prefix == "/"
+            || path == self.prefix
+            || path.starts_with(&format!("{}/", self.prefix));
+        let is_api = path == "/api" || path.starts_with("/api/");
+        let looks_like_file = path
+            .rsplit('/')
+            .next()
+            .is_some_and(|segment| segment.contains('.'));
+
+        matches!(*method, Method::GET | Method::HEAD) && under_prefix && !is_api && !looks_like_file
+    }
+
+    /// Serves the index file; `None` if it doesn't exist.
+    pub async fn serve(&self, req: Request) -> Option<Response> {
+        let res = ServeFile::new(&self.index).oneshot(req).await.ok()?;
+        (res.status() != StatusCode::NOT_FOUND).then(|| res.map(Body::new))
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    basic_auth::BasicUser, config::StaticApiKeysConfig, error::AppError, signed_urls::SignedUrl,
+    state::AppState,
+};
+
+/// `name=key,...` on top of the configured keys.
+const KEYS_ENV: &str = "STATIC_API_KEYS";
+
+/// Name `ADMIN_TOKEN` goes by once static keys are on.
+const ADMIN_KEY_NAME: &str = "admin";
+
+/// The static key a request came with, by name, once it has matched.
+#[derive(Clone, Debug)]
+pub struct StaticKey {
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct StaticKeyStats {
+    /// Requests let through, by key name.
+    requests: BTreeMap<String, u64>,
+    /// Requests to protected paths without a known key.
+    rejected: u64,
+}
+
+/// The keys from config and `STATIC_API_KEYS`, kept as SHA-256 digests.
+pub struct StaticKeys {
+    keys: Vec<(String, [u8; 32])>,
+    protect: Vec<String>,
+    requests: Mutex<BTreeMap<String, u64>>,
+    rejected: AtomicU64,
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+impl StaticKeys {
+    pub fn new(config: &StaticApiKeysConfig) -> anyhow::Result<Self> {
+        let mut keys: BTreeMap<String, String> = config.keys.clone();
+        if let Ok(list) = std::env::var(KEYS_ENV) {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let Some((name, key)) = entry.split_once('=') else {
+                    anyhow::bail!("{}: expected name=key, got {:?}", KEYS_ENV, entry);
+                };
+                keys.insert(name.trim().to_string(), key.trim().to_string());
+            }
+        }
+        if let Some((name, _)) = keys.iter().find(|(_, key)| key.is_empty()) {
+            anyhow::bail!("static API key {:?} is empty", name);
+        }
+        // The admin API's own token keeps working alongside the keys.
+        let admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+        if let Some(token) = admin_token.filter(|_| !keys.is_empty()) {
+            keys.entry(ADMIN_KEY_NAME.to_string()).or_insert(token);
+        }
+        Ok(Self {
+            keys: keys
+                .into_iter()
+                .map(|(name, key)| (name, digest(&key)))
+                .collect(),
+            protect: config.protect.clone(),
+            requests: Mutex::new(BTreeMap::new()),
+            rejected: AtomicU64::new(0),
+        })
+    }
+
+    /// The name of the key `given`. Compared as digests, and against every
+    /// key, so how long it takes says nothing about the keys.
+    fn find(&self, given: &str) -> Option<&str> {
+        let given = digest(given);
+        self.keys.iter().fold(None, |found, (name, key)| {
+            if *key == given {
+                Some(name.as_str())
+            } else {
+                found
+            }
+        })
+    }
+
+    pub fn protects(&self, path: &str) -> bool {
+        !self.keys.is_empty()
+            && self
+                .protect
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    pub fn stats(&self) -> StaticKeyStats {
+        StaticKeyStats {
+            requests: self.requests.lock().unwrap().clone(),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `X-API-Key`, or else the bearer token.
+fn presented(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-api-key")
+        .or_else(|| headers.get(header::AUTHORIZATION))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.strip_prefix("Bearer ").unwrap_or(v))
+}
+
+/// Answers `401` for requests under `static_api_keys.protect` without one
+/// of the keys, unless they signed in with Basic auth or came with a signed
+/// link. A matching key is named on the request's log lines and put
+/// in its extensions as a [`StaticKey`], which the admin API and the
+/// database-backed API keys then take as enough.
+pub async fn require_static_key(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let keys = state.static_keys();
+    if !keys.protects(req.uri().path())
+        || req.extensions().get::<BasicUser>().is_some()
+        || req.extensions().get::<SignedUrl>().is_some()
+    {
+        return next.run(req).await;
+    }
+    let Some(name) = presented(req.headers()).and_then(|given| keys.find(given)) else {
+        keys.rejected.fetch_add(1, Ordering::Relaxed);
+        let mut res = AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "An API key is required; send it as X-API-Key",
+        )
+        .into_response();
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return res;
+    };
+    let name = name.to_string();
+    tracing::Span::current().record("key", name.as_str());
+    *keys
+        .requests
+        .lock()
+        .unwrap()
+        .entry(name.clone())
+        .or_default() += 1;
+    req.extensions_mut().insert(StaticKey { name });
+    next.run(req).await
+}
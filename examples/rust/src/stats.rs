@@ -0,0 +1,309 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    response::{Json, Response},
+    serve::IncomingStream,
+    Router,
+};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tower::Service;
+
+use crate::{
+    basic_auth::BasicAuthStats, brute_force::BruteForceStats, signed_urls::SignedUrlStats,
+    state::AppState, static_keys::StaticKeyStats, watchdog::WatchdogStats,
+};
+
+/// Connections currently open, and accepted since startup.
+#[derive(Default)]
+pub struct ConnectionCounter {
+    open: AtomicU64,
+    total: AtomicU64,
+}
+
+impl ConnectionCounter {
+    pub fn open(&self) -> u64 {
+        self.open.load(Ordering::Relaxed)
+    }
+}
+
+/// Held for as long as hyper serves a connection.
+struct ConnectionGuard(AppState);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connections().open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Make-service for `axum::serve` that counts connections: each one gets its
+/// own handle on the app, dropped by hyper when the connection closes. Like
+/// `into_make_service_with_connect_info`, it puts the peer's address on each
+/// request as `ConnectInfo<SocketAddr>`.
+#[derive(Clone)]
+pub struct CountConnections {
+    app: Router,
+    state: AppState,
+}
+
+impl CountConnections {
+    pub fn new(app: Router, state: AppState) -> Self {
+        Self { app, state }
+    }
+
+    /// A handle on the app for a connection just accepted from `remote`.
+    pub fn connection(&self, remote: SocketAddr) -> CountedConnection {
+        let counter = self.state.connections();
+        counter.open.fetch_add(1, Ordering::Relaxed);
+        counter.total.fetch_add(1, Ordering::Relaxed);
+        CountedConnection {
+            app: self.app.clone(),
+            remote,
+            _guard: Arc::new(ConnectionGuard(self.state.clone())),
+        }
+    }
+}
+
+impl Service<IncomingStream<'_>> for CountConnections {
+    type Response = CountedConnection;
+    type Error = Infallible;
+    type Future = Ready<Result<CountedConnection, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, stream: IncomingStream<'_>) -> Self::Future {
+        ready(Ok(self.connection(stream.remote_addr())))
+    }
+}
+
+#[derive(Clone)]
+pub struct CountedConnection {
+    app: Router,
+    remote: SocketAddr,
+    _guard: Arc<ConnectionGuard>,
+}
+
+impl Service<Request> for CountedConnection {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <Router as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request>::poll_ready(&mut self.app, cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        req.extensions_mut().insert(ConnectInfo(self.remote));
+        self.app.call(req)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Stats {
+    uptime_secs: u64,
+    runtime: RuntimeStats,
+    connections: ConnectionStats,
+    #[cfg(feature = "database")]
+    database: crate::db::PoolStats,
+    /// By job kind.
+    #[cfg(feature = "nats")]
+    consumers: std::collections::BTreeMap<&'static str, crate::consumer::ConsumerStats>,
+    watchdog: WatchdogStats,
+    static_api_keys: StaticKeyStats,
+    basic_auth: BasicAuthStats,
+    brute_force: BruteForceStats,
+    signed_urls: SignedUrlStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocator: Option<AllocatorStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_descriptors: Option<FdStats>,
+}
+
+/// Tokio's stable metrics, plus the unstable ones in `tokio_unstable`
+/// builds (e.g. with the `console` feature).
+#[derive(Serialize, Default)]
+struct RuntimeStats {
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+    /// Per worker: total time spent busy, in milliseconds.
+    worker_busy_ms: Vec<u64>,
+    worker_park_count: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    worker_local_queue_depth: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocking_queue_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spawned_tasks: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ConnectionStats {
+    open: u64,
+    total: u64,
+}
+
+/// From `/proc/self/status`.
+#[derive(Serialize, Default)]
+struct MemoryStats {
+    resident_bytes: u64,
+    peak_resident_bytes: u64,
+    virtual_bytes: u64,
+    threads: u64,
+}
+
+#[derive(Serialize)]
+struct AllocatorStats {
+    name: &'static str,
+    in_use_bytes: u64,
+    free_bytes: u64,
+    mmap_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct FdStats {
+    open: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+}
+
+fn runtime_stats() -> RuntimeStats {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let workers = metrics.num_workers();
+    #[allow(unused_mut)]
+    let mut stats = RuntimeStats {
+        workers,
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+        worker_busy_ms: (0..workers)
+            .map(|w| metrics.worker_total_busy_duration(w).as_millis() as u64)
+            .collect(),
+        worker_park_count: (0..workers).map(|w| metrics.worker_park_count(w)).collect(),
+        ..Default::default()
+    };
+    #[cfg(tokio_unstable)]
+    {
+        stats.worker_local_queue_depth = Some(
+            (0..workers)
+                .map(|w| metrics.worker_local_queue_depth(w))
+                .collect(),
+        );
+        stats.blocking_threads = Some(metrics.num_blocking_threads());
+        stats.idle_blocking_threads = Some(metrics.num_idle_blocking_threads());
+        stats.blocking_queue_depth = Some(metrics.blocking_queue_depth());
+        stats.spawned_tasks = Some(metrics.spawned_tasks_count());
+    }
+    stats
+}
+
+#[cfg(target_os = "linux")]
+fn memory_stats() -> Option<MemoryStats> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let mut stats = MemoryStats::default();
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        // Sizes are in kB.
+        let number = value.trim().trim_end_matches(" kB").parse().unwrap_or(0);
+        match key {
+            "VmRSS" => stats.resident_bytes = number * 1024,
+            "VmHWM" => stats.peak_resident_bytes = number * 1024,
+            "VmSize" => stats.virtual_bytes = number * 1024,
+            "Threads" => stats.threads = number,
+            _ => {}
+        }
+    }
+    Some(stats)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_stats() -> Option<MemoryStats> {
+    None
+}
+
+/// The system allocator has no portable stats; glibc's `mallinfo2` is the
+/// one we can ask.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn allocator_stats() -> Option<AllocatorStats> {
+    // SAFETY: mallinfo2 only reads allocator bookkeeping.
+    let info = unsafe { libc::mallinfo2() };
+    Some(AllocatorStats {
+        name: "glibc malloc",
+        in_use_bytes: info.uordblks as u64,
+        free_bytes: info.fordblks as u64,
+        mmap_bytes: info.hblkhd as u64,
+    })
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn allocator_stats() -> Option<AllocatorStats> {
+    None
+}
+
+#[cfg(unix)]
+fn fd_stats() -> Option<FdStats> {
+    let dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    let open = std::fs::read_dir(dir).ok()?.count() as u64;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit writes into the struct we pass and nothing else.
+    #[allow(clippy::unnecessary_cast)] // rlim_t isn't u64 everywhere
+    let limit = (unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0)
+        .then_some(limit.rlim_cur as u64);
+    Some(FdStats { open, limit })
+}
+
+#[cfg(not(unix))]
+fn fd_stats() -> Option<FdStats> {
+    None
+}
+
+/// `GET /debug/stats` (debug builds): runtime, memory, connection, pool,
+/// consumer lag and file descriptor numbers for a first look at "why is it
+/// slow".
+pub async fn stats_handler(State(state): State<AppState>) -> Json<Stats> {
+    let connections = state.connections();
+    Json(Stats {
+        uptime_secs: state.uptime().as_secs(),
+        runtime: runtime_stats(),
+        connections: ConnectionStats {
+            open: connections.open.load(Ordering::Relaxed),
+            total: connections.total.load(Ordering::Relaxed),
+        },
+        #[cfg(feature = "database")]
+        database: state.pool_monitor().stats(state.db()),
+        #[cfg(feature = "nats")]
+        consumers: state.consumers().stats(),
+        watchdog: state.watchdog().stats(),
+        static_api_keys: state.static_keys().stats(),
+        basic_auth: state.basic_auth().stats(),
+        brute_force: state.brute_force().stats(),
+        signed_urls: state.url_signer().stats(),
+        memory: memory_stats(),
+        allocator: allocator_stats(),
+        file_descriptors: fd_stats(),
+    })
+}
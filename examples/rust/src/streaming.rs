@@ -0,0 +1,121 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::Query,
+    http::header,
+    response::Response,
+};
+use futures::stream;
+use serde::Deserialize;
+use std::{convert::Infallible, time::Duration};
+use utoipa::IntoParams;
+
+use crate::error::{AppError, AppResult};
+
+const MAX_ROWS: u64 = 1_000_000;
+const MAX_DELAY_MS: u64 = 10_000;
+const MAX_BYTES: u64 = 1024 * 1024 * 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NdjsonParams {
+    /// Number of rows to emit (default 1000).
+    count: Option<u64>,
+    /// Pause before each row, to simulate a slow producer.
+    delay_ms: Option<u64>,
+}
+
+/// Streams generated NDJSON rows. Rows are produced lazily as the body is
+/// polled, so a slow client applies backpressure all the way to the
+/// generator instead of buffering in memory. In-flight streams run to
+/// completion during graceful shutdown.
+#[utoipa::path(
+    get,
+    path = "/api/stream/ndjson",
+    tag = "demo",
+    params(NdjsonParams),
+    responses((status = 200, description = "Chunked NDJSON", content_type = "application/x-ndjson"))
+)]
+pub async fn ndjson_handler(Query(params): Query<NdjsonParams>) -> AppResult<Response> {
+    let count = params.count.unwrap_or(1000);
+    let delay = Duration::from_millis(params.delay_ms.unwrap_or(0));
+    if count > MAX_ROWS || delay.as_millis() as u64 > MAX_DELAY_MS {
+        return Err(AppError::bad_request(format!(
+            "count must be <= {} and delay_ms <= {}",
+            MAX_ROWS, MAX_DELAY_MS
+        )));
+    }
+
+    let rows = stream::unfold(0u64, move |i| async move {
+        if i >= count {
+            return None;
+        }
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let mut line = serde_json::to_vec(&serde_json::json!({
+            "seq": i,
+            "id": uuid::Uuid::new_v4(),
+            "timestamp": chrono::Utc::now(),
+        }))
+        .unwrap_or_default();
+        line.push(b'\n');
+        Some((Ok::<_, Infallible>(Bytes::from(line)), i + 1))
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(rows))
+        .map_err(AppError::internal)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BytesParams {
+    /// Total size in bytes (default 10 MiB, max 1 GiB).
+    size: Option<u64>,
+}
+
+/// Streams `size` bytes of filler in 64 KiB chunks with a known
+/// `Content-Length`, for exercising large downloads through the proxy.
+#[utoipa::path(
+    get,
+    path = "/api/stream/bytes",
+    tag = "demo",
+    params(BytesParams),
+    responses((status = 200, description = "Binary payload", content_type = "application/octet-stream"))
+)]
+pub async fn bytes_handler(Query(params): Query<BytesParams>) -> AppResult<Response> {
+    let size = params.size.unwrap_or(10 * 1024 * 1024);
+    if size > MAX_BYTES {
+        return Err(AppError::bad_request(format!(
+            "size must be <= {}",
+            MAX_BYTES
+        )));
+    }
+
+    let chunk = Bytes::from(vec![b'.'; CHUNK_SIZE]);
+    let chunks = stream::unfold(size, move |remaining| {
+        let chunk = chunk.clone();
+        async move {
+            if remaining == 0 {
+                return None;
+            }
+            let len = remaining.min(CHUNK_SIZE as u64);
+            Some((
+                Ok::<_, Infallible>(chunk.slice(..len as usize)),
+                remaining - len,
+            ))
+        }
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"stream.bin\"",
+        )
+        .body(Body::from_stream(chunks))
+        .map_err(AppError::internal)
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use tera::Tera;
+use tracing::{debug, error};
+
+use crate::{
+    assets::AssetManifest,
+    error::{AppError, AppResult},
+    security_headers,
+};
+
+/// Directory holding the Tera templates, relative to the working directory.
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// Tera templates loaded from `templates/`. Debug builds re-read the
+/// directory before every render, so edits show up on refresh without a
+/// rebuild.
+///
+/// The `asset(path="css/app.css")` function resolves fingerprinted URLs, and
+/// `csp_nonce()` gives the request's nonce for inline scripts and styles.
+pub struct Templates {
+    tera: RwLock<Tera>,
+    hot_reload: bool,
+}
+
+impl Templates {
+    pub fn load(dir: &str, assets: Arc<AssetManifest>) -> anyhow::Result<Self> {
+        let mut tera = Tera::new(&format!("{}/**/*.html", dir))?;
+        tera.register_function("asset", move |args: &HashMap<String, tera::Value>| {
+            let path = args
+                .get("path")
+                .and_then(tera::Value::as_str)
+                .ok_or_else(|| tera::Error::msg("asset() needs a `path` string argument"))?;
+            Ok(tera::Value::String(assets.url(path)))
+        });
+        tera.register_function("csp_nonce", |_: &HashMap<String, tera::Value>| {
+            Ok(tera::Value::String(security_headers::nonce()))
+        });
+        debug!(
+            "Loaded templates: {:?}",
+            tera.get_template_names().collect::<Vec<_>>()
+        );
+
+        Ok(Self {
+            tera: RwLock::new(tera),
+            hot_reload: cfg!(debug_assertions),
+        })
+    }
+
+    /// Re-reads templates before every render, even in release builds.
+    pub fn always_reload(mut self) -> Self {
+        self.hot_reload = true;
+        self
+    }
+
+    /// Names of the loaded templates, sorted.
+    pub fn names(&self) -> tera::Result<Vec<String>> {
+        if self.hot_reload {
+            self.tera.write().unwrap().full_reload()?;
+        }
+        let mut names: Vec<String> = self
+            .tera
+            .read()
+            .unwrap()
+            .get_template_names()
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn render(&self, name: &str, context: &impl Serialize) -> AppResult<String> {
+        let context = tera::Context::from_serialize(context).map_err(AppError::internal)?;
+        self.render_context(name, &context).map_err(template_error)
+    }
+
+    /// `render` keeping Tera's error, for showing it to whoever is editing
+    /// the template.
+    pub fn render_context(&self, name: &str, context: &tera::Context) -> tera::Result<String> {
+        if self.hot_reload {
+            self.tera.write().unwrap().full_reload()?;
+        }
+        self.tera.read().unwrap().render(name, context)
+    }
+}
+
+/// Tera nests the useful part of the message (line, missing variable) in the
+/// error's source chain.
+pub fn describe_error(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+pub fn template_error(e: tera::Error) -> AppError {
+    let message = describe_error(&e);
+    error!("Template error: {}", message);
+    AppError::internal(message)
+}
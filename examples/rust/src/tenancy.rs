@@ -0,0 +1,125 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::convert::Infallible;
+
+use crate::{error::AppError, state::AppState};
+
+/// Tenant of the bare domain, of hosts outside it, and of everything while
+/// tenancy is off. Rows from before tenancy was turned on belong to it.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Set by NSM's proxy to the domain the request came in on; wins over
+/// `Host`.
+const DOMAIN_HEADER: &str = "x-nsm-domain";
+
+/// Who a request is for, from its subdomain. As an extractor, it's the
+/// default tenant when `resolve_tenant` didn't run.
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    id: String,
+    /// Put in front of keys; empty while tenancy is off, so keys stay as
+    /// they were.
+    prefix: String,
+}
+
+impl Tenant {
+    fn new(id: &str, scoped: bool) -> Self {
+        Self {
+            id: id.to_string(),
+            prefix: if scoped {
+                format!("tenant:{}:", id)
+            } else {
+                String::new()
+            },
+        }
+    }
+
+    /// What the `tenant` column of its rows holds.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// `key` in this tenant's namespace, for KV and cache keys.
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// The key `key` was made from, or `None` if it's another tenant's.
+    pub fn unkey<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(self.prefix.as_str())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Tenant>()
+            .cloned()
+            .unwrap_or_else(|| Tenant::new(DEFAULT_TENANT, false)))
+    }
+}
+
+/// Tenant ids are single DNS labels, lowercased.
+fn is_tenant_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 63
+        && !id.starts_with('-')
+        && !id.ends_with('-')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The subdomain of `domain` the request is for: `Some("")` for the bare
+/// domain, `None` for a host outside it.
+fn subdomain(headers: &HeaderMap, domain: &str) -> Option<String> {
+    let host = headers
+        .get(DOMAIN_HEADER)
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|v| v.to_str().ok())?;
+    let host = host.rsplit_once(':').map_or(host, |(host, _port)| host);
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.to_ascii_lowercase();
+    if host == domain {
+        return Some(String::new());
+    }
+    host.strip_suffix(&format!(".{}", domain))
+        .map(str::to_string)
+}
+
+/// Works out the tenant from the subdomain in `X-NSM-Domain` or `Host` and
+/// puts it in the request's extensions. Answers `404` for a subdomain that
+/// isn't a tenant. Probes and NSM's own endpoints are left alone.
+pub async fn resolve_tenant(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config().tenancy;
+    let path = req.uri().path();
+    if !config.enabled || path == "/livez" || path == "/readyz" || path.starts_with("/__nsm/") {
+        return next.run(req).await;
+    }
+    let tenant = subdomain(req.headers(), &crate::domain())
+        .filter(|sub| !sub.is_empty())
+        .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+    let known = is_tenant_id(&tenant)
+        && (tenant == DEFAULT_TENANT
+            || config.tenants.is_empty()
+            || config.tenants.contains(&tenant));
+    if !known {
+        return AppError::new(StatusCode::NOT_FOUND, format!("No tenant {:?}", tenant))
+            .into_response();
+    }
+    req.extensions_mut().insert(Tenant::new(&tenant, true));
+    next.run(req).await
+}
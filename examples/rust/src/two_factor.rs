@@ -0,0 +1,605 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    client_addr::ClientAddr,
+    error::{AppError, AppResult},
+    sessions::{Session, SessionInfo},
+    state::AppState,
+};
+
+/// RFC 6238 as every authenticator app does it: HMAC-SHA1, six digits,
+/// thirty-second steps.
+const DIGITS: usize = 6;
+const PERIOD_SECS: i64 = 30;
+
+/// Steps either side of now a code is still good for, for clocks that
+/// drift.
+const SKEW: i64 = 1;
+
+/// 160 bits, as RFC 4226 recommends.
+const SECRET_BYTES: usize = 20;
+
+/// Session keys: the account whose password was right, waiting for a code;
+/// the secret being set up, until a code confirms it; and when the session
+/// last gave a code.
+const PENDING_KEY: &str = "two_factor_pending";
+const SETUP_KEY: &str = "two_factor_setup";
+const VERIFIED_KEY: &str = "two_factor_at";
+
+/// Recovery codes are this many base32 characters, in two halves.
+const RECOVERY_CODE_LEN: usize = 10;
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding, as `otpauth://` URLs carry secrets.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8 | byte as u32) & 0xffff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32.iter().position(|&b| b == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5 | value as u32) & 0xffff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// The code for time step `step`.
+fn code_at(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+/// The time step `code` belongs to, if it's one of the codes around now.
+fn matching_step(secret: &str, code: &str) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let now = Utc::now().timestamp() / PERIOD_SECS;
+    (now - SKEW..=now + SKEW).find(|&step| {
+        let expected = code_at(&secret, step);
+        // Compared in full either way, so timing doesn't give digits away.
+        expected
+            .bytes()
+            .zip(code.bytes())
+            .fold(expected.len() == code.len(), |same, (a, b)| same & (a == b))
+    })
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Recovery codes are compared without dashes, spaces or case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(normalize_recovery_code(code).as_bytes()))
+}
+
+fn new_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..RECOVERY_CODE_LEN)
+        .map(|_| BASE32[rng.gen_range(0..32)] as char)
+        .collect();
+    let (first, second) = code.split_at(RECOVERY_CODE_LEN / 2);
+    format!("{}-{}", first, second)
+}
+
+/// `otpauth://totp/Issuer:user?...`, which authenticator apps scan from
+/// the QR code.
+fn otpauth_url(issuer: &str, username: &str, secret: &str) -> String {
+    let encode = |s: &str| utf8_percent_encode(s, NON_ALPHANUMERIC).to_string();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(issuer),
+        encode(username),
+        secret,
+        encode(issuer),
+        DIGITS,
+        PERIOD_SECS
+    )
+}
+
+fn issuer(state: &AppState) -> String {
+    state
+        .config()
+        .two_factor
+        .issuer
+        .clone()
+        .unwrap_or_else(|| crate::PROJECT_NAME.to_string())
+}
+
+/// A logged-in account's two-factor columns.
+#[derive(sqlx::FromRow)]
+struct Enrolment {
+    id: i64,
+    totp_secret: Option<String>,
+}
+
+async fn enrolment(state: &AppState, username: &str) -> AppResult<Enrolment> {
+    sqlx::query_as("SELECT id, totp_secret FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(state.db())
+        .await
+        .map_err(AppError::internal)?
+        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "The account no longer exists"))
+}
+
+fn logged_in(session: &Session) -> AppResult<String> {
+    session
+        .get("user")
+        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Not logged in"))
+}
+
+/// Whether `code`, a TOTP code or an unused recovery code, is good for the
+/// account; either is used up by passing. A TOTP code is refused for a time
+/// step at or before the last one accepted, so one seen over a shoulder
+/// doesn't work again.
+async fn check_code(state: &AppState, account: &Enrolment, code: &str) -> AppResult<bool> {
+    let code = code.trim();
+    let Some(secret) = &account.totp_secret else {
+        return Ok(false);
+    };
+    let updated = if is_totp_code(code) {
+        let Some(step) = matching_step(secret, code) else {
+            return Ok(false);
+        };
+        sqlx::query(
+            "UPDATE users SET totp_last_step = ? \
+             WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+        )
+        .bind(step)
+        .bind(account.id)
+        .bind(step)
+        .execute(state.db())
+        .await
+    } else {
+        sqlx::query(
+            "UPDATE recovery_codes SET used_at = ? \
+             WHERE user_id = ? AND hash = ? AND used_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(account.id)
+        .bind(hash_recovery_code(code))
+        .execute(state.db())
+        .await
+    };
+    let used = updated.map_err(AppError::internal)?.rows_affected() == 1;
+    if used && !is_totp_code(code) {
+        info!("🔑 Account {} used a recovery code", account.id);
+    }
+    Ok(used)
+}
+
+/// Replaces the account's recovery codes with new ones, returned in the
+/// clear this once.
+async fn issue_recovery_codes(state: &AppState, user_id: i64) -> AppResult<Vec<String>> {
+    let codes: Vec<String> = (0..state.config().two_factor.recovery_codes)
+        .map(|_| new_recovery_code())
+        .collect();
+    let mut tx = state.db().begin().await.map_err(AppError::internal)?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::internal)?;
+    for code in &codes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash_recovery_code(code))
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::internal)?;
+    }
+    tx.commit().await.map_err(AppError::internal)?;
+    Ok(codes)
+}
+
+/// An account whose password was right, waiting for its code.
+#[derive(Serialize, Deserialize)]
+struct Pending {
+    username: String,
+    at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Challenge {
+    /// Always `true`: send a code to `/api/session/2fa/verify` to finish
+    /// logging in.
+    two_factor_required: bool,
+}
+
+/// For a login whose password was right on an account with two-factor on:
+/// nobody is logged in until `/api/session/2fa/verify` gets a code.
+pub fn challenge(session: &Session, username: String) -> Response {
+    session.regenerate();
+    session.remove("user");
+    session.remove(VERIFIED_KEY);
+    session.insert(
+        PENDING_KEY,
+        Pending {
+            username,
+            at: Utc::now(),
+        },
+    );
+    (
+        StatusCode::ACCEPTED,
+        Json(Challenge {
+            two_factor_required: true,
+        }),
+    )
+        .into_response()
+}
+
+/// Passes if the session gave a two-factor code within
+/// `two_factor.step_up_secs`, or its account doesn't use two-factor. Take it
+/// in handlers for sensitive actions to have them ask for a code again;
+/// otherwise `403`.
+pub struct StepUp;
+
+#[async_trait]
+impl FromRequestParts<AppState> for StepUp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> AppResult<Self> {
+        let session = Session::from_request_parts(parts, state).await?;
+        let username = logged_in(&session)?;
+        let window = chrono::Duration::seconds(state.config().two_factor.step_up_secs as i64);
+        let recent = session
+            .get::<DateTime<Utc>>(VERIFIED_KEY)
+            .is_some_and(|at| Utc::now() - at < window);
+        if recent || enrolment(state, &username).await?.totp_secret.is_none() {
+            return Ok(StepUp);
+        }
+        Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            "Confirm with a two-factor code at /api/session/2fa/verify first",
+        ))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TwoFactorStatus {
+    enabled: bool,
+    /// Recovery codes not yet used.
+    recovery_codes_left: i64,
+}
+
+/// Whether the logged-in account uses two-factor.
+#[utoipa::path(
+    get,
+    path = "/api/session/2fa",
+    tag = "session",
+    responses(
+        (status = 200, description = "Two-factor status", body = TwoFactorStatus),
+        (status = 401, description = "Not logged in")
+    )
+)]
+pub async fn status_handler(
+    State(state): State<AppState>,
+    session: Session,
+) -> AppResult<Json<TwoFactorStatus>> {
+    let account = enrolment(&state, &logged_in(&session)?).await?;
+    let (left,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM recovery_codes WHERE user_id = ? AND used_at IS NULL")
+            .bind(account.id)
+            .fetch_one(state.db())
+            .await
+            .map_err(AppError::internal)?;
+    Ok(Json(TwoFactorStatus {
+        enabled: account.totp_secret.is_some(),
+        recovery_codes_left: left,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Setup {
+    /// Base32, for typing into an authenticator by hand.
+    secret: String,
+    otpauth_url: String,
+    /// The same URL as an SVG QR code.
+    qr_code: &'static str,
+}
+
+/// Starts turning two-factor on: a new secret for the authenticator, kept
+/// in the session until `/api/session/2fa/enable` confirms a code from it.
+#[utoipa::path(
+    post,
+    path = "/api/session/2fa/setup",
+    tag = "session",
+    responses(
+        (status = 200, description = "The secret to add to an authenticator", body = Setup),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "Two-factor is already on")
+    )
+)]
+pub async fn setup_handler(
+    State(state): State<AppState>,
+    session: Session,
+) -> AppResult<Json<Setup>> {
+    let username = logged_in(&session)?;
+    if enrolment(&state, &username).await?.totp_secret.is_some() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            "Two-factor is already on; turn it off first to change authenticators",
+        ));
+    }
+    let mut secret = [0; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = base32_encode(&secret);
+    session.insert(SETUP_KEY, &secret);
+    Ok(Json(Setup {
+        otpauth_url: otpauth_url(&issuer(&state), &username, &secret),
+        secret,
+        qr_code: "/api/session/2fa/qr",
+    }))
+}
+
+/// The secret being set up as a QR code.
+#[utoipa::path(
+    get,
+    path = "/api/session/2fa/qr",
+    tag = "session",
+    responses(
+        (status = 200, description = "SVG QR code of the otpauth:// URL", content_type = "image/svg+xml"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "No setup in progress")
+    )
+)]
+pub async fn qr_handler(State(state): State<AppState>, session: Session) -> AppResult<Response> {
+    let username = logged_in(&session)?;
+    let secret: String = session
+        .get(SETUP_KEY)
+        .ok_or_else(|| AppError::not_found("No two-factor setup in progress"))?;
+    let url = otpauth_url(&issuer(&state), &username, &secret);
+    let svg = qrcode::QrCode::new(url.as_bytes())
+        .map_err(AppError::internal)?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "image/svg+xml".parse().unwrap());
+    headers.insert(header::CACHE_CONTROL, "no-store".parse().unwrap());
+    Ok((headers, svg).into_response())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CodeRequest {
+    /// A six-digit TOTP code, or a recovery code where one is accepted.
+    code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecoveryCodes {
+    /// Each works once in place of a TOTP code. They aren't shown again.
+    recovery_codes: Vec<String>,
+}
+
+/// Turns two-factor on once a code from the new secret checks out, and
+/// hands out recovery codes.
+#[utoipa::path(
+    post,
+    path = "/api/session/2fa/enable",
+    tag = "session",
+    request_body = CodeRequest,
+    responses(
+        (status = 200, description = "Two-factor is on", body = RecoveryCodes),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "No setup in progress"),
+        (status = 422, description = "Wrong code")
+    )
+)]
+pub async fn enable_handler(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<CodeRequest>,
+) -> AppResult<Json<RecoveryCodes>> {
+    let username = logged_in(&session)?;
+    let secret: String = session
+        .get(SETUP_KEY)
+        .ok_or_else(|| AppError::not_found("No two-factor setup in progress"))?;
+    let code = request.code.trim();
+    let Some(step) = matching_step(&secret, code).filter(|_| is_totp_code(code)) else {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "That code doesn't match; check the authenticator's clock",
+        ));
+    };
+    let account = enrolment(&state, &username).await?;
+    let updated = sqlx::query(
+        "UPDATE users SET totp_secret = ?, totp_last_step = ? \
+         WHERE id = ? AND totp_secret IS NULL",
+    )
+    .bind(&secret)
+    .bind(step)
+    .bind(account.id)
+    .execute(state.db())
+    .await
+    .map_err(AppError::internal)?;
+    if updated.rows_affected() == 0 {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            "Two-factor is already on",
+        ));
+    }
+    let recovery_codes = issue_recovery_codes(&state, account.id).await?;
+    session.remove(SETUP_KEY);
+    session.insert(VERIFIED_KEY, Utc::now());
+    info!("🔑 Account {} turned two-factor on", account.id);
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Finishes a login that's waiting for its code, or, when already logged
+/// in, confirms the session for actions behind [`StepUp`]. Takes a TOTP
+/// code or a recovery code; wrong codes count towards the brute-force
+/// lockout like wrong passwords.
+#[utoipa::path(
+    post,
+    path = "/api/session/2fa/verify",
+    tag = "session",
+    request_body = CodeRequest,
+    responses(
+        (status = 200, description = "Logged in, or confirmed", body = SessionInfo),
+        (status = 401, description = "Wrong code, no login waiting, or it took too long"),
+        (status = 429, description = "Too many failed tries")
+    )
+)]
+pub async fn verify_handler(
+    State(state): State<AppState>,
+    session: Session,
+    ClientAddr(client): ClientAddr,
+    headers: HeaderMap,
+    Json(request): Json<CodeRequest>,
+) -> Result<Json<SessionInfo>, Response> {
+    let pending: Option<Pending> = session.get(PENDING_KEY);
+    let username = match (&pending, session.get::<String>("user")) {
+        (Some(pending), _) => {
+            let window = chrono::Duration::seconds(state.config().two_factor.challenge_secs as i64);
+            if Utc::now() - pending.at > window {
+                session.remove(PENDING_KEY);
+                return Err(AppError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "The login timed out; start again with the password",
+                )
+                .into_response());
+            }
+            pending.username.clone()
+        }
+        (None, Some(user)) => user,
+        (None, None) => {
+            return Err(
+                AppError::new(StatusCode::UNAUTHORIZED, "No login is waiting for a code")
+                    .into_response(),
+            );
+        }
+    };
+
+    let guard = state.brute_force();
+    guard.admit(&client, &username, &headers).await?;
+    let account = enrolment(&state, &username)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if !check_code(&state, &account, &request.code)
+        .await
+        .map_err(IntoResponse::into_response)?
+    {
+        guard.failed(&client, &username);
+        return Err(
+            AppError::new(StatusCode::UNAUTHORIZED, "Wrong two-factor code").into_response(),
+        );
+    }
+    guard.succeeded(&username);
+
+    if pending.is_some() {
+        session.remove(PENDING_KEY);
+        crate::sessions::log_in(&session, username);
+    }
+    session.insert(VERIFIED_KEY, Utc::now());
+    Ok(Json(crate::sessions::session_info(&session)))
+}
+
+/// New recovery codes in place of the old ones. Needs a recent code.
+#[utoipa::path(
+    post,
+    path = "/api/session/2fa/recovery-codes",
+    tag = "session",
+    responses(
+        (status = 200, description = "The new codes", body = RecoveryCodes),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "No recent two-factor code"),
+        (status = 409, description = "Two-factor is off")
+    )
+)]
+pub async fn recovery_codes_handler(
+    State(state): State<AppState>,
+    _: StepUp,
+    session: Session,
+) -> AppResult<Json<RecoveryCodes>> {
+    let account = enrolment(&state, &logged_in(&session)?).await?;
+    if account.totp_secret.is_none() {
+        return Err(AppError::new(StatusCode::CONFLICT, "Two-factor is off"));
+    }
+    let recovery_codes = issue_recovery_codes(&state, account.id).await?;
+    info!("🔑 Account {} got new recovery codes", account.id);
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Turns two-factor off and drops the recovery codes. Needs a recent code.
+#[utoipa::path(
+    post,
+    path = "/api/session/2fa/disable",
+    tag = "session",
+    responses(
+        (status = 204, description = "Two-factor is off"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "No recent two-factor code")
+    )
+)]
+pub async fn disable_handler(
+    State(state): State<AppState>,
+    _: StepUp,
+    session: Session,
+) -> AppResult<StatusCode> {
+    let account = enrolment(&state, &logged_in(&session)?).await?;
+    sqlx::query("UPDATE users SET totp_secret = NULL, totp_last_step = NULL WHERE id = ?")
+        .bind(account.id)
+        .execute(state.db())
+        .await
+        .map_err(AppError::internal)?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(account.id)
+        .execute(state.db())
+        .await
+        .map_err(AppError::internal)?;
+    session.remove(VERIFIED_KEY);
+    if account.totp_secret.is_some() {
+        info!("🔑 Account {} turned two-factor off", account.id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,124 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{Sqlite, SqliteConnection, SqlitePool, Transaction};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+use tracing::error;
+
+use crate::{error::AppError, state::AppState};
+
+type AfterCommit = Box<dyn FnOnce() + Send>;
+
+/// The request's transaction, parked between the handler and
+/// [`manage_transactions`].
+struct TxState {
+    pool: SqlitePool,
+    tx: Option<Transaction<'static, Sqlite>>,
+    after_commit: Vec<AfterCommit>,
+}
+
+#[derive(Clone)]
+struct TxSlot(Arc<Mutex<TxState>>);
+
+/// A transaction that lasts as long as the request. It's committed if the
+/// response is a success or redirect and rolled back otherwise, including
+/// when the handler returns an error or panics. Use it as the executor:
+/// `.fetch_one(&mut *tx)`.
+pub struct Tx {
+    slot: TxSlot,
+    tx: Option<Transaction<'static, Sqlite>>,
+}
+
+impl Tx {
+    /// Runs `f` once the transaction has committed, and not at all if it's
+    /// rolled back.
+    pub fn after_commit(&mut self, f: impl FnOnce() + Send + 'static) {
+        self.slot.0.lock().unwrap().after_commit.push(Box::new(f));
+    }
+}
+
+impl Deref for Tx {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        self.tx.as_ref().expect("transaction is only taken on drop")
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        self.tx.as_mut().expect("transaction is only taken on drop")
+    }
+}
+
+impl Drop for Tx {
+    /// Hands the transaction back for [`manage_transactions`] to finish.
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            self.slot.0.lock().unwrap().tx = Some(tx);
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Tx {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let slot =
+            parts.extensions.get::<TxSlot>().cloned().ok_or_else(|| {
+                AppError::internal("Tx used on a route without manage_transactions")
+            })?;
+        let pool = slot.0.lock().unwrap().pool.clone();
+        let tx = pool.begin().await.map_err(AppError::internal)?;
+        Ok(Self { slot, tx: Some(tx) })
+    }
+}
+
+/// Finishes the transaction a handler's [`Tx`] began, once the response is
+/// known. A failed commit turns the response into a 500.
+pub async fn manage_transactions(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let slot = TxSlot(Arc::new(Mutex::new(TxState {
+        pool: state.db().clone(),
+        tx: None,
+        after_commit: Vec::new(),
+    })));
+    req.extensions_mut().insert(slot.clone());
+
+    let res = next.run(req).await;
+    let (tx, after_commit) = {
+        let mut state = slot.0.lock().unwrap();
+        (state.tx.take(), std::mem::take(&mut state.after_commit))
+    };
+    let Some(tx) = tx else {
+        return res;
+    };
+
+    let status = res.status();
+    if !(status.is_success() || status.is_redirection()) {
+        if let Err(e) = tx.rollback().await {
+            error!("Failed to roll back transaction: {}", e);
+        }
+        return res;
+    }
+    match tx.commit().await {
+        Ok(()) => {
+            for f in after_commit {
+                f();
+            }
+            res
+        }
+        Err(e) => AppError::internal(format!("commit failed: {}", e)).into_response(),
+    }
+}
@@ -0,0 +1,953 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{multipart::Field, Multipart, Path, Query, State},
+    http::{header, Method, StatusCode},
+    response::{Json, Response},
+};
+use futures::future::BoxFuture;
+use image::{codecs::jpeg::JpegEncoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Cursor},
+    path::PathBuf,
+    pin::Pin,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    client_addr::ClientAddr,
+    error::{AppError, AppResult},
+    pagination::{Page, Pagination, PaginationParams},
+    state::AppState,
+};
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_ALLOWED_TYPES: &str =
+    "image/png,image/jpeg,image/gif,image/webp,application/pdf,text/plain,text/csv,application/json";
+const DEFAULT_ALLOWED_EXTENSIONS: &str = "png,jpg,jpeg,gif,webp,pdf,txt,csv,json";
+const DEFAULT_MAX_DIMENSIONS: (u32, u32) = (8192, 8192);
+/// Enough of a file to tell what it is.
+const SNIFF_BYTES: usize = 512;
+/// Quality for re-encoded JPEGs.
+const JPEG_QUALITY: u8 = 90;
+const DEFAULT_URL_EXPIRY: Duration = Duration::from_secs(15 * 60);
+/// As long as S3 presigned URLs can last.
+const MAX_URL_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub type BoxReader = Pin<Box<dyn AsyncRead + Send>>;
+pub type BoxWriter = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// Where uploaded bytes (and their metadata) live. Keys are opaque ids chosen
+/// by the upload store, never client input.
+pub trait StorageBackend: Send + Sync {
+    fn writer(&self, key: &str) -> BoxFuture<'_, io::Result<BoxWriter>>;
+    fn reader(&self, key: &str) -> BoxFuture<'_, io::Result<BoxReader>>;
+    fn remove(&self, key: &str) -> BoxFuture<'_, io::Result<()>>;
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>>;
+
+    /// A time-limited URL the client can download the upload from directly,
+    /// for backends that have one.
+    fn download_url(&self, _metadata: &UploadMetadata, _expires_in: Duration) -> Option<String> {
+        None
+    }
+}
+
+/// Stores objects as plain files in a local directory.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn writer(&self, key: &str) -> BoxFuture<'_, io::Result<BoxWriter>> {
+        let path = self.root.join(key);
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.root).await?;
+            let file = tokio::fs::File::create(path).await?;
+            Ok(Box::pin(file) as BoxWriter)
+        })
+    }
+
+    fn reader(&self, key: &str) -> BoxFuture<'_, io::Result<BoxReader>> {
+        let path = self.root.join(key);
+        Box::pin(async move {
+            let file = tokio::fs::File::open(path).await?;
+            Ok(Box::pin(file) as BoxReader)
+        })
+    }
+
+    fn remove(&self, key: &str) -> BoxFuture<'_, io::Result<()>> {
+        let path = self.root.join(key);
+        Box::pin(async move {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut entries = match tokio::fs::read_dir(&self.root).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+            let mut keys = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                keys.push(entry.file_name().to_string_lossy().into_owned());
+            }
+            Ok(keys)
+        })
+    }
+}
+
+/// Metadata returned to the client and stored next to each upload.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct UploadMetadata {
+    pub id: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the stored bytes.
+    pub checksum: String,
+    pub uploaded_at: chrono::DateTime<chrono::Utc>,
+    /// Why it's held back from downloads, when it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<String>,
+}
+
+/// Upload limits plus the backend the bytes are streamed into.
+pub struct UploadStore {
+    backend: Box<dyn StorageBackend>,
+    max_bytes: u64,
+    allowed_types: Vec<String>,
+    allowed_extensions: Vec<String>,
+    max_dimensions: Option<(u32, u32)>,
+    reencode_images: bool,
+    reject_suspicious: bool,
+}
+
+impl UploadStore {
+    /// Configured by `UPLOAD_BACKEND` (`local`, or `s3` with the `s3`
+    /// feature, where it's the default), `UPLOAD_DIR` for `local` (default
+    /// `uploads`), `UPLOAD_MAX_BYTES` (default 10 MiB),
+    /// `UPLOAD_ALLOWED_TYPES`, a comma-separated list of MIME types where
+    /// `type/*` wildcards are allowed, and `UPLOAD_ALLOWED_EXTENSIONS`,
+    /// likewise of file extensions (`*` for any). Images are held to
+    /// `UPLOAD_MAX_DIMENSIONS` (default `8192x8192`, `none` for no limit)
+    /// and, with `UPLOAD_REENCODE_IMAGES=true`, decoded and encoded again.
+    /// Files that aren't what they claim to be are quarantined, or refused
+    /// with `UPLOAD_ON_SUSPICION=reject`.
+    pub fn from_env(
+        #[cfg(feature = "s3")] s3: std::sync::Arc<crate::s3::S3>,
+    ) -> anyhow::Result<Self> {
+        let default_backend = if cfg!(feature = "s3") { "s3" } else { "local" };
+        let backend: Box<dyn StorageBackend> = match std::env::var("UPLOAD_BACKEND")
+            .unwrap_or_else(|_| default_backend.to_string())
+            .as_str()
+        {
+            "local" => {
+                let dir = std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string());
+                Box::new(LocalBackend::new(dir))
+            }
+            #[cfg(feature = "s3")]
+            "s3" => Box::new(crate::s3::S3Backend::new(s3)),
+            other => anyhow::bail!(
+                "UPLOAD_BACKEND {:?} isn't one of: local{}",
+                other,
+                if cfg!(feature = "s3") { ", s3" } else { "" }
+            ),
+        };
+        let max_bytes = std::env::var("UPLOAD_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let allowed_types = std::env::var("UPLOAD_ALLOWED_TYPES")
+            .unwrap_or_else(|_| DEFAULT_ALLOWED_TYPES.to_string())
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let allowed_extensions = std::env::var("UPLOAD_ALLOWED_EXTENSIONS")
+            .unwrap_or_else(|_| DEFAULT_ALLOWED_EXTENSIONS.to_string())
+            .split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        let max_dimensions = match std::env::var("UPLOAD_MAX_DIMENSIONS") {
+            Ok(v) if v == "none" => None,
+            Ok(v) => {
+                let parsed = v
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+                let Some(dimensions) = parsed else {
+                    anyhow::bail!("UPLOAD_MAX_DIMENSIONS: expected WIDTHxHEIGHT, got {:?}", v);
+                };
+                Some(dimensions)
+            }
+            Err(_) => Some(DEFAULT_MAX_DIMENSIONS),
+        };
+        let reencode_images = std::env::var("UPLOAD_REENCODE_IMAGES").is_ok_and(|v| v == "true");
+        let reject_suspicious = match std::env::var("UPLOAD_ON_SUSPICION").as_deref() {
+            Ok("reject") => true,
+            Ok("quarantine") | Err(_) => false,
+            Ok(other) => anyhow::bail!(
+                "UPLOAD_ON_SUSPICION {:?} isn't one of: quarantine, reject",
+                other
+            ),
+        };
+
+        Ok(Self {
+            backend,
+            max_bytes,
+            allowed_types,
+            allowed_extensions,
+            max_dimensions,
+            reencode_images,
+            reject_suspicious,
+        })
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    fn is_allowed(&self, content_type: &str) -> bool {
+        self.allowed_types
+            .iter()
+            .any(|allowed| mime_matches(allowed, content_type))
+    }
+
+    fn is_allowed_extension(&self, filename: &str) -> bool {
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, e)| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        self.allowed_extensions
+            .iter()
+            .any(|allowed| allowed == "*" || *allowed == extension)
+    }
+
+    /// Streams one multipart field into the backend, enforcing size, type
+    /// and extension limits as it goes. The first bytes are checked against
+    /// the claimed type and extension; a file that doesn't match is
+    /// quarantined (or refused). Images are read whole, for their
+    /// dimensions and re-encoding. Partial files are removed on failure.
+    async fn store(&self, mut field: Field<'_>) -> AppResult<UploadMetadata> {
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        if !self.is_allowed(&content_type) {
+            return Err(AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Content type '{}' is not allowed", content_type),
+            ));
+        }
+        let filename = sanitize_filename(field.file_name().unwrap_or("upload"));
+        if !self.is_allowed_extension(&filename) {
+            return Err(AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("The extension of '{}' is not allowed", filename),
+            ));
+        }
+
+        let mut incoming = Incoming {
+            field: &mut field,
+            size: 0,
+            max_bytes: self.max_bytes,
+        };
+        let mut head = Vec::new();
+        while head.len() < SNIFF_BYTES {
+            match incoming.next().await? {
+                Some(chunk) => head.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        let sniffed = sniff(&head);
+        let mut quarantine = suspicion(&filename, &content_type, sniffed);
+        let image_format = sniffed
+            .and_then(ImageFormat::from_mime_type)
+            .filter(|_| quarantine.is_none());
+        if let Some(format) = image_format
+            && (self.max_dimensions.is_some() || self.reencode_images)
+        {
+            while let Some(chunk) = incoming.next().await? {
+                head.extend_from_slice(&chunk);
+            }
+            let (max_dimensions, reencode) = (self.max_dimensions, self.reencode_images);
+            let processed = tokio::task::spawn_blocking(move || {
+                process_image(head, format, max_dimensions, reencode)
+            })
+            .await
+            .map_err(AppError::internal)?;
+            match processed {
+                Ok(bytes) => head = bytes,
+                Err(ImageProblem::TooLarge((width, height), (max_width, max_height))) => {
+                    return Err(AppError::new(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!(
+                            "The image is {}x{}; the limit is {}x{}",
+                            width, height, max_width, max_height
+                        ),
+                    ));
+                }
+                Err(ImageProblem::Undecodable(bytes, reason)) => {
+                    head = bytes;
+                    quarantine = Some(format!("doesn't decode as {}: {}", content_type, reason));
+                }
+            }
+        }
+        if let Some(reason) = &quarantine {
+            if self.reject_suspicious {
+                return Err(AppError::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("'{}' {}", filename, reason),
+                ));
+            }
+            warn!("☣️  Quarantining upload '{}': {}", filename, reason);
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut writer = self.backend.writer(&id).await?;
+        let mut hasher = Sha256::new();
+        let mut size = head.len() as u64;
+
+        let result: AppResult<()> = async {
+            hasher.update(&head);
+            writer.write_all(&head).await?;
+            while let Some(chunk) = incoming.next().await? {
+                size += chunk.len() as u64;
+                hasher.update(&chunk);
+                writer.write_all(&chunk).await?;
+            }
+            writer.shutdown().await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            drop(writer);
+            if let Err(remove_err) = self.backend.remove(&id).await {
+                warn!("Failed to remove partial upload {}: {}", id, remove_err);
+            }
+            return Err(e);
+        }
+
+        let metadata = UploadMetadata {
+            id: id.clone(),
+            filename,
+            content_type,
+            size,
+            checksum: hex::encode(hasher.finalize()),
+            uploaded_at: chrono::Utc::now(),
+            quarantine,
+        };
+        self.save_metadata(&metadata).await?;
+
+        info!("📦 Stored upload {} ({} bytes)", metadata.id, metadata.size);
+        Ok(metadata)
+    }
+
+    async fn save_metadata(&self, metadata: &UploadMetadata) -> AppResult<()> {
+        let mut meta_writer = self.backend.writer(&meta_key(&metadata.id)).await?;
+        meta_writer
+            .write_all(&serde_json::to_vec(metadata).map_err(AppError::internal)?)
+            .await?;
+        meta_writer.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn metadata(&self, id: &str) -> AppResult<UploadMetadata> {
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Err(AppError::not_found("Upload not found"));
+        }
+
+        let mut reader = match self.backend.reader(&meta_key(id)).await {
+            Ok(reader) => reader,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(AppError::not_found("Upload not found"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut buf).await?;
+        serde_json::from_slice(&buf).map_err(AppError::internal)
+    }
+
+    /// Metadata for every stored upload, in no particular order.
+    pub async fn list(&self) -> AppResult<Vec<UploadMetadata>> {
+        let mut uploads = Vec::new();
+        for key in self.backend.list().await? {
+            if let Some(id) = key.strip_suffix(".json") {
+                match self.metadata(id).await {
+                    Ok(metadata) => uploads.push(metadata),
+                    Err(e) => warn!("Skipping unreadable upload metadata {}: {:?}", key, e),
+                }
+            }
+        }
+        Ok(uploads)
+    }
+
+    /// Metadata for an upload that may be downloaded, i.e. isn't
+    /// quarantined.
+    async fn released(&self, id: &str) -> AppResult<UploadMetadata> {
+        let metadata = self.metadata(id).await?;
+        if metadata.quarantine.is_some() {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "This upload is quarantined pending review",
+            ));
+        }
+        Ok(metadata)
+    }
+
+    /// A direct download URL, if the backend can presign one.
+    pub async fn download_url(&self, id: &str, expires_in: Duration) -> AppResult<Option<String>> {
+        let metadata = self.released(id).await?;
+        Ok(self.backend.download_url(&metadata, expires_in))
+    }
+
+    pub async fn open(&self, id: &str) -> AppResult<(UploadMetadata, BoxReader)> {
+        let metadata = self.released(id).await?;
+        let reader = self.backend.reader(id).await?;
+        Ok((metadata, reader))
+    }
+
+    /// Lets a quarantined upload be downloaded.
+    pub async fn release(&self, id: &str) -> AppResult<UploadMetadata> {
+        let mut metadata = self.metadata(id).await?;
+        if let Some(reason) = metadata.quarantine.take() {
+            self.save_metadata(&metadata).await?;
+            info!("📦 Released upload {} from quarantine ({})", id, reason);
+        }
+        Ok(metadata)
+    }
+
+    pub async fn remove(&self, id: &str) -> AppResult<()> {
+        self.metadata(id).await?;
+        self.backend.remove(id).await?;
+        self.backend.remove(&meta_key(id)).await?;
+        Ok(())
+    }
+}
+
+/// A file field read a chunk at a time, held to the size limit.
+struct Incoming<'a, 'b> {
+    field: &'a mut Field<'b>,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl Incoming<'_, '_> {
+    async fn next(&mut self) -> AppResult<Option<Bytes>> {
+        let chunk = self
+            .field
+            .chunk()
+            .await
+            .map_err(|e| AppError::new(e.status(), e.body_text()))?;
+        if let Some(chunk) = &chunk {
+            self.size += chunk.len() as u64;
+            if self.size > self.max_bytes {
+                return Err(AppError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("File exceeds the {} byte limit", self.max_bytes),
+                ));
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+/// What a file's first bytes say it is: a MIME type for the formats with a
+/// signature, `text/plain` for UTF-8 without NULs, or `None` for binary
+/// data we don't know.
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-executable"),
+        (b"MZ", "application/x-msdownload"),
+        (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+        (b"\xca\xfe\xba\xbe", "application/x-mach-binary"),
+        (b"#!", "text/x-shellscript"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(mime);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    // Cut off mid-character at the end is still text.
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+    // Markup a browser would run, if the file were ever served inline.
+    let start = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_ascii_lowercase();
+    let markup = [
+        ("<!doctype html", "text/html"),
+        ("<html", "text/html"),
+        ("<script", "text/html"),
+        ("<svg", "image/svg+xml"),
+    ];
+    if let Some((_, mime)) = markup.iter().find(|(tag, _)| start.starts_with(tag)) {
+        return Some(mime);
+    }
+    Some("text/plain")
+}
+
+/// Why a file doesn't look like what it claims to be, if it doesn't: its
+/// content against the claimed type, and the claimed type against its
+/// extension.
+fn suspicion(filename: &str, content_type: &str, sniffed: Option<&str>) -> Option<String> {
+    let claimed = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let textual =
+        claimed.starts_with("text/") || claimed.ends_with("json") || claimed.ends_with("csv");
+    let fits = match sniffed {
+        Some("text/plain") => textual,
+        Some(sniffed) => sniffed == claimed,
+        // Binary we have no signature for: fine unless it claims a type we
+        // do have one for.
+        None => !textual && !KNOWN_SIGNATURE_TYPES.contains(&claimed.as_str()),
+    };
+    if !fits {
+        return Some(format!(
+            "looks like {}, not {}",
+            sniffed.unwrap_or("unknown binary data"),
+            claimed
+        ));
+    }
+    let extension = filename.rsplit_once('.').map(|(_, e)| e)?;
+    let guesses = mime_guess::from_ext(extension);
+    if guesses.first().is_some() && !guesses.iter().any(|guess| guess.essence_str() == claimed) {
+        return Some(format!(
+            "has the extension .{}, which isn't {}",
+            extension, claimed
+        ));
+    }
+    None
+}
+
+/// The types `sniff` recognizes by signature.
+const KNOWN_SIGNATURE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "application/gzip",
+];
+
+enum ImageProblem {
+    /// Its dimensions, and the limit.
+    TooLarge((u32, u32), (u32, u32)),
+    /// The bytes back, and what the decoder said.
+    Undecodable(Vec<u8>, String),
+}
+
+/// Checks an image's dimensions and, when `reencode`, decodes it and
+/// encodes it again, which drops metadata (EXIF, GPS) and anything appended
+/// to the image data. GIFs are only checked, as re-encoding would lose
+/// their animation.
+fn process_image(
+    bytes: Vec<u8>,
+    format: ImageFormat,
+    max_dimensions: Option<(u32, u32)>,
+    reencode: bool,
+) -> Result<Vec<u8>, ImageProblem> {
+    let reader = || ImageReader::with_format(Cursor::new(&bytes), format);
+    let dimensions = match reader().into_dimensions() {
+        Ok(dimensions) => dimensions,
+        Err(e) => return Err(ImageProblem::Undecodable(bytes, e.to_string())),
+    };
+    if let Some(max) = max_dimensions
+        && (dimensions.0 > max.0 || dimensions.1 > max.1)
+    {
+        return Err(ImageProblem::TooLarge(dimensions, max));
+    }
+    if !reencode || format == ImageFormat::Gif {
+        return Ok(bytes);
+    }
+    let image = match reader().decode() {
+        Ok(image) => image,
+        Err(e) => return Err(ImageProblem::Undecodable(bytes, e.to_string())),
+    };
+    let mut out = Vec::new();
+    let encoded = match format {
+        ImageFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))
+        }
+        _ => image.write_to(&mut Cursor::new(&mut out), format),
+    };
+    match encoded {
+        Ok(()) => Ok(out),
+        Err(e) => Err(ImageProblem::Undecodable(bytes, e.to_string())),
+    }
+}
+
+/// Whether `content_type` matches `pattern` (lowercase, `type/*` allowed),
+/// ignoring parameters like `charset`.
+fn mime_matches(pattern: &str, content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => essence.split('/').next() == Some(prefix),
+        None => pattern == essence,
+    }
+}
+
+fn meta_key(id: &str) -> String {
+    format!("{}.json", id)
+}
+
+/// Keeps only the final path component and drops characters that would need
+/// escaping in a `Content-Disposition` header.
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect();
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "upload".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Accepts `multipart/form-data`; every file field is stored and described
+/// in the response.
+#[utoipa::path(
+    post,
+    path = "/api/upload",
+    tag = "files",
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "One or more file fields"
+    ),
+    responses(
+        (status = 201, description = "Files stored; those that aren't what they claim to be are quarantined", body = [UploadMetadata]),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Content type or extension not allowed"),
+        (status = 422, description = "Image dimensions over the limit")
+    )
+)]
+pub async fn upload_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> AppResult<(StatusCode, Json<Vec<UploadMetadata>>)> {
+    let mut stored = Vec::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::new(e.status(), e.body_text()))?
+    {
+        if field.file_name().is_none() {
+            continue;
+        }
+        stored.push(state.uploads().store(field).await?);
+    }
+
+    if stored.is_empty() {
+        return Err(AppError::bad_request("No file fields in request"));
+    }
+
+    Ok((StatusCode::CREATED, Json(stored)))
+}
+
+/// Filters for `/api/uploads`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadFilter {
+    /// Case-insensitive filename substring.
+    q: Option<String>,
+    /// Exact MIME type, or `type/*`.
+    content_type: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/uploads",
+    tag = "files",
+    params(PaginationParams, UploadFilter),
+    responses(
+        (status = 200, description = "One page of uploads", body = UploadPage),
+        (status = 400, description = "Invalid paging, sort or filter")
+    )
+)]
+pub async fn list_handler(
+    State(state): State<AppState>,
+    pagination: Pagination,
+    Query(filter): Query<UploadFilter>,
+) -> AppResult<Json<Page<UploadMetadata>>> {
+    let sort = pagination.sort(&["uploaded_at", "filename", "size"])?;
+    let q = filter.q.map(|q| q.to_lowercase());
+    let content_type = filter.content_type.map(|t| t.to_ascii_lowercase());
+
+    let mut uploads: Vec<_> = state
+        .uploads()
+        .list()
+        .await?
+        .into_iter()
+        .filter(|u| u.quarantine.is_none())
+        .filter(|u| {
+            q.as_ref()
+                .is_none_or(|q| u.filename.to_lowercase().contains(q))
+        })
+        .filter(|u| {
+            content_type
+                .as_ref()
+                .is_none_or(|t| mime_matches(t, &u.content_type))
+        })
+        .collect();
+
+    // Newest first unless asked otherwise; ties broken by id for stable pages.
+    uploads.sort_by(|a, b| {
+        b.uploaded_at
+            .cmp(&a.uploaded_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    if let Some(sort) = sort {
+        uploads.sort_by(|a, b| {
+            let ordering = match sort.field.as_str() {
+                "filename" => a.filename.to_lowercase().cmp(&b.filename.to_lowercase()),
+                "size" => a.size.cmp(&b.size),
+                _ => a.uploaded_at.cmp(&b.uploaded_at),
+            };
+            if sort.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    Ok(Json(pagination.page(uploads)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/uploads/{id}",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id")),
+    responses(
+        (status = 200, description = "File contents"),
+        (status = 403, description = "Quarantined"),
+        (status = 404, description = "No such upload")
+    )
+)]
+pub async fn download_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Response> {
+    let (metadata, reader) = state.uploads().open(&id).await?;
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, &metadata.content_type)
+        .header(header::CONTENT_LENGTH, metadata.size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", metadata.filename),
+        )
+        .header(header::ETAG, format!("\"{}\"", metadata.checksum))
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .map_err(AppError::internal)
+}
+
+/// `GET /admin/uploads/quarantine`: uploads held back from downloads, and
+/// why, oldest first.
+pub async fn quarantine_handler(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<UploadMetadata>>> {
+    let mut uploads: Vec<_> = state
+        .uploads()
+        .list()
+        .await?
+        .into_iter()
+        .filter(|u| u.quarantine.is_some())
+        .collect();
+    uploads.sort_by_key(|u| u.uploaded_at);
+    Ok(Json(uploads))
+}
+
+/// `POST /admin/uploads/:id/release`: lets a quarantined upload be
+/// downloaded after all.
+pub async fn release_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<UploadMetadata>> {
+    Ok(Json(state.uploads().release(&id).await?))
+}
+
+/// `DELETE /admin/uploads/:id`: removes an upload, quarantined or not.
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    state.uploads().remove(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadUrlParams {
+    /// Seconds the URL stays valid: default 900, at most a week.
+    expires_in: Option<u64>,
+    /// Tie a signed link to the caller's address; default
+    /// `signed_urls.bind_client`. S3 URLs aren't tied.
+    bind_client: Option<bool>,
+}
+
+impl DownloadUrlParams {
+    fn expires_in(&self) -> AppResult<Duration> {
+        let expires_in = self
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_URL_EXPIRY);
+        if expires_in.is_zero() || expires_in > MAX_URL_EXPIRY {
+            return Err(AppError::bad_request(format!(
+                "expires_in must be 1 to {} seconds",
+                MAX_URL_EXPIRY.as_secs()
+            )));
+        }
+        Ok(expires_in)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DownloadUrl {
+    /// Absolute for S3; a path on this server for signed links.
+    url: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A link signed by this server for `method` requests to `path`, for
+/// `client`, or anyone unless they want it bound.
+fn signed_link(
+    state: &AppState,
+    client: &str,
+    params: &DownloadUrlParams,
+    method: Method,
+    path: &str,
+    expires_in: Duration,
+) -> AppResult<String> {
+    let signer = state.url_signer();
+    let client = params
+        .bind_client
+        .unwrap_or(signer.bind_client())
+        .then_some(client);
+    signer
+        .sign(&method, path, expires_in, client)
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_IMPLEMENTED,
+                format!("signed_urls.paths doesn't cover {}", path),
+            )
+        })
+}
+
+/// Hands out a presigned URL so large downloads skip the app entirely, or,
+/// for backends without those, a signed link to `/api/uploads/:id` that
+/// works without credentials until it expires.
+#[utoipa::path(
+    get,
+    path = "/api/uploads/{id}/url",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id"), DownloadUrlParams),
+    responses(
+        (status = 200, description = "A time-limited download URL", body = DownloadUrl),
+        (status = 400, description = "Expiry out of range"),
+        (status = 403, description = "Quarantined"),
+        (status = 404, description = "No such upload"),
+        (status = 501, description = "signed_urls.paths leaves downloads out")
+    )
+)]
+pub async fn download_url_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    ClientAddr(client): ClientAddr,
+    Query(params): Query<DownloadUrlParams>,
+) -> AppResult<Json<DownloadUrl>> {
+    let expires_in = params.expires_in()?;
+    let url = match state.uploads().download_url(&id, expires_in).await? {
+        Some(url) => url,
+        None => signed_link(
+            &state,
+            &client,
+            &params,
+            Method::GET,
+            &format!("/api/uploads/{}", id),
+            expires_in,
+        )?,
+    };
+    Ok(Json(DownloadUrl {
+        url,
+        expires_at: chrono::Utc::now() + expires_in,
+    }))
+}
+
+/// Hands out a signed link to `POST /api/upload`, for a client that should
+/// upload without credentials of its own, e.g. a browser given the link by
+/// a backend that holds them.
+#[utoipa::path(
+    get,
+    path = "/api/upload/url",
+    tag = "files",
+    params(DownloadUrlParams),
+    responses(
+        (status = 200, description = "A time-limited upload URL", body = DownloadUrl),
+        (status = 400, description = "Expiry out of range"),
+        (status = 501, description = "signed_urls.paths leaves uploads out")
+    )
+)]
+pub async fn upload_url_handler(
+    State(state): State<AppState>,
+    ClientAddr(client): ClientAddr,
+    Query(params): Query<DownloadUrlParams>,
+) -> AppResult<Json<DownloadUrl>> {
+    let expires_in = params.expires_in()?;
+    let url = signed_link(
+        &state,
+        &client,
+        &params,
+        Method::POST,
+        "/api/upload",
+        expires_in,
+    )?;
+    Ok(Json(DownloadUrl {
+        url,
+        expires_at: chrono::Utc::now() + expires_in,
+    }))
+}
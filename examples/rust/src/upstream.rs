@@ -0,0 +1,395 @@
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{self, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http_body_util::{BodyExt, Full, Limited};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::PathBuf, sync::Mutex, time::Duration};
+use tracing::{debug, info};
+
+use crate::{
+    capture::{header_list, Header},
+    config::{MatchRules, UpstreamConfig, UpstreamMode},
+    error::{AppError, AppResult},
+    outbound::{self, HttpsClient},
+    state::AppState,
+};
+
+/// Default directory for cassette files, relative to the working directory.
+pub const CASSETTES_DIR: &str = "cassettes";
+
+/// Largest request or response body the proxy will buffer.
+const UPSTREAM_BODY_LIMIT: usize = 10 * 1024 * 1024;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Says where a response came from: `live`, `recorded` or `replayed`.
+const UPSTREAM_HEADER: &str = "x-nsm-upstream";
+
+/// Connection-level headers that mustn't be forwarded, plus the ones we set
+/// ourselves. `accept-encoding` is dropped so cassettes stay readable.
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+    "content-length",
+    "accept-encoding",
+];
+
+fn forwardable(headers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+}
+
+/// Text bodies are stored as-is so cassettes can be read and hand-edited.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+enum RecordedBody {
+    #[serde(rename = "body")]
+    Text(String),
+    #[serde(rename = "body_base64")]
+    Binary(String),
+}
+
+impl RecordedBody {
+    fn new(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Self::Text(text.to_string()),
+            Err(_) => Self::Binary(STANDARD.encode(bytes)),
+        }
+    }
+
+    fn into_bytes(self) -> Bytes {
+        match self {
+            Self::Text(text) => Bytes::from(text),
+            Self::Binary(data) => Bytes::from(STANDARD.decode(data).unwrap_or_default()),
+        }
+    }
+}
+
+/// Only what matching needs is kept, so credentials in request headers
+/// never end up on disk.
+#[derive(Serialize, Deserialize, Clone)]
+struct RecordedRequest {
+    method: String,
+    /// Path and query, relative to the upstream's base URL.
+    uri: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(default, flatten, skip_serializing_if = "Option::is_none")]
+    body: Option<RecordedBody>,
+}
+
+impl RecordedRequest {
+    fn matches(&self, other: &RecordedRequest, rules: &MatchRules) -> bool {
+        let path = |uri: &str| uri.split('?').next().unwrap_or_default().to_string();
+        self.method == other.method
+            && if rules.query {
+                self.uri == other.uri
+            } else {
+                path(&self.uri) == path(&other.uri)
+            }
+            && self.headers == other.headers
+            && (!rules.body || self.body == other.body)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<Header>,
+    #[serde(flatten)]
+    body: RecordedBody,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Interaction {
+    recorded_at: chrono::DateTime<chrono::Utc>,
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+/// Recorded interactions of one upstream, read on first use.
+#[derive(Serialize, Deserialize, Default)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+pub struct Upstream {
+    base: String,
+    /// `base`'s host and port, which every forwarded request must keep.
+    authority: Option<http::uri::Authority>,
+    mode: UpstreamMode,
+    matching: MatchRules,
+    path: PathBuf,
+    cassette: Mutex<Option<Cassette>>,
+}
+
+impl Upstream {
+    fn new(
+        name: &str,
+        config: &UpstreamConfig,
+        mode: Option<UpstreamMode>,
+    ) -> anyhow::Result<Self> {
+        let base: Uri = config
+            .base_url
+            .parse()
+            .with_context(|| format!("upstream {}: invalid base_url", name))?;
+        if !matches!(base.scheme_str(), Some("http" | "https")) {
+            anyhow::bail!("upstream {}: base_url must be http(s)", name);
+        }
+        Ok(Self {
+            base: config.base_url.trim_end_matches('/').to_string(),
+            authority: base.authority().cloned(),
+            mode: mode.unwrap_or(config.mode),
+            matching: MatchRules {
+                query: config.matching.query,
+                body: config.matching.body,
+                headers: config
+                    .matching
+                    .headers
+                    .iter()
+                    .map(|h| h.to_ascii_lowercase())
+                    .collect(),
+            },
+            path: config
+                .cassette
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(CASSETTES_DIR).join(format!("{}.json", name))),
+            cassette: Mutex::new(None),
+        })
+    }
+
+    fn with_cassette<T>(&self, f: impl FnOnce(&mut Cassette) -> T) -> AppResult<T> {
+        let mut guard = self.cassette.lock().unwrap();
+        if guard.is_none() {
+            let cassette = match fs::read_to_string(&self.path) {
+                Ok(contents) => serde_json::from_str(&contents)
+                    .map_err(|e| AppError::internal(format!("{}: {}", self.path.display(), e)))?,
+                Err(e) if e.kind() == ErrorKind::NotFound => Cassette::default(),
+                Err(e) => return Err(e.into()),
+            };
+            *guard = Some(cassette);
+        }
+        Ok(f(guard.as_mut().expect("cassette loaded above")))
+    }
+
+    fn find(&self, request: &RecordedRequest) -> AppResult<Option<RecordedResponse>> {
+        self.with_cassette(|cassette| {
+            cassette
+                .interactions
+                .iter()
+                .find(|i| i.request.matches(request, &self.matching))
+                .map(|i| i.response.clone())
+        })
+    }
+
+    /// Adds the interaction, replacing any that matches the same request,
+    /// and rewrites the cassette file.
+    fn record(&self, interaction: Interaction) -> AppResult<()> {
+        let contents = self.with_cassette(|cassette| {
+            let rules = &self.matching;
+            cassette
+                .interactions
+                .retain(|i| !i.request.matches(&interaction.request, rules));
+            cassette.interactions.push(interaction);
+            serde_json::to_string_pretty(cassette)
+        })?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, contents.map_err(AppError::internal)?)?;
+        Ok(())
+    }
+}
+
+/// The upstreams configured in `config.json`, sharing one HTTP(S) client.
+/// `UPSTREAM_MODE` overrides every upstream's mode, e.g. `replay` in CI.
+pub struct Upstreams {
+    client: HttpsClient,
+    upstreams: BTreeMap<String, Upstream>,
+}
+
+impl Upstreams {
+    pub fn new(configs: &BTreeMap<String, UpstreamConfig>) -> anyhow::Result<Self> {
+        let mode = match std::env::var("UPSTREAM_MODE") {
+            Ok(mode) => Some(mode.parse().map_err(anyhow::Error::msg)?),
+            Err(_) => None,
+        };
+        let upstreams = configs
+            .iter()
+            .map(|(name, config)| Ok((name.clone(), Upstream::new(name, config, mode)?)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            client: outbound::https_client()?,
+            upstreams,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Upstream> {
+        self.upstreams.get(name)
+    }
+
+    async fn fetch(
+        &self,
+        upstream: &Upstream,
+        method: Method,
+        uri: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> AppResult<(StatusCode, HeaderMap, Bytes)> {
+        let url = format!("{}{}", upstream.base, uri);
+        // The path comes from the client; it mustn't take the request to
+        // another host.
+        let target: Uri = url
+            .parse()
+            .map_err(|_| AppError::bad_request(format!("Invalid upstream path {}", uri)))?;
+        if target.authority() != upstream.authority.as_ref() {
+            return Err(AppError::bad_request(format!(
+                "Invalid upstream path {}",
+                uri
+            )));
+        }
+        let mut req = http::Request::builder().method(method).uri(target);
+        for (name, value) in forwardable(headers) {
+            req = req.header(name, value);
+        }
+        let req = req.body(Full::new(body)).map_err(AppError::internal)?;
+
+        let bad_gateway =
+            |e: String| AppError::new(StatusCode::BAD_GATEWAY, format!("{}: {}", url, e));
+        let res = tokio::time::timeout(UPSTREAM_TIMEOUT, self.client.request(req))
+            .await
+            .map_err(|_| bad_gateway("timed out".to_string()))?
+            .map_err(|e| bad_gateway(error_chain(&e)))?;
+        let (parts, body) = res.into_parts();
+        let body = Limited::new(body, UPSTREAM_BODY_LIMIT)
+            .collect()
+            .await
+            .map_err(|e| bad_gateway(e.to_string()))?
+            .to_bytes();
+        Ok((parts.status, parts.headers, body))
+    }
+}
+
+/// Client errors keep the useful part ("dns error", "connection refused")
+/// in their source chain.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    std::iter::successors(Some(e), |e| e.source())
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+fn respond(status: StatusCode, headers: &HeaderMap, body: Bytes, source: &'static str) -> Response {
+    let mut res = Response::new(Body::from(body));
+    *res.status_mut() = status;
+    for (name, value) in forwardable(headers) {
+        res.headers_mut().append(name, value.clone());
+    }
+    res.headers_mut()
+        .insert(UPSTREAM_HEADER, HeaderValue::from_static(source));
+    res
+}
+
+/// `/upstream/:name/*path`: forwards to the named upstream, or answers from
+/// its cassette, depending on its mode.
+pub async fn proxy_handler(
+    State(state): State<AppState>,
+    Path((name, path)): Path<(String, String)>,
+    req: Request,
+) -> AppResult<Response> {
+    let upstream = state
+        .upstreams()
+        .get(&name)
+        .ok_or_else(|| AppError::not_found(format!("No upstream named {}", name)))?;
+
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, UPSTREAM_BODY_LIMIT)
+        .await
+        .map_err(|e| AppError::bad_request(e.to_string()))?;
+    let uri = match parts.uri.query() {
+        Some(query) => format!("/{}?{}", path, query),
+        None => format!("/{}", path),
+    };
+    let request = RecordedRequest {
+        method: parts.method.to_string(),
+        uri: uri.clone(),
+        headers: upstream
+            .matching
+            .headers
+            .iter()
+            .filter_map(|name| {
+                let value = parts.headers.get(name)?.to_str().ok()?;
+                Some((name.clone(), value.to_string()))
+            })
+            .collect(),
+        body: upstream.matching.body.then(|| RecordedBody::new(&body)),
+    };
+
+    if matches!(upstream.mode, UpstreamMode::Auto | UpstreamMode::Replay)
+        && let Some(recorded) = upstream.find(&request)?
+    {
+        debug!("Upstream {}: replaying {} {}", name, request.method, uri);
+        let mut headers = HeaderMap::new();
+        for h in &recorded.headers {
+            if let (Ok(n), Ok(v)) = (h.name.parse::<HeaderName>(), h.value.parse()) {
+                headers.append(n, v);
+            }
+        }
+        let status = StatusCode::from_u16(recorded.status).map_err(AppError::internal)?;
+        return Ok(respond(
+            status,
+            &headers,
+            recorded.body.into_bytes(),
+            "replayed",
+        ));
+    }
+    if upstream.mode == UpstreamMode::Replay {
+        return Err(AppError::new(
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "No recorded response for {} {} in {} (replay mode)",
+                request.method,
+                uri,
+                upstream.path.display()
+            ),
+        ));
+    }
+
+    let (status, headers, body) = state
+        .upstreams()
+        .fetch(upstream, parts.method, &uri, &parts.headers, body)
+        .await?;
+    if upstream.mode == UpstreamMode::Passthrough {
+        return Ok(respond(status, &headers, body, "live"));
+    }
+
+    info!(
+        "Upstream {}: recorded {} {} -> {}",
+        name, request.method, uri, status
+    );
+    let response_headers = header_list(&headers)
+        .into_iter()
+        .filter(|h| !SKIPPED_HEADERS.contains(&h.name.as_str()))
+        .collect();
+    upstream.record(Interaction {
+        recorded_at: chrono::Utc::now(),
+        request,
+        response: RecordedResponse {
+            status: status.as_u16(),
+            headers: response_headers,
+            body: RecordedBody::new(&body),
+        },
+    })?;
+    Ok(respond(status, &headers, body, "recorded"))
+}
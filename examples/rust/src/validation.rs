@@ -0,0 +1,69 @@
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequest, Request},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+use crate::error::{AppError, FieldErrors};
+
+/// `Json<T>` that also runs `T`'s `validator` rules. Malformed bodies and
+/// failed rules both come back in the unified error format; the latter as a
+/// 422 with per-field messages under `fields`.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) =
+            Json::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection: JsonRejection| {
+                    AppError::new(rejection.status(), rejection.body_text())
+                })?;
+        value
+            .validate()
+            .map_err(|errors| AppError::validation(field_errors(&errors)))?;
+        Ok(Self(value))
+    }
+}
+
+/// Flattens nested struct and list errors into `parent.child` /
+/// `items[2].name` keys.
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+    let mut fields = FieldErrors::new();
+    collect(errors, "", &mut fields);
+    fields
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                let messages = errors.iter().map(|e| match &e.message {
+                    Some(message) => message.to_string(),
+                    None => format!("failed the `{}` rule", e.code),
+                });
+                fields.entry(path).or_default().extend(messages);
+            }
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(errors, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
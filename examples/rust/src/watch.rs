@@ -0,0 +1,200 @@
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{net::TcpListener, path::Path, sync::Mutex, time::Duration};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Set on the re-exec'd process to the fd of the listening socket it inherits.
+const LISTEN_FD_ENV: &str = "APP_LISTEN_FD";
+
+/// What counts as a source change.
+const WATCH_PATHS: &[&str] = &["src", "proto", "build.rs", "Cargo.toml"];
+
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// `LISTEN_FD_ENV` as we were started with; see `take_handoff`.
+static HANDOFF: Mutex<Option<String>> = Mutex::new(None);
+
+/// Reads and clears `LISTEN_FD_ENV`, so the handoff doesn't leak into
+/// anything we spawn ourselves. Must run first thing in `main`, while the
+/// process is still single-threaded.
+pub fn take_handoff() {
+    let Ok(fd) = std::env::var(LISTEN_FD_ENV) else {
+        return;
+    };
+    // SAFETY: called from `main` before the runtime (or anything else) has
+    // started another thread, so nothing can read the environment
+    // concurrently.
+    unsafe { std::env::remove_var(LISTEN_FD_ENV) };
+    *HANDOFF.lock().unwrap() = Some(fd);
+}
+
+/// The listening socket handed down by the previous generation, if we were
+/// started by a watch-mode restart.
+pub fn inherited_listener() -> anyhow::Result<Option<TcpListener>> {
+    let Some(fd) = HANDOFF.lock().unwrap().take() else {
+        return Ok(None);
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+        let fd: i32 = fd.parse()?;
+        // SAFETY: the parent cleared CLOEXEC on this fd for us and nothing
+        // else in this process owns it.
+        Ok(Some(unsafe { TcpListener::from_raw_fd(fd) }))
+    }
+    #[cfg(not(unix))]
+    {
+        warn!(
+            "Ignoring {}={}: socket handoff needs unix",
+            LISTEN_FD_ENV, fd
+        );
+        Ok(None)
+    }
+}
+
+/// Watch mode (`serve --watch` or `APP_WATCH=1`): watches the sources and
+/// runs `cargo build` after each burst of changes.
+/// The returned token is cancelled once a build succeeds, which should
+/// trigger a graceful shutdown followed by `exec_restart`.
+pub fn spawn() -> anyhow::Result<CancellationToken> {
+    let restart = CancellationToken::new();
+    let (changes_tx, changes_rx) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            )
+        {
+            let _ = changes_tx.send(());
+        }
+    })?;
+    for path in WATCH_PATHS.iter().map(Path::new).filter(|p| p.exists()) {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+
+    tokio::spawn(rebuild_loop(watcher, changes_rx, restart.clone()));
+    info!(
+        "👀 Watch mode: rebuilding on changes to {}",
+        WATCH_PATHS.join(", ")
+    );
+    Ok(restart)
+}
+
+async fn rebuild_loop(
+    // Kept alive for as long as we're watching.
+    _watcher: notify::RecommendedWatcher,
+    mut changes: mpsc::UnboundedReceiver<()>,
+    restart: CancellationToken,
+) {
+    while changes.recv().await.is_some() {
+        loop {
+            settle(&mut changes).await;
+            let built = build().await;
+            // Edits made while cargo was running may not be in this build.
+            if changes.try_recv().is_ok() {
+                continue;
+            }
+            if built {
+                restart.cancel();
+                return;
+            }
+            break;
+        }
+    }
+}
+
+/// Waits until no change has arrived for `DEBOUNCE`.
+async fn settle(changes: &mut mpsc::UnboundedReceiver<()>) {
+    while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, changes.recv()).await {}
+}
+
+/// Builds the same profile and features we're running with. Cargo's output
+/// goes straight to our stdout/stderr, i.e. NSM's log view.
+async fn build() -> bool {
+    let mut cmd =
+        tokio::process::Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    cmd.arg("build");
+    if !cfg!(debug_assertions) {
+        cmd.arg("--release");
+    }
+    // Spell out every feature rather than relying on the defaults, so a
+    // build started with --no-default-features stays that way.
+    cmd.arg("--no-default-features");
+    let features = enabled_features();
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features.join(","));
+    }
+
+    info!("🔨 Sources changed, rebuilding");
+    match cmd.status().await {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            warn!(
+                "Build failed ({}), still serving the previous build",
+                status
+            );
+            false
+        }
+        Err(e) => {
+            error!("Failed to run cargo: {}", e);
+            false
+        }
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("database", cfg!(feature = "database")),
+        ("graphql", cfg!(feature = "graphql")),
+        ("grpc", cfg!(feature = "grpc")),
+        ("console", cfg!(feature = "console")),
+        ("redis", cfg!(feature = "redis")),
+        ("s3", cfg!(feature = "s3")),
+        ("email", cfg!(feature = "email")),
+        ("search", cfg!(feature = "search")),
+        ("nats", cfg!(feature = "nats")),
+    ]
+    .into_iter()
+    .filter_map(|(name, on)| on.then_some(name))
+    .collect()
+}
+
+/// Replaces this process with the freshly built binary, passing `listener`
+/// down so the port is never released. Only returns on failure.
+#[cfg(unix)]
+pub fn exec_restart(listener: TcpListener) -> anyhow::Error {
+    use std::os::{fd::IntoRawFd, unix::process::CommandExt};
+
+    let fd = listener.into_raw_fd();
+    // SAFETY: plain fcntl on an fd we own; clearing FD_CLOEXEC lets it
+    // survive exec.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } == -1 {
+        return std::io::Error::last_os_error().into();
+    }
+
+    // argv[0] is cargo's output path, which now holds the new build;
+    // `current_exe` would point at the replaced (deleted) file.
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .filter(|p| Path::new(p).components().count() > 1)
+        .map(Into::into)
+        .or_else(|| std::env::current_exe().ok())
+        .unwrap_or_default();
+
+    info!("🔁 Restarting {}", Path::new(&program).display());
+    std::process::Command::new(program)
+        .args(args)
+        .env(LISTEN_FD_ENV, fd.to_string())
+        .exec()
+        .into()
+}
+
+#[cfg(not(unix))]
+pub fn exec_restart(_listener: TcpListener) -> anyhow::Error {
+    anyhow::anyhow!("watch-mode restarts need unix; restart the server manually")
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::state::AppState;
+
+/// Stalls seen since startup.
+#[derive(Default)]
+pub struct Watchdog {
+    stalls: AtomicU64,
+    longest_stall_ms: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct WatchdogStats {
+    stalls: u64,
+    longest_stall_ms: u64,
+}
+
+impl Watchdog {
+    pub fn stats(&self) -> WatchdogStats {
+        WatchdogStats {
+            stalls: self.stalls.load(Ordering::Relaxed),
+            longest_stall_ms: self.longest_stall_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Starts a thread that keeps spawning a no-op task onto the runtime and
+/// warns when one waits longer than `watchdog.threshold_ms` to run: every
+/// worker is busy, usually because a handler is doing blocking work. It runs
+/// outside the runtime so a blocked runtime can't silence it.
+pub fn spawn(state: AppState) -> anyhow::Result<()> {
+    let config = state.config().watchdog.clone();
+    if config.threshold_ms == 0 {
+        return Ok(());
+    }
+    let threshold = Duration::from_millis(config.threshold_ms);
+    let interval = Duration::from_millis(config.interval_ms);
+    let runtime = tokio::runtime::Handle::current();
+
+    thread::Builder::new()
+        .name("watchdog".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let (probe, arrived) = mpsc::sync_channel(1);
+            let sent = Instant::now();
+            runtime.spawn(async move {
+                let _ = probe.send(());
+            });
+
+            match arrived.recv_timeout(threshold) {
+                Ok(()) => continue,
+                // The runtime is gone and dropped the probe.
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            let watchdog = state.watchdog();
+            watchdog.stalls.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "🐢 Runtime blocked for over {} ms. A task is likely doing blocking work \
+                 (sync I/O or DB driver, std::thread::sleep, heavy CPU) on a worker; move it \
+                 into tokio::task::spawn_blocking. Build with --features console and run \
+                 tokio-console to find it.",
+                threshold.as_millis()
+            );
+            if arrived.recv().is_err() {
+                return;
+            }
+            let stalled = sent.elapsed().as_millis() as u64;
+            watchdog
+                .longest_stall_ms
+                .fetch_max(stalled, Ordering::Relaxed);
+            warn!("🐢 Runtime responsive again after {} ms", stalled);
+        })?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::{events::TopicFilter, state::AppState, tenancy::Tenant};
+
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Clients that haven't sent anything (including pongs) for this long are
+/// considered gone.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(75);
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WsMode {
+    /// Send each message straight back to the client that sent it.
+    #[default]
+    Echo,
+    /// Publish each message to every connected client.
+    Broadcast,
+}
+
+#[derive(Deserialize)]
+pub struct WsParams {
+    #[serde(default)]
+    mode: WsMode,
+    /// Comma-separated event kinds to receive, e.g. `note.*,job.demo`;
+    /// everything when omitted.
+    topics: Option<String>,
+}
+
+/// `/ws`: echo or broadcast depending on `?mode=`; broadcasts reach only the
+/// sender's tenant. Every socket also receives events published on the
+/// app's event hub, as JSON text frames, narrowed by `?topics=` and without
+/// other tenants' events.
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(params): Query<WsParams>,
+    State(state): State<AppState>,
+    tenant: Tenant,
+) -> Response {
+    let topics = TopicFilter::parse(params.topics.as_deref());
+    ws.on_upgrade(move |socket| handle_socket(socket, params.mode, topics, tenant, state))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    mode: WsMode,
+    topics: TopicFilter,
+    tenant: Tenant,
+    state: AppState,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut events = state.events().subscribe();
+    let shutdown = state.shutdown_token();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    debug!("WebSocket client connected");
+
+    loop {
+        tokio::select! {
+            message = receiver.next() => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        debug!("WebSocket receive error: {}", e);
+                        break;
+                    }
+                    None => break,
+                };
+                last_seen = Instant::now();
+
+                let reply = match message {
+                    Message::Text(text) if mode == WsMode::Broadcast => {
+                        state.events().publish_to(
+                            Some(tenant.id().to_string()),
+                            "ws.message",
+                            serde_json::Value::String(text),
+                        );
+                        None
+                    }
+                    Message::Text(text) => Some(Message::Text(text)),
+                    Message::Binary(data) => Some(Message::Binary(data)),
+                    Message::Close(_) => break,
+                    // axum answers pings for us; pongs only refresh `last_seen`.
+                    Message::Ping(_) | Message::Pong(_) => None,
+                };
+
+                if let Some(reply) = reply
+                    && sender.send(reply).await.is_err()
+                {
+                    break;
+                }
+            }
+            event = events.recv() => match event {
+                Ok(event) if !topics.matches(&event.kind) || !event.visible_to(&tenant) => {}
+                Ok(event) => {
+                    let payload = serde_json::to_string(&event).unwrap_or_default();
+                    if sender.send(Message::Text(payload)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client lagging, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = ping.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    debug!("WebSocket client timed out");
+                    break;
+                }
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+            _ = shutdown.cancelled() => {
+                let _ = sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "server shutting down".into(),
+                    })))
+                    .await;
+                break;
+            }
+        }
+    }
+
+    debug!("WebSocket client disconnected");
+}
@@ -82,6 +82,54 @@ func NewExampleManager() *ExampleManager {
 					"test":  "cargo test",
 				},
 			},
+			"rust-actix": {
+				Name:        "Rust Web Server (Actix Web)",
+				Description: "Rust web server using Actix Web framework",
+				Language:    "Rust",
+				Templates:   []string{"rust-actix"},
+				PostCreate:  setupRustProject,
+				Commands: map[string]string{
+					"dev":   "cargo run",
+					"build": "cargo build --release",
+					"test":  "cargo test",
+				},
+			},
+			"rust-warp": {
+				Name:        "Rust Web Server (Warp)",
+				Description: "Rust web server using Warp filters",
+				Language:    "Rust",
+				Templates:   []string{"rust-warp"},
+				PostCreate:  setupRustProject,
+				Commands: map[string]string{
+					"dev":   "cargo run",
+					"build": "cargo build --release",
+					"test":  "cargo test",
+				},
+			},
+			"rust-rocket": {
+				Name:        "Rust Web Server (Rocket)",
+				Description: "Rust web server using Rocket framework",
+				Language:    "Rust",
+				Templates:   []string{"rust-rocket"},
+				PostCreate:  setupRustProject,
+				Commands: map[string]string{
+					"dev":   "cargo run",
+					"build": "cargo build --release",
+					"test":  "cargo test",
+				},
+			},
+			"rust-hyper": {
+				Name:        "Rust Web Server (hyper)",
+				Description: "Minimal Rust web server built directly on hyper, no framework",
+				Language:    "Rust",
+				Templates:   []string{"rust-hyper"},
+				PostCreate:  setupRustProject,
+				Commands: map[string]string{
+					"dev":   "cargo run",
+					"build": "cargo build --release",
+					"test":  "cargo test",
+				},
+			},
 			"python": {
 				Name:        "Python Flask",
 				Description: "Python web application using Flask framework",
@@ -276,6 +276,10 @@ func (m *SetupModel) renderComplete() string {
 		"  • react-vite-typescript",
 		"  • go",
 		"  • rust",
+		"  • rust-actix",
+		"  • rust-warp",
+		"  • rust-rocket",
+		"  • rust-hyper",
 		"  • python",
 		"  • java",
 		"",